@@ -0,0 +1,141 @@
+#![feature(test)]
+
+extern crate test;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use test::Bencher;
+use torcc_rs::controller::Consensus;
+
+const FIXTURE: &str = include_str!("../fixtures/consensus");
+
+// Tracks the peak heap usage, to compare parsing the consensus as it is read
+// with buffering it first.
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+		PEAK.fetch_max(current, Ordering::Relaxed);
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+// How much more the heap grows while running `f` than it was before.
+fn peak_growth<T>(f: impl FnOnce() -> T) -> (T, usize) {
+	let before = CURRENT.load(Ordering::Relaxed);
+	PEAK.store(before, Ordering::Relaxed);
+	let value = f();
+	(value, PEAK.load(Ordering::Relaxed) - before)
+}
+
+#[bench]
+fn parse_consensus(b: &mut Bencher) {
+	b.iter(|| Consensus::from_str(FIXTURE).unwrap());
+}
+
+#[cfg(unix)]
+mod unix {
+	use std::io::{BufRead, BufReader, Write};
+	use std::os::unix::net::UnixListener;
+	use std::thread;
+
+	use test::Bencher;
+	use torcc_rs::controller::{Consensus, TorController};
+
+	use super::*;
+
+	// The fixture with its router entries repeated, about 17k lines.
+	fn large_consensus() -> String {
+		let start = FIXTURE.find("\nr ").unwrap() + 1;
+		let end = FIXTURE.find("directory-footer").unwrap();
+		let mut consensus = FIXTURE[..start].to_string();
+		for _ in 0..10 {
+			consensus.push_str(&FIXTURE[start..end]);
+		}
+		consensus.push_str(&FIXTURE[end..]);
+		consensus
+	}
+
+	// Answers the handshake, then every GETINFO with the consensus.
+	fn mock_tor() -> TorController {
+		let mut reply = String::from("250+dir/status-vote/current/consensus=\r\n");
+		for line in large_consensus().lines() {
+			reply.push_str(line);
+			reply.push_str("\r\n");
+		}
+		reply.push_str(".\r\n250 OK\r\n");
+
+		let path =
+			std::env::temp_dir().join(format!("torcc-consensus-bench-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let listener = UnixListener::bind(&path).unwrap();
+		thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut writer = stream.try_clone().unwrap();
+			let mut reader = BufReader::new(stream);
+			let mut line = String::new();
+			loop {
+				line.clear();
+				if reader.read_line(&mut line).unwrap_or(0) == 0 {
+					return;
+				}
+				let reply: &[u8] = if line.starts_with("PROTOCOLINFO") {
+					b"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n"
+				} else if line.starts_with("GETINFO") {
+					reply.as_bytes()
+				} else {
+					b"250 OK\r\n"
+				};
+				writer.write_all(reply).unwrap();
+			}
+		});
+
+		let controller = TorController::builder().unix_socket(&path).build().unwrap();
+		let _ = std::fs::remove_file(&path);
+		controller
+	}
+
+	#[bench]
+	fn consensus_streamed(b: &mut Bencher) {
+		let mut controller = mock_tor();
+		let (consensus, peak) = peak_growth(|| controller.consensus().unwrap());
+		eprintln!(
+			"streamed: {} routers, peak heap growth {} KiB",
+			consensus.routers.len(),
+			peak / 1024
+		);
+		b.iter(|| controller.consensus().unwrap());
+	}
+
+	#[bench]
+	fn consensus_buffered(b: &mut Bencher) {
+		let mut controller = mock_tor();
+		let buffered = |controller: &mut TorController| {
+			let text = controller
+				.raw_getinfo("dir/status-vote/current/consensus")
+				.unwrap();
+			Consensus::from_str(&text).unwrap()
+		};
+		let (consensus, peak) = peak_growth(|| buffered(&mut controller));
+		eprintln!(
+			"buffered: {} routers, peak heap growth {} KiB",
+			consensus.routers.len(),
+			peak / 1024
+		);
+		b.iter(|| buffered(&mut controller));
+	}
+}
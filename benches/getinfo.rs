@@ -0,0 +1,58 @@
+#![feature(test)]
+
+extern crate test;
+
+#[cfg(unix)]
+mod unix {
+	use std::io::{BufRead, BufReader, Write};
+	use std::os::unix::net::UnixListener;
+	use std::thread;
+
+	use test::Bencher;
+	use torcc_rs::controller::TorController;
+
+	const COMMANDS: usize = 10_000;
+
+	// Answers the handshake, then every GETINFO with the same reply.
+	fn mock_tor() -> TorController {
+		let path = std::env::temp_dir().join(format!("torcc-bench-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let listener = UnixListener::bind(&path).unwrap();
+		thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut writer = stream.try_clone().unwrap();
+			let mut reader = BufReader::new(stream);
+			let mut line = String::new();
+			loop {
+				line.clear();
+				if reader.read_line(&mut line).unwrap_or(0) == 0 {
+					return;
+				}
+				let reply: &[u8] = if line.starts_with("PROTOCOLINFO") {
+					b"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n"
+				} else if line.starts_with("GETINFO") {
+					b"250-traffic/read=123456789\r\n250-traffic/written=987654321\r\n250 OK\r\n"
+				} else {
+					b"250 OK\r\n"
+				};
+				writer.write_all(reply).unwrap();
+			}
+		});
+
+		let controller = TorController::builder().unix_socket(&path).build().unwrap();
+		let _ = std::fs::remove_file(&path);
+		controller
+	}
+
+	#[bench]
+	fn getinfo_round_trips(b: &mut Bencher) {
+		let mut controller = mock_tor();
+		b.iter(|| {
+			for _ in 0..COMMANDS {
+				controller
+					.get_info(vec!["traffic/read", "traffic/written"])
+					.unwrap();
+			}
+		});
+	}
+}
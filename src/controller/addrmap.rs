@@ -0,0 +1,72 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use super::error::Error;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// Which of Tor's address mappings to list, see
+/// `TorController::address_mappings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingScope {
+	All,
+	/// Set with MapAddress in the torrc.
+	Config,
+	/// Learned from DNS resolves and exit connections.
+	Cache,
+	/// Set by controllers with MAPADDRESS.
+	Control,
+}
+
+impl fmt::Display for MappingScope {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			MappingScope::All => "all",
+			MappingScope::Config => "config",
+			MappingScope::Cache => "cache",
+			MappingScope::Control => "control",
+		})
+	}
+}
+
+/// A mapping from one address to another in Tor's address map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressMapping {
+	pub from:   String,
+	pub to:     String,
+	/// When the mapping expires, or `None` if it never does.
+	pub expiry: Option<SystemTime>,
+}
+
+/// Parses "FROM TO EXPIRY", where EXPIRY is "NEVER" or a quoted UTC time,
+/// optionally with the quotes around "NEVER" too.
+impl FromStr for AddressMapping {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut parts = s.splitn(3, ' ');
+		let mut part = || parts.next().ok_or(Error::InternalError);
+		let from = part()?.to_string();
+		let to = part()?.to_string();
+		let expiry = part()?.trim_matches('"');
+		let expiry = match expiry {
+			"NEVER" => None,
+			expiry => Some(parse_reply(expiry, parsers::utc_time)?),
+		};
+		Ok(AddressMapping { from, to, expiry })
+	}
+}
+
+impl TorController {
+	/// Lists the current address mappings of the given kind, e.g. to find
+	/// stale mappings made by controllers.
+	pub fn address_mappings(&mut self, scope: MappingScope) -> Result<Vec<AddressMapping>, Error> {
+		let mappings = self.get_info_value(&format!("address-mappings/{}", scope))?;
+		mappings
+			.lines()
+			.filter(|line| !line.is_empty())
+			.map(AddressMapping::from_str)
+			.collect()
+	}
+}
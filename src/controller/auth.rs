@@ -0,0 +1,267 @@
+use std::fmt::{self, Write as FmtWrite};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+use super::error::Error;
+use super::version::Capabilities;
+use super::{parsers, AuthMethod, ProtocolInfo, TorController};
+
+const SERVER_TO_CONTROLLER: &[u8] = b"Tor safe cookie authentication server-to-controller hash";
+const CONTROLLER_TO_SERVER: &[u8] = b"Tor safe cookie authentication controller-to-server hash";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// One way of authenticating, for `TorController::connect_with_fallback`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum AuthAttempt {
+	/// Plain COOKIE, optionally with a cookie file other than the one named in
+	/// PROTOCOLINFO, e.g. when Tor runs in a container and sees another path.
+	Cookie(Option<PathBuf>),
+	SafeCookie,
+	Password(String),
+	Null,
+}
+
+impl AuthAttempt {
+	pub fn method(&self) -> AuthMethod {
+		match self {
+			AuthAttempt::Cookie(_) => AuthMethod::Cookie,
+			AuthAttempt::SafeCookie => AuthMethod::SafeCookie,
+			AuthAttempt::Password(_) => AuthMethod::HashedPassword,
+			AuthAttempt::Null => AuthMethod::Null,
+		}
+	}
+}
+
+// Leaves out the password so it doesn't end up in logs.
+impl fmt::Debug for AuthAttempt {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AuthAttempt::Cookie(path) => f.debug_tuple("Cookie").field(path).finish(),
+			AuthAttempt::SafeCookie => f.write_str("SafeCookie"),
+			AuthAttempt::Password(_) => f.write_str("Password(..)"),
+			AuthAttempt::Null => f.write_str("Null"),
+		}
+	}
+}
+
+impl TorController {
+	/// Connects to `addr` and tries each of `attempts` in order until one
+	/// succeeds.
+	///
+	/// Tor closes the connection after a failed AUTHENTICATE, so every attempt
+	/// uses a new connection. If all of them fail, `Error::AllAuthFailed` lists
+	/// why each one did. Failing to connect at all is returned right away.
+	pub fn connect_with_fallback<A: ToSocketAddrs>(
+		addr: A,
+		attempts: &[AuthAttempt],
+	) -> Result<TorController, Error> {
+		let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+		let mut failures = Vec::new();
+		for attempt in attempts {
+			let mut controller = TorController::from_stream(TcpStream::connect(&addrs[..])?.into());
+			match controller.try_auth_attempt(attempt) {
+				Ok(()) => return Ok(controller),
+				Err(err) => {
+					debug!("{} authentication failed: {}", attempt.method(), err);
+					failures.push((attempt.method(), err));
+				}
+			}
+		}
+		Err(Error::AllAuthFailed(failures))
+	}
+
+	fn try_auth_attempt(&mut self, attempt: &AuthAttempt) -> Result<(), Error> {
+		let protocol_info = self.protocol_info()?;
+		self.capabilities = Capabilities::for_version(&protocol_info.version);
+		if !protocol_info.auth_methods.contains(&attempt.method()) {
+			return Err(Error::AuthMethodDisabled);
+		}
+
+		match attempt {
+			AuthAttempt::Cookie(path) => {
+				let cookie = read_cookie(&protocol_info, path.as_deref())?;
+				self.send(format!("AUTHENTICATE {}", to_hex(&cookie)), parsers::is_ok)
+			}
+			AuthAttempt::SafeCookie => {
+				let cookie = read_cookie(&protocol_info, None)?;
+				self.authenticate_safe_cookie(&cookie)
+			}
+			AuthAttempt::Password(password) => self.authenticate(password.clone()),
+			AuthAttempt::Null => self.authenticate_null(),
+		}
+	}
+
+	/// Authenticates with the cookie file at `path`, or the one named in
+	/// PROTOCOLINFO. Uses SAFECOOKIE when Tor doesn't accept the plain cookie.
+	pub fn authenticate_cookie(
+		&mut self,
+		protocol_info: &ProtocolInfo,
+		path: Option<&Path>,
+	) -> Result<(), Error> {
+		let cookie = read_cookie(protocol_info, path)?;
+
+		if protocol_info.auth_methods.contains(&AuthMethod::Cookie) {
+			let msg = format!("AUTHENTICATE {}", to_hex(&cookie));
+			self.send(msg, parsers::is_ok)
+		} else if protocol_info.auth_methods.contains(&AuthMethod::SafeCookie) {
+			self.authenticate_safe_cookie(&cookie)
+		} else {
+			Err(Error::AuthMethodDisabled)
+		}
+	}
+
+	/// Proves knowledge of the cookie without sending it, and checks that Tor
+	/// knows it too.
+	fn authenticate_safe_cookie(&mut self, cookie: &[u8]) -> Result<(), Error> {
+		let mut client_nonce = [0u8; 32];
+		rand::thread_rng().fill_bytes(&mut client_nonce);
+
+		let msg = format!("AUTHCHALLENGE SAFECOOKIE {}", to_hex(&client_nonce));
+		let (server_hash, server_nonce) = self.send(msg, parsers::auth_challenge)?;
+		let server_hash = from_hex(&server_hash).ok_or(Error::InternalError)?;
+		let server_nonce = from_hex(&server_nonce).ok_or(Error::InternalError)?;
+
+		let mut message = cookie.to_vec();
+		message.extend_from_slice(&client_nonce);
+		message.extend_from_slice(&server_nonce);
+		// Compared in constant time, so that the comparison doesn't reveal how
+		// much of a forged hash was right.
+		hmac_sha256(SERVER_TO_CONTROLLER, &message)
+			.verify_slice(&server_hash)
+			.map_err(|_| Error::SafeCookieMismatch)?;
+
+		let client_hash = hmac_sha256(CONTROLLER_TO_SERVER, &message).finalize();
+		let msg = format!("AUTHENTICATE {}", to_hex(&client_hash.into_bytes()));
+		self.send(msg, parsers::is_ok)
+	}
+}
+
+// Reads the cookie file at `path`, or the one named in PROTOCOLINFO.
+fn read_cookie(protocol_info: &ProtocolInfo, path: Option<&Path>) -> Result<Vec<u8>, Error> {
+	let path = match (path, &protocol_info.cookiefile) {
+		(Some(path), _) => path,
+		(None, Some(cookiefile)) => Path::new(cookiefile),
+		(None, None) => return Err(Error::AuthMethodDisabled),
+	};
+	Ok(std::fs::read(path)?)
+}
+
+pub(super) fn to_hex(bytes: &[u8]) -> String {
+	let mut hex = String::with_capacity(bytes.len() * 2);
+	for b in bytes {
+		write!(hex, "{:02X}", b).unwrap();
+	}
+	hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+	if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+		return None;
+	}
+	(0..hex.len())
+		.step_by(2)
+		.map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+		.collect()
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> HmacSha256 {
+	let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+	mac.update(message);
+	mac
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::prelude::*;
+	use std::io::BufReader;
+	use std::net::TcpListener;
+	use std::thread;
+
+	#[test]
+	fn safe_cookie_authentication() {
+		let cookie = [0x42u8; 32];
+		let server_nonce = [0x24u8; 32];
+		let cookie_path = std::env::temp_dir().join(format!("torcc-safecookie-{}", std::process::id()));
+		std::fs::write(&cookie_path, cookie).unwrap();
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream);
+			let mut exchange = |reply: &dyn Fn(&str) -> String| {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				let reply = reply(line.trim_end());
+				reader.get_mut().write_all(reply.as_bytes()).unwrap();
+				line.trim_end().to_string()
+			};
+
+			exchange(&|_| {
+				"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=SAFECOOKIE COOKIEFILE=\"/run/tor/control.authcookie\"\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n".to_string()
+			});
+			let challenge = exchange(&|command| {
+				let client_nonce = command.strip_prefix("AUTHCHALLENGE SAFECOOKIE ").unwrap();
+				let mut message = cookie.to_vec();
+				message.extend(from_hex(client_nonce).unwrap());
+				message.extend_from_slice(&server_nonce);
+				format!(
+					"250 AUTHCHALLENGE SERVERHASH={} SERVERNONCE={}\r\n",
+					to_hex(
+						&hmac_sha256(SERVER_TO_CONTROLLER, &message)
+							.finalize()
+							.into_bytes()
+					),
+					to_hex(&server_nonce)
+				)
+			});
+			let authenticate = exchange(&|_| "250 OK\r\n".to_string());
+			(challenge, authenticate)
+		});
+
+		TorController::builder()
+			.addr(addr)
+			.auth_cookie_path(&cookie_path)
+			.build()
+			.unwrap();
+
+		let (challenge, authenticate) = server.join().unwrap();
+		let client_nonce = challenge.strip_prefix("AUTHCHALLENGE SAFECOOKIE ").unwrap();
+		let mut message = cookie.to_vec();
+		message.extend(from_hex(client_nonce).unwrap());
+		message.extend_from_slice(&server_nonce);
+		assert_eq!(
+			authenticate,
+			format!(
+				"AUTHENTICATE {}",
+				to_hex(
+					&hmac_sha256(CONTROLLER_TO_SERVER, &message)
+						.finalize()
+						.into_bytes()
+				)
+			)
+		);
+		std::fs::remove_file(&cookie_path).unwrap();
+	}
+
+	#[test]
+	fn hmac_sha256_test_vector() {
+		// RFC 4231, test case 2
+		assert_eq!(
+			to_hex(
+				&hmac_sha256(b"Jefe", b"what do ya want for nothing?")
+					.finalize()
+					.into_bytes()
+			),
+			"5BDCC146BF60754E6A042426089575C75A003F089D2739839DEC58B964EC3843"
+		);
+		assert_eq!(from_hex("5bdc"), Some(vec![0x5b, 0xdc]));
+		assert_eq!(from_hex("5bd"), None);
+	}
+}
@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::connection::Connection;
+use super::error::Error;
+use super::events::EventKind;
+use super::TorController;
+use crate::parsers;
+
+/// Bandwidth usage as seen by a `BandwidthMonitor`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bandwidth {
+	/// Bytes read per second, averaged over the monitor's window.
+	pub read_rate:     f64,
+	/// Bytes written per second, averaged over the monitor's window.
+	pub write_rate:    f64,
+	/// Bytes read since the monitor was created.
+	pub total_read:    u64,
+	/// Bytes written since the monitor was created.
+	pub total_written: u64,
+}
+
+/// Tracks Tor's bandwidth usage from BW events.
+///
+/// Created by `TorController::bandwidth_monitor`. Events are only collected
+/// when `current` is called, which never waits on the network, so it can be
+/// called from a UI thread as often as the UI redraws.
+pub struct BandwidthMonitor {
+	conn:          Arc<Mutex<Connection>>,
+	window:        Duration,
+	// (received, read, written) for each BW event still inside the window.
+	samples:       VecDeque<(Instant, u64, u64)>,
+	total_read:    u64,
+	total_written: u64,
+}
+
+impl BandwidthMonitor {
+	/// Collects the BW events received since the last call and returns the
+	/// current rates.
+	///
+	/// Seconds in which Tor sent no BW event count as idle.
+	pub fn current(&mut self) -> Result<Bandwidth, Error> {
+		let events = {
+			let mut conn = self.conn.lock().map_err(|_| Error::ConnectionLost)?;
+			conn.take_events(&EventKind::Bw)?
+		};
+
+		// Tor sends at most one BW event per second, so events collected in one
+		// go are spread out over the seconds leading up to now.
+		let now = Instant::now();
+		let count = events.len() as u32;
+		for (n, event) in events.into_iter().enumerate() {
+			let (read, written) = match parsers::bw_event(&event.raw) {
+				Ok((_, bytes)) => bytes,
+				Err(_) => return Err(Error::InternalError),
+			};
+			let age = Duration::from_secs(u64::from(count - n as u32 - 1));
+			let received = now.checked_sub(age).unwrap_or(now);
+			self.samples.push_back((received, read, written));
+			self.total_read += read;
+			self.total_written += written;
+		}
+
+		while let Some((received, _, _)) = self.samples.front() {
+			if now.duration_since(*received) < self.window {
+				break;
+			}
+			self.samples.pop_front();
+		}
+
+		let window = self.window.as_secs_f64();
+		let (read, written) = self
+			.samples
+			.iter()
+			.fold((0, 0), |(read, written), (_, r, w)| (read + r, written + w));
+		Ok(Bandwidth {
+			read_rate:     read as f64 / window,
+			write_rate:    written as f64 / window,
+			total_read:    self.total_read,
+			total_written: self.total_written,
+		})
+	}
+}
+
+/// Parses a byte count like "429496729600 bytes", as some GETINFO keys
+/// report them. The " bytes" suffix is optional.
+pub fn parse_bytes_value(s: &str) -> Result<u64, Error> {
+	let bytes = s.strip_suffix(" bytes").unwrap_or(s);
+	u64::from_str(bytes).map_err(|_| Error::InternalError)
+}
+
+impl TorController {
+	/// How many times Tor has hit its bandwidth limit, from GETINFO
+	/// bw-limit-event-count.
+	pub fn get_bw_limit_event_count(&mut self) -> Result<u64, Error> {
+		let count = self.get_info_value("bw-limit-event-count")?;
+		u64::from_str(&count).map_err(|_| Error::InternalError)
+	}
+
+	/// How much memory Tor allows its cell queues to use before it starts
+	/// closing circuits, in bytes.
+	pub fn get_max_memory_queues(&mut self) -> Result<u64, Error> {
+		parse_bytes_value(&self.get_info_value("max-mem-in-queues")?)
+	}
+
+	/// Subscribes to BW events and returns a monitor averaging them over
+	/// `window`.
+	///
+	/// BW stays in the controller's subscription; replacing the subscription
+	/// with `set_events` stops the monitor from seeing new events.
+	pub fn bandwidth_monitor(&mut self, window: Duration) -> Result<BandwidthMonitor, Error> {
+		if !self.subscribed.contains(&EventKind::Bw) {
+			let mut events = self.subscribed.clone();
+			events.push(EventKind::Bw);
+			self.send_set_events(&events)?;
+			self.subscribed = events;
+		}
+
+		Ok(BandwidthMonitor {
+			conn: self.conn.clone(),
+			window,
+			samples: VecDeque::new(),
+			total_read: 0,
+			total_written: 0,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_bytes_values() {
+		assert_eq!(
+			parse_bytes_value("429496729600 bytes").unwrap(),
+			429496729600
+		);
+		assert_eq!(parse_bytes_value("8589934592").unwrap(), 8589934592);
+		assert_eq!(parse_bytes_value("0").unwrap(), 0);
+		assert_eq!(parse_bytes_value("0 bytes").unwrap(), 0);
+		assert!(parse_bytes_value("").is_err());
+		assert!(parse_bytes_value("8 GB").is_err());
+	}
+}
@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+use super::error::Error;
+use super::events::EventKind;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// A step of Tor's bootstrap process, as reported by GETINFO
+/// status/bootstrap-phase and STATUS_CLIENT BOOTSTRAP events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootstrapPhase {
+	/// "NOTICE" while bootstrapping goes well, "WARN" when it is stuck.
+	pub severity:       String,
+	/// Percentage from 0 to 100.
+	pub progress:       u8,
+	pub tag:            String,
+	/// Tor's own description of the phase, meant to be shown to users.
+	pub summary:        String,
+	/// Why bootstrapping is stuck, for WARN phases.
+	pub warning:        Option<String>,
+	pub reason:         Option<String>,
+	/// "ignore" or "warn"; whether Tor thinks the problem is worth showing.
+	pub recommendation: Option<String>,
+}
+
+impl BootstrapPhase {
+	pub fn is_done(&self) -> bool {
+		self.progress >= 100
+	}
+
+	pub fn is_warning(&self) -> bool {
+		self.severity == "WARN"
+	}
+}
+
+impl TorController {
+	/// Returns the bootstrap phase Tor is currently in.
+	pub fn bootstrap_phase(&mut self) -> Result<BootstrapPhase, Error> {
+		let phase = self.get_info_value("status/bootstrap-phase")?;
+		parse_reply(&phase, parsers::bootstrap_phase)
+	}
+
+	/// Calls `progress` with the current bootstrap phase and every phase Tor
+	/// reports after it, including stalls, until bootstrapping is done or
+	/// `timeout` elapses. Returns the last phase seen.
+	pub fn wait_for_bootstrap<F>(
+		&mut self,
+		timeout: Duration,
+		mut progress: F,
+	) -> Result<BootstrapPhase, Error>
+	where
+		F: FnMut(BootstrapPhase),
+	{
+		let deadline = Instant::now() + timeout;
+		self.with_events(&[EventKind::StatusClient], |controller| {
+			// Subscribing first means no phase is missed between the GETINFO and
+			// the first event.
+			let mut phase = controller.bootstrap_phase()?;
+			progress(phase.clone());
+			while !phase.is_done() {
+				let event = controller.wait_for_event(deadline, |event| {
+					parsers::status_client_bootstrap(&event.raw).is_ok()
+				})?;
+				match event {
+					Some(event) => {
+						phase = parse_reply(&event.raw, parsers::status_client_bootstrap)?;
+						progress(phase.clone());
+					}
+					None => break,
+				}
+			}
+			Ok(phase)
+		})
+	}
+}
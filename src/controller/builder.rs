@@ -0,0 +1,270 @@
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::bootstrap::BootstrapPhase;
+use super::error::Error;
+use super::stream::Stream;
+use super::version::Capabilities;
+use super::{AuthMethod, ProtocolInfo, TorController, DEFAULT_API};
+
+const DEFAULT_BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(120);
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Checked in order by `TorControllerBuilder::addr_from_env`.
+const CONTROL_ADDRESS_VARIABLES: [&str; 2] = ["TOR_CONTROL", "CONTROL_PORT"];
+
+enum Target {
+	Tcp(io::Result<Vec<SocketAddr>>),
+	#[cfg(unix)]
+	Unix(PathBuf),
+}
+
+type BootstrapCallback = Box<dyn FnMut(BootstrapPhase) + Send>;
+
+/// Connects and authenticates a `TorController` in one go.
+///
+/// Created by `TorController::builder`. Without further configuration it
+/// connects to the default control port and authenticates with the cookie
+/// file named in PROTOCOLINFO.
+pub struct TorControllerBuilder {
+	target:            Target,
+	password:          Option<String>,
+	cookie_path:       Option<PathBuf>,
+	auto_auth:         bool,
+	connect_timeout:   Option<Duration>,
+	read_timeout:      Option<Duration>,
+	write_timeout:     Option<Duration>,
+	on_bootstrap:      Option<BootstrapCallback>,
+	bootstrap_timeout: Duration,
+	max_retries:       u32,
+	retry_delay:       Duration,
+}
+
+impl TorControllerBuilder {
+	pub(super) fn new() -> Self {
+		TorControllerBuilder {
+			target:            Target::Tcp(DEFAULT_API.to_socket_addrs().map(Iterator::collect)),
+			password:          None,
+			cookie_path:       None,
+			auto_auth:         false,
+			connect_timeout:   None,
+			read_timeout:      None,
+			write_timeout:     None,
+			on_bootstrap:      None,
+			bootstrap_timeout: DEFAULT_BOOTSTRAP_TIMEOUT,
+			max_retries:       0,
+			retry_delay:       DEFAULT_RETRY_DELAY,
+		}
+	}
+
+	/// The address of the control port, "127.0.0.1:9051" by default. Resolving
+	/// the address happens right away, but errors are only reported by `build`.
+	pub fn addr(mut self, addr: impl ToSocketAddrs) -> Self {
+		self.target = Target::Tcp(addr.to_socket_addrs().map(Iterator::collect));
+		self
+	}
+
+	/// Takes the control port from the TOR_CONTROL environment variable, or
+	/// CONTROL_PORT if that isn't set, falling back to the default address.
+	///
+	/// The value is either a TCP address like "127.0.0.1:9051" or the path of
+	/// a ControlSocket like "/run/tor/control". Fails with
+	/// `Error::NoControlAddress` if the variable is set but empty.
+	pub fn addr_from_env(self) -> Result<Self, Error> {
+		let value = CONTROL_ADDRESS_VARIABLES
+			.iter()
+			.find_map(|name| std::env::var(name).ok());
+		match value.as_deref().map(str::trim) {
+			None => Ok(self),
+			Some("") => Err(Error::NoControlAddress),
+			Some(path) if path.starts_with('/') => self.env_unix_socket(path),
+			Some(addr) => Ok(self.addr(addr)),
+		}
+	}
+
+	#[cfg(unix)]
+	fn env_unix_socket(self, path: &str) -> Result<Self, Error> {
+		Ok(self.unix_socket(path))
+	}
+
+	#[cfg(not(unix))]
+	fn env_unix_socket(self, path: &str) -> Result<Self, Error> {
+		let message = format!("unix sockets are not supported: {}", path);
+		Err(io::Error::new(io::ErrorKind::Unsupported, message).into())
+	}
+
+	/// Connects to a ControlSocket instead of a TCP control port.
+	#[cfg(unix)]
+	pub fn unix_socket(mut self, path: impl AsRef<Path>) -> Self {
+		self.target = Target::Unix(path.as_ref().to_path_buf());
+		self
+	}
+
+	/// Authenticates with a password instead of the cookie file.
+	pub fn password(mut self, password: impl Into<String>) -> Self {
+		self.password = Some(password.into());
+		self
+	}
+
+	/// Reads the authentication cookie from `path` rather than the location
+	/// PROTOCOLINFO reports, which may not exist from where we run.
+	pub fn auth_cookie_path(mut self, path: impl AsRef<Path>) -> Self {
+		self.cookie_path = Some(path.as_ref().to_path_buf());
+		self
+	}
+
+	/// Picks the authentication method from what PROTOCOLINFO offers: the
+	/// cookie file if it can be read, using SAFECOOKIE if plain COOKIE is
+	/// disabled, and otherwise the password, if one was given.
+	pub fn auto_auth(mut self) -> Self {
+		self.auto_auth = true;
+		self
+	}
+
+	/// Gives up connecting to a TCP control port after `timeout`.
+	pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+		self.connect_timeout = Some(timeout);
+		self
+	}
+
+	/// Fails reads from the control connection that take longer than
+	/// `timeout` with `Error::Io`.
+	pub fn read_timeout(mut self, timeout: Duration) -> Self {
+		self.read_timeout = Some(timeout);
+		self
+	}
+
+	/// Fails writes to the control connection that take longer than `timeout`
+	/// with `Error::Io`.
+	pub fn write_timeout(mut self, timeout: Duration) -> Self {
+		self.write_timeout = Some(timeout);
+		self
+	}
+
+	/// Waits for Tor to finish bootstrapping before `build` returns, passing
+	/// each bootstrap phase to `progress` as Tor reports it.
+	///
+	/// Phases with WARN severity are passed on as well, so the summary Tor gives
+	/// for a stall can be shown to the user.
+	pub fn on_bootstrap(mut self, progress: impl FnMut(BootstrapPhase) + Send + 'static) -> Self {
+		self.on_bootstrap = Some(Box::new(progress));
+		self
+	}
+
+	/// How long to wait for bootstrapping to finish, two minutes by default.
+	/// When it elapses, `build` returns the controller anyway.
+	pub fn bootstrap_timeout(mut self, timeout: Duration) -> Self {
+		self.bootstrap_timeout = timeout;
+		self
+	}
+
+	/// Reconnects and sends a command again, up to `retries` times, when the
+	/// connection is reset or Tor hangs up before replying. Off by default.
+	///
+	/// Tor may have run the command before the connection went away, so only
+	/// GETINFO, GETCONF and PROTOCOLINFO, which don't change anything, are
+	/// sent again. Other commands, like SIGNAL, fail with the error.
+	///
+	/// Parse errors and error replies are never retried, and neither are
+	/// iterators like `stream_network_status` that hand out a reply while it
+	/// is being read.
+	pub fn max_retries(mut self, retries: u32) -> Self {
+		self.max_retries = retries;
+		self
+	}
+
+	/// How long to wait before reconnecting to retry a command, half a second
+	/// by default.
+	pub fn retry_delay(mut self, delay: Duration) -> Self {
+		self.retry_delay = delay;
+		self
+	}
+
+	pub(super) fn retry_policy(&self) -> (u32, Duration) {
+		(self.max_retries, self.retry_delay)
+	}
+
+	/// Connects, authenticates and, with `on_bootstrap`, waits for Tor to
+	/// bootstrap.
+	pub fn build(mut self) -> Result<TorController, Error> {
+		let on_bootstrap = self.on_bootstrap.take();
+		let mut controller = self.connect()?;
+		if let Some(progress) = on_bootstrap {
+			controller.wait_for_bootstrap(self.bootstrap_timeout, progress)?;
+		}
+
+		controller.builder = Some(Box::new(self));
+		Ok(controller)
+	}
+
+	/// Connects and authenticates, also used by `TorController::reconnect`.
+	pub(super) fn connect(&self) -> Result<TorController, Error> {
+		let stream = self.open_stream()?;
+		stream.set_read_timeout(self.read_timeout)?;
+		stream.set_write_timeout(self.write_timeout)?;
+
+		let mut controller = TorController::from_stream(stream);
+		let protocol_info = controller.protocol_info()?;
+		self.authenticate(&mut controller, &protocol_info)?;
+		controller.capabilities = Capabilities::for_version(&protocol_info.version);
+		Ok(controller)
+	}
+
+	fn open_stream(&self) -> Result<Stream, Error> {
+		match &self.target {
+			Target::Tcp(Ok(addrs)) => {
+				let timeout = match self.connect_timeout {
+					Some(timeout) => timeout,
+					None => return Ok(TcpStream::connect(&addrs[..])?.into()),
+				};
+				let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to");
+				for addr in addrs {
+					match TcpStream::connect_timeout(addr, timeout) {
+						Ok(stream) => return Ok(stream.into()),
+						Err(err) => last_err = err,
+					}
+				}
+				Err(last_err.into())
+			}
+			Target::Tcp(Err(err)) => Err(io::Error::new(err.kind(), err.to_string()).into()),
+			#[cfg(unix)]
+			Target::Unix(path) => Ok(std::os::unix::net::UnixStream::connect(path)?.into()),
+		}
+	}
+
+	fn authenticate(
+		&self,
+		controller: &mut TorController,
+		protocol_info: &ProtocolInfo,
+	) -> Result<(), Error> {
+		let methods = &protocol_info.auth_methods;
+		let cookie_path = self.cookie_path.as_deref();
+
+		if self.auto_auth {
+			if methods.contains(&AuthMethod::Null) {
+				return controller.authenticate_null();
+			}
+			let cookie_readable = cookie_path
+				.or_else(|| protocol_info.cookiefile.as_deref().map(Path::new))
+				.is_some_and(|path| std::fs::File::open(path).is_ok());
+			let cookie_offered =
+				methods.contains(&AuthMethod::Cookie) || methods.contains(&AuthMethod::SafeCookie);
+			if cookie_offered && cookie_readable {
+				return controller.authenticate_cookie(protocol_info, cookie_path);
+			}
+		}
+
+		match &self.password {
+			Some(password) if methods.contains(&AuthMethod::HashedPassword) => {
+				controller.authenticate(password.clone())
+			}
+			Some(_) => Err(Error::AuthMethodDisabled),
+			None if self.cookie_path.is_none() && methods.contains(&AuthMethod::Null) => {
+				controller.authenticate_null()
+			}
+			None => controller.authenticate_cookie(protocol_info, cookie_path),
+		}
+	}
+}
@@ -0,0 +1,49 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::error::Error;
+use super::TorController;
+
+/// A CELL_STATS event, reporting the cells that went through a circuit's
+/// queues since the last one.
+///
+/// Tor counts cells per cell type, e.g. "relay:12,created:1". The counts and
+/// times here are summed over all types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellStatsEvent {
+	/// ID, or for circuits Tor didn't originate, the circuit's ID on its
+	/// inbound connection.
+	pub circuit_id:       u32,
+	/// The circuit's ID on the inbound connection, 0 for circuits Tor
+	/// originated since those have no inbound side.
+	pub inbound_queue:    u32,
+	pub inbound_conn:     Option<u64>,
+	pub inbound_added:    u32,
+	pub inbound_removed:  u32,
+	/// Milliseconds the removed cells spent waiting in the queue.
+	pub inbound_time:     f64,
+	/// The circuit's ID on the outbound connection.
+	pub outbound_queue:   u32,
+	pub outbound_conn:    Option<u64>,
+	pub outbound_added:   u32,
+	pub outbound_removed: u32,
+	/// Milliseconds the removed cells spent waiting in the queue.
+	pub outbound_time:    f64,
+}
+
+impl TorController {
+	/// How often Tor sends CELL_STATS events, from GETINFO cell-event-interval.
+	pub fn get_cell_event_interval(&mut self) -> Result<Duration, Error> {
+		let seconds = self.get_info_value("cell-event-interval")?;
+		let seconds = u64::from_str(seconds.trim()).map_err(|_| Error::InternalError)?;
+		Ok(Duration::from_secs(seconds))
+	}
+
+	/// Turns the CellStatistics option on or off. Tor only sends CELL_STATS
+	/// events while it is on, at the interval it reports as
+	/// `get_cell_event_interval`.
+	pub fn set_cell_statistics(&mut self, enabled: bool) -> Result<(), Error> {
+		let enabled = if enabled { "1" } else { "0" };
+		self.set_config(vec![("CellStatistics", enabled)])
+	}
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// A CIRC_BW event, reporting the bytes a circuit has carried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitBwEvent {
+	pub circuit_id:        u32,
+	/// Bytes read over the circuit's lifetime.
+	pub read:              u64,
+	/// Bytes written over the circuit's lifetime.
+	pub written:           u64,
+	/// When Tor sent the event, e.g. "2024-05-01T12:00:00.123456". Older
+	/// versions of Tor leave it out.
+	pub time:              Option<String>,
+	/// The part of `read` that was stream data delivered to the client.
+	pub delivered_read:    Option<u64>,
+	/// The part of `written` that was stream data delivered to the exit.
+	pub delivered_written: Option<u64>,
+	/// The part of `read` spent on cell headers and padding.
+	pub overhead_read:     Option<u64>,
+	/// The part of `written` spent on cell headers and padding.
+	pub overhead_written:  Option<u64>,
+}
+
+/// The latest CIRC_BW event of a circuit, with the rates since the one
+/// before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBwSnapshot {
+	pub event:      CircuitBwEvent,
+	pub updated:    Instant,
+	/// Bytes read per second, 0 until the circuit's second event.
+	pub read_rate:  f64,
+	/// Bytes written per second, 0 until the circuit's second event.
+	pub write_rate: f64,
+}
+
+/// Keeps the latest bandwidth of every circuit from CIRC_BW events.
+///
+/// Circuits stay tracked until they are removed, which callers watching CIRC
+/// events can do once a circuit closes.
+#[derive(Debug, Default)]
+pub struct CircuitBwTracker {
+	circuits: HashMap<u32, CircuitBwSnapshot>,
+}
+
+impl CircuitBwTracker {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn update(&mut self, event: CircuitBwEvent) {
+		self.update_at(event, Instant::now());
+	}
+
+	fn update_at(&mut self, event: CircuitBwEvent, now: Instant) {
+		let (read_rate, write_rate) = match self.circuits.get(&event.circuit_id) {
+			Some(previous) => {
+				let elapsed = now.duration_since(previous.updated).as_secs_f64();
+				let rate = |now: u64, before: u64| match elapsed {
+					elapsed if elapsed > 0.0 => now.saturating_sub(before) as f64 / elapsed,
+					_ => 0.0,
+				};
+				(
+					rate(event.read, previous.event.read),
+					rate(event.written, previous.event.written),
+				)
+			}
+			None => (0.0, 0.0),
+		};
+		self.circuits.insert(
+			event.circuit_id,
+			CircuitBwSnapshot {
+				event,
+				updated: now,
+				read_rate,
+				write_rate,
+			},
+		);
+	}
+
+	pub fn get(&self, circuit_id: u32) -> Option<&CircuitBwSnapshot> {
+		self.circuits.get(&circuit_id)
+	}
+
+	pub fn remove(&mut self, circuit_id: u32) -> Option<CircuitBwSnapshot> {
+		self.circuits.remove(&circuit_id)
+	}
+
+	pub fn circuits(&self) -> &HashMap<u32, CircuitBwSnapshot> {
+		&self.circuits
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	fn event(circuit_id: u32, read: u64, written: u64) -> CircuitBwEvent {
+		CircuitBwEvent {
+			circuit_id,
+			read,
+			written,
+			time: None,
+			delivered_read: None,
+			delivered_written: None,
+			overhead_read: None,
+			overhead_written: None,
+		}
+	}
+
+	#[test]
+	fn rates_between_events() {
+		let mut tracker = CircuitBwTracker::new();
+		let start = Instant::now();
+		tracker.update_at(event(5, 1000, 500), start);
+		tracker.update_at(event(7, 10, 10), start);
+		assert_eq!(tracker.get(5).unwrap().read_rate, 0.0);
+
+		tracker.update_at(event(5, 5000, 2500), start + Duration::from_secs(2));
+		let circuit = tracker.get(5).unwrap();
+		assert_eq!((circuit.read_rate, circuit.write_rate), (2000.0, 1000.0));
+		assert_eq!(circuit.event.read, 5000);
+
+		assert_eq!(tracker.circuits().len(), 2);
+		assert!(tracker.remove(7).is_some());
+		assert!(tracker.get(7).is_none());
+	}
+}
@@ -0,0 +1,443 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use super::error::Error;
+use super::events::EventKind;
+use super::identity::RelayFingerprint;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// The identifier Tor assigns to a circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CircuitId(pub u32);
+
+impl fmt::Display for CircuitId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CircuitStatus {
+	Launched,
+	Built,
+	GuardWait,
+	Extended,
+	Failed,
+	Closed,
+	Unknown(String),
+}
+
+impl FromStr for CircuitStatus {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"LAUNCHED" => CircuitStatus::Launched,
+			"BUILT" => CircuitStatus::Built,
+			"GUARD_WAIT" => CircuitStatus::GuardWait,
+			"EXTENDED" => CircuitStatus::Extended,
+			"FAILED" => CircuitStatus::Failed,
+			"CLOSED" => CircuitStatus::Closed,
+			other => CircuitStatus::Unknown(other.to_string()),
+		})
+	}
+}
+
+impl fmt::Display for CircuitStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			CircuitStatus::Launched => "LAUNCHED",
+			CircuitStatus::Built => "BUILT",
+			CircuitStatus::GuardWait => "GUARD_WAIT",
+			CircuitStatus::Extended => "EXTENDED",
+			CircuitStatus::Failed => "FAILED",
+			CircuitStatus::Closed => "CLOSED",
+			CircuitStatus::Unknown(status) => status,
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CircuitPurpose {
+	General,
+	HsClientIntro,
+	HsClientRend,
+	HsServiceIntro,
+	HsServiceRend,
+	Testing,
+	Controller,
+	MeasureTimeout,
+	HsVanguards,
+	PathBiasTesting,
+	CircuitPadding,
+	ConfluxUnlinked,
+	ConfluxLinked,
+	Unknown(String),
+}
+
+impl FromStr for CircuitPurpose {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"GENERAL" => CircuitPurpose::General,
+			"HS_CLIENT_INTRO" => CircuitPurpose::HsClientIntro,
+			"HS_CLIENT_REND" => CircuitPurpose::HsClientRend,
+			"HS_SERVICE_INTRO" => CircuitPurpose::HsServiceIntro,
+			"HS_SERVICE_REND" => CircuitPurpose::HsServiceRend,
+			"TESTING" => CircuitPurpose::Testing,
+			"CONTROLLER" => CircuitPurpose::Controller,
+			"MEASURE_TIMEOUT" => CircuitPurpose::MeasureTimeout,
+			"HS_VANGUARDS" => CircuitPurpose::HsVanguards,
+			"PATH_BIAS_TESTING" => CircuitPurpose::PathBiasTesting,
+			"CIRCUIT_PADDING" => CircuitPurpose::CircuitPadding,
+			"CONFLUX_UNLINKED" => CircuitPurpose::ConfluxUnlinked,
+			"CONFLUX_LINKED" => CircuitPurpose::ConfluxLinked,
+			other => CircuitPurpose::Unknown(other.to_string()),
+		})
+	}
+}
+
+impl fmt::Display for CircuitPurpose {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			CircuitPurpose::General => "GENERAL",
+			CircuitPurpose::HsClientIntro => "HS_CLIENT_INTRO",
+			CircuitPurpose::HsClientRend => "HS_CLIENT_REND",
+			CircuitPurpose::HsServiceIntro => "HS_SERVICE_INTRO",
+			CircuitPurpose::HsServiceRend => "HS_SERVICE_REND",
+			CircuitPurpose::Testing => "TESTING",
+			CircuitPurpose::Controller => "CONTROLLER",
+			CircuitPurpose::MeasureTimeout => "MEASURE_TIMEOUT",
+			CircuitPurpose::HsVanguards => "HS_VANGUARDS",
+			CircuitPurpose::PathBiasTesting => "PATH_BIAS_TESTING",
+			CircuitPurpose::CircuitPadding => "CIRCUIT_PADDING",
+			CircuitPurpose::ConfluxUnlinked => "CONFLUX_UNLINKED",
+			CircuitPurpose::ConfluxLinked => "CONFLUX_LINKED",
+			CircuitPurpose::Unknown(purpose) => purpose,
+		})
+	}
+}
+
+/// A relay on a circuit's path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitHop {
+	pub fingerprint: String,
+	pub nickname:    Option<String>,
+}
+
+/// A circuit as listed by GETINFO circuit-status or reported in a CIRC event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Circuit {
+	pub id:           CircuitId,
+	pub status:       CircuitStatus,
+	pub path:         Vec<CircuitHop>,
+	pub build_flags:  Vec<String>,
+	pub purpose:      Option<CircuitPurpose>,
+	pub hs_state:     Option<String>,
+	pub rend_query:   Option<String>,
+	pub time_created: Option<String>,
+	pub reason:       Option<String>,
+}
+
+/// Selects circuits for `wait_for_circuit`. Criteria that are left unset match
+/// every circuit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CircuitFilter {
+	pub purpose: Option<CircuitPurpose>,
+	pub status:  Option<CircuitStatus>,
+	/// The relay the circuit has to end at.
+	pub exit:    Option<RelayFingerprint>,
+}
+
+impl CircuitFilter {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn purpose(mut self, purpose: CircuitPurpose) -> Self {
+		self.purpose = Some(purpose);
+		self
+	}
+
+	pub fn status(mut self, status: CircuitStatus) -> Self {
+		self.status = Some(status);
+		self
+	}
+
+	pub fn exit(mut self, exit: impl Into<RelayFingerprint>) -> Self {
+		self.exit = Some(exit.into());
+		self
+	}
+
+	pub fn matches(&self, circuit: &Circuit) -> bool {
+		if self.purpose.is_some() && circuit.purpose != self.purpose {
+			return false;
+		}
+		if self
+			.status
+			.as_ref()
+			.is_some_and(|status| *status != circuit.status)
+		{
+			return false;
+		}
+		match &self.exit {
+			Some(exit) => circuit
+				.path
+				.last()
+				.and_then(|hop| RelayFingerprint::from_hex(&hop.fingerprint).ok())
+				.is_some_and(|fingerprint| fingerprint == *exit),
+			None => true,
+		}
+	}
+}
+
+/// The outcome of closing a set of circuits.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CloseReport {
+	/// Circuits Tor agreed to close.
+	pub closed:       Vec<CircuitId>,
+	/// Circuits left alone because they didn't match.
+	pub skipped:      Vec<CircuitId>,
+	/// Circuits Tor refused to close, usually because they were already gone.
+	pub failed:       Vec<CircuitId>,
+	/// New general purpose circuits that were built while waiting for
+	/// replacements in `rotate_circuits`.
+	pub replacements: Vec<CircuitId>,
+}
+
+/// Statistics about the circuits Tor has completed since it started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitStats {
+	/// In seconds.
+	pub mean_circuit_lifetime:  f64,
+	pub total_circuits_created: u64,
+}
+
+impl TorController {
+	/// Lists all circuits Tor currently knows about.
+	pub fn circuits(&mut self) -> Result<Vec<Circuit>, Error> {
+		let circuits = self.get_info_value("circuit-status")?;
+		parse_reply(&circuits, parsers::circuit_status)
+	}
+
+	/// Sets how long the circuit list fetched by `get_circuits_by_purpose`,
+	/// `get_circuits_by_status` and `count_built_circuits` is reused, 100ms by
+	/// default. Zero disables the cache.
+	pub fn set_circuit_cache_ttl(&mut self, ttl: Duration) {
+		self.circuit_cache_ttl = ttl;
+		self.circuit_cache = None;
+	}
+
+	// Tor can't filter circuit-status itself, so consecutive filtered lookups
+	// share one fetch.
+	fn cached_circuits(&mut self) -> Result<&[Circuit], Error> {
+		let fresh = self
+			.circuit_cache
+			.as_ref()
+			.is_some_and(|(fetched, _)| fetched.elapsed() < self.circuit_cache_ttl);
+		if !fresh {
+			let circuits = self.circuits()?;
+			self.circuit_cache = Some((Instant::now(), circuits));
+		}
+		Ok(&self.circuit_cache.as_ref().unwrap().1)
+	}
+
+	pub fn get_circuits_by_purpose(
+		&mut self,
+		purpose: CircuitPurpose,
+	) -> Result<Vec<Circuit>, Error> {
+		let filter = CircuitFilter::new().purpose(purpose);
+		Ok(
+			self
+				.cached_circuits()?
+				.iter()
+				.filter(|c| filter.matches(c))
+				.cloned()
+				.collect(),
+		)
+	}
+
+	pub fn get_circuits_by_status(&mut self, status: CircuitStatus) -> Result<Vec<Circuit>, Error> {
+		let filter = CircuitFilter::new().status(status);
+		Ok(
+			self
+				.cached_circuits()?
+				.iter()
+				.filter(|c| filter.matches(c))
+				.cloned()
+				.collect(),
+		)
+	}
+
+	/// How many circuits are built and ready for streams.
+	pub fn count_built_circuits(&mut self) -> Result<usize, Error> {
+		let built = self
+			.cached_circuits()?
+			.iter()
+			.filter(|circuit| circuit.status == CircuitStatus::Built)
+			.count();
+		Ok(built)
+	}
+
+	/// Reports how many circuits Tor has created and how long they lasted on
+	/// average.
+	pub fn get_circuit_lifetime_stats(&mut self) -> Result<CircuitStats, Error> {
+		let mut stats = self.get_info(vec![
+			"status/total-circuit-lifetime",
+			"status/total-circuits-created",
+		])?;
+		let lifetime = stats
+			.remove("status/total-circuit-lifetime")
+			.ok_or(Error::InternalError)?;
+		let created = stats
+			.remove("status/total-circuits-created")
+			.ok_or(Error::InternalError)?;
+
+		let total_circuits_created = u64::from_str(&created).map_err(|_| Error::InternalError)?;
+		// A freshly started Tor has no completed circuits to average over.
+		let mean_circuit_lifetime = if total_circuits_created == 0 {
+			0.0
+		} else {
+			f64::from_str(&lifetime).map_err(|_| Error::InternalError)?
+		};
+		Ok(CircuitStats {
+			mean_circuit_lifetime,
+			total_circuits_created,
+		})
+	}
+
+	/// Closes a circuit. With `if_unused`, Tor only closes it once no streams
+	/// are attached anymore.
+	pub fn close_circuit(&mut self, id: CircuitId, if_unused: bool) -> Result<(), Error> {
+		self.circuit_cache = None;
+		let mut close_circuit_command = format!("CLOSECIRCUIT {}", id);
+		if if_unused {
+			close_circuit_command.push_str(" IfUnused");
+		}
+		self.send(close_circuit_command, parsers::is_ok)
+	}
+
+	/// Closes every circuit accepted by `pred`, carrying on past circuits that
+	/// fail to close.
+	pub fn close_circuits_where<P>(&mut self, pred: P, if_unused: bool) -> Result<CloseReport, Error>
+	where
+		P: Fn(&Circuit) -> bool,
+	{
+		let mut report = CloseReport::default();
+		for circuit in self.circuits()? {
+			if !pred(&circuit) {
+				report.skipped.push(circuit.id);
+				continue;
+			}
+			match self.close_circuit(circuit.id, if_unused) {
+				Ok(()) => report.closed.push(circuit.id),
+				Err(Error::Io(err)) => return Err(Error::Io(err)),
+				Err(Error::ConnectionLost) => return Err(Error::ConnectionLost),
+				Err(_) => report.failed.push(circuit.id),
+			}
+		}
+		Ok(report)
+	}
+
+	/// Whether Tor has built at least one circuit, i.e. is ready to be used.
+	pub fn is_circuit_established(&mut self) -> Result<bool, Error> {
+		match self.get_info_value("status/circuit-established")?.as_str() {
+			"1" => Ok(true),
+			"0" => Ok(false),
+			_ => Err(Error::InternalError),
+		}
+	}
+
+	/// Polls `is_circuit_established` every `poll_interval` until it is true,
+	/// failing with `Error::Timeout` once `timeout` has passed.
+	pub fn wait_for_circuit_established(
+		&mut self,
+		timeout: Duration,
+		poll_interval: Duration,
+	) -> Result<(), Error> {
+		let started = Instant::now();
+		loop {
+			if self.is_circuit_established()? {
+				return Ok(());
+			}
+			let elapsed = started.elapsed();
+			if elapsed >= timeout {
+				return Err(Error::Timeout {
+					command: String::from("GETINFO"),
+					elapsed,
+				});
+			}
+			std::thread::sleep(poll_interval.min(timeout - elapsed));
+		}
+	}
+
+	/// Waits up to `timeout` for a circuit accepted by `filter`, failing with
+	/// `Error::Timeout` if none shows up. A circuit that already matches is
+	/// returned right away.
+	pub fn wait_for_circuit(
+		&mut self,
+		filter: CircuitFilter,
+		timeout: Duration,
+	) -> Result<Circuit, Error> {
+		let deadline = Instant::now() + timeout;
+		self.with_events(&[EventKind::Circ], |controller| {
+			// Subscribing first means no circuit is missed between the GETINFO
+			// and the first event.
+			if let Some(circuit) = controller
+				.circuits()?
+				.into_iter()
+				.find(|circuit| filter.matches(circuit))
+			{
+				return Ok(circuit);
+			}
+
+			let event =
+				controller.wait_for_event(deadline, |event| match parsers::circuit_event(&event.raw) {
+					Ok((_, circuit)) => filter.matches(&circuit),
+					Err(_) => false,
+				})?;
+			match event {
+				Some(event) => parse_reply(&event.raw, parsers::circuit_event),
+				None => Err(Error::Timeout {
+					command: EventKind::Circ.to_string(),
+					elapsed: timeout,
+				}),
+			}
+		})
+	}
+
+	/// Closes all built general purpose circuits so new streams get fresh ones.
+	///
+	/// If `wait` is given, waits up to that long for Tor to build a new general
+	/// purpose circuit and lists it in `CloseReport::replacements`. Timing out
+	/// is not an error; the list is just left empty.
+	pub fn rotate_circuits(&mut self, wait: Option<Duration>) -> Result<CloseReport, Error> {
+		let is_rotated = |circuit: &Circuit| {
+			circuit.status == CircuitStatus::Built && circuit.purpose == Some(CircuitPurpose::General)
+		};
+
+		let timeout = match wait {
+			Some(timeout) => timeout,
+			None => return self.close_circuits_where(is_rotated, false),
+		};
+
+		self.with_events(&[EventKind::Circ], |controller| {
+			let mut report = controller.close_circuits_where(is_rotated, false)?;
+			let deadline = Instant::now() + timeout;
+			let closed = report.closed.clone();
+			let replacement =
+				controller.wait_for_event(deadline, |event| match parsers::circuit_event(&event.raw) {
+					Ok((_, circuit)) => is_rotated(&circuit) && !closed.contains(&circuit.id),
+					Err(_) => false,
+				})?;
+			if let Some(event) = replacement {
+				let (_, circuit) = parsers::circuit_event(&event.raw).unwrap();
+				report.replacements.push(circuit.id);
+			}
+			Ok(report)
+		})
+	}
+}
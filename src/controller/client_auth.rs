@@ -0,0 +1,64 @@
+use super::error::Error;
+use super::version::Capability;
+use super::{parse_reply, ServiceID, TorController};
+use crate::parsers;
+
+/// Client authorization credentials Tor holds for an onion service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnionClientAuthEntry {
+	pub service_id:   ServiceID,
+	/// Currently always "x25519".
+	pub key_type:     String,
+	pub key_blob:     String,
+	/// Whether the credentials are stored on disk and survive a restart.
+	pub is_permanent: bool,
+	pub client_name:  Option<String>,
+}
+
+/// The state of client authorization for an onion service, as reported by
+/// GETINFO hs/client/auth-info/*.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HsClientAuthInfo {
+	/// Currently always "x25519".
+	pub key_type:     String,
+	pub is_permanent: bool,
+	/// Whether Tor holds the private key, as opposed to just knowing it is
+	/// needed.
+	pub has_key:      bool,
+}
+
+impl TorController {
+	/// Shows what client authorization Tor has for `service_id`, e.g. to check
+	/// that `onion_client_auth_add` worked before connecting. Returns `None` if
+	/// none is configured.
+	pub fn get_hs_client_auth_info(
+		&mut self,
+		service_id: &ServiceID,
+	) -> Result<Option<HsClientAuthInfo>, Error> {
+		let key = format!("hs/client/auth-info/{}", service_id.0);
+		let mut values = match self.send_get_info(&[&key])? {
+			Ok(values) => values,
+			Err((551, _)) | Err((552, _)) => return Ok(None),
+			Err(_) => return Err(Error::InternalError),
+		};
+		match values.remove(&key).ok_or(Error::InternalError)?.as_str() {
+			"" => Ok(None),
+			info => parse_reply(info, parsers::hs_client_auth_info).map(Some),
+		}
+	}
+
+	/// Lists the client authorization credentials Tor has, optionally only those
+	/// for `service_id`.
+	pub fn onion_client_auth_view(
+		&mut self,
+		service_id: Option<&ServiceID>,
+	) -> Result<Vec<OnionClientAuthEntry>, Error> {
+		self.capabilities.require(Capability::OnionClientAuth)?;
+		let mut view_command = String::from("ONION_CLIENT_AUTH_VIEW");
+		if let Some(service_id) = service_id {
+			view_command.push(' ');
+			view_command.push_str(&service_id.0);
+		}
+		self.send(view_command, parsers::onion_client_auth_view)
+	}
+}
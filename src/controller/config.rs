@@ -0,0 +1,199 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::incremental::{ReplyAccumulator, ReplyLine};
+use super::TorController;
+use crate::parsers;
+
+/// The value type of a configuration option, as listed by GETINFO config/names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigType {
+	Atom,
+	Flag,
+	Integer,
+	TimeInterval,
+	TimeMsecInterval,
+	DataSize,
+	Float,
+	Bool,
+	BoolAuto,
+	Linelist,
+	String,
+	LinelistS,
+	Obsolete,
+	Filename,
+	RouterList,
+	AddrPortList,
+	Hidden,
+	/// A type this version of the crate doesn't know about.
+	Unknown(std::string::String),
+}
+
+impl FromStr for ConfigType {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"Atom" => ConfigType::Atom,
+			"Flag" => ConfigType::Flag,
+			"Integer" => ConfigType::Integer,
+			"TimeInterval" => ConfigType::TimeInterval,
+			"TimeMsecInterval" => ConfigType::TimeMsecInterval,
+			"DataSize" => ConfigType::DataSize,
+			"Float" => ConfigType::Float,
+			"Bool" => ConfigType::Bool,
+			"BoolAuto" => ConfigType::BoolAuto,
+			"Linelist" => ConfigType::Linelist,
+			"String" => ConfigType::String,
+			"Linelist_S" => ConfigType::LinelistS,
+			"Obsolete" => ConfigType::Obsolete,
+			"Filename" => ConfigType::Filename,
+			"RouterList" => ConfigType::RouterList,
+			"AddrPortList" => ConfigType::AddrPortList,
+			"Hidden" => ConfigType::Hidden,
+			other => ConfigType::Unknown(other.to_string()),
+		})
+	}
+}
+
+impl fmt::Display for ConfigType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			ConfigType::Atom => "Atom",
+			ConfigType::Flag => "Flag",
+			ConfigType::Integer => "Integer",
+			ConfigType::TimeInterval => "TimeInterval",
+			ConfigType::TimeMsecInterval => "TimeMsecInterval",
+			ConfigType::DataSize => "DataSize",
+			ConfigType::Float => "Float",
+			ConfigType::Bool => "Bool",
+			ConfigType::BoolAuto => "BoolAuto",
+			ConfigType::Linelist => "Linelist",
+			ConfigType::String => "String",
+			ConfigType::LinelistS => "Linelist_S",
+			ConfigType::Obsolete => "Obsolete",
+			ConfigType::Filename => "Filename",
+			ConfigType::RouterList => "RouterList",
+			ConfigType::AddrPortList => "AddrPortList",
+			ConfigType::Hidden => "Hidden",
+			ConfigType::Unknown(name) => name,
+		};
+		f.write_str(name)
+	}
+}
+
+/// A configuration option known to Tor, from GETINFO config/names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigSchema {
+	pub name:        std::string::String,
+	pub value_type:  ConfigType,
+	pub description: Option<std::string::String>,
+}
+
+// The "Key Value" lines of GETINFO config-text.
+#[derive(Default)]
+struct ConfigText(Vec<(std::string::String, std::string::String)>);
+
+impl ReplyAccumulator for ConfigText {
+	type Output = Vec<(std::string::String, std::string::String)>;
+
+	fn push_line(&mut self, line: ReplyLine<'_>) -> Result<(), Error> {
+		let (key, value) = line.keyword_args();
+		self.0.push((key.to_string(), value.to_string()));
+		Ok(())
+	}
+
+	fn finish(self) -> Result<Self::Output, Error> {
+		Ok(self.0)
+	}
+}
+
+impl TorController {
+	/// The options that differ from their defaults, as (key, value) pairs in
+	/// torrc order. Options given more than once are listed once per value.
+	pub fn get_config_text(
+		&mut self,
+	) -> Result<Vec<(std::string::String, std::string::String)>, Error> {
+		self.get_info_incremental("config-text", ConfigText::default())
+	}
+
+	/// Sets each option to a single value with one SETCONF, so that options
+	/// that depend on each other change together. Setting a list option this
+	/// way replaces the list with just that value; see `set_config_list`.
+	pub fn set_config(&mut self, settings: Vec<(&str, &str)>) -> Result<(), Error> {
+		let settings: Vec<std::string::String> = settings
+			.iter()
+			.map(|(key, value)| format!("{}={}", key, config_value(value)))
+			.collect();
+		self.send(format!("SETCONF {}", settings.join(" ")), parsers::is_ok)
+	}
+
+	/// Replaces the whole list of a list option like ExitPolicy, by repeating
+	/// the key for every value in one SETCONF. An empty list resets the option
+	/// to its default.
+	pub fn set_config_list(&mut self, key: &str, values: Vec<&str>) -> Result<(), Error> {
+		let mut command = format!("SETCONF {}", key);
+		if !values.is_empty() {
+			let settings: Vec<std::string::String> = values
+				.iter()
+				.map(|value| format!("{}={}", key, config_value(value)))
+				.collect();
+			command = format!("SETCONF {}", settings.join(" "));
+		}
+		self.send(command, parsers::is_ok)
+	}
+
+	/// Resets a list option to its default list with RESETCONF.
+	pub fn reset_config_list(&mut self, key: &str) -> Result<(), Error> {
+		self.send(format!("RESETCONF {}", key), parsers::is_ok)
+	}
+
+	/// Writes the current configuration to the torrc with SAVECONF.
+	pub fn save_config(&mut self) -> Result<(), Error> {
+		self.send(std::string::String::from("SAVECONF"), parsers::is_ok)
+	}
+
+	/// Whether `save_config` can work, which it can't if Tor was started
+	/// without a torrc. Tors that don't know GETINFO config/can-saveconf count
+	/// as unable to save.
+	pub fn can_save_config(&mut self) -> Result<bool, Error> {
+		const KEY: &str = "config/can-saveconf";
+		let mut values = match self.send_get_info(&[KEY])? {
+			Ok(values) => values,
+			Err((551, _)) | Err((552, _)) => return Ok(false),
+			Err(_) => return Err(Error::InternalError),
+		};
+		match values.remove(KEY).as_deref() {
+			Some("1") => Ok(true),
+			Some("0") => Ok(false),
+			_ => Err(Error::InternalError),
+		}
+	}
+
+	/// Saves the configuration if `can_save_config` allows it. Returns whether
+	/// it was saved.
+	pub fn save_config_if_possible(&mut self) -> Result<bool, Error> {
+		if !self.can_save_config()? {
+			return Ok(false);
+		}
+		self.save_config()?;
+		Ok(true)
+	}
+}
+
+// Values with spaces, like ExitPolicy rules, have to be quoted.
+fn config_value(value: &str) -> std::borrow::Cow<'_, str> {
+	let plain = !value.is_empty()
+		&& !value
+			.chars()
+			.any(|c| c.is_whitespace() || c == '"' || c == '\\');
+	if plain {
+		return value.into();
+	}
+	let escaped = value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n");
+	format!("\"{}\"", escaped).into()
+}
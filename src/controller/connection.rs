@@ -0,0 +1,411 @@
+use std::collections::VecDeque;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::time::{Duration, Instant};
+
+use super::error::Error;
+use super::events::{EventKind, OverflowPolicy, RawEvent};
+use super::stream::Stream;
+use crate::parsers;
+
+pub(super) const DEFAULT_EVENT_CAPACITY: usize = 4096;
+
+/// The control connection shared between a `TorController` and its helpers.
+///
+/// Every command is written and its reply read while holding the lock around
+/// the connection, so commands from different threads never interleave.
+pub(super) struct Connection {
+	// The reader is kept for the lifetime of the connection so that bytes
+	// buffered past the end of one reply are still there for the next.
+	reader:       BufReader<Stream>,
+	broken:       bool,
+	events:       VecDeque<RawEvent>,
+	capacity:     usize,
+	policy:       OverflowPolicy,
+	dropped:      u64,
+	// Set while reading a reply with `read_reply_within`.
+	deadline:     Option<Instant>,
+	// Reused for every command, as is the reader's buffer.
+	write_buffer: Vec<u8>,
+}
+
+impl Connection {
+	pub(super) fn new(stream: Stream) -> Self {
+		Connection {
+			reader:       BufReader::new(stream),
+			broken:       false,
+			events:       VecDeque::new(),
+			capacity:     DEFAULT_EVENT_CAPACITY,
+			policy:       OverflowPolicy::DropOldest,
+			dropped:      0,
+			deadline:     None,
+			write_buffer: Vec::new(),
+		}
+	}
+
+	/// Marks the connection as unusable; every further command fails with
+	/// `Error::ConnectionLost`.
+	pub(super) fn mark_broken(&mut self) {
+		self.broken = true;
+	}
+
+	pub(super) fn is_broken(&self) -> bool {
+		self.broken
+	}
+
+	/// Writes raw bytes to the control port.
+	pub(super) fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+		if self.broken {
+			return Err(Error::ConnectionLost);
+		}
+
+		let result = self.reader.get_mut().write_all(bytes);
+		if result.is_err() {
+			self.broken = true;
+		}
+		result.map_err(Error::from)
+	}
+
+	/// Writes a command followed by the line terminator.
+	pub(super) fn write_command(&mut self, msg: &str) -> Result<(), Error> {
+		debug!("-> {}", msg);
+		let mut bytes = std::mem::take(&mut self.write_buffer);
+		bytes.clear();
+		bytes.extend_from_slice(msg.as_bytes());
+		bytes.extend_from_slice(b"\r\n");
+		let result = self.write(&bytes);
+		self.write_buffer = bytes;
+		result
+	}
+
+	/// Reads the reply to the command in flight. Asynchronous events arriving
+	/// ahead of it are queued for `read_event`.
+	pub(super) fn read_reply(&mut self) -> Result<String, Error> {
+		let mut reply = String::new();
+		self.read_reply_into(&mut reply)?;
+		Ok(reply)
+	}
+
+	/// Like `read_reply`, but reads into `reply`, replacing its contents, so
+	/// that its allocation can be reused from one command to the next.
+	pub(super) fn read_reply_into(&mut self, reply: &mut String) -> Result<(), Error> {
+		loop {
+			reply.clear();
+			self.read_message_into(reply)?;
+			if !parsers::is_event(reply) {
+				return Ok(());
+			}
+			let event = RawEvent::parse(std::mem::take(reply))?;
+			self.queue_event(event);
+		}
+	}
+
+	/// Like `read_reply`, but gives up after `timeout` with `Error::Timeout`.
+	/// The connection is broken afterwards, since the rest of the abandoned
+	/// reply would be mistaken for the next one.
+	pub(super) fn read_reply_within(
+		&mut self,
+		command: &str,
+		timeout: Duration,
+		reply: &mut String,
+	) -> Result<(), Error> {
+		self.within(command, Some(timeout), |conn| conn.read_reply_into(reply))
+	}
+
+	/// Runs `read`, giving up on whatever it reads from the connection after
+	/// `timeout` with `Error::Timeout`, or never if there is no timeout. Like
+	/// with `read_reply_within`, the connection is broken afterwards.
+	pub(super) fn within<T>(
+		&mut self,
+		command: &str,
+		timeout: Option<Duration>,
+		read: impl FnOnce(&mut Self) -> Result<T, Error>,
+	) -> Result<T, Error> {
+		let timeout = match timeout {
+			Some(timeout) => timeout,
+			None => return read(self),
+		};
+		let started = Instant::now();
+		let previous = self.reader.get_ref().read_timeout()?;
+		self.deadline = Some(started + timeout);
+		let result = read(self);
+		self.deadline = None;
+		self.reader.get_ref().set_read_timeout(previous)?;
+
+		match result {
+			Err(Error::Io(err)) if is_timeout(&err) => Err(Error::Timeout {
+				command: command.to_string(),
+				elapsed: started.elapsed(),
+			}),
+			result => result,
+		}
+	}
+
+	/// Reads the next asynchronous event, blocking until one arrives.
+	pub(super) fn read_event(&mut self) -> Result<RawEvent, Error> {
+		if let Some(event) = self.events.pop_front() {
+			return Ok(event);
+		}
+		let message = self.read_message()?;
+		RawEvent::parse(message)
+	}
+
+	/// Returns the next asynchronous event if any part of it has already been
+	/// received, without waiting for new data to arrive.
+	pub(super) fn try_read_event(&mut self) -> Result<Option<RawEvent>, Error> {
+		if let Some(event) = self.events.pop_front() {
+			return Ok(Some(event));
+		}
+		if self.reader.buffer().is_empty() && !self.has_pending_data()? {
+			return Ok(None);
+		}
+		self.read_event().map(Some)
+	}
+
+	/// Waits at most `timeout` for the next asynchronous event.
+	pub(super) fn read_event_timeout(
+		&mut self,
+		timeout: Duration,
+	) -> Result<Option<RawEvent>, Error> {
+		if let Some(event) = self.events.pop_front() {
+			return Ok(Some(event));
+		}
+		if !self.wait_for_data(timeout)? {
+			return Ok(None);
+		}
+		self.read_event().map(Some)
+	}
+
+	/// Puts events back at the front of the queue, keeping their order.
+	pub(super) fn requeue_events(&mut self, events: Vec<RawEvent>) {
+		for event in events.into_iter().rev() {
+			self.events.push_front(event);
+		}
+		while self.events.len() > self.capacity {
+			match self.policy {
+				OverflowPolicy::DropOldest => self.events.pop_front(),
+				OverflowPolicy::DropNewest | OverflowPolicy::Block => self.events.pop_back(),
+			};
+			self.dropped += 1;
+		}
+	}
+
+	/// Removes and returns all events of the given kind that have been
+	/// received so far, without waiting for new data. Other events stay queued.
+	pub(super) fn take_events(&mut self, kind: &EventKind) -> Result<Vec<RawEvent>, Error> {
+		self.queue_pending_events()?;
+		let (taken, kept): (Vec<_>, Vec<_>) =
+			self.events.drain(..).partition(|event| event.kind == *kind);
+		self.events = kept.into();
+		Ok(taken)
+	}
+
+	/// Queues every event that has been received so far, without waiting for
+	/// new data, and returns how many are queued. With `OverflowPolicy::Block`
+	/// this stops once the queue is full.
+	pub(super) fn queue_pending_events(&mut self) -> Result<usize, Error> {
+		while !self.is_blocked() && (!self.reader.buffer().is_empty() || self.has_pending_data()?) {
+			let message = self.read_message()?;
+			self.queue_event(RawEvent::parse(message)?);
+		}
+		Ok(self.events.len())
+	}
+
+	pub(super) fn set_event_limit(&mut self, capacity: usize, policy: OverflowPolicy) {
+		self.capacity = capacity;
+		self.policy = policy;
+		// Apply the new capacity to the events already queued.
+		self.requeue_events(Vec::new());
+	}
+
+	pub(super) fn dropped_events(&self) -> u64 {
+		self.dropped
+	}
+
+	fn is_blocked(&self) -> bool {
+		self.policy == OverflowPolicy::Block && self.events.len() >= self.capacity
+	}
+
+	// Queues an event, dropping one if the queue is full. Events only reach a
+	// full queue under `OverflowPolicy::Block` when they arrive ahead of a
+	// reply, and those are dropped like with `OverflowPolicy::DropNewest`.
+	fn queue_event(&mut self, event: RawEvent) {
+		if self.events.len() < self.capacity {
+			self.events.push_back(event);
+			return;
+		}
+
+		if self.dropped == 0 {
+			warn!("Event queue is full, dropping events");
+		}
+		self.dropped += 1;
+		if self.policy == OverflowPolicy::DropOldest {
+			self.events.pop_front();
+			self.events.push_back(event);
+		}
+	}
+
+	/// Drops all queued events of the given kind.
+	pub(super) fn discard_events(&mut self, kind: &EventKind) {
+		self.events.retain(|event| event.kind != *kind);
+	}
+
+	/// Waits at most `timeout` for data to arrive, without consuming any.
+	fn wait_for_data(&mut self, timeout: Duration) -> Result<bool, Error> {
+		if !self.reader.buffer().is_empty() {
+			return Ok(true);
+		}
+		if timeout.is_zero() {
+			return self.has_pending_data();
+		}
+		if self.broken {
+			return Err(Error::ConnectionLost);
+		}
+
+		let previous = self.reader.get_ref().read_timeout()?;
+		self.reader.get_ref().set_read_timeout(Some(timeout))?;
+		let filled = self.reader.fill_buf().map(|buffer| buffer.len());
+		self.reader.get_ref().set_read_timeout(previous)?;
+
+		match filled {
+			Ok(0) => {
+				self.broken = true;
+				Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()))
+			}
+			Ok(_) => Ok(true),
+			Err(err) if is_timeout(&err) => Ok(false),
+			Err(err) => {
+				self.broken = true;
+				Err(err.into())
+			}
+		}
+	}
+
+	/// Checks for unread data in the socket without consuming it.
+	fn has_pending_data(&mut self) -> Result<bool, Error> {
+		if self.broken {
+			return Err(Error::ConnectionLost);
+		}
+
+		let stream = self.reader.get_ref();
+		stream.set_nonblocking(true)?;
+		let peeked = stream.peek(&mut [0; 1]);
+		stream.set_nonblocking(false)?;
+
+		match peeked {
+			Ok(0) => {
+				self.broken = true;
+				Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()))
+			}
+			Ok(_) => Ok(true),
+			Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(false),
+			Err(err) => {
+				self.broken = true;
+				Err(err.into())
+			}
+		}
+	}
+
+	/// Reads one complete reply or event, including any data blocks it contains.
+	fn read_message(&mut self) -> Result<String, Error> {
+		let mut message = String::new();
+		self.read_message_into(&mut message)?;
+		Ok(message)
+	}
+
+	/// Like `read_message`, but appends the message to `buffer`.
+	fn read_message_into(&mut self, buffer: &mut String) -> Result<(), Error> {
+		let start = buffer.len();
+		self.read_line_into(buffer)?;
+		self.finish_message(buffer, start)
+	}
+
+	/// Reads the rest of the message starting with `first`.
+	fn read_message_from(&mut self, first: String) -> Result<String, Error> {
+		let mut buffer = first;
+		self.finish_message(&mut buffer, 0)?;
+		Ok(buffer)
+	}
+
+	/// Reads the rest of the message that starts at `start` in `buffer` and
+	/// whose first line has been read already. Lines are appended to `buffer`
+	/// as they are read rather than collected separately.
+	fn finish_message(&mut self, buffer: &mut String, start: usize) -> Result<(), Error> {
+		let mut line_start = start;
+		let mut in_data = false;
+		loop {
+			let line = &buffer[line_start..];
+			if in_data {
+				in_data = !parsers::is_data_end(line);
+			} else if parsers::is_data_line(line) {
+				in_data = true;
+			} else if parsers::is_final_line(line) {
+				break;
+			}
+			line_start = buffer.len();
+			self.read_line_into(buffer)?;
+		}
+
+		debug!("<- {}", &buffer[start..]);
+		Ok(())
+	}
+
+	/// Reads the first line of the reply to the command in flight, queueing any
+	/// events that arrive ahead of it. The rest of the reply is left to be read
+	/// line by line with `read_line`.
+	pub(super) fn begin_reply(&mut self) -> Result<String, Error> {
+		loop {
+			let first = self.read_line()?;
+			if !parsers::is_event(&first) {
+				return Ok(first);
+			}
+			let message = self.read_message_from(first)?;
+			let event = RawEvent::parse(message)?;
+			self.queue_event(event);
+		}
+	}
+
+	/// Reads a single line, marking the connection as broken if that fails since
+	/// the remainder of the reply can't be told apart from the next one.
+	pub(super) fn read_line(&mut self) -> Result<String, Error> {
+		let mut line = String::new();
+		self.read_line_into(&mut line)?;
+		Ok(line)
+	}
+
+	/// Like `read_line`, but appends the line to `buffer`.
+	pub(super) fn read_line_into(&mut self, buffer: &mut String) -> Result<(), Error> {
+		if self.broken {
+			return Err(Error::ConnectionLost);
+		}
+
+		if let Some(deadline) = self.deadline {
+			let remaining = deadline.saturating_duration_since(Instant::now());
+			if remaining.is_zero() {
+				self.broken = true;
+				return Err(Error::Io(std::io::ErrorKind::TimedOut.into()));
+			}
+			self.reader.get_ref().set_read_timeout(Some(remaining))?;
+		}
+
+		match self.reader.read_line(buffer) {
+			Ok(0) => {
+				self.broken = true;
+				Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()))
+			}
+			Ok(_) => Ok(()),
+			Err(err) => {
+				self.broken = true;
+				Err(err.into())
+			}
+		}
+	}
+}
+
+// Socket read timeouts surface as either kind, depending on the platform.
+fn is_timeout(err: &std::io::Error) -> bool {
+	matches!(
+		err.kind(),
+		std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+	)
+}
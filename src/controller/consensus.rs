@@ -0,0 +1,645 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+
+use super::error::Error;
+use super::identity::RelayFingerprint;
+use super::incremental::{InfoReader, ReplyAccumulator, ReplyLine};
+use super::policy::ExitPolicySummary;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// A relay's entry in a network status document, such as the consensus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterStatus {
+	pub nickname:     String,
+	/// The relay's identity as unpadded base64, see `fingerprint`.
+	pub identity:     String,
+	/// The digest of the relay's current descriptor as unpadded base64.
+	pub digest:       String,
+	pub published:    SystemTime,
+	pub address:      IpAddr,
+	pub or_port:      u16,
+	/// 0 if the relay has no directory port.
+	pub dir_port:     u16,
+	/// Additional OR addresses from "a" lines, e.g. "[2001:db8::1]:9001".
+	pub or_addresses: Vec<String>,
+	pub flags:        Vec<String>,
+	/// The version line, e.g. "Tor 0.4.8.9".
+	pub version:      Option<String>,
+	/// The supported subprotocols, e.g. "Cons=1-2 Desc=1-2 Link=1-5".
+	pub protocols:    Option<String>,
+	/// The consensus weight.
+	pub bandwidth:    Option<u64>,
+	pub exit_policy:  Option<ExitPolicySummary>,
+}
+
+impl RouterStatus {
+	pub fn has_flag(&self, flag: &str) -> bool {
+		self.flags.iter().any(|f| f == flag)
+	}
+
+	/// The relay fingerprint encoded by `identity`.
+	pub fn fingerprint(&self) -> Result<RelayFingerprint, Error> {
+		let identity = BASE64
+			.decode(&self.identity)
+			.map_err(|_| Error::InternalError)?;
+		let hex: String = identity
+			.iter()
+			.map(|byte| format!("{:02X}", byte))
+			.collect();
+		RelayFingerprint::from_hex(&hex)
+	}
+}
+
+impl FromStr for RouterStatus {
+	type Err = Error;
+
+	/// Parses a single router status entry, as returned by GETINFO ns/id/*.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut lines = s.lines();
+		let first = lines.next().ok_or(Error::InternalError)?;
+		let mut status = parse_reply(first, parsers::router_status_line)?;
+		for line in lines {
+			let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+			router_status_line(&mut status, keyword, args)?;
+		}
+		Ok(status)
+	}
+}
+
+// Tor writes base64 without padding in some documents and with it in others.
+pub(super) const BASE64: GeneralPurpose = GeneralPurpose::new(
+	&alphabet::STANDARD,
+	GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// The network status consensus, as served by GETINFO
+/// dir/status-vote/current/consensus.
+///
+/// Signatures aren't checked; Tor only accepts a consensus after verifying it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Consensus {
+	pub valid_after:       SystemTime,
+	pub fresh_until:       SystemTime,
+	pub valid_until:       SystemTime,
+	pub known_flags:       Vec<String>,
+	pub bandwidth_weights: HashMap<String, i64>,
+	/// The authorities whose votes the consensus was made from.
+	pub authorities:       Vec<DirectoryAuthority>,
+	pub routers:           Vec<RouterStatus>,
+}
+
+/// A directory authority, from a dir-source line of the consensus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryAuthority {
+	pub nickname:    String,
+	/// The fingerprint of the authority's relay, found by its address in the
+	/// consensus. Authorities whose relay isn't listed get their v3 identity
+	/// instead.
+	pub fingerprint: RelayFingerprint,
+	/// The IP address and ORPort.
+	pub address:     SocketAddr,
+	pub dir_port:    u16,
+	/// The identity the authority signs votes and the consensus with.
+	pub v3ident:     Option<RelayFingerprint>,
+}
+
+impl FromStr for DirectoryAuthority {
+	type Err = Error;
+
+	/// Parses "nickname identity hostname IP dirport orport", the arguments of
+	/// a dir-source line.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let parts: Vec<&str> = s.split_whitespace().collect();
+		let (nickname, identity, ip, dir_port, or_port) = match parts[..] {
+			[nickname, identity, _, ip, dir_port, or_port] => (nickname, identity, ip, dir_port, or_port),
+			_ => return Err(Error::InternalError),
+		};
+		let identity = RelayFingerprint::from_hex(identity)?;
+		let ip = IpAddr::from_str(ip).map_err(|_| Error::InternalError)?;
+		let port = |port: &str| u16::from_str(port).map_err(|_| Error::InternalError);
+		Ok(DirectoryAuthority {
+			nickname:    nickname.to_string(),
+			fingerprint: identity.clone(),
+			address:     SocketAddr::new(ip, port(or_port)?),
+			dir_port:    port(dir_port)?,
+			v3ident:     Some(identity),
+		})
+	}
+}
+
+// Well-known consensus parameters, see param-spec.txt.
+/// The circuits to build before computing a circuit build timeout.
+pub const CONSENSUS_PARAM_CBT_MINCIRCUITS: &str = "cbtmincircs";
+/// The modes of the circuit build time histogram to use.
+pub const CONSENSUS_PARAM_CBT_NUM_MODES: &str = "cbtnummodes";
+/// Whether adaptive circuit build timeouts are disabled.
+pub const CONSENSUS_PARAM_CBT_DISABLED: &str = "cbtdisabled";
+/// The scale the bandwidth weights are given in.
+pub const CONSENSUS_PARAM_BW_WEIGHT_SCALE: &str = "bwweightscale";
+/// The starting circuit-level flow control window.
+pub const CONSENSUS_PARAM_CIRCWINDOW: &str = "circwindow";
+/// Whether clients send data before a stream is confirmed open.
+pub const CONSENSUS_PARAM_USE_OPTIMISTIC_DATA: &str = "UseOptimisticData";
+
+// Parses whitespace separated "key=value" pairs with integer values, as found
+// in the params and bandwidth-weights lines.
+fn integer_params(args: &str, params: &mut HashMap<String, i64>) -> Result<(), Error> {
+	for param in args.split_whitespace() {
+		let (key, value) = param.split_once('=').ok_or(Error::InternalError)?;
+		let value = i64::from_str(value).map_err(|_| Error::InternalError)?;
+		params.insert(key.to_string(), value);
+	}
+	Ok(())
+}
+
+/// The directory authority this Tor runs as, from GETINFO ns/authority.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorityInfo {
+	pub nickname:    String,
+	/// 40 hex characters.
+	pub fingerprint: String,
+	/// The address and ORPort, e.g. "128.31.0.34:9101".
+	pub addr:        String,
+	/// E.g. "2024-05-01 02:52:29".
+	pub published:   String,
+	/// When the authority's current vote became valid, empty if not listed.
+	pub valid_after: String,
+}
+
+impl FromStr for AuthorityInfo {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut valid_after = String::new();
+		let mut info = None;
+		for line in s.lines() {
+			let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+			match keyword {
+				"valid-after" => valid_after = args.to_string(),
+				"r" => {
+					let status = RouterStatus::from_str(line)?;
+					let published = args
+						.split(' ')
+						.skip(3)
+						.take(2)
+						.collect::<Vec<_>>()
+						.join(" ");
+					info = Some(AuthorityInfo {
+						fingerprint: status.fingerprint()?.as_ref().to_string(),
+						addr: format!("{}:{}", status.address, status.or_port),
+						nickname: status.nickname,
+						published,
+						valid_after: String::new(),
+					});
+				}
+				_ => {}
+			}
+		}
+		let mut info = info.ok_or(Error::InternalError)?;
+		info.valid_after = valid_after;
+		Ok(info)
+	}
+}
+
+/// Collects router status entries one line at a time, as listed in the
+/// consensus and by GETINFO ns/all.
+#[derive(Default)]
+struct RouterStatusList {
+	routers: Vec<RouterStatus>,
+	current: Option<RouterStatus>,
+}
+
+impl RouterStatusList {
+	fn line(&mut self, line: &str, keyword: &str, args: &str) -> Result<(), Error> {
+		if keyword == "r" {
+			// The lines of an entry whose "r" line doesn't parse are skipped.
+			self.routers.extend(self.current.take());
+			self.current = Some(parse_reply(line, parsers::router_status_line)?);
+		} else if let Some(current) = &mut self.current {
+			router_status_line(current, keyword, args)?;
+		}
+		Ok(())
+	}
+
+	fn end(&mut self) {
+		self.routers.extend(self.current.take());
+	}
+}
+
+/// The entries of GETINFO ns/all, parsed one at a time as they are read, see
+/// `TorController::stream_network_status`.
+///
+/// The stream holds the connection until it is used up. Dropping it early
+/// reads the rest of the reply, so that the connection stays in sync.
+pub struct NetworkStatusStream<'a> {
+	reader:  Option<InfoReader<'a>>,
+	routers: RouterStatusList,
+	error:   Option<Error>,
+}
+
+impl Iterator for NetworkStatusStream<'_> {
+	type Item = Result<RouterStatus, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			// An entry that fails to parse is reported after the one before it.
+			if let Some(status) = self.routers.routers.pop() {
+				return Some(Ok(status));
+			}
+			if let Some(err) = self.error.take() {
+				return Some(Err(err));
+			}
+
+			match self.reader.as_mut()?.next_line() {
+				Ok(Some(line)) => {
+					let (keyword, args) = line.keyword_args();
+					if let Err(err) = self.routers.line(line.as_str(), keyword, args) {
+						self.error = Some(err);
+					}
+				}
+				Ok(None) => {
+					self.routers.end();
+					self.reader = None;
+				}
+				Err(err) => {
+					self.routers.current = None;
+					self.reader = None;
+					return Some(Err(err));
+				}
+			}
+		}
+	}
+}
+
+// Counts the entries of a router status list without keeping them.
+#[derive(Default)]
+struct RouterCount(u32);
+
+impl ReplyAccumulator for RouterCount {
+	type Output = u32;
+
+	fn push_line(&mut self, line: ReplyLine<'_>) -> Result<(), Error> {
+		if line.as_str().starts_with("r ") {
+			self.0 += 1;
+		}
+		Ok(())
+	}
+
+	fn finish(self) -> Result<Self::Output, Error> {
+		Ok(self.0)
+	}
+}
+
+impl ReplyAccumulator for RouterStatusList {
+	type Output = Vec<RouterStatus>;
+
+	fn push_line(&mut self, line: ReplyLine<'_>) -> Result<(), Error> {
+		let (keyword, args) = line.keyword_args();
+		self.line(line.as_str(), keyword, args)
+	}
+
+	fn finish(mut self) -> Result<Vec<RouterStatus>, Error> {
+		self.end();
+		Ok(self.routers)
+	}
+}
+
+/// Builds a `Consensus` one line at a time, so that the document never has to
+/// be held in memory as a whole.
+#[derive(Default)]
+struct ConsensusParser {
+	valid_after:       Option<SystemTime>,
+	fresh_until:       Option<SystemTime>,
+	valid_until:       Option<SystemTime>,
+	known_flags:       Vec<String>,
+	bandwidth_weights: HashMap<String, i64>,
+	authorities:       Vec<DirectoryAuthority>,
+	routers:           RouterStatusList,
+}
+
+impl ConsensusParser {
+	fn line(&mut self, line: &str) -> Result<(), Error> {
+		let line = line.trim_end_matches(['\r', '\n']);
+		let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+		match keyword {
+			"valid-after" => self.valid_after = Some(parse_reply(args, parsers::utc_time)?),
+			"fresh-until" => self.fresh_until = Some(parse_reply(args, parsers::utc_time)?),
+			"valid-until" => self.valid_until = Some(parse_reply(args, parsers::utc_time)?),
+			"known-flags" => self.known_flags = args.split_whitespace().map(String::from).collect(),
+			"bandwidth-weights" => integer_params(args, &mut self.bandwidth_weights)?,
+			"dir-source" => self.authorities.push(DirectoryAuthority::from_str(args)?),
+			"directory-footer" => self.routers.end(),
+			_ => self.routers.line(line, keyword, args)?,
+		}
+		Ok(())
+	}
+}
+
+impl ReplyAccumulator for ConsensusParser {
+	type Output = Consensus;
+
+	fn push_line(&mut self, line: ReplyLine<'_>) -> Result<(), Error> {
+		self.line(line.as_str())
+	}
+
+	fn finish(mut self) -> Result<Consensus, Error> {
+		self.routers.end();
+		for authority in &mut self.authorities {
+			let relay = self
+				.routers
+				.routers
+				.iter()
+				.find(|router| SocketAddr::new(router.address, router.or_port) == authority.address);
+			if let Some(fingerprint) = relay.and_then(|relay| relay.fingerprint().ok()) {
+				authority.fingerprint = fingerprint;
+			}
+		}
+		Ok(Consensus {
+			valid_after:       self.valid_after.ok_or(Error::InternalError)?,
+			fresh_until:       self.fresh_until.ok_or(Error::InternalError)?,
+			valid_until:       self.valid_until.ok_or(Error::InternalError)?,
+			known_flags:       self.known_flags,
+			bandwidth_weights: self.bandwidth_weights,
+			authorities:       self.authorities,
+			routers:           self.routers.routers,
+		})
+	}
+}
+
+// The lines following the "r" line of a router status entry. Unknown ones are
+// skipped.
+fn router_status_line(current: &mut RouterStatus, keyword: &str, args: &str) -> Result<(), Error> {
+	match keyword {
+		"a" => current.or_addresses.push(args.to_string()),
+		"s" => current.flags = args.split_whitespace().map(String::from).collect(),
+		"v" => current.version = Some(args.to_string()),
+		"pr" => current.protocols = Some(args.to_string()),
+		"w" => {
+			let bandwidth = args
+				.split_whitespace()
+				.find_map(|arg| arg.strip_prefix("Bandwidth="))
+				.ok_or(Error::InternalError)?;
+			current.bandwidth = Some(u64::from_str(bandwidth).map_err(|_| Error::InternalError)?);
+		}
+		"p" => current.exit_policy = Some(parse_reply(args, parsers::exit_policy_summary)?),
+		_ => {}
+	}
+	Ok(())
+}
+
+impl FromStr for Consensus {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut parser = ConsensusParser::default();
+		for line in s.lines() {
+			parser.line(line)?;
+		}
+		parser.finish()
+	}
+}
+
+impl TorController {
+	/// Looks up a relay's entry in the current consensus. Returns `None` if the
+	/// relay isn't listed.
+	pub fn get_router_status_by_fingerprint(
+		&mut self,
+		fingerprint: &RelayFingerprint,
+	) -> Result<Option<RouterStatus>, Error> {
+		let key = format!("ns/id/{}", fingerprint.as_ref());
+		match self.send_get_info(&[&key])? {
+			Ok(mut values) => {
+				let status = values.remove(&key).ok_or(Error::InternalError)?;
+				RouterStatus::from_str(&status).map(Some)
+			}
+			// Depending on the version, Tor answers 552 instead of 551 for relays
+			// it doesn't know.
+			Err((551, _)) | Err((552, _)) => Ok(None),
+			Err(_) => Err(Error::InternalError),
+		}
+	}
+
+	/// Information about the directory authority this Tor runs as, or `None`
+	/// if it isn't one.
+	pub fn get_authority_info(&mut self) -> Result<Option<AuthorityInfo>, Error> {
+		const KEY: &str = "ns/authority";
+		match self.send_get_info(&[KEY])? {
+			Ok(mut values) => {
+				let info = values.remove(KEY).ok_or(Error::InternalError)?;
+				AuthorityInfo::from_str(&info).map(Some)
+			}
+			Err((551, _)) => Ok(None),
+			Err(_) => Err(Error::InternalError),
+		}
+	}
+
+	/// Fetches and parses the current consensus. The document is several
+	/// megabytes, so it is parsed as it is read instead of being buffered.
+	pub fn consensus(&mut self) -> Result<Consensus, Error> {
+		self.get_info_incremental(
+			"dir/status-vote/current/consensus",
+			ConsensusParser::default(),
+		)
+	}
+
+	/// The directory authorities listed in the current consensus.
+	pub fn list_authorities(&mut self) -> Result<Vec<DirectoryAuthority>, Error> {
+		Ok(self.consensus()?.authorities)
+	}
+
+	/// The vote of the authority `fingerprint` as raw text, from GETINFO
+	/// dir/status-vote/current/<fingerprint>, or the one for the next
+	/// consensus if `current` is false. Only authorities serve votes.
+	pub fn get_authority_vote(
+		&mut self,
+		fingerprint: &RelayFingerprint,
+		current: bool,
+	) -> Result<String, Error> {
+		let period = if current { "current" } else { "next" };
+		self.get_info_value(&format!(
+			"dir/status-vote/{}/{}",
+			period,
+			fingerprint.as_ref()
+		))
+	}
+
+	/// Every router status entry Tor knows, from GETINFO ns/all. Like
+	/// `consensus`, the entries are parsed as they are read.
+	pub fn get_all_router_statuses(&mut self) -> Result<Vec<RouterStatus>, Error> {
+		self.get_info_incremental("ns/all", RouterStatusList::default())
+	}
+
+	/// Like `get_all_router_statuses`, but yields the entries one at a time
+	/// while the reply is read, so that only one of them is held in memory.
+	///
+	/// If Tor rejects the request the stream yields just the error. An entry
+	/// that doesn't parse yields an error in its place and the stream goes on
+	/// with the next one.
+	pub fn stream_network_status(&mut self) -> NetworkStatusStream<'_> {
+		let (reader, error) = match self.read_info("ns/all") {
+			Ok(reader) => (Some(reader), None),
+			Err(err) => (None, Some(err)),
+		};
+		NetworkStatusStream {
+			reader,
+			routers: RouterStatusList::default(),
+			error,
+		}
+	}
+
+	/// The network-wide parameters of the current consensus, from GETINFO
+	/// consensus-params. All of them are integers.
+	pub fn get_consensus_params(&mut self) -> Result<HashMap<String, i64>, Error> {
+		let mut params = HashMap::new();
+		integer_params(&self.get_info_value("consensus-params")?, &mut params)?;
+		Ok(params)
+	}
+
+	/// A single parameter of `get_consensus_params`, e.g.
+	/// `CONSENSUS_PARAM_CIRCWINDOW`, or None if the consensus doesn't set it.
+	pub fn get_consensus_param(&mut self, param: &str) -> Result<Option<i64>, Error> {
+		Ok(self.get_consensus_params()?.remove(param))
+	}
+
+	/// How many relays Tor knows a router status for, counted from GETINFO
+	/// ns/all without parsing the entries.
+	pub fn total_known_relay_count(&mut self) -> Result<u32, Error> {
+		self.get_info_incremental("ns/all", RouterCount::default())
+	}
+
+	/// How many fresh descriptors GETINFO status/fresh-relay-descs returns,
+	/// counting the router items of the descriptors in it.
+	pub fn get_fresh_relay_descriptor_count(&mut self) -> Result<u32, Error> {
+		let descriptors = self.get_info_value("status/fresh-relay-descs")?;
+		Ok(
+			descriptors
+				.lines()
+				.filter(|line| line.starts_with("router "))
+				.count() as u32,
+		)
+	}
+
+	/// `get_fresh_relay_descriptor_count` over `total_known_relay_count`, or 0
+	/// if Tor knows no relays.
+	pub fn relay_descriptor_freshness_ratio(&mut self) -> Result<f64, Error> {
+		let fresh = self.get_fresh_relay_descriptor_count()?;
+		let total = self.total_known_relay_count()?;
+		if total == 0 {
+			return Ok(0.0);
+		}
+		Ok(f64::from(fresh) / f64::from(total))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	const FIXTURE: &str = include_str!("../../fixtures/consensus");
+
+	#[test]
+	fn parse_consensus_fixture() {
+		let consensus = Consensus::from_str(FIXTURE).unwrap();
+		let hours =
+			|hours: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(1714564800 + hours * 3600);
+		assert_eq!(consensus.valid_after, hours(0));
+		assert_eq!(consensus.fresh_until, hours(1));
+		assert_eq!(consensus.valid_until, hours(3));
+		assert!(consensus.known_flags.iter().any(|flag| flag == "HSDir"));
+		assert_eq!(consensus.bandwidth_weights.get("Wgg"), Some(&5945));
+		assert_eq!(consensus.routers.len(), 300);
+		let moria1 = &consensus.authorities[0];
+		assert_eq!(consensus.authorities.len(), 2);
+		assert_eq!(moria1.nickname, "moria1");
+		assert_eq!(moria1.address, "128.31.0.39:9201".parse().unwrap());
+		assert_eq!(moria1.dir_port, 9231);
+		// The fixture doesn't list the authorities' relays.
+		assert_eq!(Some(&moria1.fingerprint), moria1.v3ident.as_ref());
+
+		let first = &consensus.routers[0];
+		assert_eq!(first.nickname, "relay000");
+		assert_eq!(
+			first.fingerprint().unwrap().as_ref(),
+			"2690D907A3EFA5FCD2EFC66D729B5D71E050EC97"
+		);
+		assert!(first.has_flag("Running"));
+		assert!(first.bandwidth.is_some());
+		assert!(first.version.as_deref().unwrap().starts_with("Tor "));
+
+		let exits = consensus
+			.routers
+			.iter()
+			.filter(|router| router.has_flag("Exit"))
+			.count();
+		assert!(exits > 0);
+		assert!(consensus
+			.routers
+			.iter()
+			.all(|router| router.exit_policy.is_some()));
+		assert!(consensus
+			.routers
+			.iter()
+			.any(|router| !router.or_addresses.is_empty()));
+	}
+
+	#[test]
+	fn consensus_entries() {
+		let consensus = Consensus::from_str(
+			"network-status-version 3\n\
+			valid-after 2024-05-01 12:00:00\n\
+			fresh-until 2024-05-01 13:00:00\n\
+			valid-until 2024-05-01 15:00:00\n\
+			known-flags Exit Fast Running Valid\n\
+			some-future-line with arguments\n\
+			dir-source moria1 F533C81CEF0BC0267857C99B2F471ADF249FA232 128.31.0.34 128.31.0.34 9131 9101\n\
+			contact 1024D/EB5A896A28988BF5 arma mit edu\n\
+			r moria1 lpXfw1/+uGEym58asExGOXAgzjE IpcU7dolas8+Q+oAzwgvZIWx7PA 2024-05-01 02:52:29 128.31.0.34 9101 9131\n\
+			a [2001:db8::1]:9101\n\
+			s Fast Running Valid\n\
+			v Tor 0.4.8.9\n\
+			unknown-router-line\n\
+			w Bandwidth=20 Unmeasured=1\n\
+			p reject 1-65535\n\
+			r exit1 AAoQ1DAR6kkoo19hBAX5K0QztNw BfLdeTv0m5Bzh5XmjMHgIPEngIk 2024-05-01 05:47:17 104.53.221.159 443 0\n\
+			s Exit Fast Running Valid\n\
+			directory-footer\n\
+			bandwidth-weights Wbd=0 Wee=10000\n\
+			directory-signature 14C131DFC5C6F93646BE72FA1401C02A8DF2E8B4 BF112F1C6D5543CFD0A32215ACABD4197B5279AD\n\
+			-----BEGIN SIGNATURE-----\n\
+			r+PwsgAz+yL5BCVnAgBgnnAzHJcJN5Nh0AaUgd6jK5UBokBDmx2d5eLxQT3RWA3c\n\
+			-----END SIGNATURE-----\n",
+		)
+		.unwrap();
+
+		assert_eq!(consensus.routers.len(), 2);
+		let moria1 = &consensus.routers[0];
+		assert_eq!(
+			moria1.fingerprint().unwrap().to_string(),
+			"$9695DFC35FFEB861329B9F1AB04C46397020CE31"
+		);
+		assert_eq!(moria1.or_addresses, vec!["[2001:db8::1]:9101"]);
+		assert_eq!(moria1.dir_port, 9131);
+		assert_eq!(moria1.bandwidth, Some(20));
+		assert_eq!(moria1.version.as_deref(), Some("Tor 0.4.8.9"));
+		assert!(moria1.exit_policy.is_some());
+		assert!(consensus.routers[1].has_flag("Exit"));
+		assert_eq!(consensus.routers[1].bandwidth, None);
+		assert_eq!(consensus.bandwidth_weights.get("Wee"), Some(&10000));
+		let authority = &consensus.authorities[0];
+		assert_eq!(authority.fingerprint, moria1.fingerprint().unwrap());
+		assert_eq!(
+			authority.v3ident.as_ref().unwrap().as_ref(),
+			"F533C81CEF0BC0267857C99B2F471ADF249FA232"
+		);
+
+		assert!(Consensus::from_str("network-status-version 3\n").is_err());
+	}
+}
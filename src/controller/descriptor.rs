@@ -0,0 +1,169 @@
+use std::convert::TryInto;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use super::error::Error;
+use super::identity::RelayFingerprint;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// A relay's server descriptor, as served by GETINFO desc/id/*.
+///
+/// Only the commonly needed fields are parsed; keys and signatures are
+/// skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterDescriptor {
+	pub nickname:       String,
+	pub address:        IpAddr,
+	pub or_port:        u16,
+	/// 0 if the relay has no directory port.
+	pub dir_port:       u16,
+	/// E.g. "Tor 0.4.8.9 on Linux".
+	pub platform:       Option<String>,
+	pub published:      Option<SystemTime>,
+	pub fingerprint:    Option<RelayFingerprint>,
+	/// In seconds.
+	pub uptime:         Option<u64>,
+	/// Average, burst and observed bandwidth in bytes per second.
+	pub bandwidth:      Option<(u64, u64, u64)>,
+	pub contact:        Option<String>,
+	pub family:         Vec<String>,
+	pub ntor_onion_key: Option<String>,
+	/// The "accept" and "reject" lines in order, e.g. "reject *:25".
+	pub exit_policy:    Vec<String>,
+	pub hibernating:    bool,
+}
+
+// router moria1 128.31.0.34 9101 0 9131
+fn router_line(args: &str) -> Result<(String, IpAddr, u16, u16), Error> {
+	let fields: Vec<&str> = args.split(' ').collect();
+	match fields[..] {
+		[nickname, address, or_port, _socks_port, dir_port] => Ok((
+			nickname.to_string(),
+			IpAddr::from_str(address).map_err(|_| Error::InternalError)?,
+			u16::from_str(or_port).map_err(|_| Error::InternalError)?,
+			u16::from_str(dir_port).map_err(|_| Error::InternalError)?,
+		)),
+		_ => Err(Error::InternalError),
+	}
+}
+
+fn number(s: &str) -> Result<u64, Error> {
+	u64::from_str(s).map_err(|_| Error::InternalError)
+}
+
+impl FromStr for RouterDescriptor {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut lines = s.lines().skip_while(|line| line.starts_with('@'));
+		let first = lines.next().ok_or(Error::InternalError)?;
+		let args = first.strip_prefix("router ").ok_or(Error::InternalError)?;
+		let (nickname, address, or_port, dir_port) = router_line(args)?;
+		let mut descriptor = RouterDescriptor {
+			nickname,
+			address,
+			or_port,
+			dir_port,
+			platform: None,
+			published: None,
+			fingerprint: None,
+			uptime: None,
+			bandwidth: None,
+			contact: None,
+			family: Vec::new(),
+			ntor_onion_key: None,
+			exit_policy: Vec::new(),
+			hibernating: false,
+		};
+
+		let mut in_object = false;
+		for line in lines {
+			if line.starts_with("-----BEGIN ") {
+				in_object = true;
+			}
+			if in_object {
+				in_object = !line.starts_with("-----END ");
+				continue;
+			}
+
+			let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+			match keyword {
+				"platform" => descriptor.platform = Some(args.to_string()),
+				"published" => descriptor.published = Some(parse_reply(args, parsers::utc_time)?),
+				// Written in groups of four, e.g. "9695 DFC3 5FFE ...".
+				"fingerprint" => {
+					descriptor.fingerprint = Some(RelayFingerprint::from_hex(&args.replace(' ', ""))?)
+				}
+				"uptime" => descriptor.uptime = Some(number(args)?),
+				"bandwidth" => {
+					let values = args
+						.split(' ')
+						.map(number)
+						.collect::<Result<Vec<u64>, Error>>()?;
+					match values[..] {
+						[average, burst, observed] => descriptor.bandwidth = Some((average, burst, observed)),
+						_ => return Err(Error::InternalError),
+					}
+				}
+				"contact" => descriptor.contact = Some(args.to_string()),
+				"family" => descriptor.family = args.split_whitespace().map(String::from).collect(),
+				"ntor-onion-key" => descriptor.ntor_onion_key = Some(args.to_string()),
+				"accept" | "reject" => descriptor.exit_policy.push(line.to_string()),
+				"hibernating" => descriptor.hibernating = args == "1",
+				_ => {}
+			}
+		}
+		Ok(descriptor)
+	}
+}
+
+impl TorController {
+	/// Fetches the server descriptor of a relay. Takes a `RelayFingerprint`, or
+	/// a string which fails with `Error::InvalidFingerprint` unless it is a
+	/// valid fingerprint or ".".
+	pub fn get_router_descriptor<F>(&mut self, fp: F) -> Result<RouterDescriptor, Error>
+	where
+		F: TryInto<RelayFingerprint>,
+		Error: From<F::Error>,
+	{
+		let fingerprint = fp.try_into()?;
+		self.fetch_router_descriptor(&fingerprint, fingerprint.as_ref())
+	}
+
+	/// Fetches a specific server descriptor of a relay by its hex encoded
+	/// digest, e.g. an older one Tor still has cached.
+	pub fn get_router_descriptor_by_digest<F>(
+		&mut self,
+		fp: F,
+		digest: &str,
+	) -> Result<RouterDescriptor, Error>
+	where
+		F: TryInto<RelayFingerprint>,
+		Error: From<F::Error>,
+	{
+		let fingerprint = fp.try_into()?;
+		if digest.len() != 40 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+			return Err(Error::InvalidFingerprint(digest.to_string()));
+		}
+		let id = format!("{}~{}", fingerprint.as_ref(), digest);
+		self.fetch_router_descriptor(&fingerprint, &id)
+	}
+
+	fn fetch_router_descriptor(
+		&mut self,
+		fingerprint: &RelayFingerprint,
+		id: &str,
+	) -> Result<RouterDescriptor, Error> {
+		let key = format!("desc/id/{}", id);
+		match self.send_get_info(&[&key])? {
+			Ok(mut values) => {
+				let descriptor = values.remove(&key).ok_or(Error::InternalError)?;
+				RouterDescriptor::from_str(&descriptor)
+			}
+			Err((551, _)) | Err((552, _)) => Err(Error::UnknownRelay(fingerprint.to_string())),
+			Err(_) => Err(Error::InternalError),
+		}
+	}
+}
@@ -0,0 +1,186 @@
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use super::{AuthMethod, ProtocolInfo};
+use crate::parsers;
+
+const DIAGNOSTIC_TIMEOUT: Duration = Duration::from_secs(5);
+const COOKIE_LEN: u64 = 32;
+
+/// The outcome of one of the checks run by `diagnose_connection`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticResult {
+	pub check:  String,
+	pub passed: bool,
+	pub detail: String,
+}
+
+impl DiagnosticResult {
+	fn new(check: &str, passed: bool, detail: impl Into<String>) -> Self {
+		DiagnosticResult {
+			check: check.to_string(),
+			passed,
+			detail: detail.into(),
+		}
+	}
+}
+
+/// Works out why connecting to a control port fails.
+///
+/// Checks, in order, that the address resolves and accepts a TCP connection,
+/// that it answers PROTOCOLINFO like a control port, which authentication
+/// methods it offers, and whether the cookie file is readable and 32 bytes
+/// long. Checks that depend on a failed one are skipped, so the last result is
+/// usually the one to look at.
+pub fn diagnose_connection(addr: impl ToSocketAddrs) -> Vec<DiagnosticResult> {
+	let mut results = Vec::new();
+
+	let addrs: Vec<_> = match addr.to_socket_addrs() {
+		Ok(addrs) => addrs.collect(),
+		Err(err) => {
+			results.push(DiagnosticResult::new(
+				"Resolve address",
+				false,
+				err.to_string(),
+			));
+			return results;
+		}
+	};
+	let mut stream = None;
+	let mut detail = String::from("address resolved to nothing");
+	for addr in addrs.iter() {
+		match TcpStream::connect_timeout(addr, DIAGNOSTIC_TIMEOUT) {
+			Ok(connected) => {
+				detail = format!("connected to {}", addr);
+				stream = Some(connected);
+				break;
+			}
+			Err(err) => detail = format!("{}: {}", addr, err),
+		}
+	}
+	let stream = match stream {
+		Some(stream) => stream,
+		None => {
+			results.push(DiagnosticResult::new("TCP connect", false, detail));
+			return results;
+		}
+	};
+	results.push(DiagnosticResult::new("TCP connect", true, detail));
+
+	let protocol_info = match request_protocol_info(stream) {
+		Ok(protocol_info) => {
+			let detail = format!("Tor {}", protocol_info.version);
+			results.push(DiagnosticResult::new("PROTOCOLINFO", true, detail));
+			protocol_info
+		}
+		Err(detail) => {
+			results.push(DiagnosticResult::new("PROTOCOLINFO", false, detail));
+			return results;
+		}
+	};
+
+	let methods = &protocol_info.auth_methods;
+	let detail = if methods.is_empty() {
+		String::from("no authentication method offered")
+	} else {
+		let names: Vec<String> = methods
+			.iter()
+			.map(|method| format!("{:?}", method))
+			.collect();
+		names.join(", ")
+	};
+	results.push(DiagnosticResult::new(
+		"Authentication methods",
+		!methods.is_empty(),
+		detail,
+	));
+
+	let uses_cookie =
+		methods.contains(&AuthMethod::Cookie) || methods.contains(&AuthMethod::SafeCookie);
+	if !uses_cookie {
+		return results;
+	}
+	let cookiefile = match &protocol_info.cookiefile {
+		Some(cookiefile) => cookiefile,
+		None => {
+			results.push(DiagnosticResult::new(
+				"Cookie file readable",
+				false,
+				"cookie authentication is offered but no COOKIEFILE was given",
+			));
+			return results;
+		}
+	};
+	let len = match std::fs::File::open(cookiefile).and_then(|file| file.metadata()) {
+		Ok(metadata) => {
+			results.push(DiagnosticResult::new(
+				"Cookie file readable",
+				true,
+				cookiefile.as_str(),
+			));
+			metadata.len()
+		}
+		Err(err) => {
+			let detail = format!("{}: {}", cookiefile, err);
+			results.push(DiagnosticResult::new("Cookie file readable", false, detail));
+			return results;
+		}
+	};
+	results.push(DiagnosticResult::new(
+		"Cookie file size",
+		len == COOKIE_LEN,
+		format!("{} bytes, expected {}", len, COOKIE_LEN),
+	));
+
+	results
+}
+
+/// Sends PROTOCOLINFO over a fresh connection and describes what went wrong if
+/// the answer isn't a PROTOCOLINFO reply.
+fn request_protocol_info(stream: TcpStream) -> Result<ProtocolInfo, String> {
+	let result = stream
+		.set_read_timeout(Some(DIAGNOSTIC_TIMEOUT))
+		.and_then(|_| (&stream).write_all(b"PROTOCOLINFO 1\r\n"));
+	if let Err(err) = result {
+		return Err(err.to_string());
+	}
+
+	let mut reader = BufReader::new(stream);
+	let mut reply = String::new();
+	loop {
+		let mut line = String::new();
+		match reader.read_line(&mut line) {
+			Ok(0) => break,
+			Ok(_) => {
+				reply.push_str(&line);
+				if parsers::is_final_line(&line) {
+					break;
+				}
+			}
+			Err(err) if reply.is_empty() => return Err(format!("no reply: {}", err)),
+			Err(_) => break,
+		}
+	}
+
+	if let Ok((_, protocol_info)) = parsers::protocol_info(&reply) {
+		return Ok(protocol_info);
+	}
+	if let Ok((_, version)) = parsers::protocol_info_version(&reply) {
+		return Err(format!("unsupported PROTOCOLINFO version {}", version));
+	}
+	let first_line = reply.lines().next().unwrap_or_default();
+	if first_line.starts_with("HTTP/") {
+		Err(format!(
+			"got \"{}\"; this looks like a SOCKS or HTTP proxy port, not the control port",
+			first_line
+		))
+	} else if first_line.starts_with("514") || first_line.starts_with("515") {
+		Err(format!("Tor refused PROTOCOLINFO: \"{}\"", first_line))
+	} else if first_line.is_empty() {
+		Err(String::from("connection closed without a reply"))
+	} else {
+		Err(format!("unexpected reply: \"{}\"", first_line))
+	}
+}
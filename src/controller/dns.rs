@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::{Signal, TorController};
+
+/// The state of Tor's asynchronous DNS resolver, from GETINFO
+/// net/async-dns-resolver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DnsResolverStatus {
+	pub cache_size:      u32,
+	pub max_cache_size:  u32,
+	pub pending_queries: u32,
+	pub total_resolved:  u64,
+	pub total_failed:    u64,
+}
+
+/// Parses "key=value" pairs separated by spaces or line breaks, e.g.
+/// "cache-size=12 max-cache-size=4096 ...". Unknown keys are ignored.
+impl FromStr for DnsResolverStatus {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let values: HashMap<&str, &str> = s
+			.split_whitespace()
+			.filter_map(|pair| pair.split_once('='))
+			.collect();
+		let value = |key: &str| values.get(key).ok_or(Error::InternalError);
+		let small = |key: &str| u32::from_str(value(key)?).map_err(|_| Error::InternalError);
+		let large = |key: &str| u64::from_str(value(key)?).map_err(|_| Error::InternalError);
+		Ok(DnsResolverStatus {
+			cache_size:      small("cache-size")?,
+			max_cache_size:  small("max-cache-size")?,
+			pending_queries: small("pending-queries")?,
+			total_resolved:  large("total-resolved")?,
+			total_failed:    large("total-failed")?,
+		})
+	}
+}
+
+impl TorController {
+	/// Cache and query counters of Tor's DNS resolver, for finding out why
+	/// connections are slow to start.
+	pub fn get_dns_resolver_status(&mut self) -> Result<DnsResolverStatus, Error> {
+		DnsResolverStatus::from_str(&self.get_info_value("net/async-dns-resolver")?)
+	}
+
+	/// Forgets all cached DNS results, the same as `Signal::ClearDNSCache`.
+	pub fn flush_dns_cache(&mut self) -> Result<(), Error> {
+		self.signal(Signal::ClearDNSCache)
+	}
+}
@@ -1,5 +1,9 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
+use super::{AuthMethod, HiddenService, InfoError, Signal, TorVersion};
+
 #[derive(Debug, Error)]
 pub enum Error {
 	#[error("Unknown key type")]
@@ -8,8 +12,74 @@ pub enum Error {
 	UnknownAuthMethod,
 	#[error("Auth method disabled")]
 	AuthMethodDisabled,
+	#[error("All authentication methods failed: {}", list_failures(.0))]
+	AllAuthFailed(Vec<(AuthMethod, Error)>),
 	#[error("I/O Error: `{0}`")]
 	Io(#[from] std::io::Error),
+	#[error("Connection to the controller was lost")]
+	ConnectionLost,
+	#[error("Tor only accepts this command after authenticating")]
+	NotAuthenticated,
+	#[error("The control port environment variable is set but empty")]
+	NoControlAddress,
+	#[error("Tor's SAFECOOKIE hash doesn't match the cookie file")]
+	SafeCookieMismatch,
+	#[error("`{0}` is not a 40 character hex relay fingerprint")]
+	InvalidFingerprint(String),
+	#[error("No Tor instance named `{0}` in the pool")]
+	UnknownInstance(String),
+	#[error("Tor has no descriptor for relay `{0}`")]
+	UnknownRelay(String),
+	#[error("`{0}` is not a base32 onion address")]
+	InvalidAddress(String),
+	#[error("Malformed onion service key file")]
+	MalformedKeyFile,
+	#[error("Onion service key belongs to `{expected}` but Tor created `{actual}`")]
+	ServiceIdMismatch { expected: String, actual: String },
+	#[error("Tor has no descriptor cached for onion service `{0}`")]
+	DescriptorNotCached(String),
+	#[error("Tor hasn't generated a descriptor for onion service `{0}` yet")]
+	NoDescriptor(String),
+	#[error("Tor isn't running onion service `{0}`")]
+	UnknownService(String),
+	#[error("Onion service `{0}` was created but Tor doesn't list it")]
+	OnionNotListed(String),
+	#[error("Rotated to onion service `{}` but the old one was not deleted: {source}", new.service_id.0)]
+	OnionRotationIncomplete {
+		new:    Box<HiddenService>,
+		source: Box<Error>,
+	},
+	#[error("Needs Tor {needed} or later, but Tor {running} is running")]
+	// Boxed to keep `Error` small.
+	UnsupportedByTorVersion {
+		needed:  Box<TorVersion>,
+		running: Box<TorVersion>,
+	},
+	#[error("Tor doesn't recognize the signal {0}")]
+	UnrecognizedSignal(Signal),
+	/// `command` is the keyword of the command that didn't complete, or the
+	/// event waited for by helpers like `wait_for_circuit`.
+	#[error("`{command}` didn't complete within {elapsed:?}")]
+	Timeout { command: String, elapsed: Duration },
+	#[error("Tor hasn't tested whether the relay is reachable yet")]
+	NotTested,
+	#[error("Tor replied with PROTOCOLINFO version {0}, which this crate doesn't understand")]
+	UnsupportedProtocolVersion(u32),
+	#[error("Tor isn't running as a relay")]
+	NotARelay,
+	/// Tor rejected a GETINFO key, for getters that read the value as it comes
+	/// in.
+	#[error(transparent)]
+	Info(#[from] InfoError),
 	#[error("Internal error parsing controller response")]
 	InternalError,
 }
+
+// COOKIE: Auth method disabled; NULL: ...
+fn list_failures(failures: &[(AuthMethod, Error)]) -> String {
+	let failures: Vec<String> = failures
+		.iter()
+		.map(|(method, error)| format!("{}: {}", method, error))
+		.collect();
+	failures.join("; ")
+}
@@ -0,0 +1,116 @@
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use super::bootstrap::BootstrapPhase;
+use super::cell_stats::CellStatsEvent;
+use super::circuit_bw::CircuitBwEvent;
+use super::circuits::Circuit;
+use super::connection::Connection;
+use super::error::Error;
+use super::events::{EventKind, RawEvent};
+use super::probe::HsDescEvent;
+use super::streams::StreamEntry;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// An asynchronous event, parsed according to its kind.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParsedEvent {
+	Circ(Circuit),
+	Stream(StreamEntry),
+	/// The bytes Tor read and wrote in the last second.
+	Bw {
+		read:    u64,
+		written: u64,
+	},
+	CircBw(CircuitBwEvent),
+	CellStats(CellStatsEvent),
+	/// A STATUS_CLIENT BOOTSTRAP event.
+	Bootstrap(BootstrapPhase),
+	HsDesc(HsDescEvent),
+	/// Every event this crate doesn't parse yet, as received.
+	Other(RawEvent),
+}
+
+impl ParsedEvent {
+	pub fn parse(event: RawEvent) -> Result<Self, Error> {
+		Ok(match event.kind {
+			EventKind::Circ => ParsedEvent::Circ(parse_reply(&event.raw, parsers::circuit_event)?),
+			EventKind::Stream => ParsedEvent::Stream(parse_reply(&event.raw, parsers::stream_event)?),
+			EventKind::Bw => {
+				let (read, written) = parse_reply(&event.raw, parsers::bw_event)?;
+				ParsedEvent::Bw { read, written }
+			}
+			EventKind::CircBw => ParsedEvent::CircBw(parse_reply(&event.raw, parsers::circuit_bw_event)?),
+			EventKind::CellStats => {
+				ParsedEvent::CellStats(parse_reply(&event.raw, parsers::cell_stats_event)?)
+			}
+			EventKind::StatusClient => match parsers::status_client_bootstrap(&event.raw) {
+				Ok((_, phase)) => ParsedEvent::Bootstrap(phase),
+				Err(_) => ParsedEvent::Other(event),
+			},
+			EventKind::HsDesc => ParsedEvent::HsDesc(parse_reply(&event.raw, parsers::hs_desc_event)?),
+			_ => ParsedEvent::Other(event),
+		})
+	}
+}
+
+// How long the stream holds the connection while it waits for an event,
+// before letting commands through.
+const WAIT_SLICE: Duration = Duration::from_millis(50);
+
+/// A blocking iterator over a controller's events, parsed.
+///
+/// Created by `TorController::subscribe_events`. Like `EventReceiver` it shares
+/// the event queue with `TorController::poll_event`, so every event goes to
+/// whichever asks first. While the stream waits for an event, it lets go of
+/// the connection every few milliseconds so that commands sent through the
+/// controller get their turn. A malformed event is yielded as an error and
+/// the stream carries on; the stream ends after reporting a lost connection,
+/// or once the controller is dropped.
+pub struct EventStream {
+	conn:     Weak<Mutex<Connection>>,
+	finished: bool,
+}
+
+impl Iterator for EventStream {
+	type Item = Result<ParsedEvent, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.finished {
+			return None;
+		}
+		loop {
+			let conn = self.conn.upgrade()?;
+			let mut conn = match conn.lock() {
+				Ok(conn) => conn,
+				Err(_) => return None,
+			};
+			match conn.read_event_timeout(WAIT_SLICE) {
+				Ok(Some(event)) => return Some(ParsedEvent::parse(event)),
+				Ok(None) => {}
+				Err(err) => {
+					self.finished = conn.is_broken();
+					return Some(Err(err));
+				}
+			}
+			// Give a command waiting for the connection the chance to take it
+			// before locking it again.
+			drop(conn);
+			thread::yield_now();
+		}
+	}
+}
+
+impl TorController {
+	/// Subscribes to `events`, replacing the current subscription like
+	/// `set_events`, and returns a stream of them.
+	pub fn subscribe_events(&mut self, events: Vec<EventKind>) -> Result<EventStream, Error> {
+		self.set_events(events)?;
+		Ok(EventStream {
+			conn:     Arc::downgrade(&self.conn),
+			finished: false,
+		})
+	}
+}
@@ -0,0 +1,301 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+use super::error::Error;
+use super::TorController;
+use crate::parsers;
+
+/// The keyword of an asynchronous event, as used in SETEVENTS and at the
+/// start of every 650 reply.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EventKind {
+	Circ,
+	Stream,
+	OrConn,
+	Bw,
+	Debug,
+	Info,
+	Notice,
+	Warn,
+	Err,
+	NewDesc,
+	AddrMap,
+	AuthDirNewDescs,
+	DescChanged,
+	StatusGeneral,
+	StatusClient,
+	StatusServer,
+	Guard,
+	Ns,
+	StreamBw,
+	ClientsSeen,
+	NewConsensus,
+	BuildTimeoutSet,
+	Signal,
+	ConfChanged,
+	CircMinor,
+	TransportLaunched,
+	ConnBw,
+	CircBw,
+	CellStats,
+	TbEmpty,
+	HsDesc,
+	HsDescContent,
+	NetworkLiveness,
+	PtLog,
+	PtStatus,
+	Unknown(String),
+}
+
+static ALL_EVENT_KINDS: [EventKind; 35] = [
+	EventKind::Circ,
+	EventKind::Stream,
+	EventKind::OrConn,
+	EventKind::Bw,
+	EventKind::Debug,
+	EventKind::Info,
+	EventKind::Notice,
+	EventKind::Warn,
+	EventKind::Err,
+	EventKind::NewDesc,
+	EventKind::AddrMap,
+	EventKind::AuthDirNewDescs,
+	EventKind::DescChanged,
+	EventKind::StatusGeneral,
+	EventKind::StatusClient,
+	EventKind::StatusServer,
+	EventKind::Guard,
+	EventKind::Ns,
+	EventKind::StreamBw,
+	EventKind::ClientsSeen,
+	EventKind::NewConsensus,
+	EventKind::BuildTimeoutSet,
+	EventKind::Signal,
+	EventKind::ConfChanged,
+	EventKind::CircMinor,
+	EventKind::TransportLaunched,
+	EventKind::ConnBw,
+	EventKind::CircBw,
+	EventKind::CellStats,
+	EventKind::TbEmpty,
+	EventKind::HsDesc,
+	EventKind::HsDescContent,
+	EventKind::NetworkLiveness,
+	EventKind::PtLog,
+	EventKind::PtStatus,
+];
+
+impl EventKind {
+	/// Every event kind known to this crate, i.e. all but `Unknown`.
+	pub fn all() -> &'static [EventKind] {
+		&ALL_EVENT_KINDS
+	}
+}
+
+impl FromStr for EventKind {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		Ok(match s {
+			"CIRC" => EventKind::Circ,
+			"STREAM" => EventKind::Stream,
+			"ORCONN" => EventKind::OrConn,
+			"BW" => EventKind::Bw,
+			"DEBUG" => EventKind::Debug,
+			"INFO" => EventKind::Info,
+			"NOTICE" => EventKind::Notice,
+			"WARN" => EventKind::Warn,
+			"ERR" => EventKind::Err,
+			"NEWDESC" => EventKind::NewDesc,
+			"ADDRMAP" => EventKind::AddrMap,
+			"AUTHDIR_NEWDESCS" => EventKind::AuthDirNewDescs,
+			"DESCCHANGED" => EventKind::DescChanged,
+			"STATUS_GENERAL" => EventKind::StatusGeneral,
+			"STATUS_CLIENT" => EventKind::StatusClient,
+			"STATUS_SERVER" => EventKind::StatusServer,
+			"GUARD" => EventKind::Guard,
+			"NS" => EventKind::Ns,
+			"STREAM_BW" => EventKind::StreamBw,
+			"CLIENTS_SEEN" => EventKind::ClientsSeen,
+			"NEWCONSENSUS" => EventKind::NewConsensus,
+			"BUILDTIMEOUT_SET" => EventKind::BuildTimeoutSet,
+			"SIGNAL" => EventKind::Signal,
+			"CONF_CHANGED" => EventKind::ConfChanged,
+			"CIRC_MINOR" => EventKind::CircMinor,
+			"TRANSPORT_LAUNCHED" => EventKind::TransportLaunched,
+			"CONN_BW" => EventKind::ConnBw,
+			"CIRC_BW" => EventKind::CircBw,
+			"CELL_STATS" => EventKind::CellStats,
+			"TB_EMPTY" => EventKind::TbEmpty,
+			"HS_DESC" => EventKind::HsDesc,
+			"HS_DESC_CONTENT" => EventKind::HsDescContent,
+			"NETWORK_LIVENESS" => EventKind::NetworkLiveness,
+			"PT_LOG" => EventKind::PtLog,
+			"PT_STATUS" => EventKind::PtStatus,
+			other => EventKind::Unknown(other.to_string()),
+		})
+	}
+}
+
+impl fmt::Display for EventKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			EventKind::Circ => "CIRC",
+			EventKind::Stream => "STREAM",
+			EventKind::OrConn => "ORCONN",
+			EventKind::Bw => "BW",
+			EventKind::Debug => "DEBUG",
+			EventKind::Info => "INFO",
+			EventKind::Notice => "NOTICE",
+			EventKind::Warn => "WARN",
+			EventKind::Err => "ERR",
+			EventKind::NewDesc => "NEWDESC",
+			EventKind::AddrMap => "ADDRMAP",
+			EventKind::AuthDirNewDescs => "AUTHDIR_NEWDESCS",
+			EventKind::DescChanged => "DESCCHANGED",
+			EventKind::StatusGeneral => "STATUS_GENERAL",
+			EventKind::StatusClient => "STATUS_CLIENT",
+			EventKind::StatusServer => "STATUS_SERVER",
+			EventKind::Guard => "GUARD",
+			EventKind::Ns => "NS",
+			EventKind::StreamBw => "STREAM_BW",
+			EventKind::ClientsSeen => "CLIENTS_SEEN",
+			EventKind::NewConsensus => "NEWCONSENSUS",
+			EventKind::BuildTimeoutSet => "BUILDTIMEOUT_SET",
+			EventKind::Signal => "SIGNAL",
+			EventKind::ConfChanged => "CONF_CHANGED",
+			EventKind::CircMinor => "CIRC_MINOR",
+			EventKind::TransportLaunched => "TRANSPORT_LAUNCHED",
+			EventKind::ConnBw => "CONN_BW",
+			EventKind::CircBw => "CIRC_BW",
+			EventKind::CellStats => "CELL_STATS",
+			EventKind::TbEmpty => "TB_EMPTY",
+			EventKind::HsDesc => "HS_DESC",
+			EventKind::HsDescContent => "HS_DESC_CONTENT",
+			EventKind::NetworkLiveness => "NETWORK_LIVENESS",
+			EventKind::PtLog => "PT_LOG",
+			EventKind::PtStatus => "PT_STATUS",
+			EventKind::Unknown(kind) => kind,
+		})
+	}
+}
+
+/// What happens to an event that arrives while the event queue is full, see
+/// `TorController::set_events_bounded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+	/// Make room by dropping the event that has been queued the longest.
+	DropOldest,
+	/// Drop the event that just arrived.
+	DropNewest,
+	/// Stop taking events off the socket until there is room, leaving Tor to
+	/// hold on to them. Events that arrive ahead of a command's reply still
+	/// have to be read, and are dropped like with `DropNewest` if there is no
+	/// room for them.
+	Block,
+}
+
+/// An asynchronous event as sent by Tor, before any event specific parsing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEvent {
+	/// The event keyword, e.g. `EventKind::Circ` for "CIRC".
+	pub kind: EventKind,
+	/// The complete text of the event, including the "650" status lines.
+	pub raw:  String,
+}
+
+impl RawEvent {
+	pub(super) fn parse(raw: String) -> Result<Self, Error> {
+		let kind = match parsers::event_keyword(&raw) {
+			Ok((_, keyword)) => EventKind::from_str(keyword)?,
+			Err(_) => return Err(Error::InternalError),
+		};
+		Ok(RawEvent { kind, raw })
+	}
+}
+
+impl TorController {
+	/// Like `set_events`, but also limits how many received events are kept
+	/// until they are polled, and what happens to the rest. High-rate events
+	/// like BW and CIRC_BW otherwise pile up when they aren't polled quickly.
+	pub fn set_events_bounded(
+		&mut self,
+		events: Vec<EventKind>,
+		capacity: usize,
+		policy: OverflowPolicy,
+	) -> Result<(), Error> {
+		self.lock()?.set_event_limit(capacity, policy);
+		self.event_limit = (capacity, policy);
+		self.set_events(events)
+	}
+
+	/// How many events were dropped because the event queue was full. Starts
+	/// over after `reconnect`.
+	pub fn dropped_count(&self) -> Result<u64, Error> {
+		Ok(self.lock()?.dropped_events())
+	}
+
+	/// Runs `f` with the given events subscribed on top of the caller's own
+	/// subscription, which is restored afterwards. Queued events that only came
+	/// in because of the temporary subscription are dropped.
+	pub(super) fn with_events<F, R>(&mut self, kinds: &[EventKind], f: F) -> Result<R, Error>
+	where
+		F: FnOnce(&mut TorController) -> Result<R, Error>,
+	{
+		let added: Vec<EventKind> = kinds
+			.iter()
+			.filter(|kind| !self.subscribed.contains(kind))
+			.cloned()
+			.collect();
+		if added.is_empty() {
+			return f(self);
+		}
+
+		let mut events = self.subscribed.clone();
+		events.extend(added.iter().cloned());
+		self.send_set_events(&events)?;
+
+		let result = f(self);
+
+		let subscribed = self.subscribed.clone();
+		let restored = self.send_set_events(&subscribed);
+		if let Ok(mut conn) = self.lock() {
+			for kind in added.iter() {
+				conn.discard_events(kind);
+			}
+		}
+
+		let value = result?;
+		restored?;
+		Ok(value)
+	}
+
+	/// Waits until an event accepted by `matches` arrives or the deadline
+	/// passes. Other events stay queued for `poll_event`.
+	pub(super) fn wait_for_event<P>(
+		&mut self,
+		deadline: Instant,
+		mut matches: P,
+	) -> Result<Option<RawEvent>, Error>
+	where
+		P: FnMut(&RawEvent) -> bool,
+	{
+		let mut conn = self.lock()?;
+		let mut skipped = Vec::new();
+		let result = loop {
+			let now = Instant::now();
+			if now >= deadline {
+				break Ok(None);
+			}
+			match conn.read_event_timeout(deadline - now) {
+				Ok(Some(event)) if matches(&event) => break Ok(Some(event)),
+				Ok(Some(event)) => skipped.push(event),
+				Ok(None) => break Ok(None),
+				Err(err) => break Err(err),
+			}
+		};
+		conn.requeue_events(skipped);
+		result
+	}
+}
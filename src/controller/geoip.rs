@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+use super::circuits::{CircuitId, CircuitPurpose, CircuitStatus};
+use super::error::Error;
+use super::identity::RelayFingerprint;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// A two letter country code from Tor's GeoIP database, in lower case.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CountryCode(String);
+
+impl CountryCode {
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+}
+
+impl fmt::Display for CountryCode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl TorController {
+	/// Looks up the country of `ip` in Tor's GeoIP database. Returns `None` if
+	/// the address isn't in the database.
+	pub fn ip_to_country(&mut self, ip: IpAddr) -> Result<Option<CountryCode>, Error> {
+		let country = self.get_info_value(&format!("ip-to-country/{}", ip))?;
+		Ok(match country.as_str() {
+			"" | "??" => None,
+			code => Some(CountryCode(code.to_lowercase())),
+		})
+	}
+
+	/// Looks up the address a relay advertises in the consensus.
+	pub fn relay_address(
+		&mut self,
+		fingerprint: impl Into<RelayFingerprint>,
+	) -> Result<IpAddr, Error> {
+		let fingerprint = fingerprint.into();
+		let status = self.get_info_value(&format!("ns/id/{}", fingerprint.as_ref()))?;
+		parse_reply(&status, parsers::router_status_address)
+	}
+
+	/// Lists the exit country of each built general purpose circuit.
+	///
+	/// The country is `None` when the exit relay or its address can't be
+	/// looked up. Relays shared by several circuits are only looked up once.
+	pub fn circuit_exit_countries(&mut self) -> Result<Vec<(CircuitId, Option<CountryCode>)>, Error> {
+		let mut countries: HashMap<String, Option<CountryCode>> = HashMap::new();
+		let mut exit_countries = Vec::new();
+
+		for circuit in self.circuits()? {
+			if circuit.status != CircuitStatus::Built || circuit.purpose != Some(CircuitPurpose::General)
+			{
+				continue;
+			}
+			let exit = match circuit.path.last() {
+				Some(exit) => exit.fingerprint.clone(),
+				None => {
+					exit_countries.push((circuit.id, None));
+					continue;
+				}
+			};
+
+			let country = match countries.get(&exit) {
+				Some(country) => country.clone(),
+				None => {
+					let address = RelayFingerprint::from_hex(&exit)
+						.and_then(|fingerprint| self.relay_address(fingerprint));
+					let country = match address {
+						Ok(address) => self.ip_to_country(address),
+						Err(err) => Err(err),
+					};
+					let country = match country {
+						Ok(country) => country,
+						Err(Error::Io(err)) => return Err(Error::Io(err)),
+						Err(Error::ConnectionLost) => return Err(Error::ConnectionLost),
+						Err(_) => None,
+					};
+					countries.insert(exit, country.clone());
+					country
+				}
+			};
+			exit_countries.push((circuit.id, country));
+		}
+
+		Ok(exit_countries)
+	}
+}
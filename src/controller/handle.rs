@@ -0,0 +1,58 @@
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use super::connection::Connection;
+use super::error::Error;
+use super::{parse_reply, HiddenService};
+use crate::parsers;
+
+/// A hidden service that is removed with DEL_ONION when the handle is dropped.
+///
+/// Created by `TorController::add_onion_scoped`. Use `into_inner` to keep the
+/// service running past the lifetime of the handle.
+pub struct HiddenServiceHandle {
+	service: Option<HiddenService>,
+	conn:    Arc<Mutex<Connection>>,
+}
+
+impl HiddenServiceHandle {
+	pub(super) fn new(service: HiddenService, conn: Arc<Mutex<Connection>>) -> Self {
+		HiddenServiceHandle {
+			service: Some(service),
+			conn,
+		}
+	}
+
+	/// Releases the service from the handle without deleting it.
+	pub fn into_inner(mut self) -> HiddenService {
+		self.service.take().unwrap()
+	}
+
+	fn delete(&self, service: &HiddenService) -> Result<(), Error> {
+		let mut conn = self.conn.lock().map_err(|_| Error::ConnectionLost)?;
+		conn.write_command(&format!("DEL_ONION {}", service.service_id.0))?;
+		let reply = conn.read_reply()?;
+		parse_reply(&reply, parsers::is_ok)
+	}
+}
+
+impl Deref for HiddenServiceHandle {
+	type Target = HiddenService;
+
+	fn deref(&self) -> &HiddenService {
+		self.service.as_ref().unwrap()
+	}
+}
+
+impl Drop for HiddenServiceHandle {
+	fn drop(&mut self) {
+		if let Some(service) = self.service.take() {
+			if let Err(err) = self.delete(&service) {
+				warn!(
+					"Failed to delete onion service {}: {}",
+					service.service_id.0, err
+				);
+			}
+		}
+	}
+}
@@ -0,0 +1,544 @@
+use std::convert::{TryFrom, TryInto};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use base64::Engine;
+
+use super::consensus::BASE64;
+use super::error::Error;
+use super::identity::{Base32Address, RelayFingerprint};
+use super::version::Capability;
+use super::{parse_reply, ServiceID, TorController};
+use crate::parsers;
+
+/// An introduction point listed in a v2 onion service descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntroductionPoint {
+	/// The base32 encoded identity of the relay.
+	pub identifier:  String,
+	/// The base64 body of the relay's RSA onion key.
+	pub onion_key:   String,
+	/// The base64 body of the service's RSA key for this point.
+	pub service_key: String,
+	/// The type from the point's intro-authentication line, empty if it has
+	/// none.
+	pub auth_type:   String,
+}
+
+/// A way to reach an introduction point relay, see section 5.1.2 of the
+/// tor-spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkSpecifier {
+	Ipv4(SocketAddrV4),
+	Ipv6(SocketAddrV6),
+	/// The relay's RSA identity.
+	LegacyId(RelayFingerprint),
+	/// The relay's Ed25519 identity.
+	Ed25519Id([u8; 32]),
+	Unknown {
+		kind: u8,
+		data: Vec<u8>,
+	},
+}
+
+/// An introduction point listed in a v3 onion service descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V3IntroPoint {
+	pub link_specifiers:  Vec<LinkSpecifier>,
+	/// The base64 encoded ntor onion key of the relay.
+	pub onion_key_ntor:   String,
+	/// The base64 body of the certificate for the point's authentication key.
+	pub auth_key_ed25519: String,
+}
+
+/// The outer, plain text layer of a v3 onion service descriptor, as returned
+/// by `client_hs_descriptor` for a 56 character address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V3HsDescOuter {
+	/// The descriptor format version, 3.
+	pub hs_descriptor:               u32,
+	/// How long the descriptor is valid, in minutes.
+	pub descriptor_lifetime:         u32,
+	/// The base64 body of the certificate for the descriptor signing key.
+	pub descriptor_signing_key_cert: String,
+	/// Increases with every descriptor the service publishes.
+	pub revision_counter:            u64,
+	/// The base64 body of the encrypted middle layer.
+	pub superencrypted:              String,
+	/// The base64 Ed25519 signature of the descriptor.
+	pub signature:                   String,
+}
+
+/// Whether Tor has published a descriptor for one of its onion services, see
+/// `TorController::get_all_hs_descriptor_status`. For when it was made, see
+/// `TorController::hs_descriptor_age`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HsDescriptorStatus {
+	pub service_id:  ServiceID,
+	/// Tor has a descriptor for the service and doesn't want to upload a
+	/// fresh one.
+	pub is_uploaded: bool,
+}
+
+// The keyword, arguments and object (without its BEGIN and END lines) of
+// every item in a descriptor.
+fn items(document: &str) -> Vec<(&str, &str, Option<String>)> {
+	let mut items: Vec<(&str, &str, Option<String>)> = Vec::new();
+	let mut object: Option<String> = None;
+	for line in document.lines() {
+		if line.starts_with("-----BEGIN ") {
+			object = Some(String::new());
+		} else if line.starts_with("-----END ") {
+			if let Some(item) = items.last_mut() {
+				item.2 = object.take();
+			}
+		} else if let Some(object) = object.as_mut() {
+			object.push_str(line);
+		} else {
+			let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+			items.push((keyword, args, None));
+		}
+	}
+	items
+}
+
+/// Extracts the introduction points from a v2 onion service descriptor, or
+/// from just its decoded introduction-points section.
+///
+/// Fails with `Error::InternalError` if the introduction points are encrypted
+/// for client authorization.
+pub fn parse_introduction_points(descriptor: &str) -> Result<Vec<IntroductionPoint>, Error> {
+	let decoded;
+	let mut section = descriptor;
+	if let Some((_, _, message)) = items(descriptor)
+		.into_iter()
+		.find(|(keyword, _, _)| *keyword == "introduction-points")
+	{
+		let message = message.ok_or(Error::InternalError)?;
+		let bytes = BASE64.decode(&message).map_err(|_| Error::InternalError)?;
+		// Encrypted sections start with the authorization type, 1 or 2, followed
+		// by binary data.
+		decoded = String::from_utf8(bytes).map_err(|_| Error::InternalError)?;
+		section = &decoded;
+	}
+
+	let mut points: Vec<IntroductionPoint> = Vec::new();
+	for (keyword, args, object) in items(section) {
+		if keyword == "introduction-point" {
+			points.push(IntroductionPoint {
+				identifier:  args.to_string(),
+				onion_key:   String::new(),
+				service_key: String::new(),
+				auth_type:   String::new(),
+			});
+			continue;
+		}
+		let point = points.last_mut().ok_or(Error::InternalError)?;
+		match keyword {
+			"onion-key" => point.onion_key = object.ok_or(Error::InternalError)?,
+			"service-key" => point.service_key = object.ok_or(Error::InternalError)?,
+			"intro-authentication" => {
+				point.auth_type = args.split(' ').next().unwrap_or_default().to_string()
+			}
+			_ => {}
+		}
+	}
+	Ok(points)
+}
+
+/// Extracts the introduction points from the decrypted inner layer of a v3
+/// onion service descriptor.
+///
+/// Tor hands out v3 descriptors encrypted, and decrypting them needs the
+/// service's keys, so this only works on descriptors decrypted elsewhere.
+pub fn parse_v3_introduction_points(descriptor: &str) -> Result<Vec<V3IntroPoint>, Error> {
+	let mut points: Vec<V3IntroPoint> = Vec::new();
+	for (keyword, args, object) in items(descriptor) {
+		if keyword == "introduction-point" {
+			points.push(V3IntroPoint {
+				link_specifiers:  link_specifiers(args)?,
+				onion_key_ntor:   String::new(),
+				auth_key_ed25519: String::new(),
+			});
+			continue;
+		}
+		let point = match points.last_mut() {
+			Some(point) => point,
+			// Items before the first introduction point describe the service.
+			None => continue,
+		};
+		match keyword {
+			"onion-key" => {
+				let key = args.strip_prefix("ntor ").ok_or(Error::InternalError)?;
+				point.onion_key_ntor = key.to_string();
+			}
+			"auth-key" => point.auth_key_ed25519 = object.ok_or(Error::InternalError)?,
+			_ => {}
+		}
+	}
+
+	if points
+		.iter()
+		.any(|point| point.onion_key_ntor.is_empty() || point.auth_key_ed25519.is_empty())
+	{
+		return Err(Error::InternalError);
+	}
+	Ok(points)
+}
+
+/// Parses the outer layer of a v3 onion service descriptor, see section 2.4
+/// of rend-spec-v3. Decrypting the layers within needs the service's keys.
+pub fn parse_v3_hs_descriptor_outer(data: &str) -> Result<V3HsDescOuter, Error> {
+	let items = items(data);
+	match items.first() {
+		Some(("hs-descriptor", _, _)) => {}
+		_ => return Err(Error::InternalError),
+	}
+	let item = |keyword: &str| {
+		items
+			.iter()
+			.find(|(item, _, _)| *item == keyword)
+			.ok_or(Error::InternalError)
+	};
+	let number = |keyword: &str| u64::from_str(item(keyword)?.1).map_err(|_| Error::InternalError);
+	let object = |keyword: &str| item(keyword)?.2.clone().ok_or(Error::InternalError);
+	let small = |value: u64| u32::try_from(value).map_err(|_| Error::InternalError);
+
+	Ok(V3HsDescOuter {
+		hs_descriptor:               small(number("hs-descriptor")?)?,
+		descriptor_lifetime:         small(number("descriptor-lifetime")?)?,
+		descriptor_signing_key_cert: object("descriptor-signing-key-cert")?,
+		revision_counter:            number("revision-counter")?,
+		superencrypted:              object("superencrypted")?,
+		signature:                   item("signature")?.1.to_string(),
+	})
+}
+
+// NSPEC, followed by LSTYPE, LSLEN and LSPEC for every specifier.
+fn link_specifiers(encoded: &str) -> Result<Vec<LinkSpecifier>, Error> {
+	let bytes = BASE64.decode(encoded).map_err(|_| Error::InternalError)?;
+	let (count, mut rest) = bytes.split_first().ok_or(Error::InternalError)?;
+	let mut specifiers = Vec::with_capacity(*count as usize);
+	for _ in 0..*count {
+		let (kind, len) = match rest {
+			[kind, len, ..] => (*kind, *len as usize),
+			_ => return Err(Error::InternalError),
+		};
+		let data = rest.get(2..2 + len).ok_or(Error::InternalError)?;
+		rest = &rest[2 + len..];
+
+		let port = |data: &[u8]| u16::from_be_bytes([data[data.len() - 2], data[data.len() - 1]]);
+		specifiers.push(match (kind, len) {
+			(0, 6) => {
+				let ip: [u8; 4] = data[..4].try_into().map_err(|_| Error::InternalError)?;
+				LinkSpecifier::Ipv4(SocketAddrV4::new(Ipv4Addr::from(ip), port(data)))
+			}
+			(1, 18) => {
+				let ip: [u8; 16] = data[..16].try_into().map_err(|_| Error::InternalError)?;
+				LinkSpecifier::Ipv6(SocketAddrV6::new(Ipv6Addr::from(ip), port(data), 0, 0))
+			}
+			(2, 20) => {
+				let hex: String = data.iter().map(|byte| format!("{:02X}", byte)).collect();
+				LinkSpecifier::LegacyId(RelayFingerprint::from_hex(&hex)?)
+			}
+			(3, 32) => LinkSpecifier::Ed25519Id(data.try_into().map_err(|_| Error::InternalError)?),
+			_ => LinkSpecifier::Unknown {
+				kind,
+				data: data.to_vec(),
+			},
+		});
+	}
+	Ok(specifiers)
+}
+
+// How long ago the descriptor's publication-time was, if it has one.
+fn descriptor_age(descriptor: &str) -> Result<Option<Duration>, Error> {
+	let published = match items(descriptor)
+		.into_iter()
+		.find(|(keyword, _, _)| *keyword == "publication-time")
+	{
+		Some((_, published, _)) => parse_reply(published, parsers::utc_time)?,
+		None => return Ok(None),
+	};
+	// A clock behind the service's counts as a fresh descriptor.
+	Ok(Some(
+		SystemTime::now()
+			.duration_since(published)
+			.unwrap_or_default(),
+	))
+}
+
+// The data of a multi-line command, ending with the "." line. Lines starting
+// with a dot get another one.
+fn dot_encode(data: &str) -> String {
+	let mut encoded = String::with_capacity(data.len() + 3);
+	for line in data.lines() {
+		if line.starts_with('.') {
+			encoded.push('.');
+		}
+		encoded.push_str(line);
+		encoded.push_str("\r\n");
+	}
+	encoded.push('.');
+	encoded
+}
+
+impl TorController {
+	/// The descriptor Tor has cached as a client for the onion service at
+	/// `addr`, as raw text. Fails with `Error::DescriptorNotCached` if Tor
+	/// hasn't fetched it.
+	pub fn client_hs_descriptor(&mut self, addr: &ServiceID) -> Result<String, Error> {
+		self.hs_descriptor("hs/client/desc/id", addr)
+	}
+
+	/// The descriptor Tor publishes for its own onion service at `addr`, for
+	/// comparing with what clients get from `client_hs_descriptor`.
+	pub fn service_hs_descriptor(&mut self, addr: &ServiceID) -> Result<String, Error> {
+		self.hs_descriptor("hs/service/desc/id", addr)
+	}
+
+	/// Whether Tor wants to upload a fresh descriptor for its onion service at
+	/// `addr`. If it keeps saying so, Tor's log tells why uploads fail, and
+	/// HSPOST uploads the descriptor by hand.
+	pub fn needs_hs_descriptor_upload(&mut self, addr: &ServiceID) -> Result<bool, Error> {
+		let address = Base32Address::from_str(&addr.0)?;
+		let key = format!("hs/service-desc/need-upload/{}", address);
+		let mut values = match self.send_get_info(&[&key])? {
+			Ok(values) => values,
+			Err((551, _)) | Err((552, _)) => return Err(Error::UnknownService(address.to_string())),
+			Err(_) => return Err(Error::InternalError),
+		};
+		match values.remove(&key).as_deref() {
+			Some("1") => Ok(true),
+			Some("0") => Ok(false),
+			_ => Err(Error::InternalError),
+		}
+	}
+
+	/// Uploads the descriptor Tor publishes for `addr` again with HSPOST, to
+	/// the directories Tor picks. Fails with `Error::NoDescriptor` if Tor
+	/// hasn't generated one yet.
+	pub fn upload_hs_descriptor(&mut self, addr: &ServiceID) -> Result<(), Error> {
+		let address = Base32Address::from_str(&addr.0)?;
+		let descriptor = match self.service_hs_descriptor(addr) {
+			Err(Error::DescriptorNotCached(_)) => return Err(Error::NoDescriptor(address.to_string())),
+			result => result?,
+		};
+		// Tor only takes the address of v3 services, v2 descriptors name theirs.
+		let mut command = String::from("+HSPOST");
+		if address.is_v3() {
+			command.push_str(&format!(" HSADDRESS={}", address));
+		}
+		command.push_str("\r\n");
+		command.push_str(&dot_encode(&descriptor));
+		self.send(command, parsers::is_ok)
+	}
+
+	/// The descriptor status of every onion service this controller's
+	/// connection added, asked for in one GETINFO.
+	pub fn get_all_hs_descriptor_status(&mut self) -> Result<Vec<HsDescriptorStatus>, Error> {
+		let services = self.get_onions_current()?;
+		if services.is_empty() {
+			return Ok(Vec::new());
+		}
+		let addresses = services
+			.iter()
+			.map(|service| Base32Address::from_str(&service.0))
+			.collect::<Result<Vec<_>, _>>()?;
+		let key_pairs: Vec<(String, String)> = addresses
+			.iter()
+			.map(|address| {
+				(
+					format!("hs/service/desc/id/{}", address),
+					format!("hs/service-desc/need-upload/{}", address),
+				)
+			})
+			.collect();
+		let keys: Vec<&str> = key_pairs
+			.iter()
+			.flat_map(|(descriptor, need_upload)| vec![descriptor.as_str(), need_upload.as_str()])
+			.collect();
+		let values = self.get_info_multi(&keys)?;
+
+		services
+			.into_iter()
+			.zip(&key_pairs)
+			.map(|(service_id, (descriptor, need_upload))| {
+				let descriptor = match values.get(descriptor) {
+					Some(Ok(descriptor)) => Some(descriptor),
+					_ => None,
+				};
+				let needs_upload = matches!(values.get(need_upload), Some(Ok(value)) if value == "1");
+				Ok(HsDescriptorStatus {
+					service_id,
+					is_uploaded: descriptor.is_some() && !needs_upload,
+				})
+			})
+			.collect()
+	}
+
+	/// How long ago the descriptor Tor publishes for `addr` was made, from its
+	/// publication-time. v3 descriptors only carry a revision counter that
+	/// doesn't translate to a time, so their age is `None`.
+	pub fn hs_descriptor_age(&mut self, addr: &ServiceID) -> Result<Option<Duration>, Error> {
+		descriptor_age(&self.service_hs_descriptor(addr)?)
+	}
+
+	/// Like `hs_descriptor_age`, but for the descriptor Tor has cached as a
+	/// client. `None` as well if Tor hasn't cached one.
+	pub fn get_hs_descriptor_age(&mut self, addr: &ServiceID) -> Result<Option<Duration>, Error> {
+		match self.client_hs_descriptor(addr) {
+			Ok(descriptor) => descriptor_age(&descriptor),
+			Err(Error::DescriptorNotCached(_)) => Ok(None),
+			Err(err) => Err(err),
+		}
+	}
+
+	/// Makes Tor fetch the descriptor of `addr` again with HSFETCH, which
+	/// replaces the cached one once it arrives. Tor can't drop a single
+	/// descriptor from its cache, so the old one stays in use until then.
+	pub fn invalidate_hs_descriptor_cache(&mut self, addr: &ServiceID) -> Result<(), Error> {
+		self.capabilities.require(Capability::HsFetch)?;
+		let address = Base32Address::from_str(&addr.0)?;
+		self.remember_hs_lookup(&address);
+		self.send(format!("HSFETCH {}", address), parsers::is_ok)
+	}
+
+	/// The onion services Tor has a client descriptor cached for.
+	///
+	/// Tor can't list its cache, so this only checks the services this
+	/// controller has looked up with `client_hs_descriptor`, `probe_onion` or
+	/// `invalidate_hs_descriptor_cache`.
+	pub fn get_all_cached_hs_descriptors(&mut self) -> Result<Vec<ServiceID>, Error> {
+		if self.hs_lookups.is_empty() {
+			return Ok(Vec::new());
+		}
+		let keys: Vec<String> = self
+			.hs_lookups
+			.iter()
+			.map(|address| format!("hs/client/desc/id/{}", address))
+			.collect();
+		let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+		let values = self.get_info_multi(&keys)?;
+		Ok(
+			self
+				.hs_lookups
+				.iter()
+				.zip(keys)
+				.filter(|(_, key)| matches!(values.get(*key), Some(Ok(_))))
+				.map(|(address, _)| ServiceID::from(address.to_string()))
+				.collect(),
+		)
+	}
+
+	pub(super) fn remember_hs_lookup(&mut self, address: &Base32Address) {
+		if !self.hs_lookups.contains(address) {
+			self.hs_lookups.push(address.clone());
+		}
+	}
+
+	fn hs_descriptor(&mut self, prefix: &str, addr: &ServiceID) -> Result<String, Error> {
+		let address = Base32Address::from_str(&addr.0)?;
+		if prefix == "hs/client/desc/id" {
+			self.remember_hs_lookup(&address);
+		}
+		let key = format!("{}/{}", prefix, address);
+		match self.send_get_info(&[&key])? {
+			Ok(mut values) => values.remove(&key).ok_or(Error::InternalError),
+			Err((551, _)) | Err((552, _)) => Err(Error::DescriptorNotCached(address.to_string())),
+			Err(_) => Err(Error::InternalError),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_v2_introduction_points() {
+		let points =
+			parse_introduction_points(include_str!("../../fixtures/hs_descriptor_v2")).unwrap();
+		assert_eq!(points.len(), 2);
+		assert_eq!(points[0].identifier, "7bkbwfb4lmqbf6h4jryrhvbyv4jxc6at");
+		assert_eq!(
+			points[0].onion_key,
+			"MIGJAoGBAMCUGCSvORXmmAmTL7QWPgVZIfNaKU2FUCiMHLAcNSaaNVUKyFBBVOJx"
+		);
+		assert_eq!(
+			points[0].service_key,
+			"MIGJAoGBALWIvgk7KRoEsLBl9qmF0Pg4oUnLmxS9aUDq9HpADExhmQhnoNakfPTk"
+		);
+		assert_eq!(points[0].auth_type, "");
+		assert_eq!(points[1].identifier, "nm4intbec5hjqljmrjyhc2lfrxtae7d5");
+		assert_eq!(points[1].auth_type, "1");
+	}
+
+	#[test]
+	fn parse_v3_intro_points() {
+		let points =
+			parse_v3_introduction_points(include_str!("../../fixtures/hs_descriptor_v3_inner")).unwrap();
+		assert_eq!(points.len(), 2);
+
+		let mut ed25519 = [0; 32];
+		ed25519
+			.iter_mut()
+			.enumerate()
+			.for_each(|(i, byte)| *byte = i as u8);
+		assert_eq!(
+			points[0].link_specifiers,
+			vec![
+				LinkSpecifier::Ipv4("128.31.0.34:9101".parse().unwrap()),
+				LinkSpecifier::LegacyId(
+					RelayFingerprint::from_hex("9695DFC35FFEB861329B9F1AB04C46397020CE31").unwrap()
+				),
+				LinkSpecifier::Ed25519Id(ed25519),
+			]
+		);
+		assert_eq!(
+			points[0].onion_key_ntor,
+			"yGtY/b+XOg2kTtF6hLbXMpFEKhAWIGO8MpXriY5HimQ="
+		);
+		assert!(points[0].auth_key_ed25519.starts_with("AQkABvnhAbs0"));
+		assert!(points[0].auth_key_ed25519.ends_with("uKf3"));
+
+		assert_eq!(
+			points[1].link_specifiers[0],
+			LinkSpecifier::Ipv4("104.53.221.159:443".parse().unwrap())
+		);
+		assert_eq!(
+			points[1].onion_key_ntor,
+			"mSg3IFQsH2h2VRrS7M4UavTflWIuIXKTkDaAxZZrK0g="
+		);
+
+		assert!(parse_v3_introduction_points("introduction-point AwAGgB8AIiON").is_err());
+	}
+
+	#[test]
+	fn parse_v3_outer_layer() {
+		let outer =
+			parse_v3_hs_descriptor_outer(include_str!("../../fixtures/hs_descriptor_v3")).unwrap();
+		assert_eq!(outer.hs_descriptor, 3);
+		assert_eq!(outer.descriptor_lifetime, 180);
+		assert_eq!(outer.revision_counter, 1520468874);
+		assert!(outer
+			.descriptor_signing_key_cert
+			.starts_with("eUK98iEG8IR3"));
+		assert!(outer
+			.descriptor_signing_key_cert
+			.ends_with("oL2HmcE1DUOecYl6p18="));
+		assert!(outer.superencrypted.starts_with("3jE0pKpy4FYo"));
+		assert!(outer.superencrypted.ends_with("+LHO5DiV"));
+		assert!(!outer.superencrypted.contains('\n'));
+		assert_eq!(
+			outer.signature,
+			"48JpOwPtmSeusWL4JLrYIm1/sx+reNzgK4BvpVRpb+3WvGHR99DwEZUJXjEOTZYf8RRjao373ROw72STSTTjmQ"
+		);
+
+		// hs-descriptor has to come first.
+		assert!(parse_v3_hs_descriptor_outer("descriptor-lifetime 180\nhs-descriptor 3").is_err());
+		let truncated = "hs-descriptor 3\ndescriptor-lifetime 180\nrevision-counter 1";
+		assert!(parse_v3_hs_descriptor_outer(truncated).is_err());
+	}
+}
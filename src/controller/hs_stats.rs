@@ -0,0 +1,115 @@
+use std::str::FromStr;
+
+use super::circuits::{Circuit, CircuitPurpose, CircuitStatus};
+use super::error::Error;
+use super::{ServiceID, TorController};
+
+/// Circuit counters of an onion service run by this Tor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HsServiceStats {
+	pub intro_circuits_established: u32,
+	pub intro_circuits_failed:      u32,
+	pub rend_circuits_created:      u32,
+	pub rend_circuits_successful:   u32,
+}
+
+/// Where an onion service's introduction circuit stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntroCircuitState {
+	/// The intro point accepted the circuit and relays introductions over it.
+	Established,
+	/// The circuit is still being built, so it doesn't reach the intro point
+	/// yet.
+	NoCircuit,
+	/// The circuit failed or was closed, and Tor has to replace the intro
+	/// point.
+	WaitingRekey,
+	/// The circuit is built and Tor is waiting for the intro point to accept
+	/// it.
+	WaitingIntroRequest,
+}
+
+/// An introduction circuit of an onion service run by this Tor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HsIntroCircuitInfo {
+	pub circuit_id:              u32,
+	pub intro_point_nickname:    Option<String>,
+	/// `None` while the circuit is being built, as its last hop isn't the intro
+	/// point yet.
+	pub intro_point_fingerprint: Option<String>,
+	pub state:                   IntroCircuitState,
+}
+
+impl HsIntroCircuitInfo {
+	fn from_circuit(circuit: Circuit) -> Self {
+		let built = circuit.status == CircuitStatus::Built;
+		let state = match (&circuit.status, circuit.hs_state.as_deref()) {
+			(CircuitStatus::Built, Some("HSSI_ESTABLISHED")) => IntroCircuitState::Established,
+			(CircuitStatus::Built, _) => IntroCircuitState::WaitingIntroRequest,
+			(CircuitStatus::Failed, _) | (CircuitStatus::Closed, _) => IntroCircuitState::WaitingRekey,
+			_ => IntroCircuitState::NoCircuit,
+		};
+		let intro_point = circuit.path.into_iter().last().filter(|_| built);
+		HsIntroCircuitInfo {
+			circuit_id: circuit.id.0,
+			intro_point_nickname: intro_point.as_ref().and_then(|hop| hop.nickname.clone()),
+			intro_point_fingerprint: intro_point.map(|hop| hop.fingerprint),
+			state,
+		}
+	}
+}
+
+const STATS: [&str; 4] = [
+	"intro-circuits-established",
+	"intro-circuits-failed",
+	"rend-circuits-created",
+	"rend-circuits-successful",
+];
+
+impl TorController {
+	/// Fetches the circuit counters of an onion service in one GETINFO, or
+	/// fails with `Error::UnknownService` if Tor isn't running the service.
+	pub fn get_hs_stats(&mut self, service_id: &ServiceID) -> Result<HsServiceStats, Error> {
+		let keys: Vec<String> = STATS
+			.iter()
+			.map(|stat| format!("hs/service/{}/{}", service_id.0, stat))
+			.collect();
+		let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+		let mut values = match self.send_get_info(&keys)? {
+			Ok(values) => values,
+			Err((551, _)) | Err((552, _)) => return Err(Error::UnknownService(service_id.0.clone())),
+			Err(_) => return Err(Error::InternalError),
+		};
+		let mut counter = |key: &str| {
+			let value = values.remove(key).ok_or(Error::InternalError)?;
+			u32::from_str(&value).map_err(|_| Error::InternalError)
+		};
+		Ok(HsServiceStats {
+			intro_circuits_established: counter(keys[0])?,
+			intro_circuits_failed:      counter(keys[1])?,
+			rend_circuits_created:      counter(keys[2])?,
+			rend_circuits_successful:   counter(keys[3])?,
+		})
+	}
+
+	/// Lists the introduction circuits of an onion service, from the
+	/// HS_SERVICE_INTRO circuits in GETINFO circuit-status. Tor has no GETINFO
+	/// key for them, but tags these circuits with the service they belong to
+	/// in REND_QUERY.
+	///
+	/// Empty if Tor isn't running the service.
+	pub fn get_hs_intro_circuits(
+		&mut self,
+		service_id: &ServiceID,
+	) -> Result<Vec<HsIntroCircuitInfo>, Error> {
+		let circuits = self.get_circuits_by_purpose(CircuitPurpose::HsServiceIntro)?;
+		Ok(
+			circuits
+				.into_iter()
+				.filter(|circuit| circuit.rend_query.as_deref() == Some(service_id.0.as_str()))
+				.map(HsIntroCircuitInfo::from_circuit)
+				.collect(),
+		)
+	}
+}
@@ -0,0 +1,189 @@
+use std::convert::{Infallible, TryFrom};
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::ServiceID;
+
+/// A relay identity: the hex encoded SHA-1 of its RSA identity key.
+///
+/// Stored in upper case; displayed with the leading `$` Tor uses for
+/// fingerprints in paths and events.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RelayFingerprint(String);
+
+// Stands in for the fingerprint of the relay Tor itself runs.
+const LOCAL_RELAY: &str = ".";
+
+impl RelayFingerprint {
+	/// The "." placeholder some Tor versions accept in place of their own
+	/// relay's fingerprint, e.g. in GETINFO desc/id/.
+	pub fn local_relay() -> Self {
+		RelayFingerprint(LOCAL_RELAY.to_string())
+	}
+
+	pub fn is_local_relay(&self) -> bool {
+		self.0 == LOCAL_RELAY
+	}
+
+	/// Accepts 40 hex characters, optionally preceded by `$`.
+	pub fn from_hex(s: &str) -> Result<Self, Error> {
+		let hex = s.strip_prefix('$').unwrap_or(s);
+		if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+			return Err(Error::InvalidFingerprint(s.to_string()));
+		}
+		Ok(RelayFingerprint(hex.to_ascii_uppercase()))
+	}
+}
+
+impl From<&RelayFingerprint> for RelayFingerprint {
+	fn from(fingerprint: &RelayFingerprint) -> Self {
+		fingerprint.clone()
+	}
+}
+
+/// Validates like `from_hex`, but also accepts ".", see `local_relay`.
+impl TryFrom<&str> for RelayFingerprint {
+	type Error = Error;
+
+	fn try_from(s: &str) -> Result<Self, Self::Error> {
+		match s {
+			LOCAL_RELAY => Ok(RelayFingerprint::local_relay()),
+			hex => RelayFingerprint::from_hex(hex),
+		}
+	}
+}
+
+impl TryFrom<String> for RelayFingerprint {
+	type Error = Error;
+
+	fn try_from(s: String) -> Result<Self, Self::Error> {
+		RelayFingerprint::try_from(s.as_str())
+	}
+}
+
+// Lets methods take `impl TryInto<RelayFingerprint>` and accept fingerprints,
+// which convert infallibly, as well as strings.
+impl From<Infallible> for Error {
+	fn from(never: Infallible) -> Self {
+		match never {}
+	}
+}
+
+impl AsRef<str> for RelayFingerprint {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
+
+impl fmt::Display for RelayFingerprint {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.is_local_relay() {
+			return f.write_str(&self.0);
+		}
+		write!(f, "${}", self.0)
+	}
+}
+
+/// The base32 part of an onion address, without ".onion": 16 characters for
+/// v2 services and 56 for v3 services.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Base32Address(String);
+
+impl Base32Address {
+	pub fn is_v3(&self) -> bool {
+		self.0.len() == 56
+	}
+}
+
+impl FromStr for Base32Address {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let address = s.to_ascii_lowercase();
+		let valid_chars = address.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7'));
+		if !valid_chars || !matches!(address.len(), 16 | 56) {
+			return Err(Error::InvalidAddress(s.to_string()));
+		}
+		Ok(Base32Address(address))
+	}
+}
+
+impl AsRef<str> for Base32Address {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
+
+impl fmt::Display for Base32Address {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl From<Base32Address> for ServiceID {
+	fn from(address: Base32Address) -> Self {
+		ServiceID(address.0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fingerprint_validation() {
+		let fingerprint =
+			RelayFingerprint::from_hex("$9695dfc35ffeb861329b9f1ab04c46397020ce31").unwrap();
+		assert_eq!(
+			fingerprint.as_ref(),
+			"9695DFC35FFEB861329B9F1AB04C46397020CE31"
+		);
+		assert_eq!(
+			fingerprint.to_string(),
+			"$9695DFC35FFEB861329B9F1AB04C46397020CE31"
+		);
+		assert_eq!(
+			RelayFingerprint::from_hex("9695DFC35FFEB861329B9F1AB04C46397020CE31").unwrap(),
+			fingerprint
+		);
+
+		assert!(RelayFingerprint::from_hex("9695DFC35FFEB861329B9F1AB04C46397020CE").is_err());
+		assert!(RelayFingerprint::from_hex("9695DFC35FFEB861329B9F1AB04C46397020CE3X").is_err());
+		assert!(RelayFingerprint::from_hex("").is_err());
+		assert!(RelayFingerprint::from_hex(".").is_err());
+
+		let local = RelayFingerprint::try_from(".").unwrap();
+		assert!(local.is_local_relay());
+		assert_eq!(local.to_string(), ".");
+		assert_eq!(
+			RelayFingerprint::try_from(String::from("$9695DFC35FFEB861329B9F1AB04C46397020CE31"))
+				.unwrap(),
+			fingerprint
+		);
+		match RelayFingerprint::try_from("9695DFC35FFEB861329B9F1AB04C46397020CE311") {
+			Err(Error::InvalidFingerprint(s)) => {
+				assert_eq!(s, "9695DFC35FFEB861329B9F1AB04C46397020CE311")
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn base32_address_validation() {
+		let v3 = "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid";
+		let address = Base32Address::from_str(v3).unwrap();
+		assert!(address.is_v3());
+		assert_eq!(address.as_ref(), v3);
+		assert_eq!(ServiceID::from(address), ServiceID::from(v3));
+
+		let v2 = Base32Address::from_str("EXPYUZZ4WQQYQHJN").unwrap();
+		assert!(!v2.is_v3());
+		assert_eq!(v2.to_string(), "expyuzz4wqqyqhjn");
+
+		assert!(Base32Address::from_str("expyuzz4wqqyqhj").is_err());
+		assert!(Base32Address::from_str("expyuzz4wqqyqhj1").is_err());
+		assert!(Base32Address::from_str(&v3[..55]).is_err());
+		assert!(Base32Address::from_str("expyuzz4wqqyqhjn.onion").is_err());
+	}
+}
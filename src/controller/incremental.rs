@@ -0,0 +1,236 @@
+use std::sync::MutexGuard;
+use std::time::{Duration, Instant};
+
+use super::connection::Connection;
+use super::error::Error;
+use super::info::InfoError;
+use super::stats::TorControllerStats;
+use super::TorController;
+use crate::parsers;
+
+/// One line of a GETINFO value, without its line ending and with the
+/// escaping of data blocks undone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct ReplyLine<'a>(&'a str);
+
+impl<'a> ReplyLine<'a> {
+	pub(super) fn as_str(&self) -> &'a str {
+		self.0
+	}
+
+	/// The line split at its first space, e.g. into an item's keyword and
+	/// arguments.
+	pub(super) fn keyword_args(&self) -> (&'a str, &'a str) {
+		self.0.split_once(' ').unwrap_or((self.0, ""))
+	}
+}
+
+/// Builds a typed result from a GETINFO value as it is read, so that large
+/// values like the consensus are never held in memory as text.
+pub(super) trait ReplyAccumulator {
+	type Output;
+
+	fn push_line(&mut self, line: ReplyLine<'_>) -> Result<(), Error>;
+
+	fn finish(self) -> Result<Self::Output, Error>;
+}
+
+/// Reads a GETINFO value off the connection one line at a time, see
+/// `TorController::read_info`.
+///
+/// Holds the connection until the whole reply has been read. Dropping the
+/// reader early reads the rest of the reply, so that the connection stays in
+/// sync.
+pub(super) struct InfoReader<'a> {
+	// None once the final line of the reply has been read.
+	conn:     Option<MutexGuard<'a, Connection>>,
+	line:     String,
+	// Whether `line` holds a value that fit on the first line of the reply and
+	// hasn't been returned yet.
+	inline:   bool,
+	// How long Tor gets to send each line, see `command_timeout`.
+	timeout:  Option<Duration>,
+	stats:    &'a mut TorControllerStats,
+	started:  Instant,
+	sent:     usize,
+	received: usize,
+}
+
+impl<'a> InfoReader<'a> {
+	/// The next line of the value, or None at its end.
+	pub(super) fn next_line(&mut self) -> Result<Option<ReplyLine<'_>>, Error> {
+		if self.inline {
+			self.inline = false;
+			return Ok(Some(ReplyLine(&self.line)));
+		}
+		let conn = match &mut self.conn {
+			Some(conn) => conn,
+			None => return Ok(None),
+		};
+
+		self.line.clear();
+		let line = &mut self.line;
+		if let Err(err) = conn.within("GETINFO", self.timeout, |conn| conn.read_line_into(line)) {
+			self.finish(true);
+			return Err(err);
+		}
+		self.received += self.line.len();
+		if parsers::is_data_end(&self.line) {
+			let last = conn.within("GETINFO", self.timeout, Connection::read_line);
+			let result = match last {
+				Ok(last) if parsers::is_final_line(&last) => {
+					self.received += last.len();
+					Ok(None)
+				}
+				Ok(_) => {
+					conn.mark_broken();
+					Err(Error::InternalError)
+				}
+				Err(err) => Err(err),
+			};
+			self.finish(result.is_err());
+			return result;
+		}
+
+		// Lines starting with a period are escaped by doubling it.
+		let text = self.line.trim_end_matches(['\r', '\n']);
+		Ok(Some(ReplyLine(text.strip_prefix('.').unwrap_or(text))))
+	}
+
+	// Releases the connection once the reply has been read or reading it
+	// failed, counting the exchange towards `TorController::stats`.
+	fn finish(&mut self, failed: bool) {
+		self.conn = None;
+		self
+			.stats
+			.record(self.started, self.sent, self.received, failed);
+	}
+}
+
+impl Drop for InfoReader<'_> {
+	fn drop(&mut self) {
+		// Errors leave the connection marked as broken.
+		while let Ok(Some(_)) = self.next_line() {}
+	}
+}
+
+impl TorController {
+	/// Asks for the GETINFO value of `key` and returns a reader for it. Fails
+	/// with `Error::Info` if Tor rejects the key.
+	///
+	/// The reply is counted in `stats`, and `command_timeout` applies to each
+	/// of its lines.
+	pub(super) fn read_info(&mut self, key: &str) -> Result<InfoReader<'_>, Error> {
+		let command = format!("GETINFO {}", key);
+		self.check_authenticated(&command)?;
+		self.last_reply = None;
+		let timeout = self.command_timeout;
+		let mut reader = InfoReader {
+			conn: None,
+			line: String::new(),
+			inline: false,
+			timeout,
+			stats: &mut self.stats,
+			started: Instant::now(),
+			sent: command.len() + 2,
+			received: 0,
+		};
+		let started = self
+			.conn
+			.lock()
+			.map_err(|_| Error::ConnectionLost)
+			.and_then(|mut conn| {
+				conn.write_command(&command)?;
+				let first = conn.within("GETINFO", timeout, Connection::begin_reply)?;
+				Ok((conn, first))
+			});
+		let (mut conn, first) = match started {
+			Ok(started) => started,
+			Err(err) => {
+				reader.finish(true);
+				return Err(err);
+			}
+		};
+		reader.received = first.len();
+
+		let value = first
+			.trim_end_matches(['\r', '\n'])
+			.get(4..)
+			.and_then(|line| line.strip_prefix(key))
+			.and_then(|line| line.strip_prefix('='));
+		match (first.get(..4), value) {
+			// A value that fits on the line, or an empty one.
+			(Some("250-"), Some(value)) => {
+				let last = conn.within("GETINFO", timeout, Connection::read_line);
+				let result = match last {
+					Ok(last) if parsers::is_final_line(&last) => {
+						reader.received += last.len();
+						Ok(())
+					}
+					Ok(_) => {
+						conn.mark_broken();
+						Err(Error::InternalError)
+					}
+					Err(err) => Err(err),
+				};
+				reader.finish(result.is_err());
+				result?;
+				reader.line = value.to_string();
+				reader.inline = !reader.line.is_empty();
+				Ok(reader)
+			}
+			(Some("250+"), Some("")) => {
+				reader.conn = Some(conn);
+				Ok(reader)
+			}
+			_ => {
+				if !parsers::is_final_line(&first) {
+					conn.mark_broken();
+				}
+				reader.finish(true);
+				match parsers::error_reply(&first) {
+					Ok((_, (code, message))) => Err(InfoError::from_reply(code, message).into()),
+					Err(_) => Err(Error::InternalError),
+				}
+			}
+		}
+	}
+
+	/// Like `get_info_value`, but feeds the value to `accumulator` one line at
+	/// a time instead of returning it. Fails with `Error::Info` if Tor rejects
+	/// the key.
+	pub(super) fn get_info_incremental<A>(
+		&mut self,
+		key: &str,
+		mut accumulator: A,
+	) -> Result<A::Output, Error>
+	where
+		A: ReplyAccumulator,
+	{
+		// Only the request can be retried, not the rest of a reply that has
+		// started to arrive.
+		let command = format!("GETINFO {}", key);
+		let mut retries = 0;
+		let mut reconnected = Ok(());
+		let mut reader = loop {
+			let err = match reconnected.and_then(|_| self.read_info(key)) {
+				Ok(reader) => break reader,
+				Err(err) => err,
+			};
+			reconnected = match self.prepare_retry(&command, &err, &mut retries) {
+				Some(reconnected) => reconnected,
+				None => return Err(err),
+			};
+		};
+
+		// Keep reading to the end of the reply even if a line doesn't parse, so
+		// that the connection stays in sync.
+		let mut result = Ok(());
+		while let Some(line) = reader.next_line()? {
+			if result.is_ok() {
+				result = accumulator.push_line(line);
+			}
+		}
+		result.and_then(|_| accumulator.finish())
+	}
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::error::Error;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// Why Tor didn't return a value for a single GETINFO key.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InfoError {
+	/// Tor doesn't know the key (552).
+	#[error("Unrecognized key: {0}")]
+	Unrecognized(String),
+	/// Tor knows the key but has no value for it right now (551), e.g.
+	/// `address` before Tor has guessed its address.
+	#[error("Value unavailable: {0}")]
+	Unavailable(String),
+	#[error("GETINFO failed with {code}: {message}")]
+	Other { code: u16, message: String },
+}
+
+impl InfoError {
+	pub(super) fn from_reply(code: u16, message: String) -> Self {
+		match code {
+			552 => InfoError::Unrecognized(message),
+			551 => InfoError::Unavailable(message),
+			code => InfoError::Other { code, message },
+		}
+	}
+}
+
+// The values, or the status code and message of an error reply.
+pub(super) type GetInfoReply = Result<HashMap<String, String>, (u16, String)>;
+
+impl TorController {
+	/// Like `get_info`, but reports an outcome for every key instead of
+	/// failing as a whole.
+	///
+	/// Tor rejects the entire command if any one key is unrecognized (552) or
+	/// unavailable (551). In that case every key is asked for again on its own,
+	/// so one bad key only costs its own value. Other error replies are
+	/// reported for each key without retrying.
+	pub fn get_info_multi(
+		&mut self,
+		keys: &[&str],
+	) -> Result<HashMap<String, Result<String, InfoError>>, Error> {
+		let (code, message) = match self.send_get_info(keys)? {
+			Ok(mut values) => {
+				return keys
+					.iter()
+					.map(|key| match values.remove(*key) {
+						Some(value) => Ok((key.to_string(), Ok(value))),
+						None => Err(Error::InternalError),
+					})
+					.collect();
+			}
+			Err(reply) => reply,
+		};
+
+		if keys.len() == 1 || ![551, 552].contains(&code) {
+			let error = InfoError::from_reply(code, message);
+			return Ok(
+				keys
+					.iter()
+					.map(|key| (key.to_string(), Err(error.clone())))
+					.collect(),
+			);
+		}
+
+		let mut results = HashMap::new();
+		for key in keys {
+			let outcome = match self.send_get_info(&[key])? {
+				Ok(mut values) => Ok(values.remove(*key).ok_or(Error::InternalError)?),
+				Err((code, message)) => Err(InfoError::from_reply(code, message)),
+			};
+			results.insert(key.to_string(), outcome);
+		}
+		Ok(results)
+	}
+
+	/// The value of any GETINFO key, for keys that don't have a typed method
+	/// yet.
+	pub fn raw_getinfo(&mut self, key: &str) -> Result<String, Error> {
+		self.get_info_value(key)
+	}
+
+	/// Tor's internal scheduler state. The format isn't specified and changes
+	/// between versions, so it is returned as is.
+	pub fn get_scheduler_info(&mut self) -> Result<String, Error> {
+		self.raw_getinfo("debug/scheduler/state")
+	}
+
+	/// Tor's internal list of circuits, unparsed like `get_scheduler_info`.
+	/// See `circuits` for the parsed circuit status.
+	pub fn get_circuit_stats(&mut self) -> Result<String, Error> {
+		self.raw_getinfo("debug/circuit_list")
+	}
+
+	/// Tor's internal directory state, unparsed like `get_scheduler_info`.
+	pub fn get_dir_info(&mut self) -> Result<String, Error> {
+		self.raw_getinfo("debug/dir/state")
+	}
+
+	pub(super) fn send_get_info(&mut self, keys: &[&str]) -> Result<GetInfoReply, Error> {
+		let reply = self.send_raw(&format!("GETINFO {}", keys.join(" ")))?;
+		match parsers::error_reply(&reply) {
+			Ok((_, error)) => Ok(Err(error)),
+			Err(_) => parse_reply(&reply, parsers::get_info).map(Ok),
+		}
+	}
+}
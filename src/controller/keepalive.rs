@@ -0,0 +1,65 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::connection::Connection;
+use crate::parsers;
+
+// How long a probe waits for its reply before the connection counts as dead.
+// A peer that silently went away never answers, so without a limit the probe
+// would hold the connection forever.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handle to the background keepalive thread, which stops once this is dropped.
+pub(super) struct Keepalive {
+	_stop:    Sender<()>,
+	interval: Duration,
+}
+
+impl Keepalive {
+	/// Issues a `GETINFO version` every `interval` while nothing else holds the
+	/// connection, marking it as broken as soon as a probe fails or goes
+	/// unanswered for `PROBE_TIMEOUT`, or `interval` if that is shorter.
+	pub(super) fn spawn(conn: Arc<Mutex<Connection>>, interval: Duration) -> Self {
+		let (stop, stopped) = mpsc::channel::<()>();
+		let timeout = interval.min(PROBE_TIMEOUT);
+		thread::spawn(move || {
+			while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+				let mut conn = match conn.lock() {
+					Ok(conn) => conn,
+					Err(_) => break,
+				};
+				if conn.is_broken() {
+					break;
+				}
+
+				let mut reply = String::new();
+				let alive = conn
+					.write_command("GETINFO version")
+					.and_then(|_| conn.read_reply_within("GETINFO", timeout, &mut reply))
+					.map(|_| parsers::get_info(&reply).is_ok());
+				match alive {
+					Ok(true) => {}
+					Ok(false) => {
+						warn!("Keepalive probe got an unexpected reply");
+					}
+					Err(err) => {
+						warn!("Keepalive probe failed: {}", err);
+						conn.mark_broken();
+						break;
+					}
+				}
+			}
+		});
+
+		Keepalive {
+			_stop: stop,
+			interval,
+		}
+	}
+
+	pub(super) fn interval(&self) -> Duration {
+		self.interval
+	}
+}
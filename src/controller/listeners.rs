@@ -0,0 +1,147 @@
+use std::fmt;
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::error::Error;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// An address Tor listens on, as reported by GETINFO net/listeners/*.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listener {
+	Tcp(SocketAddr),
+	Unix(PathBuf),
+}
+
+impl FromStr for Listener {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.strip_prefix("unix:") {
+			Some(path) => Ok(Listener::Unix(PathBuf::from(path))),
+			None => s
+				.parse()
+				.map(Listener::Tcp)
+				.map_err(|_| Error::InternalError),
+		}
+	}
+}
+
+impl fmt::Display for Listener {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Listener::Tcp(addr) => write!(f, "{}", addr),
+			Listener::Unix(path) => write!(f, "unix:{}", path.display()),
+		}
+	}
+}
+
+/// The kinds of listeners Tor reports with GETINFO net/listeners/*.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerType {
+	Or,
+	Dir,
+	Socks,
+	/// TransPort, for transparent proxying.
+	TransparentProxy,
+	Natd,
+	Dns,
+	Control,
+	HttpTunnel,
+	/// Extended ORPorts, which pluggable transports forward connections to.
+	/// Only open on bridges with ExtORPort configured.
+	ExtOr,
+	/// The listener of a server pluggable transport, by transport name. Only
+	/// open on bridges running that transport.
+	Transport(String),
+}
+
+impl fmt::Display for ListenerType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			ListenerType::Or => "or",
+			ListenerType::Dir => "dir",
+			ListenerType::Socks => "socks",
+			ListenerType::TransparentProxy => "trans",
+			ListenerType::Natd => "natd",
+			ListenerType::Dns => "dns",
+			ListenerType::Control => "control",
+			ListenerType::HttpTunnel => "httptunnel",
+			ListenerType::ExtOr => "ext-or",
+			ListenerType::Transport(name) => return write!(f, "trans/{}", name),
+		})
+	}
+}
+
+// Only TCP listeners have a socket address.
+fn tcp_addrs(listeners: Vec<Listener>) -> Vec<SocketAddr> {
+	listeners
+		.into_iter()
+		.filter_map(|listener| match listener {
+			Listener::Tcp(addr) => Some(addr),
+			Listener::Unix(_) => None,
+		})
+		.collect()
+}
+
+impl TorController {
+	/// Every listener of the given type Tor has open, from GETINFO
+	/// net/listeners/*. The list is empty if the port isn't configured.
+	pub fn get_listeners(&mut self, listener_type: ListenerType) -> Result<Vec<Listener>, Error> {
+		let listeners = self.get_info_value(&format!("net/listeners/{}", listener_type))?;
+		parse_reply(&listeners, parsers::listeners)?
+			.iter()
+			.map(|listener| Listener::from_str(listener))
+			.collect()
+	}
+
+	/// The Extended ORPorts pluggable transports connect to, see
+	/// `ListenerType::ExtOr`.
+	pub fn get_ext_or_listeners(&mut self) -> Result<Vec<SocketAddr>, Error> {
+		Ok(tcp_addrs(self.get_listeners(ListenerType::ExtOr)?))
+	}
+
+	/// Where the server pluggable transport `transport` listens, see
+	/// `ListenerType::Transport`.
+	pub fn get_transport_listeners(&mut self, transport: &str) -> Result<Vec<SocketAddr>, Error> {
+		let listener_type = ListenerType::Transport(transport.to_string());
+		Ok(tcp_addrs(self.get_listeners(listener_type)?))
+	}
+
+	/// Every SOCKS listener Tor has open, from GETINFO net/listeners/socks.
+	/// The list is empty if SocksPort is 0.
+	pub fn socks_listeners(&mut self) -> Result<Vec<Listener>, Error> {
+		self.get_listeners(ListenerType::Socks)
+	}
+
+	/// The address a SOCKS client should connect to, preferring a loopback
+	/// TCP listener. Returns `None` if SOCKS is disabled or only listens on
+	/// unix sockets.
+	pub fn socks_addr(&mut self) -> Result<Option<SocketAddr>, Error> {
+		let addrs = tcp_addrs(self.socks_listeners()?);
+
+		Ok(
+			addrs
+				.iter()
+				.find(|addr| addr.ip().is_loopback())
+				.or_else(|| addrs.first())
+				.copied(),
+		)
+	}
+
+	/// The address of the first TCP SOCKS listener, from GETINFO
+	/// net/listeners/socks. Listeners on unix sockets are skipped.
+	pub fn get_socks_proxy(&mut self) -> Result<Option<SocketAddr>, Error> {
+		Ok(tcp_addrs(self.socks_listeners()?).first().copied())
+	}
+
+	/// Checks that `addr` is one of Tor's SOCKS listeners and accepts
+	/// connections within `timeout`.
+	pub fn verify_socks_proxy(&mut self, addr: SocketAddr, timeout: Duration) -> Result<bool, Error> {
+		let is_listener = tcp_addrs(self.socks_listeners()?).contains(&addr);
+
+		Ok(is_listener && TcpStream::connect_timeout(&addr, timeout).is_ok())
+	}
+}
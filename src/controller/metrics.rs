@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::TorController;
+
+// The GETINFO keys `get_relay_metrics` reports. Values with two numbers, like
+// accounting/bytes, are read and written counts.
+const METRIC_KEYS: &[&str] = &[
+	"uptime",
+	"traffic/read",
+	"traffic/written",
+	"bw-limit-event-count",
+	"accounting/bytes",
+	"accounting/bytes-left",
+];
+
+// Prometheus metric names only allow letters, digits and underscores.
+fn metric_name(key: &str) -> String {
+	key
+		.chars()
+		.map(|c| match c {
+			'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+			_ => '_',
+		})
+		.collect()
+}
+
+/// Formats `metrics` in the Prometheus text format, one "tor_<name> <value>"
+/// line per metric, sorted by name. Characters Prometheus doesn't allow in
+/// names, like the "/" of GETINFO keys, become "_".
+pub fn format_prometheus_metrics(metrics: &HashMap<String, f64>) -> String {
+	let mut names: Vec<(String, f64)> = metrics
+		.iter()
+		.map(|(name, value)| (metric_name(name), *value))
+		.collect();
+	names.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+	let mut text = String::new();
+	for (name, value) in names {
+		let _ = writeln!(text, "tor_{} {}", name, value);
+	}
+	text
+}
+
+impl TorController {
+	/// A set of GETINFO values useful for monitoring a relay, such as uptime
+	/// and traffic/read, keyed by their names with "/" and "-" replaced by
+	/// "_". Read and written counts like accounting/bytes are split into
+	/// "accounting_bytes_read" and "accounting_bytes_written".
+	///
+	/// Keys Tor can't answer, e.g. the accounting ones while accounting is
+	/// disabled, are left out.
+	pub fn get_relay_metrics(&mut self) -> Result<HashMap<String, f64>, Error> {
+		let mut metrics = HashMap::new();
+		for (key, value) in self.get_info_multi(METRIC_KEYS)? {
+			let value = match value {
+				Ok(value) => value,
+				Err(_) => continue,
+			};
+			let numbers = value
+				.split_whitespace()
+				.map(f64::from_str)
+				.collect::<Result<Vec<f64>, _>>()
+				.map_err(|_| Error::InternalError)?;
+			let name = metric_name(&key);
+			match numbers[..] {
+				[number] => {
+					metrics.insert(name, number);
+				}
+				[read, written] => {
+					metrics.insert(format!("{}_read", name), read);
+					metrics.insert(format!("{}_written", name), written);
+				}
+				_ => return Err(Error::InternalError),
+			}
+		}
+		Ok(metrics)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn prometheus_format() {
+		let mut metrics = HashMap::new();
+		metrics.insert("traffic/read".to_string(), 1048576.0);
+		metrics.insert("bw-limit-event-count".to_string(), 3.0);
+		assert_eq!(
+			format_prometheus_metrics(&metrics),
+			"tor_bw_limit_event_count 3\ntor_traffic_read 1048576\n"
+		);
+		assert_eq!(format_prometheus_metrics(&HashMap::new()), "");
+	}
+}
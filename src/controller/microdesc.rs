@@ -0,0 +1,106 @@
+use super::error::Error;
+use super::incremental::InfoReader;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// A relay's microdescriptor, as served by GETINFO md/*.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Microdescriptor {
+	/// The legacy RSA onion key in PEM format; newer relays no longer have one.
+	pub onion_key:      Option<String>,
+	pub ntor_onion_key: String,
+	pub family:         Vec<String>,
+	/// The IPv4 exit policy summary, e.g. "accept 80,443".
+	pub policy:         Option<String>,
+	/// The IPv6 exit policy summary.
+	pub policy_ipv6:    Option<String>,
+	pub ed25519_id:     Option<String>,
+	/// Additional OR addresses from "a" lines.
+	pub addresses:      Vec<String>,
+}
+
+/// Iterator over the microdescriptors in a GETINFO md/all reply, parsing each
+/// one as soon as it has been received.
+///
+/// The connection is held until the iterator is dropped; dropping it early
+/// reads and discards the rest of the reply so that the connection stays in
+/// sync.
+pub struct Microdescriptors<'a> {
+	reader:     InfoReader<'a>,
+	next_first: Option<String>,
+	finished:   bool,
+}
+
+impl<'a> Iterator for Microdescriptors<'a> {
+	type Item = Result<Microdescriptor, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.finished {
+			return None;
+		}
+
+		let mut text = self.next_first.take().unwrap_or_default();
+		loop {
+			let line = match self.reader.next_line() {
+				Ok(Some(line)) => line.as_str(),
+				Ok(None) => {
+					self.finished = true;
+					if text.is_empty() {
+						return None;
+					}
+					return Some(parse_reply(&text, parsers::microdescriptor));
+				}
+				Err(err) => {
+					self.finished = true;
+					return Some(Err(err));
+				}
+			};
+
+			// Annotations like @last-listed come before a microdescriptor.
+			if line.starts_with('@') {
+				if text.is_empty() {
+					continue;
+				}
+				return Some(parse_reply(&text, parsers::microdescriptor));
+			}
+			if starts_microdescriptor(&text, line) {
+				self.next_first = Some(format!("{}\n", line));
+				return Some(parse_reply(&text, parsers::microdescriptor));
+			}
+			text.push_str(line);
+			text.push('\n');
+		}
+	}
+}
+
+// Whether `line` begins the microdescriptor after `text`: its onion-key, or
+// for relays without one, its ntor-onion-key.
+fn starts_microdescriptor(text: &str, line: &str) -> bool {
+	if text.is_empty() {
+		return false;
+	}
+	let keyword = line.split_whitespace().next();
+	match keyword {
+		Some("onion-key") => true,
+		Some("ntor-onion-key") => text.lines().any(|line| line.starts_with("ntor-onion-key")),
+		_ => false,
+	}
+}
+
+impl<'a> Drop for Microdescriptors<'a> {
+	fn drop(&mut self) {
+		while self.next().is_some() {}
+	}
+}
+
+impl TorController {
+	/// Streams all cached microdescriptors without holding the complete reply,
+	/// which can be several megabytes, in memory.
+	pub fn get_all_microdescriptors_iter(&mut self) -> Result<Microdescriptors<'_>, Error> {
+		Ok(Microdescriptors {
+			reader:     self.read_info("md/all")?,
+			next_first: None,
+			finished:   false,
+		})
+	}
+}
@@ -1,28 +1,184 @@
 use std::collections::HashMap;
-use std::io::prelude::*;
-use std::fmt::{Debug, Write as FmtWrite};
-use std::io::{BufReader, Write};
-use std::net::{TcpStream, ToSocketAddrs};
+use std::fmt::{self, Debug};
+use std::net::ToSocketAddrs;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
 
 use nom::IResult;
 
 use crate::parsers;
 
+mod addrmap;
+pub use addrmap::{AddressMapping, MappingScope};
+mod auth;
+pub use auth::AuthAttempt;
+
+mod bandwidth;
+pub use bandwidth::{parse_bytes_value, Bandwidth, BandwidthMonitor};
+
+mod bootstrap;
+pub use bootstrap::BootstrapPhase;
+
+mod builder;
+pub use builder::TorControllerBuilder;
+
+mod cell_stats;
+pub use cell_stats::CellStatsEvent;
+
+mod circuit_bw;
+pub use circuit_bw::{CircuitBwEvent, CircuitBwSnapshot, CircuitBwTracker};
+
+mod circuits;
+pub use circuits::{
+	Circuit, CircuitFilter, CircuitHop, CircuitId, CircuitPurpose, CircuitStats, CircuitStatus,
+	CloseReport,
+};
+
+mod client_auth;
+pub use client_auth::{HsClientAuthInfo, OnionClientAuthEntry};
+
+mod config;
+pub use config::{ConfigSchema, ConfigType};
+
+mod connection;
+use connection::{Connection, DEFAULT_EVENT_CAPACITY};
+
+mod consensus;
+pub use consensus::{
+	AuthorityInfo, Consensus, DirectoryAuthority, NetworkStatusStream, RouterStatus,
+	CONSENSUS_PARAM_BW_WEIGHT_SCALE, CONSENSUS_PARAM_CBT_DISABLED, CONSENSUS_PARAM_CBT_MINCIRCUITS,
+	CONSENSUS_PARAM_CBT_NUM_MODES, CONSENSUS_PARAM_CIRCWINDOW, CONSENSUS_PARAM_USE_OPTIMISTIC_DATA,
+};
+
+mod descriptor;
+pub use descriptor::RouterDescriptor;
+
+mod diagnostics;
+pub use diagnostics::{diagnose_connection, DiagnosticResult};
+
+mod dns;
+pub use dns::DnsResolverStatus;
+
 mod error;
-use error::Error;
+pub use error::Error;
+
+mod event_stream;
+pub use event_stream::{EventStream, ParsedEvent};
+
+mod events;
+pub use events::{EventKind, OverflowPolicy, RawEvent};
+
+mod geoip;
+pub use geoip::CountryCode;
+
+mod handle;
+pub use handle::HiddenServiceHandle;
+
+mod hs_descriptor;
+pub use hs_descriptor::{
+	parse_introduction_points, parse_v3_hs_descriptor_outer, parse_v3_introduction_points,
+	HsDescriptorStatus, IntroductionPoint, LinkSpecifier, V3HsDescOuter, V3IntroPoint,
+};
+mod hs_stats;
+pub use hs_stats::{HsIntroCircuitInfo, HsServiceStats, IntroCircuitState};
+
+mod identity;
+pub use identity::{Base32Address, RelayFingerprint};
+
+mod incremental;
+mod info;
+pub use info::InfoError;
+
+mod keepalive;
+use keepalive::Keepalive;
+
+mod listeners;
+pub use listeners::{Listener, ListenerType};
+
+mod metrics;
+pub use metrics::format_prometheus_metrics;
+
+mod microdesc;
+pub use microdesc::{Microdescriptor, Microdescriptors};
+
+mod onion;
+pub use onion::OnionPort;
+use onion::ServiceList;
+
+mod orconn;
+pub use orconn::{BwSample, CellHistory, OrConnInfo, OrConnStats, OrConnStatus};
+
+mod pipeline;
+pub use pipeline::Pipeline;
+
+mod policy;
+pub use policy::{
+	AddressPattern, ExitPolicy, ExitPolicyRule, ExitPolicySummary, PolicyAction, PortRange,
+};
+
+mod pool;
+pub use pool::{ControllerPool, PoolReport, PooledController, TorControllerPool};
+
+mod probe;
+pub use probe::{HsDescAction, HsDescEvent, OnionProbe, OnionProbeStatus};
+
+mod receiver;
+pub use receiver::{Disconnected, EventReceiver};
+
+mod relay;
+pub use relay::{
+	AccountingStats, HibernationState, PublishStatus, ReachabilityStatus, RelayInfo, RelayUptime,
+	ServerPublishStatusDetailed,
+};
+
+mod stream;
+use stream::Stream;
+
+mod signal_queue;
+pub use signal_queue::SignalQueue;
+
+mod stats;
+pub use stats::TorControllerStats;
+
+mod streams;
+pub use streams::{StreamEntry, StreamId, StreamStatus};
+
+mod timeout;
+pub use timeout::TimeoutGuard;
+
+mod version;
+pub use version::{Capabilities, Capability, TorVersion, VersionStatus};
 
 const DEFAULT_API: &'static str = "127.0.0.1:9051";
 
+/// The only commands Tor accepts before AUTHENTICATE succeeded.
+const PRE_AUTH_COMMANDS: [&str; 4] = ["PROTOCOLINFO", "AUTHCHALLENGE", "AUTHENTICATE", "QUIT"];
+
+// Commands that are safe to send again after the connection went away, since
+// they don't change anything even if Tor already ran them.
+const RETRIED_COMMANDS: [&str; 3] = ["GETINFO", "GETCONF", "PROTOCOLINFO"];
+
+fn command_keyword(command: &str) -> &str {
+	command.split(' ').next().unwrap_or_default()
+}
+const DEFAULT_LAST_REPLY_LIMIT: usize = 64 * 1024;
+const DEFAULT_CIRCUIT_CACHE_TTL: Duration = Duration::from_millis(100);
+
+/// The PROTOCOLINFO versions whose replies can be parsed. Tor bumps the
+/// version when the reply changes in a way older controllers can't follow.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[u32] = &[1];
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct ProtocolInfo {
 	pub auth_methods: Vec<AuthMethod>,
 	pub version:      String,
-	pub cookiefile: String,
+	pub cookiefile:   Option<String>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AuthMethod {
+	Null,
 	Cookie,
 	SafeCookie,
 	HashedPassword,
@@ -33,6 +189,7 @@ impl FromStr for AuthMethod {
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
 		match s {
+			"NULL" => Ok(AuthMethod::Null),
 			"COOKIE" => Ok(AuthMethod::Cookie),
 			"SAFECOOKIE" => Ok(AuthMethod::SafeCookie),
 			"HASHEDPASSWORD" => Ok(AuthMethod::HashedPassword),
@@ -41,6 +198,54 @@ impl FromStr for AuthMethod {
 	}
 }
 
+impl fmt::Display for AuthMethod {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			AuthMethod::Null => "NULL",
+			AuthMethod::Cookie => "COOKIE",
+			AuthMethod::SafeCookie => "SAFECOOKIE",
+			AuthMethod::HashedPassword => "HASHEDPASSWORD",
+		})
+	}
+}
+
+impl AuthMethod {
+	/// Every method, from least to most secure.
+	pub fn all() -> &'static [AuthMethod] {
+		&[
+			AuthMethod::Null,
+			AuthMethod::HashedPassword,
+			AuthMethod::Cookie,
+			AuthMethod::SafeCookie,
+		]
+	}
+
+	/// A name to show users, as opposed to the protocol string from `Display`.
+	pub fn display_name(&self) -> &'static str {
+		match self {
+			AuthMethod::Null => "No authentication",
+			AuthMethod::Cookie => "Cookie file",
+			AuthMethod::SafeCookie => "Safe cookie",
+			AuthMethod::HashedPassword => "Password hash",
+		}
+	}
+
+	pub fn is_cookie_based(&self) -> bool {
+		matches!(self, AuthMethod::Cookie | AuthMethod::SafeCookie)
+	}
+
+	/// Higher is more secure, so callers can pick the best method Tor offers.
+	pub fn security_level(&self) -> u8 {
+		match self {
+			AuthMethod::Null => 0,
+			AuthMethod::HashedPassword => 1,
+			AuthMethod::Cookie => 2,
+			AuthMethod::SafeCookie => 3,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Signal {
 	Reload,
 	Shutdown,
@@ -54,27 +259,79 @@ pub enum Signal {
 	Active,
 }
 
-impl ToString for Signal {
-	fn to_string(&self) -> String {
+impl fmt::Display for Signal {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Signal::Reload => "RELOAD",
+			Signal::Shutdown => "SHUTDOWN",
+			Signal::Dump => "DUMP",
+			Signal::Debug => "DEBUG",
+			Signal::Halt => "HALT",
+			Signal::ClearDNSCache => "CLEARDNSCACHE",
+			Signal::Newnym => "NEWNYM",
+			Signal::Heartbeat => "HEARTBEAT",
+			Signal::Dormant => "DORMANT",
+			Signal::Active => "ACTIVE",
+		})
+	}
+}
+
+impl FromStr for Signal {
+	type Err = Error;
+
+	// Tor also accepts the names of the Unix signals it handles.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"RELOAD" | "HUP" => Ok(Signal::Reload),
+			"SHUTDOWN" | "INT" => Ok(Signal::Shutdown),
+			"DUMP" | "USR1" => Ok(Signal::Dump),
+			"DEBUG" | "USR2" => Ok(Signal::Debug),
+			"HALT" | "TERM" => Ok(Signal::Halt),
+			"CLEARDNSCACHE" => Ok(Signal::ClearDNSCache),
+			"NEWNYM" => Ok(Signal::Newnym),
+			"HEARTBEAT" => Ok(Signal::Heartbeat),
+			"DORMANT" => Ok(Signal::Dormant),
+			"ACTIVE" => Ok(Signal::Active),
+			_ => Err(Error::InternalError),
+		}
+	}
+}
+
+impl Signal {
+	/// Whether Tor exits, and so closes the control connection, on this
+	/// signal.
+	pub fn is_exit(&self) -> bool {
+		matches!(self, Signal::Shutdown | Signal::Halt)
+	}
+
+	/// How urgent the signal is, higher first, see `SignalQueue`.
+	pub fn priority(&self) -> u8 {
 		match self {
-			Signal::Reload => "RELOAD".to_string(),
-			Signal::Shutdown => "SHUTDOWN".to_string(),
-			Signal::Dump => "DUMP".to_string(),
-			Signal::Debug => "DEBUG".to_string(),
-			Signal::Halt => "HALT".to_string(),
-			Signal::ClearDNSCache => "CLEARDNSCACHE".to_string(),
-			Signal::Newnym => "NEWNYM".to_string(),
-			Signal::Heartbeat => "HEARTBEAT".to_string(),
-			Signal::Dormant => "DORMANT".to_string(),
-			Signal::Active => "ACTIVE".to_string(),
+			Signal::Halt | Signal::Shutdown => 10,
+			Signal::Reload => 5,
+			_ => 1,
 		}
 	}
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddOnionFlag {
 	DiscardPK,
 	Detach,
 	BasicAuth,
+	/// Client authorization for v3 services; needs Tor 0.4.6.1 or later.
+	V3Auth,
+}
+
+impl fmt::Display for AddOnionFlag {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			AddOnionFlag::DiscardPK => "DiscardPK",
+			AddOnionFlag::Detach => "Detach",
+			AddOnionFlag::BasicAuth => "BasicAuth",
+			AddOnionFlag::V3Auth => "V3Auth",
+		})
+	}
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -112,7 +369,7 @@ impl FromStr for KeyType {
 	}
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ServiceID(String);
 
 impl From<&str> for ServiceID {
@@ -127,14 +384,158 @@ impl From<String> for ServiceID {
 	}
 }
 
+/// A guard relay as identified in Tor's guard lists.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EntryGuard {
+	pub fingerprint: String,
+	pub nickname:    Option<String>,
+}
+
+/// A member of the guard sample, as listed by GETINFO entry-guards/in-sample.
+///
+/// Guards that were never tried have no connection or confirmation times.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EntryGuardInfo {
+	pub guard:                    EntryGuard,
+	pub is_listed:                bool,
+	pub is_filtered:              bool,
+	pub is_usable:                bool,
+	pub is_running:               bool,
+	pub sampled_on:               String,
+	pub last_tried_to_connect_on: Option<String>,
+	pub last_confirmed_on:        Option<String>,
+	pub path_bias_successes:      f64,
+	pub path_bias_use_successes:  f64,
+}
+
+/// The state of Tor's guard selection, from GETINFO status/guards. Every
+/// list but `sampled` is a subset of the guards before it.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct GuardSetStatus {
+	pub sampled:         Vec<EntryGuardInfo>,
+	/// The sampled guards the current configuration allows.
+	pub filtered:        Vec<EntryGuardInfo>,
+	/// The filtered guards that are listed and not known to be down.
+	pub usable_filtered: Vec<EntryGuardInfo>,
+	/// The guards Tor has successfully used, in the order it prefers them.
+	pub confirmed:       Vec<EntryGuardInfo>,
+	/// The guards Tor picks from when building circuits.
+	pub primary:         Vec<EntryGuardInfo>,
+	pub sampled_count:   usize,
+	pub filtered_count:  usize,
+	pub usable_count:    usize,
+	pub confirmed_count: usize,
+	pub primary_count:   usize,
+}
+
 pub struct HiddenService {
 	pub service_id:  ServiceID,
-	pub key_type:    KeyType,
+	pub key_kind:    KeyType,
 	pub private_key: String,
 }
 
+// Leaves out the private key so it doesn't end up in logs.
+impl Debug for HiddenService {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("HiddenService")
+			.field("service_id", &self.service_id)
+			.field("key_kind", &self.key_kind)
+			.finish_non_exhaustive()
+	}
+}
+
+impl HiddenService {
+	pub fn builder<S: Into<ServiceID>>(service_id: S) -> HiddenServiceBuilder {
+		HiddenServiceBuilder::new(service_id)
+	}
+
+	pub fn service_id_str(&self) -> &str {
+		&self.service_id.0
+	}
+
+	pub fn key_type(&self) -> &KeyType {
+		&self.key_kind
+	}
+
+	pub fn private_key_str(&self) -> &str {
+		&self.private_key
+	}
+
+	/// The service's address including the ".onion" suffix.
+	pub fn onion_address(&self) -> String {
+		format!("{}.onion", self.service_id_str())
+	}
+}
+
+/// Builds a `HiddenService` without asking Tor, e.g. for tests. The key type
+/// defaults to ED25519-V3 and the private key to empty.
+pub struct HiddenServiceBuilder {
+	service_id:  ServiceID,
+	key_kind:    KeyType,
+	private_key: String,
+}
+
+impl HiddenServiceBuilder {
+	pub fn new<S: Into<ServiceID>>(service_id: S) -> Self {
+		HiddenServiceBuilder {
+			service_id:  service_id.into(),
+			key_kind:    KeyType::ED25519V3,
+			private_key: String::new(),
+		}
+	}
+
+	pub fn key_kind(mut self, key_kind: KeyType) -> Self {
+		self.key_kind = key_kind;
+		self
+	}
+
+	pub fn private_key<S: Into<String>>(mut self, private_key: S) -> Self {
+		self.private_key = private_key.into();
+		self
+	}
+
+	pub fn build(self) -> HiddenService {
+		HiddenService {
+			service_id:  self.service_id,
+			key_kind:    self.key_kind,
+			private_key: self.private_key,
+		}
+	}
+}
+
 pub struct TorController {
-	conn: TcpStream,
+	conn:              Arc<Mutex<Connection>>,
+	_keepalive:        Option<Keepalive>,
+	last_reply:        Option<String>,
+	last_reply_limit:  usize,
+	// The reply to the most recent command, reused across commands.
+	reply:             String,
+	subscribed:        Vec<EventKind>,
+	capabilities:      Capabilities,
+	authenticated:     bool,
+	command_timeout:   Option<Duration>,
+	circuit_cache:     Option<(Instant, Vec<Circuit>)>,
+	circuit_cache_ttl: Duration,
+	// How this controller was connected, for `reconnect`.
+	builder:           Option<Box<TorControllerBuilder>>,
+	// The event queue's capacity and overflow policy, for `reconnect`.
+	event_limit:       (usize, OverflowPolicy),
+	ownership:         OwnershipState,
+	sent_retries:      u64,
+	stats:             TorControllerStats,
+	// Onion services looked up as a client, see
+	// `get_all_cached_hs_descriptors`.
+	hs_lookups:        Vec<Base32Address>,
+	// Set while reconnecting to retry a command.
+	retrying:          bool,
+}
+
+/// Whether Tor exits once the controller's connection closes, see
+/// `TorController::take_ownership`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OwnershipState {
+	NotOwned,
+	Owned,
 }
 
 impl TorController {
@@ -143,128 +544,368 @@ impl TorController {
 		T: Debug,
 		F: Fn(&str) -> IResult<&str, T>,
 	{
-		debug!("-> {}", &msg);
-		let bytes = format!("{}\r\n", msg).into_bytes();
-		self.conn.write_all(&bytes)?;
+		self.exchange(&msg)?;
+		parse_reply(&self.reply, reply_parser)
+	}
 
-		let mut reader = BufReader::new(&self.conn);
-		let mut buffer = String::new();
-		loop {
-			let mut line = String::new();
-			reader.read_line(&mut line)?;
-			buffer.push_str(&line);
-			if parsers::is_final_line(&line) {
-				break;
+	/// Sends `msg` and returns the reply unparsed, whatever its status.
+	fn send_raw(&mut self, msg: &str) -> Result<String, Error> {
+		self.exchange(msg)?;
+		Ok(self.reply.clone())
+	}
+
+	/// Sends `msg` and reads the reply into `self.reply`, reusing its
+	/// allocation. If the connection is reset, reconnects and tries again as
+	/// configured with `TorControllerBuilder::max_retries`.
+	fn exchange(&mut self, msg: &str) -> Result<(), Error> {
+		let mut retries = 0;
+		let mut result = self.exchange_once(msg);
+		while let Some(reconnected) = result
+			.as_ref()
+			.err()
+			.and_then(|err| self.prepare_retry(msg, err, &mut retries))
+		{
+			result = reconnected.and_then(|_| self.exchange_once(msg));
+		}
+		result
+	}
+
+	// Waits and reconnects if `command` may be sent again after failing with
+	// `err`, having been retried `retries` times so far. None if it may not.
+	fn prepare_retry(
+		&mut self,
+		command: &str,
+		err: &Error,
+		retries: &mut u32,
+	) -> Option<Result<(), Error>> {
+		let (max_retries, retry_delay) = match &self.builder {
+			Some(builder) if !self.retrying => builder.retry_policy(),
+			_ => return None,
+		};
+		if *retries >= max_retries
+			|| !is_transient(err)
+			|| !RETRIED_COMMANDS.contains(&command_keyword(command))
+		{
+			return None;
+		}
+
+		*retries += 1;
+		self.sent_retries += 1;
+		warn!(
+			"Retrying `{}` after {} ({}/{})",
+			command_keyword(command),
+			err,
+			retries,
+			max_retries
+		);
+		std::thread::sleep(retry_delay);
+		// Commands sent while reconnecting aren't retried themselves.
+		self.retrying = true;
+		let reconnected = self.reconnect();
+		self.retrying = false;
+		Some(reconnected)
+	}
+
+	/// How many times a command has been sent again after the connection was
+	/// reset, see `TorControllerBuilder::max_retries`.
+	pub fn sent_retries(&self) -> u64 {
+		self.sent_retries
+	}
+
+	fn exchange_once(&mut self, msg: &str) -> Result<(), Error> {
+		self.check_authenticated(msg)?;
+		let timeout = self.command_timeout;
+		let mut reply = std::mem::take(&mut self.reply);
+		let started = Instant::now();
+		let result = self.lock().and_then(|mut conn| {
+			conn.write_command(msg)?;
+			match timeout {
+				Some(timeout) => conn.read_reply_within(command_keyword(msg), timeout, &mut reply),
+				None => conn.read_reply_into(&mut reply),
 			}
+		});
+		let received = result.as_ref().map_or(0, |_| reply.len());
+		let failed = result.is_err() || parsers::error_reply(&reply).is_ok();
+		self.stats.record(started, msg.len() + 2, received, failed);
+		self.remember_reply(msg, result.as_ref().ok().map(|_| reply.as_str()));
+		if result.is_ok() && command_keyword(msg) == "AUTHENTICATE" {
+			self.authenticated = parsers::is_ok(&reply).is_ok();
 		}
+		self.reply = reply;
+		result
+	}
+
+	/// Fails with `Error::NotAuthenticated` instead of sending `command` before
+	/// authenticating, which Tor answers by closing the connection.
+	fn check_authenticated(&self, command: &str) -> Result<(), Error> {
+		if self.authenticated || PRE_AUTH_COMMANDS.contains(&command_keyword(command)) {
+			Ok(())
+		} else {
+			Err(Error::NotAuthenticated)
+		}
+	}
+
+	pub fn is_authenticated(&self) -> bool {
+		self.authenticated
+	}
+
+	/// Keeps the reply to `command` around for `last_reply`, truncated to the
+	/// configured limit. Replies to AUTHENTICATE are never kept.
+	fn remember_reply(&mut self, command: &str, reply: Option<&str>) {
+		let mut kept = self.last_reply.take().unwrap_or_default();
+		self.last_reply = match reply {
+			Some(_) if command.starts_with("AUTHENTICATE") => None,
+			Some(reply) => {
+				let mut end = reply.len().min(self.last_reply_limit);
+				while !reply.is_char_boundary(end) {
+					end -= 1;
+				}
+				kept.clear();
+				kept.push_str(&reply[..end]);
+				Some(kept)
+			}
+			None => None,
+		};
+	}
+
+	/// The raw text of the most recent complete reply, for debugging.
+	///
+	/// This is `None` after a connection error and after authenticating.
+	pub fn last_reply(&self) -> Option<&str> {
+		self.last_reply.as_deref()
+	}
 
-		debug!("<- {}", &buffer);
-		let comparison = "250-PROTOCOLINFO 1\n250-AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE=\"/var/run/tor/control.authcookie\"\n250-VERSION Tor=\"0.1.2.3\"\n250 OK".to_string();
-		debug!("<- {}", &comparison);
+	/// Sets how many bytes of the most recent reply are kept for `last_reply`.
+	pub fn set_last_reply_limit(&mut self, limit: usize) {
+		self.last_reply_limit = limit;
+	}
+
+	fn lock(&self) -> Result<MutexGuard<'_, Connection>, Error> {
+		self.conn.lock().map_err(|_| Error::ConnectionLost)
+	}
+
+	/// Starts a batch of commands that are written in one go and whose replies
+	/// are read back in order.
+	pub fn pipeline<T>(&mut self) -> Pipeline<'_, T> {
+		Pipeline::new(self)
+	}
+
+	/// Probes the connection with a `GETINFO version` every `interval` from a
+	/// background thread, so that a connection silently dropped while idle is
+	/// noticed before the next command. Once a probe fails, every call returns
+	/// `Error::ConnectionLost`.
+	///
+	/// Probes are only sent between commands, never in the middle of one.
+	pub fn enable_keepalive(&mut self, interval: Duration) {
+		self._keepalive = Some(Keepalive::spawn(self.conn.clone(), interval));
+	}
+
+	/// Checks that Tor still answers on the control connection by sending a
+	/// `GETINFO version`.
+	pub fn is_alive(&mut self) -> bool {
+		!self.is_connection_lost() && self.get_info_value("version").is_ok()
+	}
+
+	fn is_connection_lost(&self) -> bool {
+		self.lock().map(|conn| conn.is_broken()).unwrap_or(true)
+	}
 
-		return match reply_parser(&buffer) {
-			Ok((_, response)) => Ok(response),
-			Err(_) => Err(Error::InternalError),
+	/// Replaces the control connection with a new one, connecting and
+	/// authenticating the way `TorControllerBuilder::build` did, then restores
+	/// the event subscription and keepalive.
+	///
+	/// Handles and receivers created before keep using the old connection.
+	/// Controllers that weren't created by a builder can't reconnect and get
+	/// `Error::ConnectionLost`.
+	pub fn reconnect(&mut self) -> Result<(), Error> {
+		let mut fresh = match &self.builder {
+			Some(builder) => builder.connect()?,
+			None => return Err(Error::ConnectionLost),
 		};
+		std::mem::swap(&mut self.conn, &mut fresh.conn);
+		self.capabilities = fresh.capabilities.clone();
+		self.authenticated = fresh.authenticated;
+		// Ownership ended with the old connection.
+		self.ownership = OwnershipState::NotOwned;
+		self.last_reply = None;
+		self.circuit_cache = None;
+		self.stats.reconnections += 1;
+
+		if let Some(interval) = self._keepalive.as_ref().map(Keepalive::interval) {
+			self.enable_keepalive(interval);
+		}
+		let (capacity, policy) = self.event_limit;
+		self.lock()?.set_event_limit(capacity, policy);
+		if !self.subscribed.is_empty() {
+			let subscribed = self.subscribed.clone();
+			self.send_set_events(&subscribed)?;
+		}
+		Ok(())
 	}
 
-	fn authenticate(&mut self, password: String) -> Result<(), Error> {
+	/// Authenticates with the control port password.
+	pub fn authenticate(&mut self, password: String) -> Result<(), Error> {
 		let authentication_string = format!("AUTHENTICATE \"{}\"", password.replace("\"", "\\\""));
 		self.send(authentication_string, parsers::is_ok)?;
 
 		Ok(())
 	}
 
+	/// Authenticates to a control port that doesn't require authentication.
+	pub fn authenticate_null(&mut self) -> Result<(), Error> {
+		self.send(String::from("AUTHENTICATE"), parsers::is_ok)
+	}
+
 	// pub fn authenticate_authcookie(&mut self, password: String) -> Result<(), Error> {
 	// 	let protocol_info = self.protocol_info()?;
 	// 	unimplemented!();
 	// 	Ok(())
 	// }
 
+	/// Fails with `Error::UnsupportedProtocolVersion` if Tor answers with a
+	/// version not in `SUPPORTED_PROTOCOL_VERSIONS`.
 	pub fn protocol_info(&mut self) -> Result<ProtocolInfo, Error> {
-		let response = self.send(String::from("PROTOCOLINFO"), parsers::protocol_info)?;
+		self.exchange("PROTOCOLINFO")?;
+		check_protocol_version(&self.reply)?;
+		parse_reply(&self.reply, parsers::protocol_info)
+	}
 
-		Ok(response)
+	/// Configures how to connect and authenticate to Tor.
+	pub fn builder() -> TorControllerBuilder {
+		TorControllerBuilder::new()
+	}
+
+	fn from_stream(stream: Stream) -> TorController {
+		Self {
+			conn:              Arc::new(Mutex::new(Connection::new(stream))),
+			_keepalive:        None,
+			last_reply:        None,
+			last_reply_limit:  DEFAULT_LAST_REPLY_LIMIT,
+			reply:             String::new(),
+			subscribed:        Vec::new(),
+			capabilities:      Capabilities::default(),
+			authenticated:     false,
+			command_timeout:   None,
+			circuit_cache:     None,
+			circuit_cache_ttl: DEFAULT_CIRCUIT_CACHE_TTL,
+			builder:           None,
+			event_limit:       (DEFAULT_EVENT_CAPACITY, OverflowPolicy::DropOldest),
+			ownership:         OwnershipState::NotOwned,
+			sent_retries:      0,
+			stats:             TorControllerStats::default(),
+			hs_lookups:        Vec::new(),
+			retrying:          false,
+		}
+	}
+
+	/// Connects without authenticating, for callers that run PROTOCOLINFO and
+	/// one of the `authenticate*` methods themselves. Until then only those
+	/// commands can be sent.
+	pub fn connect_unauthenticated<A: ToSocketAddrs>(addr: A) -> Result<TorController, Error> {
+		let conn = std::net::TcpStream::connect(addr)?;
+		Ok(TorController::from_stream(conn.into()))
 	}
 
+	// A connection to a mock server that accepts commands right away.
+	#[cfg(test)]
 	fn connect<A: ToSocketAddrs>(addr: A) -> Result<TorController, Error> {
-		let conn = TcpStream::connect(addr)?;
-		let controller = Self { conn };
+		let mut controller = TorController::connect_unauthenticated(addr)?;
+		controller.authenticated = true;
 		Ok(controller)
 	}
 
-	pub fn connect_default_with_authcookie() -> Result<TorController, Error> {
-		TorController::connect_with_authcookie(DEFAULT_API)
+	/// Connects to the control port named in the environment, see
+	/// `TorControllerBuilder::addr_from_env`, and picks an authentication
+	/// method like `TorControllerBuilder::auto_auth`.
+	pub fn connect_from_env() -> Result<TorController, Error> {
+		TorController::builder()
+			.addr_from_env()?
+			.auto_auth()
+			.build()
 	}
 
-	pub fn connect_with_authcookie<A: ToSocketAddrs>(addr: A) -> Result<TorController, Error> {
-		let mut controller = TorController::connect(addr)?;
-		let protocol_info = controller.protocol_info()?;
+	pub fn connect_from_env_with_authcookie() -> Result<TorController, Error> {
+		TorController::builder().addr_from_env()?.build()
+	}
 
-		let contents = std::fs::read(&protocol_info.cookiefile).unwrap();
-		let mut cookie_string = String::new();
-		contents.into_iter().for_each(|b| write!(cookie_string, "{:02X}", b).unwrap());
+	pub fn connect_from_env_with_password(password: String) -> Result<TorController, Error> {
+		TorController::builder()
+			.addr_from_env()?
+			.password(password)
+			.build()
+	}
 
-		let msg = format!("AUTHENTICATE {}", cookie_string);
-		controller.send(msg, parsers::is_ok)?;
+	#[deprecated(note = "use `TorController::builder()` instead")]
+	pub fn connect_default_with_authcookie() -> Result<TorController, Error> {
+		TorController::builder().build()
+	}
 
-		Ok(controller)
+	#[deprecated(note = "use `TorController::builder().addr(addr)` instead")]
+	pub fn connect_with_authcookie<A: ToSocketAddrs>(addr: A) -> Result<TorController, Error> {
+		TorController::builder().addr(addr).build()
 	}
 
+	#[deprecated(note = "use `TorController::builder().password(password)` instead")]
 	pub fn connect_default_with_password(password: String) -> Result<TorController, Error> {
-		TorController::connect_with_password(DEFAULT_API, password)
+		TorController::builder().password(password).build()
 	}
 
+	#[deprecated(note = "use `TorController::builder().addr(addr).password(password)` instead")]
 	pub fn connect_with_password<A: ToSocketAddrs>(
 		addr: A,
 		password: String,
 	) -> Result<TorController, Error> {
-		let mut controller = TorController::connect(addr)?;
-
-		let protocol_info = controller.protocol_info()?;
-
-		if !protocol_info
-			.auth_methods
-			.contains(&AuthMethod::HashedPassword)
-		{
-			return Err(Error::AuthMethodDisabled);
-		}
-
-		controller.authenticate(password)?;
-
-		Ok(controller)
+		TorController::builder()
+			.addr(addr)
+			.password(password)
+			.build()
 	}
 
 	pub fn add_onion(&mut self, key_type: KeyType, port: u16) -> Result<HiddenService, Error> {
+		self.capabilities.require(Capability::AddOnion)?;
 		let add_onion_command = format!("ADD_ONION NEW:{} port={}", key_type.to_string(), port);
 		let (service_id, key) = self.send(add_onion_command, parsers::add_onion)?;
 		let (key_type, private_key) = key.unwrap();
 		let hidden_service = HiddenService {
 			service_id,
-			key_type,
+			key_kind: key_type,
 			private_key,
 		};
 
 		Ok(hidden_service)
 	}
 
+	/// Like `add_onion`, but the service is deleted again when the returned
+	/// handle goes out of scope.
+	pub fn add_onion_scoped(
+		&mut self,
+		key_type: KeyType,
+		port: u16,
+	) -> Result<HiddenServiceHandle, Error> {
+		let hidden_service = self.add_onion(key_type, port)?;
+		Ok(HiddenServiceHandle::new(hidden_service, self.conn.clone()))
+	}
+
 	pub fn add_onion_default(&mut self, port: u16) -> Result<HiddenService, Error> {
 		self.add_onion(KeyType::default(), port)
 	}
 
-	pub fn add_onion_with_key(
-		&mut self,
-		key_type: KeyType,
-		key: String,
-		port: u16,
-	) -> Result<HiddenService, Error> {
+	/// Adds an onion service for an existing key, given as its type and blob,
+	/// e.g. `(KeyType::ED25519V3, key)`, or as a `keys::OnionKeyPair`.
+	pub fn add_onion_with_key<K>(&mut self, key: K, port: u16) -> Result<HiddenService, Error>
+	where
+		K: Into<(KeyType, String)>,
+	{
+		let (key_type, key) = key.into();
+		self.capabilities.require(Capability::AddOnion)?;
 		let add_onion_command = format!("ADD_ONION {}:{} port={}", key_type.to_string(), key, port);
 		let (service_id, optional_key) = self.send(add_onion_command, parsers::add_onion)?;
-		let (key_type, private_key) = optional_key.unwrap();
+		// Tor only sends the private key back for keys it generated itself.
+		let (key_type, private_key) = optional_key.unwrap_or((key_type, key));
 		let hidden_service = HiddenService {
 			service_id,
 			private_key,
-			key_type,
+			key_kind: key_type,
 		};
 
 		Ok(hidden_service)
@@ -285,24 +926,4053 @@ impl TorController {
 		Ok(response)
 	}
 
+	fn get_info_value(&mut self, key: &str) -> Result<String, Error> {
+		let mut response = self.get_info(vec![key])?;
+		response.remove(key).ok_or(Error::InternalError)
+	}
+
+	pub fn get_guard_sample(&mut self) -> Result<Vec<EntryGuardInfo>, Error> {
+		let sample = self.get_info_value("entry-guards/in-sample")?;
+		parse_reply(&sample, parsers::guard_sample)
+	}
+
+	/// The sections of the guard state as `GuardSetStatus`. Sections this
+	/// crate doesn't know are skipped.
+	pub fn get_guard_status(&mut self) -> Result<GuardSetStatus, Error> {
+		let sections = self.get_info_value("status/guards")?;
+		let mut status = GuardSetStatus::default();
+		for (section, guards) in parse_reply(&sections, parsers::guard_status_sections)? {
+			match section.as_str() {
+				"sampled" => status.sampled = guards,
+				"filtered" => status.filtered = guards,
+				"usable-filtered" => status.usable_filtered = guards,
+				"confirmed" => status.confirmed = guards,
+				"primary" => status.primary = guards,
+				_ => {}
+			}
+		}
+		status.sampled_count = status.sampled.len();
+		status.filtered_count = status.filtered.len();
+		status.usable_count = status.usable_filtered.len();
+		status.confirmed_count = status.confirmed.len();
+		status.primary_count = status.primary.len();
+		Ok(status)
+	}
+
+	/// Lists the onion services added by this control connection (and any
+	/// detached ones) through ADD_ONION.
+	pub fn get_onions_current(&mut self) -> Result<Vec<ServiceID>, Error> {
+		self.get_info_incremental("onions/current", ServiceList::default())
+	}
+
+	/// Lists the onion services configured in the torrc rather than through the
+	/// controller; see `get_onions_current` for the latter.
+	pub fn get_configured_hidden_services(&mut self) -> Result<Vec<ServiceID>, Error> {
+		let services = self.get_info_value("hs/service-list")?;
+		parse_reply(&services, parsers::service_list)
+	}
+
+	/// Lists every configuration option Tor knows about along with its type.
+	pub fn get_config_schema(&mut self) -> Result<Vec<ConfigSchema>, Error> {
+		let names = self.get_info_value("config/names")?;
+		parse_reply(&names, parsers::config_names)
+	}
+
+	/// Whether Tor has enough directory information to build circuits.
+	pub fn has_directory_info(&mut self) -> Result<bool, Error> {
+		match self.get_info_value("status/enough-dir-info")?.as_str() {
+			"1" => Ok(true),
+			"0" => Ok(false),
+			_ => Err(Error::InternalError),
+		}
+	}
+
+	/// When Tor downloaded its current consensus.
+	pub fn get_consensus_arrival_time(&mut self) -> Result<SystemTime, Error> {
+		let arrival_time = self.get_info_value("consensus-arrival-time")?;
+		let seconds = u64::from_str(&arrival_time).map_err(|_| Error::InternalError)?;
+		Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+	}
+
+	/// Whether the current consensus arrived within `max_age`. Tor's network
+	/// view is likely outdated once the consensus is more than about three
+	/// hours old.
+	pub fn is_consensus_fresh(&mut self, max_age: Duration) -> Result<bool, Error> {
+		let arrival_time = self.get_consensus_arrival_time()?;
+		// An arrival time in the future (clock skew) counts as just arrived.
+		let age = SystemTime::now()
+			.duration_since(arrival_time)
+			.unwrap_or_default();
+		Ok(age <= max_age)
+	}
+
+	/// Subscribes to the given asynchronous events, replacing any previous
+	/// subscription. An empty list unsubscribes from all events.
+	pub fn set_events(&mut self, events: Vec<EventKind>) -> Result<(), Error> {
+		self.send_set_events(&events)?;
+		self.subscribed = events;
+
+		Ok(())
+	}
+
+	fn send_set_events(&mut self, events: &[EventKind]) -> Result<(), Error> {
+		let events: Vec<String> = events.iter().map(EventKind::to_string).collect();
+		let set_events_command = format!("SETEVENTS {}", events.join(" "));
+		self.send(set_events_command.trim_end().to_string(), parsers::is_ok)
+	}
+
+	/// Waits for the next asynchronous event.
+	pub fn poll_event(&mut self) -> Result<RawEvent, Error> {
+		self.lock()?.read_event()
+	}
+
+	/// Returns the next asynchronous event, or `None` if no event has arrived
+	/// yet, without blocking.
+	///
+	/// This is best-effort: it peeks at the socket to see whether any data is
+	/// waiting and then reads a complete event, so an event that is only partially
+	/// received will block until the rest arrives. Calling it while another thread
+	/// is sending commands through a shared handle is racy.
+	pub fn try_poll_event(&mut self) -> Result<Option<RawEvent>, Error> {
+		self.lock()?.try_read_event()
+	}
+
+	/// Fails with `Error::UnrecognizedSignal` if the running Tor doesn't know
+	/// the signal, e.g. DORMANT on versions before 0.4.0.
 	pub fn signal(&mut self, signal: Signal) -> Result<(), Error> {
-		let signal_command = format!("SIGNAL {}", signal.to_string());
-		self.send(signal_command, parsers::is_ok)?;
+		let signal_command = format!("SIGNAL {}", signal);
+		let reply = self.send_raw(&signal_command)?;
+		match parsers::error_reply(&reply) {
+			Ok((_, (552, _))) => Err(Error::UnrecognizedSignal(signal)),
+			_ => parse_reply(&reply, parsers::is_ok),
+		}
+	}
+
+	/// Like `signal`, but for SHUTDOWN and HALT a connection that closes while
+	/// waiting for the reply counts as success, since Tor is exiting anyway.
+	pub fn signal_and_confirm(&mut self, signal: Signal) -> Result<(), Error> {
+		match self.signal(signal) {
+			Err(Error::Io(err)) if signal.is_exit() && is_disconnect(&err) => Ok(()),
+			Err(Error::ConnectionLost) if signal.is_exit() => Ok(()),
+			result => result,
+		}
+	}
+
+	/// Makes Tor shut down once this control connection is closed.
+	///
+	/// Dropping the controller hands ownership back first, so only a lost
+	/// connection or an exiting process takes Tor down with it.
+	pub fn take_ownership(&mut self) -> Result<(), Error> {
+		self.capabilities.require(Capability::TakeOwnership)?;
+		self.send(String::from("TAKEOWNERSHIP"), parsers::is_ok)?;
+		self.ownership = OwnershipState::Owned;
+		Ok(())
+	}
 
+	/// Undoes `take_ownership` and any __OwningControllerProcess option, so
+	/// Tor keeps running without this controller.
+	pub fn drop_ownership(&mut self) -> Result<(), Error> {
+		self.capabilities.require(Capability::DropOwnership)?;
+		self.send(String::from("DROPOWNERSHIP"), parsers::is_ok)?;
+		self.ownership = OwnershipState::NotOwned;
 		Ok(())
 	}
+
+	/// Whether `take_ownership` succeeded and hasn't been undone.
+	pub fn is_owner(&self) -> bool {
+		self.ownership == OwnershipState::Owned
+	}
+
+	/// Forgets about ownership without handing it back, for a Tor that is
+	/// about to be shut down anyway.
+	#[cfg(feature = "launch")]
+	pub(crate) fn forget_ownership(&mut self) {
+		self.ownership = OwnershipState::NotOwned;
+	}
+}
+
+impl Drop for TorController {
+	fn drop(&mut self) {
+		if !self.is_owner() || self.is_connection_lost() {
+			return;
+		}
+		if let Err(err) = self.drop_ownership() {
+			warn!("Failed to drop ownership of Tor: {}", err);
+		}
+	}
+}
+
+fn is_disconnect(err: &std::io::Error) -> bool {
+	matches!(
+		err.kind(),
+		std::io::ErrorKind::UnexpectedEof
+			| std::io::ErrorKind::ConnectionReset
+			| std::io::ErrorKind::ConnectionAborted
+			| std::io::ErrorKind::BrokenPipe
+	)
+}
+
+fn parse_reply<F, T>(reply: &str, reply_parser: F) -> Result<T, Error>
+where
+	F: Fn(&str) -> IResult<&str, T>,
+{
+	match reply_parser(reply) {
+		Ok((_, response)) => Ok(response),
+		Err(_) => Err(Error::InternalError),
+	}
+}
+
+// Errors a fresh connection may get past. Parse errors and error replies never
+// are.
+fn is_transient(err: &Error) -> bool {
+	match err {
+		Error::Io(err) => matches!(
+			err.kind(),
+			std::io::ErrorKind::ConnectionReset
+				| std::io::ErrorKind::BrokenPipe
+				| std::io::ErrorKind::UnexpectedEof
+		),
+		_ => false,
+	}
+}
+
+fn check_protocol_version(reply: &str) -> Result<(), Error> {
+	let version = parse_reply(reply, parsers::protocol_info_version)?;
+	if !SUPPORTED_PROTOCOL_VERSIONS.contains(&version) {
+		return Err(Error::UnsupportedProtocolVersion(version));
+	}
+	Ok(())
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use std::io::prelude::*;
+	use std::io::BufReader;
+	use std::net::{SocketAddr, TcpListener};
+	use std::thread::{self, JoinHandle};
 
 	fn init() {
 		let _ = env_logger::builder().is_test(true).try_init();
 	}
 
 	fn get_controller() -> TorController {
-		TorController::connect_default_with_authcookie().unwrap()
+		TorController::builder().build().unwrap()
+	}
+
+	/// Starts a control port on localhost that accepts a single connection and,
+	/// for every scripted reply, reads one command line and writes the reply
+	/// verbatim. The join handle yields the commands that were received.
+	fn mock_server<S: Into<String>>(replies: Vec<S>) -> (SocketAddr, JoinHandle<Vec<String>>) {
+		let replies: Vec<String> = replies.into_iter().map(Into::into).collect();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let handle = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			serve_session(stream, replies)
+		});
+		(addr, handle)
+	}
+
+	/// Like `mock_server`, but accepts one connection per session, one after
+	/// the other.
+	fn mock_server_sessions(
+		sessions: Vec<Vec<String>>,
+	) -> (SocketAddr, JoinHandle<Vec<Vec<String>>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let handle = thread::spawn(move || {
+			sessions
+				.into_iter()
+				.map(|replies| serve_session(listener.accept().unwrap().0, replies))
+				.collect()
+		});
+		(addr, handle)
+	}
+
+	// Multi-line commands are recorded with their data, one "\n" per line.
+	fn serve_session(stream: std::net::TcpStream, replies: Vec<String>) -> Vec<String> {
+		let mut reader = BufReader::new(stream);
+		let mut commands = Vec::new();
+		for reply in replies {
+			let mut line = String::new();
+			if reader.read_line(&mut line).unwrap() == 0 {
+				break;
+			}
+			if line.starts_with('+') {
+				let mut data = String::new();
+				while data != ".\r\n" {
+					data.clear();
+					reader.read_line(&mut data).unwrap();
+					line.push_str(&data);
+				}
+				line = line.replace("\r\n", "\n");
+			}
+			commands.push(line.trim_end().to_string());
+			reader.get_mut().write_all(reply.as_bytes()).unwrap();
+		}
+		commands
+	}
+
+	#[test]
+	fn buffered_replies_survive_across_commands() {
+		let (addr, server) = mock_server(vec![
+			"250-version=0.4.7.16\r\n250 OK\r\n250-dormant=0\r\n250 OK\r\n",
+			"",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let info = controller.get_info(vec!["version"]).unwrap();
+		assert_eq!(info.get("version"), Some(&String::from("0.4.7.16")));
+		let info = controller.get_info(vec!["dormant"]).unwrap();
+		assert_eq!(info.get("dormant"), Some(&String::from("0")));
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["GETINFO version", "GETINFO dormant"]
+		);
+	}
+
+	#[test]
+	fn pipeline_writes_all_commands_before_reading() {
+		// The replies are only sent once the third command has arrived, so a client
+		// waiting for each reply before sending the next command would hang here.
+		let (addr, server) = mock_server(vec![
+			"",
+			"",
+			"250-version=0.4.7.16\r\n250 OK\r\n552 Unrecognized key \"bogus\"\r\n250-dormant=0\r\n250 OK\r\n",
+			"250-uptime=42\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let replies = controller
+			.pipeline()
+			.push("GETINFO version", parsers::get_info)
+			.push("GETINFO bogus", parsers::get_info)
+			.push("GETINFO dormant", parsers::get_info)
+			.run()
+			.unwrap();
+		assert_eq!(replies.len(), 3);
+		assert_eq!(
+			replies[0].as_ref().unwrap().get("version"),
+			Some(&String::from("0.4.7.16"))
+		);
+		assert!(replies[1].is_err());
+		assert_eq!(
+			replies[2].as_ref().unwrap().get("dormant"),
+			Some(&String::from("0"))
+		);
+
+		// The failed reply in the middle must not leave the connection out of sync.
+		let info = controller.get_info(vec!["uptime"]).unwrap();
+		assert_eq!(info.get("uptime"), Some(&String::from("42")));
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO version",
+				"GETINFO bogus",
+				"GETINFO dormant",
+				"GETINFO uptime"
+			]
+		);
+	}
+
+	#[test]
+	fn keepalive_flags_dropped_connection() {
+		let (addr, server) = mock_server(vec!["250-version=0.4.7.16\r\n250 OK\r\n"]);
+		let mut controller = TorController::connect(addr).unwrap();
+		controller.enable_keepalive(Duration::from_millis(10));
+
+		// The first probe is answered, the server hangs up and the next one fails.
+		assert_eq!(server.join().unwrap(), vec!["GETINFO version"]);
+		thread::sleep(Duration::from_millis(100));
+
+		let result = controller.get_info(vec!["version"]);
+		assert!(matches!(result, Err(Error::ConnectionLost)));
+	}
+
+	#[test]
+	fn keepalive_flags_silent_peer() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			// Reads the probe but never answers, like a peer dropped by a NAT.
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream);
+			let mut line = String::new();
+			reader.read_line(&mut line).unwrap();
+			thread::sleep(Duration::from_secs(2));
+			line
+		});
+		let mut controller = TorController::connect(addr).unwrap();
+		controller.enable_keepalive(Duration::from_millis(10));
+		thread::sleep(Duration::from_millis(100));
+
+		let started = Instant::now();
+		let result = controller.get_info(vec!["version"]);
+		assert!(matches!(result, Err(Error::ConnectionLost)));
+		assert!(started.elapsed() < Duration::from_secs(1));
+		assert_eq!(server.join().unwrap(), "GETINFO version\r\n");
+	}
+
+	#[test]
+	fn last_reply_tracks_each_command() {
+		let (addr, server) = mock_server(vec![
+			"250-version=0.4.7.16\r\n250 OK\r\n",
+			"250 OK\r\n",
+			"250-dormant=0\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		assert_eq!(controller.last_reply(), None);
+
+		controller.get_info(vec!["version"]).unwrap();
+		assert_eq!(
+			controller.last_reply(),
+			Some("250-version=0.4.7.16\r\n250 OK\r\n")
+		);
+
+		controller.authenticate(String::from("secret")).unwrap();
+		assert_eq!(controller.last_reply(), None);
+
+		controller.set_last_reply_limit(10);
+		controller.get_info(vec!["dormant"]).unwrap();
+		assert_eq!(controller.last_reply(), Some("250-dorman"));
+
+		// The server hangs up after its last reply.
+		server.join().unwrap();
+		assert!(controller.get_info(vec!["version"]).is_err());
+		assert_eq!(controller.last_reply(), None);
+	}
+
+	#[test]
+	fn subscribe_events() {
+		let (addr, server) = mock_server(vec![concat!(
+			"250 OK\r\n",
+			"650 CIRC 1 LAUNCHED\r\n",
+			"650 BW 1024 2048\r\n",
+			"650 BW garbage\r\n",
+			"650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=100 TAG=done SUMMARY=\"Done\"\r\n",
+			"650 STATUS_CLIENT NOTICE CIRCUIT_ESTABLISHED\r\n",
+		)]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let mut events = controller
+			.subscribe_events(vec![
+				EventKind::Circ,
+				EventKind::Bw,
+				EventKind::StatusClient,
+			])
+			.unwrap();
+
+		match events.next() {
+			Some(Ok(ParsedEvent::Circ(circuit))) => assert_eq!(circuit.id, CircuitId(1)),
+			other => panic!("unexpected event: {:?}", other),
+		}
+		assert!(matches!(
+			events.next(),
+			Some(Ok(ParsedEvent::Bw {
+				read:    1024,
+				written: 2048,
+			}))
+		));
+		assert!(matches!(events.next(), Some(Err(Error::InternalError))));
+		match events.next() {
+			Some(Ok(ParsedEvent::Bootstrap(phase))) => assert_eq!(phase.progress, 100),
+			other => panic!("unexpected event: {:?}", other),
+		}
+		match events.next() {
+			Some(Ok(ParsedEvent::Other(event))) => assert_eq!(event.kind, EventKind::StatusClient),
+			other => panic!("unexpected event: {:?}", other),
+		}
+
+		// The stream ends once the server hangs up.
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["SETEVENTS CIRC BW STATUS_CLIENT"]
+		);
+		assert!(matches!(events.next(), Some(Err(_))));
+		assert!(events.next().is_none());
+	}
+
+	#[test]
+	fn parse_circ_bw_event() {
+		let event = RawEvent::parse(String::from(
+			"650 CIRC_BW ID=17 READ=36864 WRITTEN=6144 TIME=2024-05-01T12:00:00.500813\r\n",
+		))
+		.unwrap();
+		assert_eq!(event.kind, EventKind::CircBw);
+		match ParsedEvent::parse(event) {
+			Ok(ParsedEvent::CircBw(event)) => {
+				assert_eq!(
+					(event.circuit_id, event.read, event.written),
+					(17, 36864, 6144)
+				)
+			}
+			other => panic!("unexpected event: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn event_stream_lets_commands_through() {
+		let (addr, server) = mock_server(vec![
+			"250 OK\r\n",
+			"250-version=0.4.8.9\r\n250 OK\r\n650 CIRC 1 LAUNCHED\r\n",
+			"",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let events = controller.subscribe_events(vec![EventKind::Circ]).unwrap();
+		let (sender, received) = std::sync::mpsc::channel();
+		let stream = thread::spawn(move || {
+			for event in events {
+				sender.send(event.map_err(|err| err.to_string())).unwrap();
+			}
+		});
+
+		// The stream is waiting for an event by now.
+		thread::sleep(Duration::from_millis(100));
+		let info = controller.get_info(vec!["version"]).unwrap();
+		assert_eq!(info.get("version").map(String::as_str), Some("0.4.8.9"));
+		match received.recv_timeout(Duration::from_secs(5)) {
+			Ok(Ok(ParsedEvent::Circ(circuit))) => assert_eq!(circuit.id, CircuitId(1)),
+			other => panic!("unexpected event: {:?}", other),
+		}
+
+		// Dropping the controller ends the stream, even while it waits.
+		drop(controller);
+		stream.join().unwrap();
+		drop(server);
+	}
+
+	#[test]
+	fn event_receiver_states() {
+		let (addr, server) = mock_server(vec![
+			"250 OK\r\n650 CIRC 1 LAUNCHED\r\n650 CIRC 1 BUILT\r\n",
+			"250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let mut receiver = controller.event_receiver();
+
+		assert_eq!(receiver.poll_event(), Ok(None));
+		assert!(receiver.is_empty());
+
+		controller.set_events(vec![EventKind::Circ]).unwrap();
+		assert_eq!(receiver.len(), 2);
+		let event = receiver.poll_event().unwrap().unwrap();
+		assert_eq!(event.raw, "650 CIRC 1 LAUNCHED\r\n");
+		let event = receiver
+			.poll_deadline(std::time::Instant::now() + Duration::from_secs(1))
+			.unwrap()
+			.unwrap();
+		assert_eq!(event.raw, "650 CIRC 1 BUILT\r\n");
+		assert_eq!(
+			receiver.poll_deadline(std::time::Instant::now() + Duration::from_millis(50)),
+			Ok(None)
+		);
+
+		// The server hangs up after its last reply.
+		controller.signal(Signal::Newnym).unwrap();
+		server.join().unwrap();
+		assert_eq!(
+			receiver.poll_deadline(std::time::Instant::now() + Duration::from_secs(1)),
+			Err(Disconnected)
+		);
+		assert_eq!(receiver.len(), 0);
+
+		// A dropped controller disconnects the receiver as well.
+		let (addr, server) = mock_server(vec![""]);
+		let controller = TorController::connect(addr).unwrap();
+		let mut receiver = controller.event_receiver();
+		drop(controller);
+		assert_eq!(receiver.poll_event(), Err(Disconnected));
+		drop(server);
+	}
+
+	#[test]
+	fn bounded_event_queue() {
+		const EVENTS: usize = 100_000;
+		let bandwidth: String = (0..EVENTS).map(|i| format!("650 BW {} 0\r\n", i)).collect();
+		// The last, empty reply keeps the connection open.
+		let (addr, server) = mock_server(vec![
+			"250 OK\r\n".to_string(),
+			bandwidth + "250 OK\r\n",
+			String::new(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let mut receiver = controller.event_receiver();
+
+		controller
+			.set_events_bounded(vec![EventKind::Bw], 16, OverflowPolicy::DropOldest)
+			.unwrap();
+		controller.signal(Signal::Newnym).unwrap();
+		assert_eq!(receiver.len(), 16);
+		assert_eq!(controller.dropped_count().unwrap(), (EVENTS - 16) as u64);
+		let event = receiver.poll_event().unwrap().unwrap();
+		assert_eq!(event.raw, format!("650 BW {} 0\r\n", EVENTS - 16));
+		drop(server);
+
+		// Blocking leaves the rest unread until there is room.
+		let (addr, server) = mock_server(vec![
+			"250 OK\r\n650 BW 0 0\r\n650 BW 1 0\r\n650 BW 2 0\r\n",
+			"",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let mut receiver = controller.event_receiver();
+		controller
+			.set_events_bounded(vec![EventKind::Bw], 2, OverflowPolicy::Block)
+			.unwrap();
+		assert_eq!(receiver.len(), 2);
+		assert_eq!(receiver.dropped_count(), Ok(0));
+		for i in 0..3 {
+			let event = receiver.poll_event().unwrap().unwrap();
+			assert_eq!(event.raw, format!("650 BW {} 0\r\n", i));
+		}
+		drop(server);
+
+		// Events read along with a reply still don't go past the limit.
+		let (addr, server) = mock_server(vec![
+			"250 OK\r\n",
+			"650 BW 0 0\r\n650 BW 1 0\r\n650 BW 2 0\r\n250 OK\r\n",
+			"",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let mut receiver = controller.event_receiver();
+		controller
+			.set_events_bounded(vec![EventKind::Bw], 2, OverflowPolicy::Block)
+			.unwrap();
+		controller.signal(Signal::Newnym).unwrap();
+		assert_eq!(receiver.len(), 2);
+		assert_eq!(receiver.dropped_count(), Ok(1));
+		for i in 0..2 {
+			let event = receiver.poll_event().unwrap().unwrap();
+			assert_eq!(event.raw, format!("650 BW {} 0\r\n", i));
+		}
+		drop(server);
+	}
+
+	#[test]
+	fn try_poll_event_does_not_block() {
+		let (addr, server) = mock_server(vec![
+			"250 OK\r\n650 CIRC 1 LAUNCHED\r\n",
+			"650 CIRC 1 EXTENDED $7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82~Quintex41\r\n250-version=0.4.7.16\r\n250 OK\r\n",
+			"",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		controller.set_events(vec![EventKind::Circ]).unwrap();
+		let event = controller.try_poll_event().unwrap().unwrap();
+		assert_eq!(event.kind, EventKind::Circ);
+		assert_eq!(event.raw, "650 CIRC 1 LAUNCHED\r\n");
+		assert_eq!(controller.try_poll_event().unwrap(), None);
+
+		// Events arriving ahead of a reply are kept for later.
+		let info = controller.get_info(vec!["version"]).unwrap();
+		assert_eq!(info.get("version"), Some(&String::from("0.4.7.16")));
+		let event = controller.try_poll_event().unwrap().unwrap();
+		assert!(event.raw.starts_with("650 CIRC 1 EXTENDED"));
+		assert_eq!(controller.try_poll_event().unwrap(), None);
+
+		drop(controller);
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["SETEVENTS CIRC", "GETINFO version"]
+		);
+	}
+
+	#[test]
+	fn scoped_onion_is_deleted_on_drop() {
+		let (addr, server) = mock_server(vec![
+			"250-ServiceID=rdwu5tfgmibbgvff\r\n250-PrivateKey=RSA1024:MIIC\r\n250 OK\r\n",
+			"250 OK\r\n",
+			"250-ServiceID=k2edzso5c4rxyay3\r\n250-PrivateKey=RSA1024:MIIC\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let handle = controller.add_onion_scoped(KeyType::RSA1024, 80).unwrap();
+		assert_eq!(handle.service_id, ServiceID::from("rdwu5tfgmibbgvff"));
+		drop(handle);
+
+		let kept = controller
+			.add_onion_scoped(KeyType::RSA1024, 80)
+			.unwrap()
+			.into_inner();
+		assert_eq!(kept.service_id, ServiceID::from("k2edzso5c4rxyay3"));
+		drop(controller);
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"ADD_ONION NEW:RSA1024 port=80",
+				"DEL_ONION rdwu5tfgmibbgvff",
+				"ADD_ONION NEW:RSA1024 port=80"
+			]
+		);
+	}
+
+	#[test]
+	fn wait_for_circuit() {
+		let exit = "9695DFC35FFEB861329B9F1AB04C46397020CE31";
+		let (addr, server) = mock_server(vec![
+			"250 OK\r\n".to_string(),
+			format!(
+				"250+circuit-status=\r\n1 BUILT $AAAA~a,${}~moria1 PURPOSE=GENERAL\r\n.\r\n250 OK\r\n",
+				exit
+			),
+			"250 OK\r\n".to_string(),
+			"250 OK\r\n".to_string(),
+			"250+circuit-status=\r\n1 BUILT $AAAA~a PURPOSE=GENERAL\r\n.\r\n250 OK\r\n\
+			650 CIRC 2 LAUNCHED PURPOSE=HS_SERVICE_REND\r\n\
+			650 CIRC 1 CLOSED $AAAA~a PURPOSE=GENERAL REASON=FINISHED\r\n\
+			650 CIRC 2 BUILT $BBBB~b,$CCCC~c PURPOSE=HS_SERVICE_REND\r\n"
+				.to_string(),
+			"250 OK\r\n".to_string(),
+			"250 OK\r\n".to_string(),
+			"250 OK\r\n".to_string(),
+			"250+circuit-status=\r\n.\r\n250 OK\r\n".to_string(),
+			"250 OK\r\n".to_string(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let timeout = Duration::from_secs(5);
+
+		let filter = CircuitFilter::new()
+			.status(CircuitStatus::Built)
+			.exit(RelayFingerprint::from_hex(exit).unwrap());
+		let circuit = controller.wait_for_circuit(filter, timeout).unwrap();
+		assert_eq!(circuit.id, CircuitId(1));
+
+		let filter = CircuitFilter::new()
+			.purpose(CircuitPurpose::HsServiceRend)
+			.status(CircuitStatus::Built);
+		let circuit = controller
+			.wait_for_circuit(filter.clone(), timeout)
+			.unwrap();
+		assert_eq!(circuit.id, CircuitId(2));
+		assert_eq!(controller.try_poll_event().unwrap(), None);
+
+		controller.set_events(vec![EventKind::Stream]).unwrap();
+		let result = controller.wait_for_circuit(filter, Duration::from_millis(50));
+		assert!(matches!(result, Err(Error::Timeout { command, .. }) if command == "CIRC"));
+
+		drop(controller);
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"SETEVENTS CIRC",
+				"GETINFO circuit-status",
+				"SETEVENTS",
+				"SETEVENTS CIRC",
+				"GETINFO circuit-status",
+				"SETEVENTS",
+				"SETEVENTS STREAM",
+				"SETEVENTS STREAM CIRC",
+				"GETINFO circuit-status",
+				"SETEVENTS STREAM",
+			]
+		);
+	}
+
+	#[test]
+	fn filtered_circuits_share_one_fetch() {
+		let circuits = "250+circuit-status=\r\n\
+			1 BUILT $AAAA~a PURPOSE=GENERAL\r\n\
+			2 LAUNCHED PURPOSE=GENERAL\r\n\
+			3 BUILT $BBBB~b PURPOSE=HS_SERVICE_INTRO\r\n\
+			.\r\n250 OK\r\n";
+		let (addr, server) = mock_server(vec![circuits, circuits]);
+		let mut controller = TorController::connect(addr).unwrap();
+		controller.set_circuit_cache_ttl(Duration::from_secs(60));
+
+		let ids = |circuits: Vec<Circuit>| circuits.iter().map(|c| c.id).collect::<Vec<_>>();
+		let general = controller
+			.get_circuits_by_purpose(CircuitPurpose::General)
+			.unwrap();
+		assert_eq!(ids(general), vec![CircuitId(1), CircuitId(2)]);
+		let built = controller
+			.get_circuits_by_status(CircuitStatus::Built)
+			.unwrap();
+		assert_eq!(ids(built), vec![CircuitId(1), CircuitId(3)]);
+		assert_eq!(controller.count_built_circuits().unwrap(), 2);
+
+		controller.set_circuit_cache_ttl(Duration::ZERO);
+		assert_eq!(controller.count_built_circuits().unwrap(), 2);
+
+		drop(controller);
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["GETINFO circuit-status", "GETINFO circuit-status"]
+		);
+	}
+
+	#[test]
+	fn wait_for_circuit_established() {
+		let (addr, server) = mock_server(vec![
+			"250-status/circuit-established=0\r\n250 OK\r\n",
+			"250-status/circuit-established=1\r\n250 OK\r\n",
+			"250-status/circuit-established=0\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let poll_interval = Duration::from_millis(10);
+
+		controller
+			.wait_for_circuit_established(Duration::from_secs(5), poll_interval)
+			.unwrap();
+
+		let result = controller.wait_for_circuit_established(Duration::ZERO, poll_interval);
+		assert!(matches!(result, Err(Error::Timeout { .. })));
+		drop(controller);
+		assert_eq!(server.join().unwrap().len(), 3);
+	}
+
+	#[test]
+	fn rotate_circuits_waits_for_replacement() {
+		let (addr, server) = mock_server(vec![
+			"250 OK\r\n",
+			"250+circuit-status=\r\n1 BUILT $AAAA~a PURPOSE=GENERAL\r\n2 BUILT $BBBB~b PURPOSE=HS_SERVICE_INTRO\r\n3 BUILT $CCCC~c PURPOSE=GENERAL\r\n.\r\n250 OK\r\n",
+			"250 OK\r\n",
+			"552 Unknown circuit \"3\"\r\n650 CIRC 1 CLOSED $AAAA~a PURPOSE=GENERAL REASON=REQUESTED\r\n650 CIRC 4 BUILT $DDDD~d PURPOSE=GENERAL\r\n",
+			"250 OK\r\n",
+			"",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let report = controller
+			.rotate_circuits(Some(Duration::from_secs(5)))
+			.unwrap();
+		assert_eq!(report.closed, vec![CircuitId(1)]);
+		assert_eq!(report.skipped, vec![CircuitId(2)]);
+		assert_eq!(report.failed, vec![CircuitId(3)]);
+		assert_eq!(report.replacements, vec![CircuitId(4)]);
+
+		// Events from the temporary subscription don't leak to the caller.
+		assert_eq!(controller.try_poll_event().unwrap(), None);
+
+		drop(controller);
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"SETEVENTS CIRC",
+				"GETINFO circuit-status",
+				"CLOSECIRCUIT 1",
+				"CLOSECIRCUIT 3",
+				"SETEVENTS"
+			]
+		);
+	}
+
+	#[test]
+	fn socks_proxy_from_listeners() {
+		let socks = TcpListener::bind("127.0.0.1:0").unwrap();
+		let socks_addr = socks.local_addr().unwrap();
+		let single = format!("250-net/listeners/socks=\"{}\"\r\n250 OK\r\n", socks_addr);
+		let multiple = format!(
+			"250-net/listeners/socks=\"unix:/run/tor/socks\" \"{}\" \"[::1]:9050\"\r\n250 OK\r\n",
+			socks_addr
+		);
+		let (addr, server) = mock_server(vec![single, multiple.clone(), multiple]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(controller.get_socks_proxy().unwrap(), Some(socks_addr));
+		assert_eq!(controller.get_socks_proxy().unwrap(), Some(socks_addr));
+		assert!(controller
+			.verify_socks_proxy(socks_addr, Duration::from_secs(1))
+			.unwrap());
+
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn auth_method_security_levels() {
+		assert!(AuthMethod::HashedPassword.security_level() < AuthMethod::Cookie.security_level());
+		assert!(AuthMethod::Cookie.security_level() < AuthMethod::SafeCookie.security_level());
+		assert!(AuthMethod::all()
+			.windows(2)
+			.all(|pair| pair[0].security_level() < pair[1].security_level()));
+
+		assert!(AuthMethod::Cookie.is_cookie_based());
+		assert!(AuthMethod::SafeCookie.is_cookie_based());
+		assert!(!AuthMethod::HashedPassword.is_cookie_based());
+		assert!(!AuthMethod::Null.is_cookie_based());
+
+		for method in AuthMethod::all() {
+			assert_eq!(&AuthMethod::from_str(&method.to_string()).unwrap(), method);
+		}
+		assert_eq!(AuthMethod::SafeCookie.display_name(), "Safe cookie");
+	}
+
+	#[test]
+	fn event_kinds_round_trip() {
+		let mut names = std::collections::HashSet::new();
+		for kind in EventKind::all() {
+			let name = kind.to_string();
+			assert_eq!(&EventKind::from_str(&name).unwrap(), kind);
+			assert!(names.insert(name));
+		}
+		assert_eq!(
+			EventKind::from_str("NEW_EVENT").unwrap(),
+			EventKind::Unknown("NEW_EVENT".to_string())
+		);
+		assert_eq!(
+			EventKind::Unknown("NEW_EVENT".to_string()).to_string(),
+			"NEW_EVENT"
+		);
+	}
+
+	#[test]
+	fn consensus_freshness() {
+		let hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+		let timestamp = hour_ago
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap()
+			.as_secs();
+		let reply = format!("250-consensus-arrival-time={}\r\n250 OK\r\n", timestamp);
+		let (addr, server) = mock_server(vec![reply.clone(), reply.clone(), reply]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_consensus_arrival_time().unwrap(),
+			SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp)
+		);
+		assert!(controller
+			.is_consensus_fresh(Duration::from_secs(2 * 60 * 60))
+			.unwrap());
+		assert!(!controller
+			.is_consensus_fresh(Duration::from_secs(30 * 60))
+			.unwrap());
+
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn accounting_stats() {
+		let stats = |hibernating: &str| {
+			format!(
+				"250-accounting/interval-end=2024-02-01 00:00:00\r\n\
+				250-accounting/bytes=1024 2048\r\n\
+				250-accounting/bytes-left=4096 8192\r\n\
+				250-accounting/hibernating={}\r\n\
+				250 OK\r\n",
+				hibernating
+			)
+		};
+		let (addr, server) = mock_server(vec![
+			"250-accounting/enabled=0\r\n250 OK\r\n".to_string(),
+			"250-accounting/enabled=1\r\n250 OK\r\n".to_string(),
+			stats("awake"),
+			"250-accounting/enabled=1\r\n250 OK\r\n".to_string(),
+			stats("soft"),
+			"250-accounting/enabled=1\r\n250 OK\r\n".to_string(),
+			stats("hard"),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(controller.get_accounting_stats().unwrap(), None);
+		assert_eq!(
+			controller.get_accounting_stats().unwrap(),
+			Some(AccountingStats {
+				interval_end:       "2024-02-01 00:00:00".to_string(),
+				bytes_read:         1024,
+				bytes_written:      2048,
+				bytes_left_read:    4096,
+				bytes_left_written: 8192,
+				hibernating:        HibernationState::None,
+			})
+		);
+		let hibernating = |stats: Option<AccountingStats>| stats.unwrap().hibernating;
+		assert_eq!(
+			hibernating(controller.get_accounting_stats().unwrap()),
+			HibernationState::SoftLimit
+		);
+		assert_eq!(
+			hibernating(controller.get_accounting_stats().unwrap()),
+			HibernationState::HardLimit
+		);
+
+		let commands = server.join().unwrap();
+		assert_eq!(commands[0], "GETINFO accounting/enabled");
+		assert_eq!(commands.len(), 7);
+	}
+
+	#[test]
+	fn hidden_service_accessors() {
+		let hidden_service = HiddenService::builder("rdwu5tfgmibbgvff")
+			.key_kind(KeyType::RSA1024)
+			.private_key("MIICXAIBAAKBgQ")
+			.build();
+
+		assert_eq!(hidden_service.service_id_str(), "rdwu5tfgmibbgvff");
+		assert_eq!(hidden_service.key_type(), &KeyType::RSA1024);
+		assert_eq!(hidden_service.private_key_str(), "MIICXAIBAAKBgQ");
+		assert_eq!(hidden_service.onion_address(), "rdwu5tfgmibbgvff.onion");
+
+		let defaults = HiddenServiceBuilder::new("rdwu5tfgmibbgvff").build();
+		assert_eq!(defaults.key_kind, KeyType::ED25519V3);
+		assert_eq!(defaults.private_key_str(), "");
+	}
+
+	#[test]
+	fn get_info_multi_retries_keys_individually() {
+		let (addr, server) = mock_server(vec![
+			"250-version=0.4.8.9\r\n250-dormant=0\r\n250 OK\r\n",
+			"552 Unrecognized key \"bogus\"\r\n",
+			"250-version=0.4.8.9\r\n250 OK\r\n",
+			"552 Unrecognized key \"bogus\"\r\n",
+			"551 Address unknown\r\n",
+			"514 Authentication required.\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let results = controller.get_info_multi(&["version", "dormant"]).unwrap();
+		assert_eq!(results["version"], Ok("0.4.8.9".to_string()));
+		assert_eq!(results["dormant"], Ok("0".to_string()));
+
+		let results = controller
+			.get_info_multi(&["version", "bogus", "address"])
+			.unwrap();
+		assert_eq!(results.len(), 3);
+		assert_eq!(results["version"], Ok("0.4.8.9".to_string()));
+		assert_eq!(
+			results["bogus"],
+			Err(InfoError::Unrecognized(
+				"Unrecognized key \"bogus\"".to_string()
+			))
+		);
+		assert_eq!(
+			results["address"],
+			Err(InfoError::Unavailable("Address unknown".to_string()))
+		);
+
+		let results = controller.get_info_multi(&["version", "dormant"]).unwrap();
+		assert!(matches!(
+			results["version"],
+			Err(InfoError::Other { code: 514, .. })
+		));
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO version dormant",
+				"GETINFO version bogus address",
+				"GETINFO version",
+				"GETINFO bogus",
+				"GETINFO address",
+				"GETINFO version dormant",
+			]
+		);
+	}
+
+	#[test]
+	fn reachability_status() {
+		let (addr, server) = mock_server(vec![
+			"250-status/reachability-succeeded=ORPORT=1 DIRPORT=1\r\n250 OK\r\n",
+			"250-status/reachability-succeeded=ORPORT=1 DIRPORT=0 EXTORPORT=1\r\n250 OK\r\n",
+			"250-status/reachability-succeeded=OR=1\r\n250 OK\r\n",
+			"551 Reachability not tested yet\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_reachability_status().unwrap(),
+			ReachabilityStatus {
+				or_port:     true,
+				dir_port:    Some(true),
+				ext_or_port: None,
+			}
+		);
+		let status = controller.get_reachability_status().unwrap();
+		assert_eq!(status.dir_port, Some(false));
+		assert!(!status.all_reachable());
+		assert!(controller.is_reachable().unwrap());
+		assert!(matches!(
+			controller.get_reachability_status(),
+			Err(Error::NotTested)
+		));
+
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn consensus_is_read_to_the_end() {
+		let (addr, server) = mock_server(vec![
+			"250+dir/status-vote/current/consensus=\r\n\
+			valid-after 2024-05-01 12:00:00\r\n\
+			fresh-until 2024-05-01 13:00:00\r\n\
+			valid-until 2024-05-01 15:00:00\r\n\
+			r moria1 lpXfw1/+uGEym58asExGOXAgzjE IpcU7dolas8+Q+oAzwgvZIWx7PA 2024-05-01 02:52:29 128.31.0.34 9101 9131\r\n\
+			s Fast Running Valid\r\n\
+			.\r\n\
+			250 OK\r\n",
+			"250+dir/status-vote/current/consensus=\r\n\
+			valid-after yesterday\r\n\
+			.\r\n\
+			250 OK\r\n",
+			"250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let consensus = controller.consensus().unwrap();
+		assert_eq!(consensus.routers.len(), 1);
+		assert_eq!(consensus.routers[0].nickname, "moria1");
+
+		assert!(matches!(controller.consensus(), Err(Error::InternalError)));
+		controller.signal(Signal::Newnym).unwrap();
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn consensus_params() {
+		let params = "250-consensus-params=cbtnummodes=20 circwindow=1000\r\n250 OK\r\n";
+		let (addr, server) = mock_server(vec![
+			params,
+			params,
+			params,
+			"250-consensus-params=circwindow=many\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let mut expected = HashMap::new();
+		expected.insert("cbtnummodes".to_string(), 20);
+		expected.insert("circwindow".to_string(), 1000);
+		assert_eq!(controller.get_consensus_params().unwrap(), expected);
+		assert_eq!(
+			controller
+				.get_consensus_param(CONSENSUS_PARAM_CIRCWINDOW)
+				.unwrap(),
+			Some(1000)
+		);
+		assert_eq!(
+			controller
+				.get_consensus_param(CONSENSUS_PARAM_BW_WEIGHT_SCALE)
+				.unwrap(),
+			None
+		);
+		assert!(controller.get_consensus_params().is_err());
+		assert_eq!(server.join().unwrap()[0], "GETINFO consensus-params");
+	}
+
+	#[test]
+	fn relay_descriptor_freshness() {
+		let fresh = "250+status/fresh-relay-descs=\r\n\
+			router moria1 128.31.0.34 9101 0 9131\r\n\
+			published 2024-05-01 02:00:00\r\n\
+			router-signature\r\n\
+			extra-info moria1 9695DFC35FFEB861329B9F1AB04C46397020CE31\r\n\
+			.\r\n250 OK\r\n";
+		let ns_all = "250+ns/all=\r\n\
+			r moria1 lpXfw1/+uGEym58asExGOXAgzjE IpcU7dolas8+Q+oAzwgvZIWx7PA 2024-05-01 02:52:29 128.31.0.34 9101 9131\r\n\
+			s Authority Fast Running\r\n\
+			r tor26 hHtfhQNE13h2SaVIkvkEk05OS4U YNfebb44SCzMCiis9iwK7GL/VbM 2024-05-01 03:11:08 86.59.21.38 443 80\r\n\
+			s Authority Running\r\n\
+			r seele AAoQ1DAR6kkoo19hBAX5K0QztNw BfLdeTv0m5Bzh5XmjMHgIPEngIk 2024-05-01 05:47:17 104.53.221.159 9001 0\r\n\
+			r relay0 AAoQ1DAR6kkoo19hBAX5K0QztNw BfLdeTv0m5Bzh5XmjMHgIPEngIk 2024-05-01 05:47:17 104.53.221.159 9001 0\r\n\
+			.\r\n250 OK\r\n";
+		let (addr, server) = mock_server(vec![
+			fresh,
+			ns_all,
+			fresh,
+			ns_all,
+			fresh,
+			"250+ns/all=\r\n.\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(controller.get_fresh_relay_descriptor_count().unwrap(), 1);
+		assert_eq!(controller.total_known_relay_count().unwrap(), 4);
+		assert_eq!(controller.relay_descriptor_freshness_ratio().unwrap(), 0.25);
+		// No relays known yet.
+		assert_eq!(controller.relay_descriptor_freshness_ratio().unwrap(), 0.0);
+		assert_eq!(
+			server.join().unwrap()[..2],
+			["GETINFO status/fresh-relay-descs", "GETINFO ns/all"]
+		);
+	}
+
+	#[test]
+	fn authority_votes() {
+		const CONSENSUS: &str = include_str!("../../fixtures/consensus");
+		let consensus: String = CONSENSUS
+			.lines()
+			.map(|line| format!("{}\r\n", line))
+			.collect();
+		let fingerprint =
+			RelayFingerprint::from_hex("F533C81CEF0BC0267857C99B2F471ADF249FA232").unwrap();
+		let (addr, server) = mock_server(vec![
+			format!(
+				"250+dir/status-vote/current/consensus=\r\n{}.\r\n250 OK\r\n",
+				consensus
+			),
+			"250+dir/status-vote/current/F533C81CEF0BC0267857C99B2F471ADF249FA232=\r\n\
+			network-status-version 3\r\n\
+			vote-status vote\r\n\
+			.\r\n250 OK\r\n"
+				.to_string(),
+			"551 Not an authority\r\n".to_string(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let authorities = controller.list_authorities().unwrap();
+		let nicknames: Vec<&str> = authorities
+			.iter()
+			.map(|authority| authority.nickname.as_str())
+			.collect();
+		assert_eq!(nicknames, ["moria1", "tor26"]);
+		assert_eq!(
+			authorities[1].address,
+			"217.196.147.77:443".parse().unwrap()
+		);
+		assert_eq!(authorities[1].dir_port, 80);
+
+		assert_eq!(
+			controller.get_authority_vote(&fingerprint, true).unwrap(),
+			"network-status-version 3\nvote-status vote"
+		);
+		assert!(controller.get_authority_vote(&fingerprint, false).is_err());
+		assert_eq!(
+			server.join().unwrap()[1..],
+			[
+				"GETINFO dir/status-vote/current/F533C81CEF0BC0267857C99B2F471ADF249FA232",
+				"GETINFO dir/status-vote/next/F533C81CEF0BC0267857C99B2F471ADF249FA232"
+			]
+		);
+	}
+
+	#[test]
+	fn stream_network_status() {
+		const CONSENSUS: &str = include_str!("../../fixtures/consensus");
+		let ns_all: String = CONSENSUS
+			.lines()
+			.skip_while(|line| !line.starts_with("r "))
+			.take_while(|line| !line.starts_with("directory-footer"))
+			.map(|line| format!("{}\r\n", line))
+			.collect();
+		let (addr, server) = mock_server(vec![
+			format!("250+ns/all=\r\n{}.\r\n250 OK\r\n", ns_all),
+			format!("250+ns/all=\r\n{}.\r\n250 OK\r\n", ns_all),
+			"250-version=0.4.8.9\r\n250 OK\r\n".to_string(),
+			"250+ns/all=\r\nr bad\r\ns Running\r\n.\r\n250 OK\r\n".to_string(),
+			"552 Unrecognized key \"ns/all\"\r\n".to_string(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let routers = controller
+			.stream_network_status()
+			.collect::<Result<Vec<RouterStatus>, Error>>()
+			.unwrap();
+		assert_eq!(routers.len(), 300);
+		assert_eq!(routers[0].nickname, "relay000");
+		assert_eq!(routers[299].nickname, "relay299");
+
+		// Stopping early still reads the rest of the reply.
+		let first = controller.stream_network_status().next().unwrap().unwrap();
+		assert_eq!(first.nickname, "relay000");
+		assert_eq!(controller.get_info_value("version").unwrap(), "0.4.8.9");
+
+		let mut stream = controller.stream_network_status();
+		assert!(stream.next().unwrap().is_err());
+		assert!(stream.next().is_none());
+		drop(stream);
+
+		let mut stream = controller.stream_network_status();
+		assert!(matches!(
+			stream.next(),
+			Some(Err(Error::Info(InfoError::Unrecognized(_))))
+		));
+		assert!(stream.next().is_none());
+		drop(stream);
+
+		let commands = server.join().unwrap();
+		assert_eq!(commands[2], "GETINFO version");
+		assert_eq!(commands.len(), 5);
+	}
+
+	#[test]
+	fn incremental_getinfo() {
+		const CONSENSUS: &str = include_str!("../../fixtures/consensus");
+		let ns_all: String = CONSENSUS
+			.lines()
+			.skip_while(|line| !line.starts_with("r "))
+			.take_while(|line| !line.starts_with("directory-footer"))
+			.map(|line| format!("{}\r\n", line))
+			.collect();
+		let (addr, server) = mock_server(vec![
+			format!("250+ns/all=\r\n{}.\r\n250 OK\r\n", ns_all),
+			"250-onions/current=rdwu5tfgmibbgvff\r\n250 OK\r\n".to_string(),
+			"250-onions/current=\r\n250 OK\r\n".to_string(),
+			"250+config-text=\r\n\
+			ControlPort 9051\r\n\
+			SocksPort 9050\r\n\
+			SocksPort 127.0.0.1:9150 IsolateDestAddr\r\n\
+			RunAsDaemon\r\n\
+			.\r\n250 OK\r\n"
+				.to_string(),
+			"552 Unrecognized key \"ns/all\"\r\n".to_string(),
+			"250 OK\r\n".to_string(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let routers = controller.get_all_router_statuses().unwrap();
+		assert_eq!(routers.len(), 300);
+		assert_eq!(routers[0].nickname, "relay000");
+		assert_eq!(routers[299].nickname, "relay299");
+
+		assert_eq!(
+			controller.get_onions_current().unwrap(),
+			vec![ServiceID::from("rdwu5tfgmibbgvff")]
+		);
+		assert!(controller.get_onions_current().unwrap().is_empty());
+
+		let config = controller.get_config_text().unwrap();
+		let option = |key: &str, value: &str| (key.to_string(), value.to_string());
+		assert_eq!(
+			config,
+			vec![
+				option("ControlPort", "9051"),
+				option("SocksPort", "9050"),
+				option("SocksPort", "127.0.0.1:9150 IsolateDestAddr"),
+				option("RunAsDaemon", ""),
+			]
+		);
+
+		match controller.get_all_router_statuses() {
+			Err(Error::Info(InfoError::Unrecognized(message))) => {
+				assert_eq!(message, "Unrecognized key \"ns/all\"")
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+		controller.signal(Signal::Newnym).unwrap();
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn authority_info() {
+		let (addr, server) = mock_server(vec![
+			"551 Not an authority\r\n",
+			"250+ns/authority=\r\n\
+			valid-after 2024-05-01 12:00:00\r\n\
+			r moria1 lpXfw1/+uGEym58asExGOXAgzjE IpcU7dolas8+Q+oAzwgvZIWx7PA 2024-05-01 02:52:29 128.31.0.34 9101 9131\r\n\
+			s Authority Fast Running Stable V2Dir Valid\r\n\
+			.\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(controller.get_authority_info().unwrap(), None);
+		assert_eq!(
+			controller.get_authority_info().unwrap(),
+			Some(AuthorityInfo {
+				nickname:    "moria1".to_string(),
+				fingerprint: "9695DFC35FFEB861329B9F1AB04C46397020CE31".to_string(),
+				addr:        "128.31.0.34:9101".to_string(),
+				published:   "2024-05-01 02:52:29".to_string(),
+				valid_after: "2024-05-01 12:00:00".to_string(),
+			})
+		);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn save_config_if_possible() {
+		let (addr, server) = mock_server(vec![
+			"250-config/can-saveconf=1\r\n250 OK\r\n",
+			"250 OK\r\n",
+			"250-config/can-saveconf=0\r\n250 OK\r\n",
+			"551 Not running with a torrc\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert!(controller.save_config_if_possible().unwrap());
+		assert!(!controller.save_config_if_possible().unwrap());
+		assert!(!controller.save_config_if_possible().unwrap());
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO config/can-saveconf",
+				"SAVECONF",
+				"GETINFO config/can-saveconf",
+				"GETINFO config/can-saveconf",
+			]
+		);
+	}
+
+	#[test]
+	fn set_config_lists() {
+		let (addr, server) = mock_server(vec!["250 OK\r\n"; 5]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		controller
+			.set_config_list(
+				"ExitPolicy",
+				vec!["accept *:80", "accept *:443", "reject *:*"],
+			)
+			.unwrap();
+		controller.set_config_list("ExitPolicy", vec![]).unwrap();
+		controller.reset_config_list("ExitPolicy").unwrap();
+		controller
+			.set_config(vec![
+				("SocksPort", "9150"),
+				("ContactInfo", "tor \"admin\""),
+			])
+			.unwrap();
+		controller.set_config(vec![("Nickname", "")]).unwrap();
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				r#"SETCONF ExitPolicy="accept *:80" ExitPolicy="accept *:443" ExitPolicy="reject *:*""#,
+				"SETCONF ExitPolicy",
+				"RESETCONF ExitPolicy",
+				r#"SETCONF SocksPort=9150 ContactInfo="tor \"admin\"""#,
+				r#"SETCONF Nickname="""#,
+			]
+		);
+	}
+
+	#[test]
+	fn hs_stats() {
+		let service = "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid";
+		let (addr, server) = mock_server(vec![
+			format!(
+				"250-hs/service/{0}/intro-circuits-established=3\r\n\
+				250-hs/service/{0}/intro-circuits-failed=1\r\n\
+				250-hs/service/{0}/rend-circuits-created=42\r\n\
+				250-hs/service/{0}/rend-circuits-successful=40\r\n\
+				250 OK\r\n",
+				service
+			),
+			format!(
+				"552 Unrecognized key \"hs/service/{}/intro-circuits-established\"\r\n",
+				service
+			),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let service_id = ServiceID::from(service);
+
+		let stats = controller.get_hs_stats(&service_id).unwrap();
+		assert_eq!(
+			stats,
+			HsServiceStats {
+				intro_circuits_established: 3,
+				intro_circuits_failed:      1,
+				rend_circuits_created:      42,
+				rend_circuits_successful:   40,
+			}
+		);
+
+		match controller.get_hs_stats(&service_id) {
+			Err(Error::UnknownService(id)) => assert_eq!(id, service),
+			other => panic!("unexpected result: {:?}", other),
+		}
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn hs_intro_circuits() {
+		let service = "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid";
+		let established = format!(
+			"250+circuit-status=\r\n\
+			7 BUILT $AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA~guard,$BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB~intro1 PURPOSE=HS_SERVICE_INTRO HS_STATE=HSSI_ESTABLISHED REND_QUERY={0}\r\n\
+			8 BUILT $AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA~guard,$CCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC PURPOSE=HS_SERVICE_INTRO HS_STATE=HSSI_ESTABLISHED REND_QUERY={0}\r\n\
+			9 BUILT $AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA~guard,$DDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDD~other PURPOSE=HS_SERVICE_INTRO HS_STATE=HSSI_ESTABLISHED REND_QUERY=otherservice\r\n\
+			10 BUILT $AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA~guard PURPOSE=GENERAL\r\n\
+			.\r\n250 OK\r\n",
+			service
+		);
+		let building = format!(
+			"250+circuit-status=\r\n\
+			11 EXTENDED $AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA~guard PURPOSE=HS_SERVICE_INTRO HS_STATE=HSSI_CONNECTING REND_QUERY={0}\r\n\
+			12 BUILT $AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA~guard,$BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB~intro1 PURPOSE=HS_SERVICE_INTRO HS_STATE=HSSI_CONNECTING REND_QUERY={0}\r\n\
+			.\r\n250 OK\r\n",
+			service
+		);
+		let (addr, server) = mock_server(vec![established, building]);
+		let mut controller = TorController::connect(addr).unwrap();
+		controller.set_circuit_cache_ttl(Duration::ZERO);
+		let service_id = ServiceID::from(service);
+
+		assert_eq!(
+			controller.get_hs_intro_circuits(&service_id).unwrap(),
+			vec![
+				HsIntroCircuitInfo {
+					circuit_id:              7,
+					intro_point_nickname:    Some("intro1".to_string()),
+					intro_point_fingerprint: Some("B".repeat(40)),
+					state:                   IntroCircuitState::Established,
+				},
+				HsIntroCircuitInfo {
+					circuit_id:              8,
+					intro_point_nickname:    None,
+					intro_point_fingerprint: Some("C".repeat(40)),
+					state:                   IntroCircuitState::Established,
+				},
+			]
+		);
+
+		let circuits = controller.get_hs_intro_circuits(&service_id).unwrap();
+		assert_eq!(
+			circuits
+				.iter()
+				.map(|circuit| (circuit.circuit_id, circuit.state))
+				.collect::<Vec<_>>(),
+			vec![
+				(11, IntroCircuitState::NoCircuit),
+				(12, IntroCircuitState::WaitingIntroRequest),
+			]
+		);
+		assert_eq!(circuits[0].intro_point_fingerprint, None);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn dns_resolver() {
+		let (addr, server) = mock_server(vec![
+			"250+net/async-dns-resolver=\r\n\
+			cache-size=12 max-cache-size=4096\r\n\
+			pending-queries=2\r\n\
+			total-resolved=1234 total-failed=17\r\n\
+			.\r\n250 OK\r\n",
+			"250-net/async-dns-resolver=cache-size=0\r\n250 OK\r\n",
+			"250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_dns_resolver_status().unwrap(),
+			DnsResolverStatus {
+				cache_size:      12,
+				max_cache_size:  4096,
+				pending_queries: 2,
+				total_resolved:  1234,
+				total_failed:    17,
+			}
+		);
+		assert!(controller.get_dns_resolver_status().is_err());
+		controller.flush_dns_cache().unwrap();
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO net/async-dns-resolver",
+				"GETINFO net/async-dns-resolver",
+				"SIGNAL CLEARDNSCACHE",
+			]
+		);
+	}
+
+	#[test]
+	fn signals_round_trip() {
+		let signals = [
+			Signal::Reload,
+			Signal::Shutdown,
+			Signal::Dump,
+			Signal::Debug,
+			Signal::Halt,
+			Signal::ClearDNSCache,
+			Signal::Newnym,
+			Signal::Heartbeat,
+			Signal::Dormant,
+			Signal::Active,
+		];
+		for signal in signals {
+			assert_eq!(Signal::from_str(&signal.to_string()).unwrap(), signal);
+		}
+		assert_eq!(Signal::from_str("HUP").unwrap(), Signal::Reload);
+		assert_eq!(Signal::from_str("TERM").unwrap(), Signal::Halt);
+		assert!(Signal::from_str("newnym").is_err());
+	}
+
+	#[test]
+	fn signal_failures() {
+		let (addr, server) = mock_server(vec!["552 Unrecognized signal\r\n", "250 OK\r\n", ""]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		match controller.signal(Signal::Dormant) {
+			Err(Error::UnrecognizedSignal(signal)) => assert_eq!(signal, Signal::Dormant),
+			other => panic!("unexpected result: {:?}", other),
+		}
+		controller.signal_and_confirm(Signal::Newnym).unwrap();
+		// Tor exits before answering.
+		controller.signal_and_confirm(Signal::Halt).unwrap();
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["SIGNAL DORMANT", "SIGNAL NEWNYM", "SIGNAL HALT"]
+		);
+
+		let (addr, server) = mock_server(vec![""]);
+		let mut controller = TorController::connect(addr).unwrap();
+		assert!(matches!(
+			controller.signal(Signal::Shutdown),
+			Err(Error::Io(_))
+		));
+		server.join().unwrap();
+
+		let (addr, server) = mock_server(vec![""]);
+		let mut controller = TorController::connect(addr).unwrap();
+		assert!(matches!(
+			controller.signal_and_confirm(Signal::Newnym),
+			Err(Error::Io(_))
+		));
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn hs_client_auth_info() {
+		let service = "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid";
+		let key = format!("hs/client/auth-info/{}", service);
+		let (addr, server) = mock_server(vec![
+			format!("552 Unrecognized key \"{}\"\r\n", key),
+			format!("250-{}=TYPE=x25519 HAS_KEY=1\r\n250 OK\r\n", key),
+			format!(
+				"250-{}=TYPE=x25519 FLAGS=Permanent HAS_KEY=1\r\n250 OK\r\n",
+				key
+			),
+			format!(
+				"250-{}=TYPE=x25519 FLAGS=Permanent HAS_KEY=0\r\n250 OK\r\n",
+				key
+			),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let service_id = ServiceID::from(service);
+		let info = |key_type: &str, is_permanent, has_key| {
+			Some(HsClientAuthInfo {
+				key_type: key_type.to_string(),
+				is_permanent,
+				has_key,
+			})
+		};
+
+		assert_eq!(
+			controller.get_hs_client_auth_info(&service_id).unwrap(),
+			None
+		);
+		assert_eq!(
+			controller.get_hs_client_auth_info(&service_id).unwrap(),
+			info("x25519", false, true)
+		);
+		assert_eq!(
+			controller.get_hs_client_auth_info(&service_id).unwrap(),
+			info("x25519", true, true)
+		);
+		assert_eq!(
+			controller.get_hs_client_auth_info(&service_id).unwrap(),
+			info("x25519", true, false)
+		);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn guard_status() {
+		let guard = |n: usize| {
+			format!(
+				"in=default rsa_id={:040X} nickname=guard{:02} sampled_on=2024-04-{:02}T08:34:12 listed=1 \
+				filtered=1 usable=1 running=1\r\n",
+				n,
+				n,
+				n + 1
+			)
+		};
+		let mut reply = String::from("250+status/guards=\r\n");
+		for (section, count) in [
+			("sampled", 20),
+			("filtered", 15),
+			("usable-filtered", 12),
+			("confirmed", 5),
+			("primary", 3),
+			("future-section", 0),
+		] {
+			reply.push_str(section);
+			reply.push_str("\r\n");
+			for n in 0..count {
+				reply.push_str(&guard(n));
+			}
+		}
+		reply.push_str(".\r\n250 OK\r\n");
+		let (addr, server) = mock_server(vec![reply]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let status = controller.get_guard_status().unwrap();
+		assert_eq!(
+			(
+				status.sampled_count,
+				status.filtered_count,
+				status.usable_count,
+				status.confirmed_count,
+				status.primary_count
+			),
+			(20, 15, 12, 5, 3)
+		);
+		assert_eq!(
+			status.sampled[19].guard.nickname.as_deref(),
+			Some("guard19")
+		);
+		let primary: Vec<&str> = status
+			.primary
+			.iter()
+			.map(|guard| guard.guard.fingerprint.as_str())
+			.collect();
+		assert_eq!(
+			primary,
+			[
+				"0000000000000000000000000000000000000000",
+				"0000000000000000000000000000000000000001",
+				"0000000000000000000000000000000000000002"
+			]
+		);
+		assert!(status
+			.primary
+			.iter()
+			.all(|guard| status.sampled.contains(guard)));
+		assert_eq!(server.join().unwrap(), ["GETINFO status/guards"]);
+	}
+
+	#[test]
+	fn server_publish_status() {
+		let (addr, server) = mock_server(vec![
+			"250+dir/server-status=\r\n\
+			$9695DFC35FFEB861329B9F1AB04C46397020CE31 ACCEPTED=1 LAST_TRIED=\"2024-05-01 11:20:00\" LAST_UPLOADED=\"2024-05-01 11:20:01\"\r\n\
+			$847B1F850344D7876491A54892F904934E4EB85D ACCEPTED=1 LAST_TRIED=\"2024-05-01 11:20:00\" LAST_UPLOADED=\"2024-05-01 11:20:03\"\r\n\
+			$7EA6EAD6FD83083C538F44038BBFA077587DD755 ACCEPTED=0 LAST_TRIED=\"2024-05-01 11:20:00\"\r\n\
+			$F2044413DAC2E02E3D6BCF4735A19BCA1DE97281 ACCEPTED=0 LAST_TRIED=\"2024-05-01 11:20:00\" LAST_UPLOADED=\"2024-05-01 10:02:11\"\r\n\
+			$7BE683E65D48141321C5ED92F075C55364AC7123 ACCEPTED=1 LAST_TRIED=\"2024-05-01 11:19:58\" LAST_UPLOADED=\"2024-05-01 11:19:59\"\r\n\
+			$BD6A829255CB08E66FBE7D3748363586E46B3810 ACCEPTED=0\r\n\
+			$74A910646BCEEFBCD2E874FC1DC997430F968145 ACCEPTED=1 LAST_TRIED=\"2024-05-01 11:20:00\" LAST_UPLOADED=\"2024-05-01 11:20:02\"\r\n\
+			$24E2F139121D4394C54B5BCC368B3B411857C413\r\n\
+			cf6d0aafb385be71b8e111fc5cff4b47923733bc ACCEPTED=1 LAST_UPLOADED=\"2024-05-01 09:00:00\"\r\n\
+			.\r\n250 OK\r\n",
+			"551 Not running in server mode\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let status = controller
+			.get_server_publish_status_detailed()
+			.unwrap()
+			.descriptors_published_to;
+		assert_eq!(status.len(), 9);
+		assert_eq!(status.values().filter(|status| status.accepted).count(), 5);
+		assert_eq!(
+			status["9695DFC35FFEB861329B9F1AB04C46397020CE31"],
+			PublishStatus {
+				accepted:      true,
+				last_tried:    Some("2024-05-01 11:20:00".to_string()),
+				last_uploaded: Some("2024-05-01 11:20:01".to_string()),
+			}
+		);
+		assert_eq!(
+			status["7EA6EAD6FD83083C538F44038BBFA077587DD755"].last_uploaded,
+			None
+		);
+		assert_eq!(
+			status["F2044413DAC2E02E3D6BCF4735A19BCA1DE97281"]
+				.last_uploaded
+				.as_deref(),
+			Some("2024-05-01 10:02:11")
+		);
+		assert_eq!(
+			status["24E2F139121D4394C54B5BCC368B3B411857C413"],
+			PublishStatus::default()
+		);
+		assert!(status["CF6D0AAFB385BE71B8E111FC5CFF4B47923733BC"].accepted);
+
+		assert!(matches!(
+			controller.get_server_publish_status_detailed(),
+			Err(Error::NotARelay)
+		));
+		assert_eq!(server.join().unwrap()[0], "GETINFO dir/server-status");
+	}
+
+	#[test]
+	fn relay_info() {
+		const FINGERPRINT: &str = "9695DFC35FFEB861329B9F1AB04C46397020CE31";
+		let (addr, server) = mock_server(vec![
+			"551 Not running in server mode\r\n".to_string(),
+			format!(
+				"250-fingerprint={}\r\n\
+				250-status/version/current=recommended\r\n\
+				250-net/listeners/or=\"0.0.0.0:9101\" \"[::]:9101\"\r\n\
+				250 OK\r\n",
+				FINGERPRINT
+			),
+			format!(
+				"250+ns/id/{}=\r\n\
+				r moria1 lpXfw1/+uGEym58asExGOXAgzjE IpcU7dolas8+Q+oAzwgvZIWx7PA 2024-05-01 02:52:29 128.31.0.34 9101 9131\r\n\
+				s Fast Guard Running Stable Valid\r\n\
+				w Bandwidth=20\r\n\
+				.\r\n250 OK\r\n",
+				FINGERPRINT
+			),
+			format!(
+				"250+desc/id/{}=\r\n\
+				router moria1 128.31.0.34 9101 0 9131\r\n\
+				published 2024-05-01 02:52:29\r\n\
+				bandwidth 512000 62914560 752023\r\n\
+				reject *:*\r\n\
+				.\r\n250 OK\r\n",
+				FINGERPRINT
+			),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert!(matches!(controller.relay_info(), Err(Error::NotARelay)));
+
+		let info = controller.relay_info().unwrap();
+		assert_eq!(info.fingerprint.as_ref(), FINGERPRINT);
+		assert_eq!(
+			info.flags,
+			vec!["Fast", "Guard", "Running", "Stable", "Valid"]
+		);
+		assert_eq!(info.advertised_bandwidth, Some(512000));
+		assert_eq!(info.version_status, "recommended");
+		assert_eq!(info.or_listeners, vec!["0.0.0.0:9101", "[::]:9101"]);
+		assert!(info.descriptor_up_to_date);
+
+		assert_eq!(
+			server.join().unwrap()[1..],
+			[
+				"GETINFO fingerprint status/version/current net/listeners/or".to_string(),
+				format!("GETINFO ns/id/{}", FINGERPRINT),
+				format!("GETINFO desc/id/{}", FINGERPRINT),
+			]
+		);
+	}
+
+	#[test]
+	fn raw_getinfo() {
+		let (addr, server) = mock_server(vec![
+			"250-traffic/read=1048576\r\n250 OK\r\n",
+			"250+debug/scheduler/state=\r\nscheduler KIST\r\nchannels pending 0\r\n.\r\n250 OK\r\n",
+			"250-debug/circuit_list=\r\n250 OK\r\n",
+			"250-debug/dir/state=fetching consensus\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(controller.raw_getinfo("traffic/read").unwrap(), "1048576");
+		assert_eq!(
+			controller.get_scheduler_info().unwrap(),
+			"scheduler KIST\nchannels pending 0"
+		);
+		assert_eq!(controller.get_circuit_stats().unwrap(), "");
+		assert_eq!(controller.get_dir_info().unwrap(), "fetching consensus");
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO traffic/read",
+				"GETINFO debug/scheduler/state",
+				"GETINFO debug/circuit_list",
+				"GETINFO debug/dir/state",
+			]
+		);
+	}
+
+	#[test]
+	fn hs_descriptors() {
+		let service = "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid";
+		let (addr, server) = mock_server(vec![
+			format!(
+				"250+hs/client/desc/id/{}=\r\n\
+				hs-descriptor 3\r\n\
+				descriptor-lifetime 180\r\n\
+				revision-counter 42\r\n\
+				.\r\n250 OK\r\n",
+				service
+			),
+			"551 Not found\r\n".to_string(),
+			format!(
+				"552 Unrecognized key \"hs/service/desc/id/{}\"\r\n",
+				service
+			),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let service_id = ServiceID::from(service);
+
+		assert_eq!(
+			controller.client_hs_descriptor(&service_id).unwrap(),
+			"hs-descriptor 3\ndescriptor-lifetime 180\nrevision-counter 42"
+		);
+		match controller.client_hs_descriptor(&service_id) {
+			Err(Error::DescriptorNotCached(addr)) => assert_eq!(addr, service),
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert!(matches!(
+			controller.service_hs_descriptor(&service_id),
+			Err(Error::DescriptorNotCached(_))
+		));
+		assert!(matches!(
+			controller.service_hs_descriptor(&ServiceID::from("not an onion")),
+			Err(Error::InvalidAddress(_))
+		));
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				format!("GETINFO hs/client/desc/id/{}", service),
+				format!("GETINFO hs/client/desc/id/{}", service),
+				format!("GETINFO hs/service/desc/id/{}", service),
+			]
+		);
+	}
+
+	#[test]
+	fn all_hs_descriptor_status() {
+		let uploaded = "rdwu5tfgmibbgvff";
+		let pending = "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid";
+		let mut descriptor = format!("250+hs/service/desc/id/{}=\r\n", uploaded);
+		for line in include_str!("../../fixtures/hs_descriptor_v2").lines() {
+			descriptor.push_str(line);
+			descriptor.push_str("\r\n");
+		}
+		descriptor.push_str(".\r\n250 OK\r\n");
+		let need_upload = |service: &str, value: &str| {
+			format!(
+				"250-hs/service-desc/need-upload/{}={}\r\n250 OK\r\n",
+				service, value
+			)
+		};
+		let (addr, server) = mock_server(vec![
+			format!(
+				"250+onions/current=\r\n{}\r\n{}\r\n.\r\n250 OK\r\n",
+				uploaded, pending
+			),
+			"551 No descriptor yet\r\n".to_string(),
+			descriptor,
+			need_upload(uploaded, "0"),
+			"551 No descriptor yet\r\n".to_string(),
+			need_upload(pending, "1"),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let statuses = controller.get_all_hs_descriptor_status().unwrap();
+		assert_eq!(
+			statuses,
+			vec![
+				HsDescriptorStatus {
+					service_id:  ServiceID::from(uploaded),
+					is_uploaded: true,
+				},
+				HsDescriptorStatus {
+					service_id:  ServiceID::from(pending),
+					is_uploaded: false,
+				},
+			]
+		);
+		assert_eq!(
+			server.join().unwrap()[1],
+			format!(
+				"GETINFO hs/service/desc/id/{0} hs/service-desc/need-upload/{0} \
+				hs/service/desc/id/{1} hs/service-desc/need-upload/{1}",
+				uploaded, pending
+			)
+		);
+	}
+
+	#[test]
+	fn upload_hs_descriptor() {
+		let service = "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid";
+		let descriptor = include_str!("../../fixtures/hs_descriptor_v3");
+		let mut reply = format!("250+hs/service/desc/id/{}=\r\n", service);
+		for line in descriptor.lines() {
+			reply.push_str(line);
+			reply.push_str("\r\n");
+		}
+		reply.push_str(".\r\n250 OK\r\n");
+		let (addr, server) = mock_server(vec![
+			reply,
+			"250 OK\r\n".to_string(),
+			"551 No such key\r\n".to_string(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let service_id = ServiceID::from(service);
+
+		controller.upload_hs_descriptor(&service_id).unwrap();
+		assert!(matches!(
+			controller.upload_hs_descriptor(&service_id),
+			Err(Error::NoDescriptor(_))
+		));
+
+		let commands = server.join().unwrap();
+		assert_eq!(
+			commands[1],
+			format!(
+				"+HSPOST HSADDRESS={}\n{}\n.",
+				service,
+				descriptor.trim_end()
+			)
+		);
+		assert_eq!(commands.len(), 3);
+	}
+
+	#[test]
+	fn hs_descriptor_freshness() {
+		let service = "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid";
+		let need_upload = |value: &str| {
+			format!(
+				"250-hs/service-desc/need-upload/{}={}\r\n250 OK\r\n",
+				service, value
+			)
+		};
+		let descriptor = |text: &str| {
+			let mut reply = format!("250+hs/service/desc/id/{}=\r\n", service);
+			for line in text.lines() {
+				reply.push_str(line);
+				reply.push_str("\r\n");
+			}
+			reply.push_str(".\r\n250 OK\r\n");
+			reply
+		};
+		let (addr, server) = mock_server(vec![
+			need_upload("1"),
+			need_upload("0"),
+			"552 Unrecognized key\r\n".to_string(),
+			descriptor(include_str!("../../fixtures/hs_descriptor_v2")),
+			descriptor(include_str!("../../fixtures/hs_descriptor_v3")),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let service_id = ServiceID::from(service);
+
+		assert!(controller.needs_hs_descriptor_upload(&service_id).unwrap());
+		assert!(!controller.needs_hs_descriptor_upload(&service_id).unwrap());
+		assert!(matches!(
+			controller.needs_hs_descriptor_upload(&service_id),
+			Err(Error::UnknownService(_))
+		));
+
+		// publication-time 2024-05-01 02:00:00
+		let published = SystemTime::UNIX_EPOCH + Duration::from_secs(1714528800);
+		let expected = SystemTime::now().duration_since(published).unwrap();
+		let age = controller.hs_descriptor_age(&service_id).unwrap().unwrap();
+		assert!(age >= expected && age - expected < Duration::from_secs(5));
+		assert_eq!(controller.hs_descriptor_age(&service_id).unwrap(), None);
+
+		assert_eq!(
+			server.join().unwrap()[0],
+			format!("GETINFO hs/service-desc/need-upload/{}", service)
+		);
+	}
+
+	#[test]
+	fn client_hs_descriptor_cache() {
+		let cached = "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid";
+		let missing = "rdwu5tfgmibbgvff";
+		let mut descriptor = format!("250+hs/client/desc/id/{}=\r\n", cached);
+		for line in include_str!("../../fixtures/hs_descriptor_v2").lines() {
+			descriptor.push_str(line);
+			descriptor.push_str("\r\n");
+		}
+		descriptor.push_str(".\r\n250 OK\r\n");
+		let (addr, server) = mock_server(vec![
+			descriptor.clone(),
+			"551 Not found\r\n".to_string(),
+			"250 OK\r\n".to_string(),
+			// Both at once fails, so each is asked for on its own.
+			"551 Not found\r\n".to_string(),
+			descriptor,
+			"551 Not found\r\n".to_string(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		// Nothing has been looked up yet, so nothing is asked for.
+		assert_eq!(controller.get_all_cached_hs_descriptors().unwrap(), vec![]);
+
+		let published = SystemTime::UNIX_EPOCH + Duration::from_secs(1714528800);
+		let expected = SystemTime::now().duration_since(published).unwrap();
+		let age = controller
+			.get_hs_descriptor_age(&ServiceID::from(cached))
+			.unwrap()
+			.unwrap();
+		assert!(age >= expected && age - expected < Duration::from_secs(5));
+		assert_eq!(
+			controller
+				.get_hs_descriptor_age(&ServiceID::from(missing))
+				.unwrap(),
+			None
+		);
+		controller
+			.invalidate_hs_descriptor_cache(&ServiceID::from(missing))
+			.unwrap();
+
+		assert_eq!(
+			controller.get_all_cached_hs_descriptors().unwrap(),
+			vec![ServiceID::from(cached)]
+		);
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				format!("GETINFO hs/client/desc/id/{}", cached),
+				format!("GETINFO hs/client/desc/id/{}", missing),
+				format!("HSFETCH {}", missing),
+				format!(
+					"GETINFO hs/client/desc/id/{} hs/client/desc/id/{}",
+					cached, missing
+				),
+				format!("GETINFO hs/client/desc/id/{}", cached),
+				format!("GETINFO hs/client/desc/id/{}", missing),
+			]
+		);
+	}
+
+	#[test]
+	fn cell_event_interval() {
+		let (addr, server) = mock_server(vec![
+			"250-cell-event-interval=10\r\n250 OK\r\n",
+			"250 OK\r\n",
+			"250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_cell_event_interval().unwrap(),
+			Duration::from_secs(10)
+		);
+		controller.set_cell_statistics(true).unwrap();
+		controller.set_cell_statistics(false).unwrap();
+
+		let commands = server.join().unwrap();
+		assert_eq!(
+			commands[1..],
+			["SETCONF CellStatistics=1", "SETCONF CellStatistics=0"]
+		);
+	}
+
+	#[test]
+	fn tor_version_keys() {
+		let (addr, server) = mock_server(vec![
+			"250-tor/version=0.4.8.9\r\n250 OK\r\n",
+			"552 Unrecognized key \"tor/version\"\r\n",
+			"250-version=0.4.7.16 (git-abc123def)\r\n250 OK\r\n",
+			"250-tor/git-commit=7bcaf9b3b1e65f43\r\n250 OK\r\n",
+			"250-tor/git-commit=\r\n250 OK\r\n",
+			"552 Unrecognized key \"tor/git-commit\"\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(controller.get_tor_version().unwrap().to_string(), "0.4.8.9");
+		let version = controller.get_tor_version().unwrap();
+		assert_eq!(version.to_string(), "0.4.7.16");
+		assert_eq!(version.tag.as_deref(), Some("git-abc123def"));
+
+		assert_eq!(
+			controller.get_tor_version_hash().unwrap().as_deref(),
+			Some("7bcaf9b3b1e65f43")
+		);
+		assert_eq!(controller.get_tor_version_hash().unwrap(), None);
+		assert_eq!(controller.get_tor_version_hash().unwrap(), None);
+
+		let commands = server.join().unwrap();
+		assert_eq!(commands[1..3], ["GETINFO tor/version", "GETINFO version"]);
+	}
+
+	#[test]
+	fn tor_versions() {
+		let (addr, server) = mock_server(vec![
+			"552 Unrecognized key \"tor/version\"\r\n",
+			"250-version=0.4.7.16 (git-abc123def)\r\n250 OK\r\n",
+			"250-status/version/recommended=0.4.7.16,0.4.8.9,0.4.9.1-alpha\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let version = controller.get_version().unwrap();
+		assert_eq!(version.to_string(), "0.4.7.16");
+		assert_eq!(version.tag.as_deref(), Some("git-abc123def"));
+
+		let recommended = controller.get_recommended_versions().unwrap();
+		let recommended: Vec<String> = recommended.iter().map(TorVersion::to_string).collect();
+		assert_eq!(recommended, vec!["0.4.7.16", "0.4.8.9", "0.4.9.1-alpha"]);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn version_status() {
+		let reply = |status: &str| {
+			format!(
+				"250-version=0.4.8.4\r\n\
+				250-status/version/current={}\r\n\
+				250-status/version/recommended=0.4.7.16,0.4.8.7,0.4.8.9,0.4.9.1-alpha\r\n\
+				250 OK\r\n",
+				status
+			)
+		};
+		let (addr, server) = mock_server(vec![
+			reply("recommended"),
+			reply("obsolete"),
+			reply("unrecommended"),
+			reply("new"),
+			"250-version=0.4.6.10\r\n\
+			250-status/version/current=obsolete\r\n\
+			250-status/version/recommended=0.4.7.16,0.4.8.9\r\n\
+			250 OK\r\n"
+				.to_string(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_version_status().unwrap(),
+			VersionStatus::Recommended
+		);
+		let (running, status) = controller.get_version_full_status().unwrap();
+		assert_eq!(running.to_string(), "0.4.8.4");
+		match status {
+			VersionStatus::NewVersionAvailable(version) => assert_eq!(version.to_string(), "0.4.8.9"),
+			other => panic!("unexpected status: {:?}", other),
+		}
+		assert_eq!(
+			controller.get_version_status().unwrap(),
+			VersionStatus::UnrecommendedVersion
+		);
+		assert_eq!(
+			controller.get_version_status().unwrap(),
+			VersionStatus::Unknown("new".to_string())
+		);
+		assert_eq!(
+			controller.get_version_status().unwrap(),
+			VersionStatus::ObsoleteVersion
+		);
+
+		assert_eq!(
+			server.join().unwrap()[0],
+			"GETINFO version status/version/current status/version/recommended"
+		);
+	}
+
+	#[test]
+	fn connection_usage_history() {
+		let (addr, server) = mock_server(vec![
+			"250+usage-tracking/cell-history/7=\r\n\
+			1714528800 120 80\r\n\
+			1714528860 0 3\r\n\
+			1714528920 4096 2048\r\n\
+			.\r\n250 OK\r\n",
+			"250+usage-tracking/bw-history/7=\r\n\
+			1714528800 61440 40960\r\n\
+			.\r\n250 OK\r\n",
+			"250-usage-tracking/cell-history/8=1714528800 120\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_connection_cell_history(7).unwrap(),
+			CellHistory {
+				cells_read:    vec![120, 0, 4096],
+				cells_written: vec![80, 3, 2048],
+				timestamp:     vec![1714528800, 1714528860, 1714528920],
+			}
+		);
+		assert_eq!(
+			controller.get_per_conn_bw(7).unwrap(),
+			vec![BwSample {
+				timestamp:     1714528800,
+				bytes_read:    61440,
+				bytes_written: 40960,
+			}]
+		);
+		assert!(controller.get_connection_cell_history(8).is_err());
+		assert_eq!(
+			server.join().unwrap()[..2],
+			[
+				"GETINFO usage-tracking/cell-history/7",
+				"GETINFO usage-tracking/bw-history/7"
+			]
+		);
+	}
+
+	#[test]
+	fn relay_metrics() {
+		let (addr, server) = mock_server(vec![
+			"551 Accounting not enabled\r\n",
+			"250-uptime=86400\r\n250 OK\r\n",
+			"250-traffic/read=1048576\r\n250 OK\r\n",
+			"250-traffic/written=524288\r\n250 OK\r\n",
+			"250-bw-limit-event-count=3\r\n250 OK\r\n",
+			"250-accounting/bytes=1024 2048\r\n250 OK\r\n",
+			"551 Accounting not enabled\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let mut metrics = controller.get_relay_metrics().unwrap();
+		assert_eq!(metrics.len(), 6);
+		assert_eq!(metrics["traffic_read"], 1048576.0);
+		assert_eq!(metrics["accounting_bytes_written"], 2048.0);
+		metrics.retain(|name, _| !name.starts_with("accounting"));
+		assert_eq!(metrics["bw_limit_event_count"], 3.0);
+		assert_eq!(
+			format_prometheus_metrics(&metrics),
+			"tor_bw_limit_event_count 3\n\
+			tor_traffic_read 1048576\n\
+			tor_traffic_written 524288\n\
+			tor_uptime 86400\n"
+		);
+		assert_eq!(
+			server.join().unwrap()[0],
+			"GETINFO uptime traffic/read traffic/written bw-limit-event-count accounting/bytes \
+			accounting/bytes-left"
+		);
+	}
+
+	#[test]
+	fn orconn_status() {
+		let reply = "250+orconn-status=\r\n\
+			$9695DFC35FFEB861329B9F1AB04C46397020CE31~moria1 CONNECTED ID=17 NCIRCS=4\r\n\
+			$847B1F850344D7876491A54892F904934E4EB85D~tor26 CONNECTED\r\n\
+			128.31.0.34:9101 FAILED\r\n\
+			.\r\n250 OK\r\n";
+		let (addr, server) = mock_server(vec![reply, reply]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let conns = controller.get_orconn_status().unwrap();
+		assert_eq!(conns.len(), 3);
+		assert_eq!(
+			conns[0],
+			OrConnInfo {
+				target:     "$9695DFC35FFEB861329B9F1AB04C46397020CE31~moria1".to_string(),
+				status:     OrConnStatus::Connected,
+				id:         Some(17),
+				n_circuits: Some(4),
+			}
+		);
+		assert_eq!(conns[1].n_circuits, None);
+		assert_eq!(conns[2].target, "128.31.0.34:9101");
+		assert_eq!(conns[2].status, OrConnStatus::Failed);
+
+		assert_eq!(
+			controller.orconn_stats().unwrap(),
+			OrConnStats {
+				total_connections: 3,
+				total_circuits:    4,
+				connected:         2,
+				failed:            1,
+			}
+		);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn throttling_info() {
+		let (addr, server) = mock_server(vec![
+			"250-bw-limit-event-count=17\r\n250 OK\r\n",
+			"250-max-mem-in-queues=429496729600 bytes\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(controller.get_bw_limit_event_count().unwrap(), 17);
+		assert_eq!(controller.get_max_memory_queues().unwrap(), 429496729600);
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["GETINFO bw-limit-event-count", "GETINFO max-mem-in-queues"]
+		);
+	}
+
+	#[test]
+	fn add_onion_with_key_keeps_the_callers_key() {
+		let (addr, server) = mock_server(vec![
+			"250-ServiceID=2gzyxa5ihm2nl6ag3eikq6lobtktkvzxfy2emprx3c6st4evgmpzmyad\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let hidden_service = controller
+			.add_onion_with_key((KeyType::ED25519V3, "kBN5Ro9Q2Ywi7NYx".to_string()), 80)
+			.unwrap();
+		assert_eq!(
+			hidden_service.service_id,
+			ServiceID::from("2gzyxa5ihm2nl6ag3eikq6lobtktkvzxfy2emprx3c6st4evgmpzmyad")
+		);
+		assert_eq!(hidden_service.key_kind, KeyType::ED25519V3);
+		assert_eq!(hidden_service.private_key, "kBN5Ro9Q2Ywi7NYx");
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["ADD_ONION ED25519-V3:kBN5Ro9Q2Ywi7NYx port=80"]
+		);
+	}
+
+	#[test]
+	fn address_mappings() {
+		let (addr, server) = mock_server(vec![
+			"250+address-mappings/all=\r\n\
+			www.example.com 127.192.10.10 \"2024-05-01 12:00:00\"\r\n\
+			torproject.org 2gzyxa5ihm2nl6ag3eikq6lobtktkvzxfy2emprx3c6st4evgmpzmyad.onion NEVER\r\n\
+			10.40.40.40 example.onion \"NEVER\"\r\n\
+			.\r\n250 OK\r\n",
+			"250-address-mappings/control=\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let mappings = controller.address_mappings(MappingScope::All).unwrap();
+		assert_eq!(mappings.len(), 3);
+		assert_eq!(mappings[0].from, "www.example.com");
+		assert_eq!(mappings[0].to, "127.192.10.10");
+		assert_eq!(
+			mappings[0].expiry,
+			Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1714564800))
+		);
+		assert_eq!(mappings[1].expiry, None);
+		assert_eq!(mappings[2].to, "example.onion");
+		assert_eq!(mappings[2].expiry, None);
+
+		assert!(controller
+			.address_mappings(MappingScope::Control)
+			.unwrap()
+			.is_empty());
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO address-mappings/all",
+				"GETINFO address-mappings/control"
+			]
+		);
+	}
+
+	#[test]
+	fn ext_or_and_transport_listeners() {
+		let (addr, server) = mock_server(vec![
+			"250-net/listeners/ext-or=\"127.0.0.1:42001\" \"[::1]:42001\"\r\n250 OK\r\n",
+			"250-net/listeners/trans/obfs4=\"0.0.0.0:443\" \"[::]:443\"\r\n250 OK\r\n",
+			"250-net/listeners/or=\"0.0.0.0:9001\" \"unix:/run/tor/or\"\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_ext_or_listeners().unwrap(),
+			vec![
+				"127.0.0.1:42001".parse::<SocketAddr>().unwrap(),
+				"[::1]:42001".parse().unwrap()
+			]
+		);
+		assert_eq!(
+			controller.get_transport_listeners("obfs4").unwrap(),
+			vec![
+				"0.0.0.0:443".parse::<SocketAddr>().unwrap(),
+				"[::]:443".parse().unwrap()
+			]
+		);
+		assert_eq!(
+			controller.get_listeners(ListenerType::Or).unwrap(),
+			vec![
+				Listener::Tcp("0.0.0.0:9001".parse().unwrap()),
+				Listener::Unix("/run/tor/or".into())
+			]
+		);
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO net/listeners/ext-or",
+				"GETINFO net/listeners/trans/obfs4",
+				"GETINFO net/listeners/or"
+			]
+		);
+	}
+
+	#[test]
+	fn socks_addr_prefers_loopback() {
+		let (addr, server) = mock_server(vec![
+			"250-net/listeners/socks=\"unix:/run/tor/socks\" \"10.0.0.1:9050\" \"127.0.0.1:9150\"\r\n250 OK\r\n",
+			"250-net/listeners/socks=\"unix:/run/tor/socks\" \"10.0.0.1:9050\"\r\n250 OK\r\n",
+			"250-net/listeners/socks=\"unix:/run/tor/socks\"\r\n250 OK\r\n",
+			"250-net/listeners/socks=\r\n250 OK\r\n",
+			"250-net/listeners/socks=\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let loopback: SocketAddr = "127.0.0.1:9150".parse().unwrap();
+		let external: SocketAddr = "10.0.0.1:9050".parse().unwrap();
+		assert_eq!(controller.socks_addr().unwrap(), Some(loopback));
+		assert_eq!(controller.socks_addr().unwrap(), Some(external));
+		assert_eq!(controller.socks_addr().unwrap(), None);
+		assert_eq!(controller.socks_addr().unwrap(), None);
+		assert_eq!(controller.socks_listeners().unwrap(), vec![]);
+
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn listeners_round_trip() {
+		for listener in ["127.0.0.1:9050", "[::1]:9050", "unix:/run/tor/socks"] {
+			assert_eq!(Listener::from_str(listener).unwrap().to_string(), listener);
+		}
+		assert_eq!(
+			Listener::from_str("unix:/run/tor/socks").unwrap(),
+			Listener::Unix(std::path::PathBuf::from("/run/tor/socks"))
+		);
+		assert!(Listener::from_str("localhost").is_err());
+	}
+
+	#[test]
+	fn exit_countries_are_looked_up_once_per_relay() {
+		const EXIT: &str = "$8B5A8D8A5C5DD4DE5A84B6F8F17E1E3C6CF9A7A5~exit";
+		let circuits = format!(
+			"250+circuit-status=\r\n\
+			1 BUILT $A1B2C3D4E5F60718293A4B5C6D7E8F9012345678~guard,{exit} PURPOSE=GENERAL\r\n\
+			2 BUILT $A1B2C3D4E5F60718293A4B5C6D7E8F9012345678~guard,{exit} PURPOSE=GENERAL\r\n\
+			3 BUILT $A1B2C3D4E5F60718293A4B5C6D7E8F9012345678~guard PURPOSE=HS_VANGUARDS\r\n\
+			.\r\n250 OK\r\n",
+			exit = EXIT
+		);
+		let (addr, server) = mock_server(vec![
+			circuits,
+			"250+ns/id/8B5A8D8A5C5DD4DE5A84B6F8F17E1E3C6CF9A7A5=\r\n\
+			r exit i1qNilxd1N5ahLb48X4ePGz5p6U IpcU7dolas8+Q+oAzwgvZIWx7PA 2018-05-23 02:52:29 128.31.0.34 9101 0\r\n\
+			s Exit Fast Running Stable Valid\r\n\
+			.\r\n250 OK\r\n"
+				.to_string(),
+			"250-ip-to-country/128.31.0.34=US\r\n250 OK\r\n".to_string(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let countries = controller.circuit_exit_countries().unwrap();
+		let us = Some("us");
+		assert_eq!(countries.len(), 2);
+		assert_eq!(countries[0].0, CircuitId(1));
+		assert_eq!(countries[0].1.as_ref().map(CountryCode::as_str), us);
+		assert_eq!(countries[1].0, CircuitId(2));
+		assert_eq!(countries[1].1.as_ref().map(CountryCode::as_str), us);
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO circuit-status",
+				"GETINFO ns/id/8B5A8D8A5C5DD4DE5A84B6F8F17E1E3C6CF9A7A5",
+				"GETINFO ip-to-country/128.31.0.34"
+			]
+		);
+	}
+
+	#[test]
+	fn router_status_by_fingerprint() {
+		let (addr, server) = mock_server(vec![
+			"250+ns/id/9695DFC35FFEB861329B9F1AB04C46397020CE31=\r\n\
+			r moria1 lpXfw1/+uGEym58asExGOXAgzjE IpcU7dolas8+Q+oAzwgvZIWx7PA 2024-05-01 02:52:29 128.31.0.34 9101 9131\r\n\
+			s Authority Fast Guard HSDir Running Stable V2Dir Valid\r\n\
+			w Bandwidth=20 Unmeasured=1\r\n\
+			.\r\n250 OK\r\n",
+			"250+ns/id/2690D907A3EFA5FCD2EFC66D729B5D71E050EC97=\r\n\
+			r exit1 JpDZB6PvpfzS78ZtcptdceBQ7Jc IpcU7dolas8+Q+oAzwgvZIWx7PA 2024-05-01 05:47:17 104.53.221.159 443 0\r\n\
+			s BadExit Exit Running Valid\r\n\
+			.\r\n250 OK\r\n",
+			"551 Unrecognized key \"ns/id/0000000000000000000000000000000000000000\"\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let fingerprint = |hex| RelayFingerprint::from_hex(hex).unwrap();
+
+		let moria1 = controller
+			.get_router_status_by_fingerprint(&fingerprint("9695DFC35FFEB861329B9F1AB04C46397020CE31"))
+			.unwrap()
+			.unwrap();
+		assert_eq!(moria1.nickname, "moria1");
+		assert!(moria1.has_flag("Authority") && moria1.has_flag("Guard"));
+		assert!(!moria1.has_flag("Exit"));
+		assert_eq!(moria1.bandwidth, Some(20));
+
+		let exit = fingerprint("2690D907A3EFA5FCD2EFC66D729B5D71E050EC97");
+		let exit1 = controller
+			.get_router_status_by_fingerprint(&exit)
+			.unwrap()
+			.unwrap();
+		assert!(exit1.has_flag("Exit") && exit1.has_flag("BadExit"));
+		assert!(!exit1.has_flag("Guard"));
+		assert_eq!(exit1.fingerprint().unwrap(), exit);
+
+		let unknown = fingerprint("0000000000000000000000000000000000000000");
+		assert_eq!(
+			controller
+				.get_router_status_by_fingerprint(&unknown)
+				.unwrap(),
+			None
+		);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn router_descriptors() {
+		let descriptor = |key: &str| {
+			format!(
+				"250+desc/id/{}=\r\n\
+				router moria1 128.31.0.34 9101 0 9131\r\n\
+				identity-ed25519\r\n\
+				-----BEGIN ED25519 CERT-----\r\n\
+				AQQABvnhAbs0ltnxvXB5Ctg7VKeAKzBbA8PEoP6sUBsr8xf6o/f3AQAgBAAo7Ddm\r\n\
+				-----END ED25519 CERT-----\r\n\
+				platform Tor 0.4.8.9 on Linux\r\n\
+				published 2024-05-01 02:52:29\r\n\
+				fingerprint 9695 DFC3 5FFE B861 329B 9F1A B04C 4639 7020 CE31\r\n\
+				uptime 1205946\r\n\
+				bandwidth 512000 62914560 752023\r\n\
+				contact 1024D/EB5A896A28988BF5 arma mit edu\r\n\
+				ntor-onion-key ivBSYJpiZRfQGf2AlrgjT5UeWvVCB8uPS5Mz3aBHjHE=\r\n\
+				reject *:*\r\n\
+				router-signature\r\n\
+				-----BEGIN SIGNATURE-----\r\n\
+				accept this line is part of the signature\r\n\
+				-----END SIGNATURE-----\r\n\
+				.\r\n250 OK\r\n",
+				key
+			)
+		};
+		let fingerprint = "9695DFC35FFEB861329B9F1AB04C46397020CE31";
+		let digest = "2D2DF5E6C4C66E453DE8E6A1F1E29D7D3196BB8E";
+		let (addr, server) = mock_server(vec![
+			descriptor(fingerprint),
+			descriptor("."),
+			descriptor(&format!("{}~{}", fingerprint, digest)),
+			"552 Unrecognized key \"desc/id/0000000000000000000000000000000000000000\"\r\n".to_string(),
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let moria1 = controller.get_router_descriptor(fingerprint).unwrap();
+		assert_eq!(moria1.nickname, "moria1");
+		assert_eq!(moria1.address.to_string(), "128.31.0.34");
+		assert_eq!((moria1.or_port, moria1.dir_port), (9101, 9131));
+		assert_eq!(moria1.platform.as_deref(), Some("Tor 0.4.8.9 on Linux"));
+		assert_eq!(
+			moria1.fingerprint.as_ref().map(AsRef::as_ref),
+			Some(fingerprint)
+		);
+		assert_eq!(moria1.uptime, Some(1205946));
+		assert_eq!(moria1.bandwidth, Some((512000, 62914560, 752023)));
+		assert_eq!(moria1.exit_policy, vec!["reject *:*"]);
+		assert!(!moria1.hibernating);
+
+		let invalid = format!("{}1", fingerprint);
+		match controller.get_router_descriptor(invalid.as_str()) {
+			Err(Error::InvalidFingerprint(s)) => assert_eq!(s, invalid),
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert!(matches!(
+			controller.get_router_descriptor_by_digest(fingerprint, "2D2D"),
+			Err(Error::InvalidFingerprint(_))
+		));
+
+		let own = controller
+			.get_router_descriptor(RelayFingerprint::local_relay())
+			.unwrap();
+		assert_eq!(own, moria1);
+		let by_digest = controller
+			.get_router_descriptor_by_digest(String::from(fingerprint), digest)
+			.unwrap();
+		assert_eq!(by_digest, moria1);
+
+		let unknown = RelayFingerprint::from_hex("0000000000000000000000000000000000000000").unwrap();
+		assert!(matches!(
+			controller.get_router_descriptor(&unknown),
+			Err(Error::UnknownRelay(_))
+		));
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				format!("GETINFO desc/id/{}", fingerprint),
+				"GETINFO desc/id/.".to_string(),
+				format!("GETINFO desc/id/{}~{}", fingerprint, digest),
+				"GETINFO desc/id/0000000000000000000000000000000000000000".to_string(),
+			]
+		);
+	}
+
+	#[test]
+	fn circuit_lifetime_stats() {
+		let (addr, server) = mock_server(vec![
+			"250-status/total-circuit-lifetime=30.5\r\n250-status/total-circuits-created=12\r\n250 OK\r\n",
+			"250-status/total-circuit-lifetime=0\r\n250-status/total-circuits-created=0\r\n250 OK\r\n",
+			"250-status/enough-dir-info=1\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_circuit_lifetime_stats().unwrap(),
+			CircuitStats {
+				mean_circuit_lifetime:  30.5,
+				total_circuits_created: 12,
+			}
+		);
+		assert_eq!(
+			controller.get_circuit_lifetime_stats().unwrap(),
+			CircuitStats {
+				mean_circuit_lifetime:  0.0,
+				total_circuits_created: 0,
+			}
+		);
+		assert!(controller.has_directory_info().unwrap());
+
+		let commands = server.join().unwrap();
+		assert_eq!(
+			commands[0],
+			"GETINFO status/total-circuit-lifetime status/total-circuits-created"
+		);
+		assert_eq!(commands[2], "GETINFO status/enough-dir-info");
+	}
+
+	#[test]
+	fn bandwidth_monitor_averages_over_window() {
+		let (addr, server) = mock_server(vec![
+			"250 OK\r\n650 BW 1000 2000\r\n650 CIRC 1 LAUNCHED\r\n650 BW 3000 4000\r\n650 BW 5000 6000\r\n",
+			"",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let mut monitor = controller
+			.bandwidth_monitor(Duration::from_millis(2500))
+			.unwrap();
+		let bandwidth = monitor.current().unwrap();
+		assert_eq!(bandwidth.total_read, 9000);
+		assert_eq!(bandwidth.total_written, 12000);
+		assert_eq!(bandwidth.read_rate, 9000.0 / 2.5);
+		assert_eq!(bandwidth.write_rate, 12000.0 / 2.5);
+
+		// Other events are left for poll_event.
+		let event = controller.try_poll_event().unwrap().unwrap();
+		assert_eq!(event.kind, EventKind::Circ);
+
+		// The oldest sample was taken two seconds before the others and leaves
+		// the window first.
+		thread::sleep(Duration::from_millis(600));
+		let bandwidth = monitor.current().unwrap();
+		assert_eq!(bandwidth.total_read, 9000);
+		assert_eq!(bandwidth.read_rate, 8000.0 / 2.5);
+		assert_eq!(bandwidth.write_rate, 10000.0 / 2.5);
+
+		drop(monitor);
+		drop(controller);
+		assert_eq!(server.join().unwrap(), vec!["SETEVENTS BW"]);
+	}
+
+	#[test]
+	fn builder_reports_bootstrap_progress() {
+		let (addr, server) = mock_server(vec![
+			"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=COOKIE,HASHEDPASSWORD COOKIEFILE=\"/run/tor/control.authcookie\"\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n",
+			"250 OK\r\n",
+			"250 OK\r\n",
+			"250-status/bootstrap-phase=NOTICE BOOTSTRAP PROGRESS=50 TAG=loading_descriptors SUMMARY=\"Loading relay descriptors\"\r\n250 OK\r\n\
+			650 STATUS_CLIENT WARN BOOTSTRAP PROGRESS=50 TAG=loading_descriptors SUMMARY=\"Loading relay descriptors\" WARNING=\"Connection refused\" REASON=CONNECTREFUSED RECOMMENDATION=warn\r\n\
+			650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=100 TAG=done SUMMARY=\"Done\"\r\n",
+			"250 OK\r\n",
+		]);
+		let phases = Arc::new(Mutex::new(Vec::new()));
+		let seen = phases.clone();
+
+		let controller = TorController::builder()
+			.addr(addr.to_string())
+			.password("hunter2")
+			.on_bootstrap(move |phase| seen.lock().unwrap().push(phase))
+			.build()
+			.unwrap();
+
+		let phases = phases.lock().unwrap();
+		assert_eq!(phases.len(), 3);
+		assert_eq!(phases[0].progress, 50);
+		assert!(!phases[0].is_warning());
+		assert!(phases[1].is_warning());
+		assert_eq!(phases[1].summary, "Loading relay descriptors");
+		assert_eq!(phases[1].warning.as_deref(), Some("Connection refused"));
+		assert!(phases[2].is_done());
+
+		drop(controller);
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"PROTOCOLINFO",
+				"AUTHENTICATE \"hunter2\"",
+				"SETEVENTS STATUS_CLIENT",
+				"GETINFO status/bootstrap-phase",
+				"SETEVENTS"
+			]
+		);
+	}
+
+	#[test]
+	fn protocol_info_versions() {
+		let (addr, server) = mock_server(vec![
+			protocol_info_reply("NULL", ""),
+			"250-PROTOCOLINFO 2\r\n250-AUTH METHODS=NULL\r\n250-SOMETHING NEW\r\n250 OK\r\n".to_string(),
+		]);
+		let mut controller = TorController::connect_unauthenticated(addr).unwrap();
+
+		let protocol_info = controller.protocol_info().unwrap();
+		assert_eq!(protocol_info.auth_methods, vec![AuthMethod::Null]);
+		assert!(matches!(
+			controller.protocol_info(),
+			Err(Error::UnsupportedProtocolVersion(2))
+		));
+		server.join().unwrap();
+	}
+
+	fn protocol_info_reply(methods: &str, cookiefile: &str) -> String {
+		format!(
+			"250-PROTOCOLINFO 1\r\n250-AUTH METHODS={} COOKIEFILE=\"{}\"\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n",
+			methods, cookiefile
+		)
+	}
+
+	#[test]
+	fn pipeline_respects_command_timeout() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let (hang_up, hung_up) = std::sync::mpsc::channel::<()>();
+		let server = thread::spawn(move || {
+			// Answers the first command of the batch and sits on the second.
+			let (stream, _) = listener.accept().unwrap();
+			let commands = serve_session(
+				stream.try_clone().unwrap(),
+				vec![
+					"250-version=0.4.8.9\r\n250 OK\r\n".to_string(),
+					String::new(),
+				],
+			);
+			hung_up.recv().unwrap();
+			commands
+		});
+
+		let mut controller = TorController::connect(addr).unwrap();
+		let started = Instant::now();
+		let result = controller
+			.with_timeout(Duration::from_millis(100))
+			.pipeline()
+			.push("GETINFO version", parsers::get_info)
+			.push("GETINFO dormant", parsers::get_info)
+			.run();
+		match result {
+			Err(Error::Timeout { command, .. }) => assert_eq!(command, "GETINFO"),
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert!(started.elapsed() < Duration::from_secs(5));
+		hang_up.send(()).unwrap();
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["GETINFO version", "GETINFO dormant"]
+		);
+	}
+
+	#[test]
+	fn command_timeout_breaks_connection_until_reconnect() {
+		let handshake = || vec![protocol_info_reply("NULL", ""), "250 OK\r\n".to_string()];
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let (hang_up, hung_up) = std::sync::mpsc::channel::<()>();
+		let server = thread::spawn(move || {
+			// Answers the handshake, then sits on the next command.
+			let (stream, _) = listener.accept().unwrap();
+			let mut commands = serve_session(stream.try_clone().unwrap(), handshake());
+			let mut line = String::new();
+			BufReader::new(&stream).read_line(&mut line).unwrap();
+			commands.push(line.trim_end().to_string());
+			hung_up.recv().unwrap();
+			drop(stream);
+
+			let (stream, _) = listener.accept().unwrap();
+			let mut replies = handshake();
+			replies.push("250-version=0.4.8.9\r\n250 OK\r\n".to_string());
+			commands.extend(serve_session(stream, replies));
+			commands
+		});
+
+		let mut controller = TorController::builder().addr(addr).build().unwrap();
+		let started = std::time::Instant::now();
+		match controller
+			.with_timeout(Duration::from_millis(100))
+			.get_info(vec!["version"])
+		{
+			Err(Error::Timeout { command, elapsed }) => {
+				assert_eq!(command, "GETINFO");
+				assert!(elapsed >= Duration::from_millis(100));
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert!(started.elapsed() < Duration::from_secs(5));
+		assert!(matches!(
+			controller.get_info(vec!["version"]),
+			Err(Error::ConnectionLost)
+		));
+
+		hang_up.send(()).unwrap();
+		controller.reconnect().unwrap();
+		let info = controller.get_info(vec!["version"]).unwrap();
+		assert_eq!(info.get("version").map(String::as_str), Some("0.4.8.9"));
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"PROTOCOLINFO",
+				"AUTHENTICATE",
+				"GETINFO version",
+				"PROTOCOLINFO",
+				"AUTHENTICATE",
+				"GETINFO version"
+			]
+		);
+	}
+
+	#[test]
+	fn retries_after_connection_reset() {
+		let handshake = || vec![protocol_info_reply("NULL", ""), "250 OK\r\n".to_string()];
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			// Hangs up on the first command after the handshake.
+			let (stream, _) = listener.accept().unwrap();
+			let mut commands = serve_session(stream.try_clone().unwrap(), handshake());
+			let mut line = String::new();
+			BufReader::new(&stream).read_line(&mut line).unwrap();
+			commands.push(line.trim_end().to_string());
+			drop(stream);
+
+			let (stream, _) = listener.accept().unwrap();
+			let mut replies = handshake();
+			replies.push("250-version=0.4.8.9\r\n250 OK\r\n".to_string());
+			replies.push("552 Unrecognized key \"bogus\"\r\n".to_string());
+			commands.extend(serve_session(stream, replies));
+			commands
+		});
+
+		let mut controller = TorController::builder()
+			.addr(addr)
+			.max_retries(2)
+			.retry_delay(Duration::from_millis(10))
+			.build()
+			.unwrap();
+		assert_eq!(controller.sent_retries(), 0);
+		let info = controller.get_info(vec!["version"]).unwrap();
+		assert_eq!(info.get("version").map(String::as_str), Some("0.4.8.9"));
+		assert_eq!(controller.sent_retries(), 1);
+		assert_eq!(controller.stats().reconnections, 1);
+
+		// An error reply isn't retried.
+		assert!(controller.get_info(vec!["bogus"]).is_err());
+		assert_eq!(controller.sent_retries(), 1);
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"PROTOCOLINFO",
+				"AUTHENTICATE",
+				"GETINFO version",
+				"PROTOCOLINFO",
+				"AUTHENTICATE",
+				"GETINFO version",
+				"GETINFO bogus"
+			]
+		);
+	}
+
+	#[test]
+	fn signals_are_not_retried() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			// Hangs up on the first command after the handshake.
+			let (stream, _) = listener.accept().unwrap();
+			let mut commands = serve_session(
+				stream.try_clone().unwrap(),
+				vec![protocol_info_reply("NULL", ""), "250 OK\r\n".to_string()],
+			);
+			let mut line = String::new();
+			BufReader::new(&stream).read_line(&mut line).unwrap();
+			commands.push(line.trim_end().to_string());
+			commands
+		});
+
+		let mut controller = TorController::builder()
+			.addr(addr)
+			.max_retries(2)
+			.retry_delay(Duration::from_millis(10))
+			.build()
+			.unwrap();
+		assert!(controller.signal(Signal::Shutdown).is_err());
+		assert_eq!(controller.sent_retries(), 0);
+		assert_eq!(controller.stats().reconnections, 0);
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["PROTOCOLINFO", "AUTHENTICATE", "SIGNAL SHUTDOWN"]
+		);
+	}
+
+	#[test]
+	fn controller_stats() {
+		let (addr, server) = mock_server(vec![
+			"250-version=0.4.8.9\r\n250 OK\r\n",
+			"552 Unrecognized key \"bogus\"\r\n",
+			"250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		assert_eq!(controller.stats(), &TorControllerStats::default());
+		assert_eq!(controller.stats().average_command_latency(), Duration::ZERO);
+
+		let before = Instant::now();
+		controller.get_info(vec!["version"]).unwrap();
+		assert!(controller.get_info(vec!["bogus"]).is_err());
+		controller.signal(Signal::Newnym).unwrap();
+		server.join().unwrap();
+		// The server hung up, so this one never gets a reply.
+		assert!(controller.signal(Signal::Newnym).is_err());
+
+		let stats = controller.stats();
+		assert_eq!(stats.commands_sent, 4);
+		assert_eq!(stats.commands_failed, 2);
+		assert_eq!(
+			stats.bytes_sent,
+			("GETINFO version\r\n".len() + "GETINFO bogus\r\n".len() + 2 * "SIGNAL NEWNYM\r\n".len())
+				as u64
+		);
+		assert_eq!(
+			stats.bytes_received,
+			("250-version=0.4.8.9\r\n250 OK\r\n".len()
+				+ "552 Unrecognized key \"bogus\"\r\n".len()
+				+ "250 OK\r\n".len()) as u64
+		);
+		assert_eq!(stats.reconnections, 0);
+		assert!(stats.last_command_at.unwrap() >= before);
+		assert!(stats.average_command_latency() <= stats.total_command_time);
+	}
+
+	#[test]
+	fn streamed_replies_are_counted_and_time_out() {
+		const CONFIG_TEXT: &str = "250+config-text=\r\nLog notice stdout\r\n.\r\n250 OK\r\n";
+		let (addr, server) = mock_server(vec![
+			CONFIG_TEXT,
+			"552 Unrecognized key \"md/all\"\r\n",
+			// The rest of the reply never comes.
+			"250+md/all=\r\nonion-key\r\n",
+			"",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_config_text().unwrap(),
+			vec![(String::from("Log"), String::from("notice stdout"))]
+		);
+		assert!(matches!(
+			controller.get_all_microdescriptors_iter(),
+			Err(Error::Info(InfoError::Unrecognized(_)))
+		));
+		let stats = controller.stats();
+		assert_eq!(stats.commands_sent, 2);
+		assert_eq!(stats.commands_failed, 1);
+		assert_eq!(
+			stats.bytes_received,
+			(CONFIG_TEXT.len() + "552 Unrecognized key \"md/all\"\r\n".len()) as u64
+		);
+
+		let started = Instant::now();
+		let mut controller = controller.with_timeout(Duration::from_millis(100));
+		let mut microdescriptors = controller.get_all_microdescriptors_iter().unwrap();
+		match microdescriptors.next() {
+			Some(Err(Error::Timeout { command, .. })) => assert_eq!(command, "GETINFO"),
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert!(microdescriptors.next().is_none());
+		drop(microdescriptors);
+		assert!(started.elapsed() < Duration::from_secs(5));
+		assert_eq!(controller.stats().commands_failed, 2);
+		drop(server);
+	}
+
+	#[test]
+	fn reconnect_needs_a_builder() {
+		let (addr, server) = mock_server(Vec::<String>::new());
+		let mut controller = TorController::connect(addr).unwrap();
+		assert!(matches!(controller.reconnect(), Err(Error::ConnectionLost)));
+		drop(controller);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn controller_pool_reports_each_instance() {
+		fn assert_send<T: Send>() {}
+		assert_send::<ControllerPool>();
+
+		let (connected_addr, connected) =
+			mock_server(vec!["250 OK\r\n", "250-version=0.4.8.9\r\n250 OK\r\n"]);
+		let (lazy_addr, lazy) = mock_server(vec![
+			protocol_info_reply("NULL", ""),
+			"250 OK\r\n".to_string(),
+			"250 OK\r\n".to_string(),
+		]);
+		// Nothing listens here once the listener is dropped.
+		let closed_addr = TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap();
+
+		let mut pool = ControllerPool::new();
+		pool.add("connected", TorController::connect(connected_addr).unwrap());
+		pool.add_lazy("lazy", TorController::builder().addr(lazy_addr));
+		pool.add_lazy("unreachable", TorController::builder().addr(closed_addr));
+		assert_eq!(
+			pool.names().collect::<Vec<_>>(),
+			vec!["connected", "lazy", "unreachable"]
+		);
+
+		let report = pool.broadcast_signal(Signal::Newnym);
+		assert!(!report.is_success());
+		let succeeded: Vec<&str> = report
+			.succeeded
+			.iter()
+			.map(|(name, _)| name.as_str())
+			.collect();
+		assert_eq!(succeeded, vec!["connected", "lazy"]);
+		assert_eq!(report.failed.len(), 1);
+		assert_eq!(report.failed[0].0, "unreachable");
+		assert!(matches!(report.failed[0].1, Error::Io(_)));
+
+		assert!(pool.get("connected").unwrap().is_alive());
+		assert!(matches!(
+			pool.get("missing"),
+			Err(Error::UnknownInstance(_))
+		));
+
+		drop(pool.remove("lazy"));
+		assert_eq!(pool.len(), 2);
+		drop(pool);
+		assert_eq!(
+			connected.join().unwrap(),
+			vec!["SIGNAL NEWNYM", "GETINFO version"]
+		);
+		assert_eq!(
+			lazy.join().unwrap(),
+			vec!["PROTOCOLINFO", "AUTHENTICATE", "SIGNAL NEWNYM"]
+		);
+	}
+
+	#[test]
+	fn builder_authenticates_with_cookie_file() {
+		let cookie_path = temp_key_path("cookie");
+		std::fs::write(&cookie_path, [0xde, 0xad, 0xbe, 0xef]).unwrap();
+		let (addr, server) = mock_server(vec![
+			protocol_info_reply("COOKIE,SAFECOOKIE", "/nonexistent/control_auth_cookie"),
+			"250 OK\r\n".to_string(),
+		]);
+
+		TorController::builder()
+			.addr(addr)
+			.auth_cookie_path(&cookie_path)
+			.read_timeout(Duration::from_secs(5))
+			.build()
+			.unwrap();
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["PROTOCOLINFO", "AUTHENTICATE DEADBEEF"]
+		);
+		std::fs::remove_file(&cookie_path).unwrap();
+	}
+
+	#[test]
+	fn builder_auto_auth_picks_available_method() {
+		let cookie_path = temp_key_path("auto-cookie");
+		std::fs::write(&cookie_path, [0x01, 0x02]).unwrap();
+		let cookie_file = cookie_path.to_str().unwrap();
+		let (addr, server) = mock_server(vec![
+			protocol_info_reply("COOKIE,HASHEDPASSWORD", cookie_file),
+			"250 OK\r\n".to_string(),
+		]);
+		TorController::builder()
+			.addr(addr)
+			.password("hunter2")
+			.auto_auth()
+			.build()
+			.unwrap();
+		assert_eq!(server.join().unwrap()[1], "AUTHENTICATE 0102");
+
+		// An unreadable cookie file falls back to the password.
+		let (addr, server) = mock_server(vec![
+			protocol_info_reply(
+				"COOKIE,SAFECOOKIE,HASHEDPASSWORD",
+				"/nonexistent/control_auth_cookie",
+			),
+			"250 OK\r\n".to_string(),
+		]);
+		TorController::builder()
+			.addr(addr)
+			.password("hunter2")
+			.auto_auth()
+			.connect_timeout(Duration::from_secs(5))
+			.build()
+			.unwrap();
+		assert_eq!(server.join().unwrap()[1], "AUTHENTICATE \"hunter2\"");
+
+		let (addr, server) = mock_server(vec![
+			"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n",
+			"250 OK\r\n",
+		]);
+		TorController::builder()
+			.addr(addr)
+			.auto_auth()
+			.build()
+			.unwrap();
+		assert_eq!(server.join().unwrap()[1], "AUTHENTICATE");
+
+		std::fs::remove_file(&cookie_path).unwrap();
+	}
+
+	#[test]
+	fn old_tor_rejects_unsupported_commands() {
+		let (addr, server) = mock_server(vec![
+			"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250-VERSION Tor=\"0.3.5.17\"\r\n250 OK\r\n",
+			"250 OK\r\n",
+			"250 OK\r\n",
+		]);
+		let mut controller = TorController::builder().addr(addr).build().unwrap();
+
+		assert_eq!(
+			controller.capabilities().version().map(ToString::to_string),
+			Some("0.3.5.17".to_string())
+		);
+		assert!(matches!(
+			controller.onion_client_auth_view(None),
+			Err(Error::UnsupportedByTorVersion { .. })
+		));
+		assert!(matches!(
+			controller.drop_ownership(),
+			Err(Error::UnsupportedByTorVersion { .. })
+		));
+		assert!(matches!(
+			controller.send_add_onion("NEW:BEST", &[AddOnionFlag::V3Auth], &[]),
+			Err(Error::UnsupportedByTorVersion { .. })
+		));
+		controller.take_ownership().unwrap();
+
+		let commands = server.join().unwrap();
+		assert_eq!(commands[2], "TAKEOWNERSHIP");
+	}
+
+	#[test]
+	fn ownership_is_handed_back_on_drop() {
+		let (addr, server) = mock_server(vec!["250 OK\r\n", "250 OK\r\n", "250 OK\r\n", "250 OK\r\n"]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert!(!controller.is_owner());
+		controller.take_ownership().unwrap();
+		assert!(controller.is_owner());
+		controller.drop_ownership().unwrap();
+		assert!(!controller.is_owner());
+
+		controller.take_ownership().unwrap();
+		drop(controller);
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"TAKEOWNERSHIP",
+				"DROPOWNERSHIP",
+				"TAKEOWNERSHIP",
+				"DROPOWNERSHIP"
+			]
+		);
+
+		// A lost connection is left alone.
+		let (addr, server) = mock_server(vec!["250 OK\r\n"]);
+		let mut controller = TorController::connect(addr).unwrap();
+		controller.take_ownership().unwrap();
+		server.join().unwrap();
+		assert!(controller.get_info(vec!["version"]).is_err());
+		drop(controller);
+	}
+
+	#[test]
+	fn controller_pool_blocks_when_exhausted() {
+		let handshake = || vec![protocol_info_reply("NULL", ""), "250 OK\r\n".to_string()];
+		let (addr, server) = mock_server_sessions(vec![handshake(), handshake()]);
+		let pool = std::sync::Arc::new(
+			TorControllerPool::new(
+				addr,
+				AuthAttempt::Null,
+				std::num::NonZeroUsize::new(2).unwrap(),
+			)
+			.unwrap(),
+		);
+		assert_eq!(server.join().unwrap().len(), 2);
+
+		let first = pool.acquire().unwrap();
+		let second = pool.acquire().unwrap();
+		assert!(first.is_authenticated() && second.is_authenticated());
+		assert_eq!(pool.idle_count(), 0);
+		assert!(pool.try_acquire().unwrap().is_none());
+
+		let (acquired, third) = std::sync::mpsc::channel();
+		let waiting = pool.clone();
+		let waiter = thread::spawn(move || {
+			let _controller = waiting.acquire().unwrap();
+			acquired.send(()).unwrap();
+		});
+		assert!(third.recv_timeout(Duration::from_millis(200)).is_err());
+		drop(first);
+		third.recv_timeout(Duration::from_secs(5)).unwrap();
+		waiter.join().unwrap();
+
+		drop(second);
+		assert_eq!(pool.idle_count(), 2);
+		assert_eq!(pool.size(), 2);
+	}
+
+	#[test]
+	fn connect_with_fallback_reconnects_after_each_failure() {
+		let cookie_path = temp_key_path("fallback-cookie");
+		std::fs::write(&cookie_path, [0xab; 32]).unwrap();
+		let methods = protocol_info_reply("COOKIE,HASHEDPASSWORD", "/nonexistent/control_auth_cookie");
+		let (addr, server) = mock_server_sessions(vec![
+			vec![
+				methods.clone(),
+				"515 Authentication failed: Wrong length on authentication cookie.\r\n".to_string(),
+			],
+			vec![methods.clone()],
+			vec![methods.clone(), "250 OK\r\n".to_string()],
+		]);
+
+		let controller = TorController::connect_with_fallback(
+			addr,
+			&[
+				AuthAttempt::Cookie(Some(cookie_path.clone())),
+				AuthAttempt::SafeCookie,
+				AuthAttempt::Password("hunter2".to_string()),
+				AuthAttempt::Null,
+			],
+		);
+		assert!(controller.is_ok());
+		let sessions = server.join().unwrap();
+		assert_eq!(sessions[0][1], format!("AUTHENTICATE {}", "AB".repeat(32)));
+		assert_eq!(sessions[1], vec!["PROTOCOLINFO"]);
+		assert_eq!(sessions[2][1], "AUTHENTICATE \"hunter2\"");
+
+		let (addr, server) = mock_server_sessions(vec![
+			vec![methods.clone(), "515 Authentication failed: Password did not match HashedControlPassword value from configuration\r\n".to_string()],
+			vec![methods],
+		]);
+		let result = TorController::connect_with_fallback(
+			addr,
+			&[
+				AuthAttempt::Password("wrong".to_string()),
+				AuthAttempt::Null,
+			],
+		);
+		match result {
+			Err(Error::AllAuthFailed(failures)) => {
+				assert_eq!(failures.len(), 2);
+				assert_eq!(failures[0].0, AuthMethod::HashedPassword);
+				assert!(matches!(
+					failures[1],
+					(AuthMethod::Null, Error::AuthMethodDisabled)
+				));
+				let message = Error::AllAuthFailed(failures).to_string();
+				assert!(message.contains("HASHEDPASSWORD: "), "{}", message);
+				assert!(
+					message.contains("NULL: Auth method disabled"),
+					"{}",
+					message
+				);
+			}
+			other => panic!("unexpected result: {:?}", other.map(|_| ())),
+		}
+		server.join().unwrap();
+
+		std::fs::remove_file(&cookie_path).unwrap();
+	}
+
+	// The only test touching TOR_CONTROL and CONTROL_PORT, since the
+	// environment is shared between test threads.
+	#[test]
+	fn connect_from_env() {
+		let null_auth = || {
+			vec![
+				"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n",
+				"250 OK\r\n",
+			]
+		};
+
+		let (addr, server) = mock_server(null_auth());
+		std::env::set_var("TOR_CONTROL", addr.to_string());
+		std::env::set_var("CONTROL_PORT", "");
+		TorController::connect_from_env().unwrap();
+		assert_eq!(server.join().unwrap(), vec!["PROTOCOLINFO", "AUTHENTICATE"]);
+
+		let (addr, server) = mock_server(null_auth());
+		std::env::remove_var("TOR_CONTROL");
+		std::env::set_var("CONTROL_PORT", addr.to_string());
+		TorController::connect_from_env().unwrap();
+		server.join().unwrap();
+
+		#[cfg(unix)]
+		{
+			use std::os::unix::net::UnixListener;
+
+			let socket_path = temp_key_path("env-control-socket");
+			let listener = UnixListener::bind(&socket_path).unwrap();
+			let server = thread::spawn(move || {
+				let (stream, _) = listener.accept().unwrap();
+				let mut reader = BufReader::new(stream);
+				for reply in null_auth() {
+					let mut line = String::new();
+					reader.read_line(&mut line).unwrap();
+					reader.get_mut().write_all(reply.as_bytes()).unwrap();
+				}
+			});
+			std::env::set_var("TOR_CONTROL", &socket_path);
+			TorController::connect_from_env().unwrap();
+			server.join().unwrap();
+			std::fs::remove_file(&socket_path).unwrap();
+		}
+
+		std::env::set_var("TOR_CONTROL", "");
+		assert!(matches!(
+			TorController::connect_from_env(),
+			Err(Error::NoControlAddress)
+		));
+
+		std::env::remove_var("TOR_CONTROL");
+		std::env::remove_var("CONTROL_PORT");
+	}
+
+	#[test]
+	fn commands_before_authenticating_are_rejected_locally() {
+		let (addr, server) = mock_server(vec![
+			"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL,HASHEDPASSWORD\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n",
+			"515 Authentication failed: Password did not match HashedControlPassword value from configuration\r\n",
+			"250 OK\r\n",
+			"250-version=0.4.8.9\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect_unauthenticated(addr).unwrap();
+
+		assert!(matches!(
+			controller.get_info(vec!["version"]),
+			Err(Error::NotAuthenticated)
+		));
+		assert!(matches!(
+			controller
+				.pipeline::<()>()
+				.push("SIGNAL NEWNYM", parsers::is_ok)
+				.run(),
+			Err(Error::NotAuthenticated)
+		));
+		controller.protocol_info().unwrap();
+		assert!(controller.authenticate("wrong".to_string()).is_err());
+		assert!(!controller.is_authenticated());
+		controller.authenticate_null().unwrap();
+		assert!(controller.is_authenticated());
+		controller.get_info(vec!["version"]).unwrap();
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"PROTOCOLINFO",
+				"AUTHENTICATE \"wrong\"",
+				"AUTHENTICATE",
+				"GETINFO version"
+			]
+		);
+	}
+
+	#[test]
+	fn builder_rejects_disabled_password_auth() {
+		let (addr, server) = mock_server(vec![protocol_info_reply(
+			"COOKIE,SAFECOOKIE",
+			"/run/tor/control.authcookie",
+		)]);
+
+		let result = TorController::builder()
+			.addr(addr)
+			.password("hunter2")
+			.build();
+		assert!(matches!(result, Err(Error::AuthMethodDisabled)));
+		assert_eq!(server.join().unwrap(), vec!["PROTOCOLINFO"]);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn builder_connects_to_unix_socket() {
+		use std::os::unix::net::UnixListener;
+
+		let socket_path = temp_key_path("control.sock");
+		let listener = UnixListener::bind(&socket_path).unwrap();
+		let server = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream);
+			let mut commands = Vec::new();
+			for reply in [
+				"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=HASHEDPASSWORD\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n",
+				"250 OK\r\n",
+				"250-version=0.4.8.9\r\n250 OK\r\n",
+			] {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				commands.push(line.trim_end().to_string());
+				reader.get_mut().write_all(reply.as_bytes()).unwrap();
+			}
+			commands
+		});
+
+		let mut controller = TorController::builder()
+			.unix_socket(&socket_path)
+			.password("hunter2")
+			.build()
+			.unwrap();
+		let info = controller.get_info(vec!["version"]).unwrap();
+		assert_eq!(info.get("version"), Some(&String::from("0.4.8.9")));
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"PROTOCOLINFO",
+				"AUTHENTICATE \"hunter2\"",
+				"GETINFO version"
+			]
+		);
+		std::fs::remove_file(&socket_path).unwrap();
+	}
+
+	#[test]
+	fn probe_onion_waits_for_matching_hs_desc() {
+		const DIR1: &str = "$F6740DEABFD5F62612FA025A5079EA72846B1F67~dir1";
+		const DIR2: &str = "$7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82~dir2";
+		let fetched = format!(
+			"250 OK\r\n\
+			650 HS_DESC REQUESTED k2edzso5c4rxyay3 NO_AUTH {dir1} b4ftvqowwk3e3ydm\r\n\
+			650 HS_DESC REQUESTED rdwu5tfgmibbgvff NO_AUTH {dir1} b4ftvqowwk3e3ydm\r\n\
+			650 HS_DESC REQUESTED rdwu5tfgmibbgvff NO_AUTH {dir2} b4ftvqowwk3e3ydm\r\n\
+			650 HS_DESC RECEIVED k2edzso5c4rxyay3 NO_AUTH {dir1} b4ftvqowwk3e3ydm\r\n\
+			650 HS_DESC FAILED rdwu5tfgmibbgvff NO_AUTH {dir1} b4ftvqowwk3e3ydm REASON=NOT_FOUND\r\n\
+			650 HS_DESC RECEIVED rdwu5tfgmibbgvff NO_AUTH {dir2} b4ftvqowwk3e3ydm\r\n",
+			dir1 = DIR1,
+			dir2 = DIR2
+		);
+		let failed = format!(
+			"250 OK\r\n\
+			650 HS_DESC REQUESTED rdwu5tfgmibbgvff NO_AUTH {dir1} b4ftvqowwk3e3ydm\r\n\
+			650 HS_DESC FAILED rdwu5tfgmibbgvff NO_AUTH {dir1} b4ftvqowwk3e3ydm REASON=NOT_FOUND\r\n",
+			dir1 = DIR1
+		);
+		let ok = "250 OK\r\n".to_string();
+		let (addr, server) = mock_server(vec![
+			ok.clone(),
+			fetched,
+			ok.clone(),
+			ok.clone(),
+			failed,
+			ok,
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let service_id = ServiceID::from("rdwu5tfgmibbgvff");
+
+		let probe = controller
+			.probe_onion(&service_id, Duration::from_secs(5))
+			.unwrap();
+		assert_eq!(probe.status, OnionProbeStatus::Received);
+		assert_eq!(probe.hs_dir.as_deref(), Some(DIR2));
+		assert_eq!(probe.reason, None);
+		// Events of the temporary subscription don't outlive the probe.
+		assert_eq!(controller.try_poll_event().unwrap(), None);
+
+		let probe = controller
+			.probe_onion(&service_id, Duration::from_secs(5))
+			.unwrap();
+		assert_eq!(probe.status, OnionProbeStatus::Failed);
+		assert_eq!(probe.hs_dir.as_deref(), Some(DIR1));
+		assert_eq!(probe.reason.as_deref(), Some("NOT_FOUND"));
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"SETEVENTS HS_DESC",
+				"HSFETCH rdwu5tfgmibbgvff",
+				"SETEVENTS",
+				"SETEVENTS HS_DESC",
+				"HSFETCH rdwu5tfgmibbgvff",
+				"SETEVENTS"
+			]
+		);
+	}
+
+	#[test]
+	fn diagnose_connection_reports_each_check() {
+		let cookie_path = temp_key_path("diagnose-cookie");
+		std::fs::write(&cookie_path, [0u8; 16]).unwrap();
+		let (addr, server) = mock_server(vec![protocol_info_reply(
+			"COOKIE,SAFECOOKIE",
+			cookie_path.to_str().unwrap(),
+		)]);
+
+		let results = diagnose_connection(addr);
+		let checks: Vec<(&str, bool)> = results
+			.iter()
+			.map(|result| (result.check.as_str(), result.passed))
+			.collect();
+		assert_eq!(
+			checks,
+			vec![
+				("TCP connect", true),
+				("PROTOCOLINFO", true),
+				("Authentication methods", true),
+				("Cookie file readable", true),
+				("Cookie file size", false),
+			]
+		);
+		assert_eq!(results[4].detail, "16 bytes, expected 32");
+		assert_eq!(server.join().unwrap(), vec!["PROTOCOLINFO 1"]);
+		std::fs::remove_file(&cookie_path).unwrap();
+
+		// Pointing at the SOCKS port is a common mistake.
+		let (addr, server) = mock_server(vec!["HTTP/1.0 501 Tor is not an HTTP Proxy\r\n"]);
+		let results = diagnose_connection(addr);
+		server.join().unwrap();
+		assert_eq!(results.len(), 2);
+		assert!(!results[1].passed);
+		assert!(results[1].detail.contains("not the control port"));
+
+		let unused = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = unused.local_addr().unwrap();
+		drop(unused);
+		let results = diagnose_connection(addr);
+		assert_eq!(results.len(), 1);
+		assert!(!results[0].passed);
+	}
+
+	#[test]
+	fn streams_are_joined_to_circuits() {
+		let (addr, server) = mock_server(vec![
+			"250+stream-status=\r\n\
+			12 SUCCEEDED 5 www.torproject.org:443\r\n\
+			13 NEW 0 example.com:80\r\n\
+			14 SENTCONNECT 7 check.torproject.org:443\r\n\
+			.\r\n\
+			250+circuit-status=\r\n\
+			5 BUILT $7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82~Quintex41 PURPOSE=GENERAL\r\n\
+			.\r\n\
+			250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let streams = controller.streams_with_circuits().unwrap();
+		assert_eq!(streams.len(), 3);
+		assert_eq!(streams[0].0.id, StreamId(12));
+		assert_eq!(streams[0].0.status, StreamStatus::Succeeded);
+		assert_eq!(streams[0].0.target, "www.torproject.org:443");
+		assert_eq!(
+			streams[0].1.as_ref().map(|circuit| circuit.id),
+			Some(CircuitId(5))
+		);
+		// Unattached, and attached to a circuit that is already gone.
+		assert_eq!(streams[1].0.circuit_id, None);
+		assert_eq!(streams[1].1, None);
+		assert_eq!(streams[2].0.circuit_id, Some(CircuitId(7)));
+		assert_eq!(streams[2].1, None);
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["GETINFO stream-status circuit-status"]
+		);
+	}
+
+	#[test]
+	fn circuits_are_joined_to_streams() {
+		let reply = "250+stream-status=\r\n\
+			12 SUCCEEDED 1 www.torproject.org:443\r\n\
+			13 SUCCEEDED 1 example.com:80\r\n\
+			14 SENTCONNECT 2 check.torproject.org:443\r\n\
+			.\r\n\
+			250+circuit-status=\r\n\
+			1 BUILT $7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82~Quintex41 PURPOSE=GENERAL\r\n\
+			2 BUILT $9695DFC35FFEB861329B9F1AB04C46397020CE31~moria1 PURPOSE=GENERAL\r\n\
+			3 EXTENDED $9695DFC35FFEB861329B9F1AB04C46397020CE31~moria1 PURPOSE=GENERAL\r\n\
+			.\r\n\
+			250 OK\r\n";
+		let (addr, server) = mock_server(vec![reply, reply]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let circuits = controller.get_circuits_with_streams().unwrap();
+		let ids =
+			|streams: &[StreamEntry]| streams.iter().map(|stream| stream.id.0).collect::<Vec<_>>();
+		assert_eq!(circuits.len(), 3);
+		assert_eq!(circuits[0].0.id, CircuitId(1));
+		assert_eq!(ids(&circuits[0].1), vec![12, 13]);
+		assert_eq!(circuits[1].0.id, CircuitId(2));
+		assert_eq!(ids(&circuits[1].1), vec![14]);
+		assert!(circuits[2].1.is_empty());
+
+		let counts = controller.get_circuit_stream_counts().unwrap();
+		assert_eq!(
+			counts,
+			vec![(CircuitId(1), 2), (CircuitId(2), 1), (CircuitId(3), 0)]
+				.into_iter()
+				.collect()
+		);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn relay_uptime_and_hibernation() {
+		let (addr, server) = mock_server(vec![
+			"250-uptime=86400\r\n250-accounting/hibernating=awake\r\n250 OK\r\n",
+			"552 Unrecognized key \"relay/down-since\"\r\n",
+			"250-uptime=3600\r\n250-accounting/hibernating=soft\r\n250 OK\r\n",
+			"250-relay/down-since=2021-11-30 10:00:41\r\n250 OK\r\n",
+			"250-accounting/hibernating=soft\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert_eq!(
+			controller.get_relay_uptime().unwrap(),
+			RelayUptime {
+				uptime_seconds: 86400,
+				is_hibernating: false,
+				last_restarted: None,
+			}
+		);
+		assert_eq!(
+			controller.get_relay_uptime().unwrap(),
+			RelayUptime {
+				uptime_seconds: 3600,
+				is_hibernating: true,
+				last_restarted: Some("2021-11-30 10:00:41".to_string()),
+			}
+		);
+		assert_eq!(
+			controller.get_hibernation_state().unwrap(),
+			HibernationState::SoftLimit
+		);
+
+		assert_eq!(
+			server.join().unwrap()[..2],
+			[
+				"GETINFO uptime accounting/hibernating",
+				"GETINFO relay/down-since"
+			]
+		);
+	}
+
+	#[test]
+	fn exit_policy_lookups() {
+		let (addr, server) = mock_server(vec![
+			"250-exit-policy/reject-private=1\r\n250 OK\r\n",
+			"250-exit-policy/summary=accept 80,443,8080-8090 reject *\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		assert!(controller.rejects_private_addresses().unwrap());
+		let summary = controller.get_exit_policy_summary().unwrap();
+		assert_eq!(summary.accept_ports.len(), 3);
+		assert!(summary.accept_ports[2].contains(8085));
+		assert_eq!(summary.reject_ports, vec![PortRange::ALL]);
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO exit-policy/reject-private",
+				"GETINFO exit-policy/summary"
+			]
+		);
+	}
+
+	#[test]
+	fn exit_policy_rules() {
+		let (addr, server) = mock_server(vec![
+			"250-exit-policy/full=reject 0.0.0.0/8:*,reject 10.0.0.0/8:*,accept *:80,reject *:*\r\n250 OK\r\n",
+			"250-exit-policy/ipv6=reject6 [fc00::]/7:*,accept6 *:443,reject6 *:*\r\n250 OK\r\n",
+			"250-exit-policy/ipv6-enabled=1\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let full = controller.get_exit_policy_full().unwrap();
+		assert_eq!(full.rules.len(), 4);
+		assert!(full.can_exit_to("93.184.216.34".parse().unwrap(), 80));
+		assert!(!full.can_exit_to("10.0.0.1".parse().unwrap(), 80));
+		assert_eq!(
+			full.to_summary().accept_ports,
+			vec![PortRange {
+				start: 80,
+				end:   80,
+			}]
+		);
+
+		let ipv6 = controller.get_exit_policy_ipv6().unwrap();
+		assert_eq!(ipv6.rules[1].address, AddressPattern::AnyIpv6);
+		assert!(ipv6.can_exit_to("2001:db8::1".parse().unwrap(), 443));
+		assert!(!ipv6.can_exit_to("fd00::1".parse().unwrap(), 443));
+		assert!(controller.get_ipv6_reachability().unwrap());
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"GETINFO exit-policy/full",
+				"GETINFO exit-policy/ipv6",
+				"GETINFO exit-policy/ipv6-enabled"
+			]
+		);
+	}
+
+	#[test]
+	fn microdescriptors_without_onion_keys() {
+		let (addr, server) = mock_server(vec![
+			"250+md/all=\r\n\
+			ntor-onion-key 3dZm3xJcPtkGQ5iRX+xQEaTgm8s3+tjxAOyw1VsUWBg=\r\n\
+			p accept 80,443\r\n\
+			ntor-onion-key vlBz2TXNlzGzhlbHGkMHZbp1yID9LhQLAHHkOgDUcWY=\r\n\
+			family $7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82\r\n\
+			@last-listed 2024-05-01 12:00:00\r\n\
+			onion-key\r\n\
+			-----BEGIN RSA PUBLIC KEY-----\r\n\
+			MIGJAoGBAK3q\r\n\
+			-----END RSA PUBLIC KEY-----\r\n\
+			ntor-onion-key 2pF8y7iGhBz3jA9Ct3JcT/MWzV4EYgKzZ7jUlPdXjTA=\r\n\
+			.\r\n\
+			250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let microdescriptors: Vec<Microdescriptor> = controller
+			.get_all_microdescriptors_iter()
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap();
+		assert_eq!(microdescriptors.len(), 3);
+		assert_eq!(microdescriptors[0].onion_key, None);
+		assert_eq!(
+			microdescriptors[0].policy,
+			Some(String::from("accept 80,443"))
+		);
+		assert!(microdescriptors[0].family.is_empty());
+		assert_eq!(
+			microdescriptors[1].ntor_onion_key,
+			"vlBz2TXNlzGzhlbHGkMHZbp1yID9LhQLAHHkOgDUcWY="
+		);
+		assert_eq!(
+			(
+				microdescriptors[1].family.len(),
+				microdescriptors[1].policy.as_deref()
+			),
+			(1, None)
+		);
+		assert!(microdescriptors[2].onion_key.is_some());
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn microdescriptors_are_streamed() {
+		const MD_ALL: &str = "250+md/all=\r\n\
+			onion-key\r\n\
+			-----BEGIN RSA PUBLIC KEY-----\r\n\
+			MIGJAoGBAMs8\r\n\
+			-----END RSA PUBLIC KEY-----\r\n\
+			ntor-onion-key 3dZm3xJcPtkGQ5iRX+xQEaTgm8s3+tjxAOyw1VsUWBg=\r\n\
+			family $7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82 $05C3A6B5EAD301FA8DD6D82F3E3E8F3D4ED0E5D4\r\n\
+			p accept 80,443\r\n\
+			id ed25519 d8fEJ5w4gI8V7C0eIUKqmvQFSEmhLJfNqYo1ksj4cwk\r\n\
+			onion-key\r\n\
+			-----BEGIN RSA PUBLIC KEY-----\r\n\
+			MIGJAoGBAOx1\r\n\
+			-----END RSA PUBLIC KEY-----\r\n\
+			ntor-onion-key vlBz2TXNlzGzhlbHGkMHZbp1yID9LhQLAHHkOgDUcWY=\r\n\
+			a [2001:db8::1]:9001\r\n\
+			p reject 1-65535\r\n\
+			p6 accept 80\r\n\
+			onion-key\r\n\
+			-----BEGIN RSA PUBLIC KEY-----\r\n\
+			MIGJAoGBAK3q\r\n\
+			-----END RSA PUBLIC KEY-----\r\n\
+			ntor-onion-key 2pF8y7iGhBz3jA9Ct3JcT/MWzV4EYgKzZ7jUlPdXjTA=\r\n\
+			.\r\n\
+			250 OK\r\n";
+		let (addr, server) = mock_server(vec![
+			MD_ALL,
+			"250-version=0.4.7.16\r\n250 OK\r\n",
+			MD_ALL,
+			"250-version=0.4.7.16\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+
+		let microdescriptors: Vec<Microdescriptor> = controller
+			.get_all_microdescriptors_iter()
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap();
+		assert_eq!(microdescriptors.len(), 3);
+		assert_eq!(
+			microdescriptors[0].ntor_onion_key,
+			"3dZm3xJcPtkGQ5iRX+xQEaTgm8s3+tjxAOyw1VsUWBg="
+		);
+		assert_eq!(microdescriptors[0].family.len(), 2);
+		assert_eq!(
+			microdescriptors[1].addresses,
+			vec![String::from("[2001:db8::1]:9001")]
+		);
+		assert_eq!(
+			microdescriptors[1].policy_ipv6,
+			Some(String::from("accept 80"))
+		);
+		assert!(controller.get_info(vec!["version"]).is_ok());
+
+		// Stopping early still consumes the whole reply.
+		let first = controller
+			.get_all_microdescriptors_iter()
+			.unwrap()
+			.next()
+			.unwrap()
+			.unwrap();
+		assert_eq!(first.policy, Some(String::from("accept 80,443")));
+		assert!(controller.get_info(vec!["version"]).is_ok());
+
+		server.join().unwrap();
+	}
+
+	fn temp_key_path(name: &str) -> std::path::PathBuf {
+		let path = std::env::temp_dir().join(format!("torcc-{}-{}", name, std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		path
+	}
+
+	#[test]
+	fn serve_hidden_reuses_saved_key() {
+		let key_path = temp_key_path("serve-hidden");
+		let (addr, server) = mock_server(vec![
+			"250-ServiceID=qcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd\r\n250-PrivateKey=ED25519-V3:kBN5Ro9Q2Ywi7NYx\r\n250 OK\r\n",
+			"250-ServiceID=qcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd\r\n250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+
+		let created = controller.serve_hidden(local, 80, &key_path).unwrap();
+		assert_eq!(created.private_key, "kBN5Ro9Q2Ywi7NYx");
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = std::fs::metadata(&key_path).unwrap().permissions().mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+
+		let restored = controller.serve_hidden(local, 80, &key_path).unwrap();
+		assert_eq!(restored.service_id, created.service_id);
+		assert_eq!(restored.key_kind, KeyType::ED25519V3);
+		assert_eq!(restored.private_key, "kBN5Ro9Q2Ywi7NYx");
+
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"ADD_ONION NEW:ED25519-V3 Port=80,127.0.0.1:8080",
+				"ADD_ONION ED25519-V3:kBN5Ro9Q2Ywi7NYx Port=80,127.0.0.1:8080"
+			]
+		);
+		std::fs::remove_file(&key_path).unwrap();
+	}
+
+	#[test]
+	fn serve_hidden_removes_service_if_key_cant_be_saved() {
+		let key_path = temp_key_path("serve-hidden-unwritable")
+			.join("no-such-dir")
+			.join("key");
+		let (addr, server) = mock_server(vec![
+			"250-ServiceID=qcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd\r\n250-PrivateKey=ED25519-V3:kBN5Ro9Q2Ywi7NYx\r\n250 OK\r\n",
+			"250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+
+		let result = controller.serve_hidden(local, 80, &key_path);
+		assert!(matches!(result, Err(Error::Io(_))));
+		assert_eq!(
+			server.join().unwrap()[1],
+			"DEL_ONION qcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd"
+		);
+	}
+
+	#[test]
+	fn serve_hidden_rejects_mismatched_service_id() {
+		let key_path = temp_key_path("serve-hidden-mismatch");
+		std::fs::write(
+			&key_path,
+			"ED25519-V3:kBN5Ro9Q2Ywi7NYx\nqcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd.onion\n",
+		)
+		.unwrap();
+		let (addr, server) = mock_server(vec![
+			"250-ServiceID=rdwu5tfgmibbgvff\r\n250 OK\r\n",
+			"250 OK\r\n",
+		]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+
+		let result = controller.serve_hidden(local, 80, &key_path);
+		assert!(matches!(result, Err(Error::ServiceIdMismatch { .. })));
+		assert_eq!(server.join().unwrap()[1], "DEL_ONION rdwu5tfgmibbgvff");
+		std::fs::remove_file(&key_path).unwrap();
+	}
+
+	#[test]
+	fn rotate_onion_replaces_service() {
+		const ADDED: &str = "250-ServiceID=qcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd\r\n250-PrivateKey=ED25519-V3:kBN5Ro9Q2Ywi7NYx\r\n250 OK\r\n";
+		const LISTED: &str = "250+onions/current=\r\nrdwu5tfgmibbgvff\r\nqcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd\r\n.\r\n250 OK\r\n";
+		let old = ServiceID::from("rdwu5tfgmibbgvff");
+		let ports = [OnionPort::new(80, "127.0.0.1:8080".parse().unwrap())];
+
+		let (addr, server) = mock_server(vec![ADDED, LISTED, "250 OK\r\n"]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let new = controller
+			.rotate_onion(&old, &ports, &[AddOnionFlag::BasicAuth])
+			.unwrap();
+		assert_eq!(
+			new.service_id,
+			ServiceID::from("qcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd")
+		);
+		assert_eq!(
+			server.join().unwrap(),
+			vec![
+				"ADD_ONION NEW:ED25519-V3 Flags=BasicAuth Port=80,127.0.0.1:8080",
+				"GETINFO onions/current",
+				"DEL_ONION rdwu5tfgmibbgvff"
+			]
+		);
+	}
+
+	#[test]
+	fn rotate_onion_keeps_old_service_when_creation_fails() {
+		let old = ServiceID::from("rdwu5tfgmibbgvff");
+		let ports = [OnionPort::new(80, "127.0.0.1:8080".parse().unwrap())];
+
+		let (addr, server) = mock_server(vec!["512 Invalid VIRTPORT/TARGET\r\n"]);
+		let mut controller = TorController::connect(addr).unwrap();
+		assert!(controller.rotate_onion(&old, &ports, &[]).is_err());
+		assert_eq!(
+			server.join().unwrap(),
+			vec!["ADD_ONION NEW:ED25519-V3 Port=80,127.0.0.1:8080"]
+		);
+	}
+
+	#[test]
+	fn rotate_onion_returns_new_service_when_deletion_fails() {
+		const ADDED: &str =
+			"250-ServiceID=qcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd\r\n250 OK\r\n";
+		const LISTED: &str =
+			"250-onions/detached=qcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd\r\n250 OK\r\n";
+		let old = ServiceID::from("rdwu5tfgmibbgvff");
+		let ports = [OnionPort::new(80, "127.0.0.1:8080".parse().unwrap())];
+
+		let (addr, server) = mock_server(vec![ADDED, LISTED, "552 Unknown Onion Service id\r\n"]);
+		let mut controller = TorController::connect(addr).unwrap();
+		let flags = [AddOnionFlag::DiscardPK, AddOnionFlag::Detach];
+		match controller.rotate_onion(&old, &ports, &flags) {
+			Err(Error::OnionRotationIncomplete { new, .. }) => {
+				assert_eq!(
+					new.service_id,
+					ServiceID::from("qcu2ypqqeyhd3mvh2fg6p2mwxyi4dhxjldyteoze4uvstuqzzu4wk7yd")
+				);
+				assert_eq!(new.private_key, "");
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert_eq!(
+			server.join().unwrap()[..2],
+			[
+				"ADD_ONION NEW:ED25519-V3 Flags=DiscardPK,Detach Port=80,127.0.0.1:8080",
+				"GETINFO onions/detached"
+			]
+		);
+	}
+
+	/// Needs a local tor with an authenticated control port; run with
+	/// `cargo test -- --ignored`.
+	#[test]
+	#[ignore]
+	fn serve_hidden_live() {
+		let key_path = temp_key_path("serve-hidden-live");
+		let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+
+		let created = get_controller().serve_hidden(local, 80, &key_path).unwrap();
+		let restored = get_controller().serve_hidden(local, 80, &key_path).unwrap();
+		assert_eq!(restored.service_id, created.service_id);
+		std::fs::remove_file(&key_path).unwrap();
+	}
+
+	/// Needs a local tor, see `serve_hidden_live`.
+	#[cfg(feature = "keys")]
+	#[test]
+	#[ignore]
+	fn add_onion_with_generated_key_live() {
+		let key = crate::keys::OnionKeyPair::generate();
+		let mut controller = get_controller();
+
+		let hidden_service = controller.add_onion_with_key(&key, 80).unwrap();
+		assert_eq!(hidden_service.service_id, key.service_id());
+		controller.delete_onion(hidden_service.service_id).unwrap();
 	}
 
 	#[test]
@@ -311,7 +4981,7 @@ mod tests {
 
 		let password =
 			std::env::var("TOR_CONTROLLER_PASSWORD").expect("TOR_CONTROLLER_PASSWORD is not set");
-		let result = TorController::connect_default_with_password(password);
+		let result = TorController::builder().password(password).build();
 		assert!(result.is_ok());
 	}
 
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::incremental::{ReplyAccumulator, ReplyLine};
+use super::version::Capability;
+use super::{AddOnionFlag, HiddenService, KeyType, ServiceID, TorController};
+use crate::parsers;
+
+/// A virtual port of an onion service and where Tor forwards it to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OnionPort {
+	pub virt_port: u16,
+	/// Defaults to the same port on localhost when not given.
+	pub target:    Option<SocketAddr>,
+}
+
+impl OnionPort {
+	pub fn new(virt_port: u16, target: SocketAddr) -> Self {
+		OnionPort {
+			virt_port,
+			target: Some(target),
+		}
+	}
+}
+
+impl fmt::Display for OnionPort {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.target {
+			Some(target) => write!(f, "Port={},{}", self.virt_port, target),
+			None => write!(f, "Port={}", self.virt_port),
+		}
+	}
+}
+
+/// Collects the services listed by GETINFO onions/*, one per line. Services
+/// with many ephemeral onions list thousands.
+#[derive(Default)]
+pub(super) struct ServiceList(Vec<ServiceID>);
+
+impl ReplyAccumulator for ServiceList {
+	type Output = Vec<ServiceID>;
+
+	fn push_line(&mut self, line: ReplyLine<'_>) -> Result<(), Error> {
+		let service = line.as_str().trim_end_matches(".onion");
+		self.0.push(ServiceID::from(service));
+		Ok(())
+	}
+
+	fn finish(self) -> Result<Vec<ServiceID>, Error> {
+		Ok(self.0)
+	}
+}
+
+/// Reads a key file written by `serve_hidden`: the private key in the form
+/// ADD_ONION expects, followed by the onion address it belongs to.
+fn read_key_file(key_path: &Path) -> Result<(KeyType, String, ServiceID), Error> {
+	let contents = fs::read_to_string(key_path)?;
+	let mut lines = contents.lines();
+	let (key_type, key_blob) = lines
+		.next()
+		.and_then(|line| line.split_once(':'))
+		.ok_or(Error::MalformedKeyFile)?;
+	let key_type = KeyType::from_str(key_type).map_err(|_| Error::MalformedKeyFile)?;
+	let service_id = lines
+		.next()
+		.and_then(|line| line.strip_suffix(".onion"))
+		.ok_or(Error::MalformedKeyFile)?;
+
+	Ok((key_type, key_blob.to_string(), ServiceID::from(service_id)))
+}
+
+/// Writes a new key file that only the current user can read.
+fn write_key_file(key_path: &Path, hidden_service: &HiddenService) -> Result<(), Error> {
+	let mut options = OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+
+	let mut file = options.open(key_path)?;
+	let written = writeln!(
+		file,
+		"{}:{}",
+		hidden_service.key_kind.to_string(),
+		hidden_service.private_key
+	)
+	.and_then(|_| writeln!(file, "{}.onion", hidden_service.service_id.0));
+	// A partly written file would be taken for a saved key on the next run.
+	if written.is_err() {
+		let _ = fs::remove_file(key_path);
+	}
+	Ok(written?)
+}
+
+impl TorController {
+	/// Sends ADD_ONION with the given key argument and port mappings.
+	pub(super) fn send_add_onion(
+		&mut self,
+		key: &str,
+		flags: &[AddOnionFlag],
+		ports: &[OnionPort],
+	) -> Result<(ServiceID, Option<(KeyType, String)>), Error> {
+		self.capabilities.require(Capability::AddOnion)?;
+		if flags.contains(&AddOnionFlag::V3Auth) {
+			self.capabilities.require(Capability::V3Auth)?;
+		}
+		let mut add_onion_command = format!("ADD_ONION {}", key);
+		if !flags.is_empty() {
+			let flags: Vec<String> = flags.iter().map(AddOnionFlag::to_string).collect();
+			add_onion_command.push_str(" Flags=");
+			add_onion_command.push_str(&flags.join(","));
+		}
+		for port in ports {
+			add_onion_command.push(' ');
+			add_onion_command.push_str(&port.to_string());
+		}
+		self.send(add_onion_command, parsers::add_onion)
+	}
+
+	/// Exposes `local` as `virt_port` of an onion service whose address stays the
+	/// same across restarts.
+	///
+	/// The first time, a new ED25519-V3 service is created and its key saved to
+	/// `key_path`, readable only by the current user. Afterwards the saved key is
+	/// used to bring back the same service. If Tor reports a different address
+	/// than the one saved with the key, the service is removed again and
+	/// `Error::ServiceIdMismatch` is returned.
+	pub fn serve_hidden(
+		&mut self,
+		local: SocketAddr,
+		virt_port: u16,
+		key_path: &Path,
+	) -> Result<HiddenService, Error> {
+		let ports = [OnionPort::new(virt_port, local)];
+
+		if !key_path.exists() {
+			let key = format!("NEW:{}", KeyType::ED25519V3.to_string());
+			let (service_id, key) = self.send_add_onion(&key, &[], &ports)?;
+			let (key_type, private_key) = key.ok_or(Error::InternalError)?;
+			let hidden_service = HiddenService {
+				service_id,
+				key_kind: key_type,
+				private_key,
+			};
+			// Without the saved key the address would be lost for good, so the
+			// service doesn't stay up if the key can't be written.
+			if let Err(err) = write_key_file(key_path, &hidden_service) {
+				error!(
+					"Failed to save the key of {}.onion to {}, removing it: {}",
+					hidden_service.service_id.0,
+					key_path.display(),
+					err
+				);
+				if let Err(err) = self.delete_onion(hidden_service.service_id) {
+					warn!("Failed to remove the onion service: {}", err);
+				}
+				return Err(err);
+			}
+			return Ok(hidden_service);
+		}
+
+		let (key_type, private_key, expected) = read_key_file(key_path)?;
+		let key = format!("{}:{}", key_type.to_string(), private_key);
+		let (service_id, _) = self.send_add_onion(&key, &[], &ports)?;
+		if service_id != expected {
+			error!(
+				"Key in {} belongs to {}.onion but Tor created {}.onion",
+				key_path.display(),
+				expected.0,
+				service_id.0
+			);
+			let actual = service_id.0.clone();
+			self.delete_onion(service_id)?;
+			return Err(Error::ServiceIdMismatch {
+				expected: expected.0,
+				actual,
+			});
+		}
+
+		Ok(HiddenService {
+			service_id,
+			key_kind: key_type,
+			private_key,
+		})
+	}
+
+	/// Replaces the onion service `old` with a new ED25519-V3 service, without
+	/// a moment where neither is running.
+	///
+	/// The new service is created and checked to be listed by Tor before `old`
+	/// is deleted. If anything goes wrong before that point, `old` is left
+	/// untouched. If only deleting `old` fails, the new service keeps running and
+	/// is returned in `Error::OnionRotationIncomplete`.
+	///
+	/// With `AddOnionFlag::DiscardPK`, the returned `private_key` is empty.
+	pub fn rotate_onion(
+		&mut self,
+		old: &ServiceID,
+		ports: &[OnionPort],
+		flags: &[AddOnionFlag],
+	) -> Result<HiddenService, Error> {
+		let key = format!("NEW:{}", KeyType::ED25519V3.to_string());
+		let (service_id, key) = self.send_add_onion(&key, flags, ports)?;
+		let (key_type, private_key) = key.unwrap_or((KeyType::ED25519V3, String::new()));
+		let new = HiddenService {
+			service_id,
+			key_kind: key_type,
+			private_key,
+		};
+
+		let listed = if flags.contains(&AddOnionFlag::Detach) {
+			self.get_info_incremental("onions/detached", ServiceList::default())?
+		} else {
+			self.get_onions_current()?
+		};
+		if !listed.contains(&new.service_id) {
+			let service_id = new.service_id.0.clone();
+			if let Err(err) = self.delete_onion(new.service_id) {
+				warn!(
+					"Failed to delete unlisted onion service {}: {}",
+					service_id, err
+				);
+			}
+			return Err(Error::OnionNotListed(service_id));
+		}
+
+		match self.delete_onion(old.clone()) {
+			Ok(()) => Ok(new),
+			Err(err) => Err(Error::OnionRotationIncomplete {
+				new:    Box::new(new),
+				source: Box::new(err),
+			}),
+		}
+	}
+}
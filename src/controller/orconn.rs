@@ -0,0 +1,192 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::TorController;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrConnStatus {
+	New,
+	Launched,
+	Connected,
+	Failed,
+	Closed,
+	Unknown(String),
+}
+
+impl FromStr for OrConnStatus {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"NEW" => OrConnStatus::New,
+			"LAUNCHED" => OrConnStatus::Launched,
+			"CONNECTED" => OrConnStatus::Connected,
+			"FAILED" => OrConnStatus::Failed,
+			"CLOSED" => OrConnStatus::Closed,
+			other => OrConnStatus::Unknown(other.to_string()),
+		})
+	}
+}
+
+impl fmt::Display for OrConnStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			OrConnStatus::New => "NEW",
+			OrConnStatus::Launched => "LAUNCHED",
+			OrConnStatus::Connected => "CONNECTED",
+			OrConnStatus::Failed => "FAILED",
+			OrConnStatus::Closed => "CLOSED",
+			OrConnStatus::Unknown(status) => status,
+		})
+	}
+}
+
+/// A connection to a relay, as listed by GETINFO orconn-status.
+///
+/// Tor only lists every connection's target and status, plus the ID and
+/// NCIRCS arguments of ORCONN events where it includes them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrConnInfo {
+	/// "$fingerprint~nickname" for relays Tor knows, otherwise "address:port".
+	pub target:     String,
+	pub status:     OrConnStatus,
+	pub id:         Option<u64>,
+	pub n_circuits: Option<u32>,
+}
+
+/// Parses "TARGET STATUS", optionally followed by KEY=VALUE arguments.
+impl FromStr for OrConnInfo {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut parts = s.split(' ');
+		let target = parts.next().filter(|target| !target.is_empty());
+		let status = parts.next();
+		let (target, status) = match (target, status) {
+			(Some(target), Some(status)) => (target, OrConnStatus::from_str(status)?),
+			_ => return Err(Error::InternalError),
+		};
+
+		let mut conn = OrConnInfo {
+			target: target.to_string(),
+			status,
+			id: None,
+			n_circuits: None,
+		};
+		for (key, value) in parts.filter_map(|arg| arg.split_once('=')) {
+			match key {
+				"ID" => conn.id = Some(u64::from_str(value).map_err(|_| Error::InternalError)?),
+				"NCIRCS" => conn.n_circuits = Some(u32::from_str(value).map_err(|_| Error::InternalError)?),
+				_ => {}
+			}
+		}
+		Ok(conn)
+	}
+}
+
+/// Totals over all OR connections, see `TorController::orconn_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OrConnStats {
+	pub total_connections: usize,
+	/// The circuits on connections whose circuit count Tor reported.
+	pub total_circuits:    u64,
+	pub connected:         usize,
+	pub failed:            usize,
+}
+
+/// The cells read and written on a connection, one entry per time bucket,
+/// oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CellHistory {
+	pub cells_read:    Vec<u32>,
+	pub cells_written: Vec<u32>,
+	/// When each bucket started, in seconds since the Unix epoch.
+	pub timestamp:     Vec<u64>,
+}
+
+/// The bytes read and written on a connection during one time bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BwSample {
+	/// When the bucket started, in seconds since the Unix epoch.
+	pub timestamp:     u64,
+	pub bytes_read:    u64,
+	pub bytes_written: u64,
+}
+
+// "timestamp read written" lines, as in the usage-tracking/* replies.
+fn usage_samples(history: &str) -> Result<Vec<(u64, u64, u64)>, Error> {
+	history
+		.lines()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| {
+			let numbers = line
+				.split_whitespace()
+				.map(u64::from_str)
+				.collect::<Result<Vec<u64>, _>>()
+				.map_err(|_| Error::InternalError)?;
+			match numbers[..] {
+				[timestamp, read, written] => Ok((timestamp, read, written)),
+				_ => Err(Error::InternalError),
+			}
+		})
+		.collect()
+}
+
+impl TorController {
+	/// Lists Tor's connections to other relays.
+	pub fn get_orconn_status(&mut self) -> Result<Vec<OrConnInfo>, Error> {
+		let conns = self.get_info_value("orconn-status")?;
+		conns
+			.lines()
+			.filter(|line| !line.is_empty())
+			.map(OrConnInfo::from_str)
+			.collect()
+	}
+
+	/// The cell counts of the connection `conn_id`, see `OrConnInfo::id`, from
+	/// GETINFO usage-tracking/cell-history/<conn_id>.
+	pub fn get_connection_cell_history(&mut self, conn_id: u64) -> Result<CellHistory, Error> {
+		let history = self.get_info_value(&format!("usage-tracking/cell-history/{}", conn_id))?;
+		let cells = |count: u64| u32::try_from(count).map_err(|_| Error::InternalError);
+		let mut cell_history = CellHistory::default();
+		for (timestamp, read, written) in usage_samples(&history)? {
+			cell_history.timestamp.push(timestamp);
+			cell_history.cells_read.push(cells(read)?);
+			cell_history.cells_written.push(cells(written)?);
+		}
+		Ok(cell_history)
+	}
+
+	/// The bandwidth used by the connection `conn_id`, from GETINFO
+	/// usage-tracking/bw-history/<conn_id>.
+	pub fn get_per_conn_bw(&mut self, conn_id: u64) -> Result<Vec<BwSample>, Error> {
+		let history = self.get_info_value(&format!("usage-tracking/bw-history/{}", conn_id))?;
+		Ok(
+			usage_samples(&history)?
+				.into_iter()
+				.map(|(timestamp, bytes_read, bytes_written)| BwSample {
+					timestamp,
+					bytes_read,
+					bytes_written,
+				})
+				.collect(),
+		)
+	}
+
+	/// Sums up `get_orconn_status`.
+	pub fn orconn_stats(&mut self) -> Result<OrConnStats, Error> {
+		let mut stats = OrConnStats::default();
+		for conn in self.get_orconn_status()? {
+			stats.total_connections += 1;
+			stats.total_circuits += u64::from(conn.n_circuits.unwrap_or(0));
+			match conn.status {
+				OrConnStatus::Connected => stats.connected += 1,
+				OrConnStatus::Failed => stats.failed += 1,
+				_ => {}
+			}
+		}
+		Ok(stats)
+	}
+}
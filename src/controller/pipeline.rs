@@ -0,0 +1,83 @@
+use nom::IResult;
+
+use super::error::Error;
+use super::{command_keyword, parse_reply, TorController};
+
+type ReplyParser<T> = fn(&str) -> IResult<&str, T>;
+
+/// A batch of commands sent to Tor without waiting for the individual replies.
+///
+/// All queued commands are written with a single write when the pipeline is
+/// run, after which the replies are read back in the order the commands were
+/// queued. Every reply is consumed even if an earlier one fails to parse, so the
+/// connection stays in sync for the commands that follow.
+pub struct Pipeline<'a, T> {
+	controller: &'a mut TorController,
+	commands:   Vec<(String, ReplyParser<T>)>,
+}
+
+impl<'a, T> Pipeline<'a, T> {
+	pub(super) fn new(controller: &'a mut TorController) -> Self {
+		Pipeline {
+			controller,
+			commands: Vec::new(),
+		}
+	}
+
+	/// Queues a command together with the parser for its reply.
+	pub fn push<S: Into<String>>(mut self, command: S, reply_parser: ReplyParser<T>) -> Self {
+		self.commands.push((command.into(), reply_parser));
+		self
+	}
+
+	/// Sends all queued commands and returns the parsed replies in order.
+	///
+	/// The outer error is returned when the connection itself fails, in which case
+	/// the remaining replies can no longer be read.
+	pub fn run(self) -> Result<Vec<Result<T, Error>>, Error> {
+		let raw_replies = self.exchange();
+		let last_command = self.commands.last().map(|(command, _)| command.as_str());
+		let last_reply = raw_replies.as_ref().ok().and_then(|replies| replies.last());
+		if let Some(command) = last_command {
+			self
+				.controller
+				.remember_reply(command, last_reply.map(String::as_str));
+		}
+
+		let replies = raw_replies?
+			.iter()
+			.zip(self.commands.iter())
+			.map(|(reply, (_, reply_parser))| parse_reply(reply, reply_parser))
+			.collect();
+		Ok(replies)
+	}
+
+	fn exchange(&self) -> Result<Vec<String>, Error> {
+		for (command, _) in self.commands.iter() {
+			self.controller.check_authenticated(command)?;
+		}
+		// Hold the connection for the whole batch so nothing gets in between.
+		let mut conn = self.controller.lock()?;
+
+		let mut bytes = Vec::new();
+		for (command, _) in self.commands.iter() {
+			debug!("-> {}", command);
+			bytes.extend_from_slice(command.as_bytes());
+			bytes.extend_from_slice(b"\r\n");
+		}
+		conn.write(&bytes)?;
+
+		// The command timeout bounds each reply, as it does for single commands.
+		let timeout = self.controller.command_timeout;
+		let mut replies = Vec::with_capacity(self.commands.len());
+		for (command, _) in self.commands.iter() {
+			let mut reply = String::new();
+			match timeout {
+				Some(timeout) => conn.read_reply_within(command_keyword(command), timeout, &mut reply)?,
+				None => conn.read_reply_into(&mut reply)?,
+			}
+			replies.push(reply);
+		}
+		Ok(replies)
+	}
+}
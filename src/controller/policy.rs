@@ -0,0 +1,394 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use super::error::Error;
+use super::microdesc::Microdescriptor;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// An inclusive range of ports, e.g. "8080-8090". A single port has the same
+/// start and end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PortRange {
+	pub start: u16,
+	pub end:   u16,
+}
+
+impl PortRange {
+	/// Every port, written "*" in policy summaries.
+	pub const ALL: PortRange = PortRange {
+		start: 1,
+		end:   65535,
+	};
+
+	pub fn contains(&self, port: u16) -> bool {
+		self.start <= port && port <= self.end
+	}
+}
+
+impl fmt::Display for PortRange {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.start == self.end {
+			write!(f, "{}", self.start)
+		} else {
+			write!(f, "{}-{}", self.start, self.end)
+		}
+	}
+}
+
+/// A compact exit policy, as in "accept 80,443,8080-8090 reject *".
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExitPolicySummary {
+	pub accept_ports: Vec<PortRange>,
+	pub reject_ports: Vec<PortRange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+	Accept,
+	Reject,
+}
+
+/// The addresses an exit policy rule applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressPattern {
+	/// "*"
+	Any,
+	/// "*4"
+	AnyIpv4,
+	/// "*6"
+	AnyIpv6,
+	/// An address with the number of leading bits that have to match, e.g.
+	/// "10.0.0.0/8" or "[2001:db8::]/32". A plain address matches all bits.
+	Network(IpAddr, u8),
+}
+
+impl AddressPattern {
+	pub fn matches(&self, addr: IpAddr) -> bool {
+		match (*self, addr) {
+			(AddressPattern::Any, _) => true,
+			(AddressPattern::AnyIpv4, addr) => addr.is_ipv4(),
+			(AddressPattern::AnyIpv6, addr) => addr.is_ipv6(),
+			(AddressPattern::Network(IpAddr::V4(network), bits), IpAddr::V4(addr)) => {
+				let mask = u32::MAX.checked_shl(32 - u32::from(bits)).unwrap_or(0);
+				u32::from(network) & mask == u32::from(addr) & mask
+			}
+			(AddressPattern::Network(IpAddr::V6(network), bits), IpAddr::V6(addr)) => {
+				let mask = u128::MAX.checked_shl(128 - u32::from(bits)).unwrap_or(0);
+				u128::from(network) & mask == u128::from(addr) & mask
+			}
+			_ => false,
+		}
+	}
+
+	// Whether the pattern covers every IPv4 address, as counted in summaries.
+	fn covers_ipv4(&self) -> bool {
+		match self {
+			AddressPattern::Any | AddressPattern::AnyIpv4 => true,
+			AddressPattern::Network(IpAddr::V4(_), bits) => *bits == 0,
+			_ => false,
+		}
+	}
+}
+
+impl FromStr for AddressPattern {
+	type Err = Error;
+
+	/// Parses "*", "*4", "*6", "a.b.c.d", "[v6]" or either address followed by
+	/// "/bits". IPv4 networks may also be given by a mask, as in
+	/// "10.0.0.0/255.0.0.0".
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || Error::InternalError;
+		match s {
+			"*" => return Ok(AddressPattern::Any),
+			"*4" => return Ok(AddressPattern::AnyIpv4),
+			"*6" => return Ok(AddressPattern::AnyIpv6),
+			_ => {}
+		}
+		let (addr, bits) = match s.rsplit_once('/') {
+			Some((addr, bits)) => (addr, Some(bits)),
+			None => (s, None),
+		};
+		let addr = match addr
+			.strip_prefix('[')
+			.and_then(|addr| addr.strip_suffix(']'))
+		{
+			Some(v6) => IpAddr::V6(Ipv6Addr::from_str(v6).map_err(|_| invalid())?),
+			None => IpAddr::V4(Ipv4Addr::from_str(addr).map_err(|_| invalid())?),
+		};
+		let max_bits = if addr.is_ipv4() { 32 } else { 128 };
+		let bits = match bits {
+			None => max_bits,
+			Some(bits) => match (u8::from_str(bits), Ipv4Addr::from_str(bits), addr) {
+				(Ok(bits), _, _) if bits <= max_bits => bits,
+				(_, Ok(mask), IpAddr::V4(_)) => u32::from(mask).count_ones() as u8,
+				_ => return Err(invalid()),
+			},
+		};
+		Ok(AddressPattern::Network(addr, bits))
+	}
+}
+
+/// A line of an exit policy, e.g. "reject 10.0.0.0/8:*".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitPolicyRule {
+	pub action:  PolicyAction,
+	pub address: AddressPattern,
+	pub ports:   PortRange,
+}
+
+impl ExitPolicyRule {
+	pub fn matches(&self, addr: IpAddr, port: u16) -> bool {
+		self.ports.contains(port) && self.address.matches(addr)
+	}
+}
+
+impl FromStr for ExitPolicyRule {
+	type Err = Error;
+
+	/// Parses "accept" or "reject", or "accept6" and "reject6" for IPv6 only
+	/// rules, followed by "address:ports".
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (action, target) = s.trim().split_once(' ').ok_or(Error::InternalError)?;
+		let (action, ipv6_only) = match action {
+			"accept" => (PolicyAction::Accept, false),
+			"reject" => (PolicyAction::Reject, false),
+			"accept6" => (PolicyAction::Accept, true),
+			"reject6" => (PolicyAction::Reject, true),
+			_ => return Err(Error::InternalError),
+		};
+		let (address, ports) = target.rsplit_once(':').ok_or(Error::InternalError)?;
+		let mut address = AddressPattern::from_str(address)?;
+		if ipv6_only {
+			address = match address {
+				AddressPattern::Any => AddressPattern::AnyIpv6,
+				AddressPattern::Network(IpAddr::V6(_), _) => address,
+				_ => return Err(Error::InternalError),
+			};
+		}
+		let ports = match ports {
+			"*" => PortRange::ALL,
+			ports => {
+				let (start, end) = ports.split_once('-').unwrap_or((ports, ports));
+				let port = |port: &str| u16::from_str(port).map_err(|_| Error::InternalError);
+				PortRange {
+					start: port(start)?,
+					end:   port(end)?,
+				}
+			}
+		};
+		Ok(ExitPolicyRule {
+			action,
+			address,
+			ports,
+		})
+	}
+}
+
+/// An exit policy as a list of rules, the first matching one deciding.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExitPolicy {
+	pub rules: Vec<ExitPolicyRule>,
+}
+
+impl ExitPolicy {
+	/// Whether the policy allows connecting to `addr` on `port`. Like Tor,
+	/// addresses no rule matches are accepted.
+	pub fn can_exit_to(&self, addr: IpAddr, port: u16) -> bool {
+		self
+			.rules
+			.iter()
+			.find(|rule| rule.matches(addr, port))
+			.is_none_or(|rule| rule.action == PolicyAction::Accept)
+	}
+
+	/// The IPv4 ports the policy accepts for all but a few addresses, as Tor
+	/// summarizes policies in microdescriptors. Rules for specific networks,
+	/// such as rejecting private addresses, don't count. Like Tor's, the
+	/// summary lists whichever of the accepted or rejected ports is shorter,
+	/// and a policy accepting nothing is "reject 1-65535".
+	pub fn to_summary(&self) -> ExitPolicySummary {
+		let rules: Vec<&ExitPolicyRule> = self
+			.rules
+			.iter()
+			.filter(|rule| rule.address.covers_ipv4())
+			.collect();
+
+		// Within each segment between rule boundaries, every port is decided
+		// by the same rule.
+		let mut starts: Vec<u32> = vec![1];
+		for rule in &rules {
+			starts.push(u32::from(rule.ports.start));
+			starts.push(u32::from(rule.ports.end) + 1);
+		}
+		starts.retain(|start| *start <= 65535);
+		starts.sort_unstable();
+		starts.dedup();
+
+		let mut accepted: Vec<PortRange> = Vec::new();
+		let mut rejected: Vec<PortRange> = Vec::new();
+		for (n, start) in starts.iter().enumerate() {
+			let end = starts.get(n + 1).map_or(65535, |next| next - 1);
+			let (start, end) = (*start as u16, end as u16);
+			let accept = rules
+				.iter()
+				.find(|rule| rule.ports.contains(start))
+				.is_none_or(|rule| rule.action == PolicyAction::Accept);
+			let ranges = if accept { &mut accepted } else { &mut rejected };
+			match ranges.last_mut() {
+				Some(last) if u32::from(last.end) + 1 == u32::from(start) => last.end = end,
+				_ => ranges.push(PortRange { start, end }),
+			}
+		}
+
+		if !accepted.is_empty() && accepted.len() <= rejected.len() {
+			ExitPolicySummary {
+				accept_ports: accepted,
+				reject_ports: Vec::new(),
+			}
+		} else {
+			ExitPolicySummary {
+				accept_ports: Vec::new(),
+				reject_ports: rejected,
+			}
+		}
+	}
+}
+
+/// Parses rules separated by commas or newlines, as in GETINFO
+/// exit-policy/full.
+impl FromStr for ExitPolicy {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let rules = s
+			.split([',', '\n'])
+			.map(str::trim)
+			.filter(|rule| !rule.is_empty())
+			.map(ExitPolicyRule::from_str)
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(ExitPolicy { rules })
+	}
+}
+
+impl Microdescriptor {
+	/// Parses the relay's IPv4 exit policy summary, if it has one.
+	pub fn policy_summary(&self) -> Option<ExitPolicySummary> {
+		let policy = self.policy.as_deref()?;
+		parse_reply(policy, parsers::exit_policy_summary).ok()
+	}
+}
+
+impl TorController {
+	/// Whether the exit policy rejects connections to private addresses.
+	pub fn rejects_private_addresses(&mut self) -> Result<bool, Error> {
+		match self.get_info_value("exit-policy/reject-private")?.as_str() {
+			"1" => Ok(true),
+			"0" => Ok(false),
+			_ => Err(Error::InternalError),
+		}
+	}
+
+	pub fn get_exit_policy_summary(&mut self) -> Result<ExitPolicySummary, Error> {
+		let summary = self.get_info_value("exit-policy/summary")?;
+		parse_reply(&summary, parsers::exit_policy_summary)
+	}
+
+	/// Every rule of the exit policy, including the ones Tor adds itself.
+	pub fn get_exit_policy_full(&mut self) -> Result<ExitPolicy, Error> {
+		ExitPolicy::from_str(&self.get_info_value("exit-policy/full")?)
+	}
+
+	/// The rules of the exit policy that apply to IPv6 addresses.
+	pub fn get_exit_policy_ipv6(&mut self) -> Result<ExitPolicy, Error> {
+		ExitPolicy::from_str(&self.get_info_value("exit-policy/ipv6")?)
+	}
+
+	/// Whether the relay allows exiting to IPv6 addresses.
+	pub fn get_ipv6_reachability(&mut self) -> Result<bool, Error> {
+		match self.get_info_value("exit-policy/ipv6-enabled")?.as_str() {
+			"1" => Ok(true),
+			"0" => Ok(false),
+			_ => Err(Error::InternalError),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn addr(addr: &str) -> IpAddr {
+		IpAddr::from_str(addr).unwrap()
+	}
+
+	#[test]
+	fn can_exit_to() {
+		let web = ExitPolicy::from_str("accept *:80,reject *:*").unwrap();
+		assert!(web.can_exit_to(addr("93.184.216.34"), 80));
+		assert!(web.can_exit_to(addr("2001:db8::1"), 80));
+		assert!(!web.can_exit_to(addr("93.184.216.34"), 443));
+
+		let no_private = ExitPolicy::from_str("reject 10.0.0.0/8:*\naccept *:*").unwrap();
+		assert!(!no_private.can_exit_to(addr("10.1.2.3"), 80));
+		assert!(no_private.can_exit_to(addr("11.1.2.3"), 80));
+		let masked = ExitPolicy::from_str("reject 10.0.0.0/255.0.0.0:*").unwrap();
+		assert_eq!(masked, ExitPolicy::from_str("reject 10.0.0.0/8:*").unwrap());
+
+		let reject_all = ExitPolicy::from_str("reject *:*").unwrap();
+		assert!(!reject_all.can_exit_to(addr("93.184.216.34"), 80));
+		assert!(!reject_all.can_exit_to(addr("::1"), 22));
+
+		let v6 = ExitPolicy::from_str("reject6 [2001:db8::]/32:*,accept6 *:443").unwrap();
+		assert!(!v6.can_exit_to(addr("2001:db8::1"), 443));
+		assert!(v6.can_exit_to(addr("2001:db9::1"), 443));
+		// No rule applies to IPv4 addresses.
+		assert!(v6.can_exit_to(addr("93.184.216.34"), 22));
+		assert!(ExitPolicy::from_str("accept6 1.2.3.4:80").is_err());
+		assert!(ExitPolicy::from_str("allow *:80").is_err());
+	}
+
+	#[test]
+	fn summarize() {
+		let policy = ExitPolicy::from_str(
+			"reject 0.0.0.0/8:*,reject 10.0.0.0/8:*,accept *:80,accept *:443,\
+			accept *:8080-8090,accept 192.0.2.0/24:22,reject *:*",
+		)
+		.unwrap();
+		let summary = policy.to_summary();
+		assert_eq!(
+			summary.accept_ports,
+			vec![
+				PortRange {
+					start: 80,
+					end:   80,
+				},
+				PortRange {
+					start: 443,
+					end:   443,
+				},
+				PortRange {
+					start: 8080,
+					end:   8090,
+				},
+			]
+		);
+		assert!(summary.reject_ports.is_empty());
+
+		let summary = ExitPolicy::from_str("reject *:25,accept *:*")
+			.unwrap()
+			.to_summary();
+		assert_eq!(
+			summary.reject_ports,
+			vec![PortRange {
+				start: 25,
+				end:   25,
+			}]
+		);
+		assert!(summary.accept_ports.is_empty());
+
+		let summary = ExitPolicy::from_str("reject *:*").unwrap().to_summary();
+		assert_eq!(summary.reject_ports, vec![PortRange::ALL]);
+	}
+}
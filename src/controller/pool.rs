@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::num::NonZeroUsize;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use super::auth::AuthAttempt;
+use super::builder::TorControllerBuilder;
+use super::error::Error;
+use super::{Signal, TorController};
+
+/// How often `TorControllerPool` probes the connections nobody is using.
+const POOL_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+struct PoolEntry {
+	name:       String,
+	controller: Option<TorController>,
+	// Kept until the first connection succeeds.
+	builder:    Option<Box<TorControllerBuilder>>,
+}
+
+impl PoolEntry {
+	/// Connects on first use, and reconnects once the connection was lost.
+	fn controller(&mut self) -> Result<&mut TorController, Error> {
+		if let Some(builder) = &self.builder {
+			let mut controller = builder.connect()?;
+			controller.builder = self.builder.take();
+			self.controller = Some(controller);
+		}
+		let controller = self.controller.as_mut().ok_or(Error::ConnectionLost)?;
+		if controller.is_connection_lost() {
+			controller.reconnect()?;
+		}
+		Ok(controller)
+	}
+}
+
+/// The outcome of running an operation on every instance of a
+/// `ControllerPool`. A failing instance doesn't stop the others.
+#[derive(Debug)]
+pub struct PoolReport<T = ()> {
+	pub succeeded: Vec<(String, T)>,
+	pub failed:    Vec<(String, Error)>,
+}
+
+impl<T> PoolReport<T> {
+	pub fn is_success(&self) -> bool {
+		self.failed.is_empty()
+	}
+}
+
+/// Several Tor instances, e.g. one per isolation domain, controlled by name.
+///
+/// Instances added with `add_lazy` connect when first used. An instance whose
+/// connection was lost reconnects the next time it is used, if it was created
+/// by a builder.
+#[derive(Default)]
+pub struct ControllerPool {
+	entries: Vec<PoolEntry>,
+}
+
+impl ControllerPool {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a connected controller, replacing any instance of the same name.
+	pub fn add(&mut self, name: impl Into<String>, controller: TorController) {
+		self.insert(name.into(), Some(controller), None);
+	}
+
+	/// Adds an instance that connects with `builder` when first used.
+	pub fn add_lazy(&mut self, name: impl Into<String>, builder: TorControllerBuilder) {
+		self.insert(name.into(), None, Some(Box::new(builder)));
+	}
+
+	fn insert(
+		&mut self,
+		name: String,
+		controller: Option<TorController>,
+		builder: Option<Box<TorControllerBuilder>>,
+	) {
+		self.entries.retain(|entry| entry.name != name);
+		self.entries.push(PoolEntry {
+			name,
+			controller,
+			builder,
+		});
+	}
+
+	/// Removes an instance, returning its controller if it was connected.
+	pub fn remove(&mut self, name: &str) -> Option<TorController> {
+		let index = self.entries.iter().position(|entry| entry.name == name)?;
+		self.entries.remove(index).controller
+	}
+
+	/// The instance names, in the order they were added.
+	pub fn names(&self) -> impl Iterator<Item = &str> {
+		self.entries.iter().map(|entry| entry.name.as_str())
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// The controller of an instance, connecting or reconnecting it if needed.
+	pub fn get(&mut self, name: &str) -> Result<&mut TorController, Error> {
+		self
+			.entries
+			.iter_mut()
+			.find(|entry| entry.name == name)
+			.ok_or_else(|| Error::UnknownInstance(name.to_string()))?
+			.controller()
+	}
+
+	/// Runs `f` on every instance, connecting them as needed.
+	pub fn for_each<F, T>(&mut self, mut f: F) -> PoolReport<T>
+	where
+		F: FnMut(&mut TorController) -> Result<T, Error>,
+	{
+		let mut report = PoolReport {
+			succeeded: Vec::new(),
+			failed:    Vec::new(),
+		};
+		for entry in self.entries.iter_mut() {
+			match entry.controller().and_then(&mut f) {
+				Ok(value) => report.succeeded.push((entry.name.clone(), value)),
+				Err(err) => report.failed.push((entry.name.clone(), err)),
+			}
+		}
+		report
+	}
+
+	pub fn broadcast_signal(&mut self, signal: Signal) -> PoolReport {
+		self.for_each(|controller| controller.signal(signal))
+	}
+
+	/// Probes every instance with `TorController::is_alive`, reconnecting
+	/// those that don't answer. Instances that can't be reached are reported
+	/// as failed.
+	pub fn health_check(&mut self) -> PoolReport {
+		self.for_each(|controller| {
+			if controller.is_alive() {
+				return Ok(());
+			}
+			controller.reconnect()?;
+			if controller.is_alive() {
+				Ok(())
+			} else {
+				Err(Error::ConnectionLost)
+			}
+		})
+	}
+}
+
+/// A fixed number of authenticated connections to the same control port, for
+/// sending commands from several threads at once.
+///
+/// `acquire` hands out the connection that has been idle the longest and
+/// blocks while all of them are in use. Idle connections are probed with the
+/// keepalive of `TorController::enable_keepalive`, and one found dead is
+/// replaced by a fresh connection the next time it is acquired.
+///
+/// Like the rest of the crate, the pool is blocking: `acquire` waits on the
+/// calling thread rather than returning a future, since there's no async
+/// runtime to drive one. Async code can call it from a blocking task, or use
+/// `try_acquire` to poll.
+pub struct TorControllerPool {
+	addrs:    Vec<SocketAddr>,
+	auth:     AuthAttempt,
+	size:     usize,
+	// Least recently used first.
+	idle:     Mutex<VecDeque<TorController>>,
+	released: Condvar,
+}
+
+impl TorControllerPool {
+	/// Opens and authenticates all `size` connections to `addr` up front. A
+	/// pool can't be empty, since `acquire` would wait forever.
+	pub fn new(
+		addr: impl ToSocketAddrs,
+		auth: AuthAttempt,
+		size: NonZeroUsize,
+	) -> Result<Self, Error> {
+		let size = size.get();
+		let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+		let mut idle = VecDeque::with_capacity(size);
+		for _ in 0..size {
+			idle.push_back(Self::open(&addrs, &auth)?);
+		}
+		Ok(TorControllerPool {
+			addrs,
+			auth,
+			size,
+			idle: Mutex::new(idle),
+			released: Condvar::new(),
+		})
+	}
+
+	fn open(addrs: &[SocketAddr], auth: &AuthAttempt) -> Result<TorController, Error> {
+		let mut controller = TorController::connect_with_fallback(addrs, std::slice::from_ref(auth))?;
+		controller.enable_keepalive(POOL_KEEPALIVE_INTERVAL);
+		Ok(controller)
+	}
+
+	/// Takes a connection out of the pool, waiting until one is returned if
+	/// all of them are in use.
+	pub fn acquire(&self) -> Result<PooledController<'_>, Error> {
+		let mut idle = self.lock();
+		loop {
+			if let Some(controller) = idle.pop_front() {
+				drop(idle);
+				return self.checked_out(controller);
+			}
+			idle = self
+				.released
+				.wait(idle)
+				.unwrap_or_else(PoisonError::into_inner);
+		}
+	}
+
+	/// Like `acquire`, but returns `None` instead of waiting.
+	pub fn try_acquire(&self) -> Result<Option<PooledController<'_>>, Error> {
+		let controller = self.lock().pop_front();
+		controller
+			.map(|controller| self.checked_out(controller))
+			.transpose()
+	}
+
+	// Replaces a connection the keepalive found dead. If that fails, the dead
+	// one goes back to the pool to be replaced on a later acquire.
+	fn checked_out(&self, controller: TorController) -> Result<PooledController<'_>, Error> {
+		let controller = if controller.is_connection_lost() {
+			match Self::open(&self.addrs, &self.auth) {
+				Ok(fresh) => fresh,
+				Err(err) => {
+					self.release(controller);
+					return Err(err);
+				}
+			}
+		} else {
+			controller
+		};
+		Ok(PooledController {
+			pool:       self,
+			controller: Some(controller),
+		})
+	}
+
+	fn release(&self, controller: TorController) {
+		self.lock().push_back(controller);
+		self.released.notify_one();
+	}
+
+	fn lock(&self) -> MutexGuard<'_, VecDeque<TorController>> {
+		self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// The number of connections, in use or not.
+	pub fn size(&self) -> usize {
+		self.size
+	}
+
+	/// The number of connections `acquire` can hand out right away.
+	pub fn idle_count(&self) -> usize {
+		self.lock().len()
+	}
+}
+
+/// A connection taken out of a `TorControllerPool`, returned to it when
+/// dropped.
+pub struct PooledController<'a> {
+	pool:       &'a TorControllerPool,
+	controller: Option<TorController>,
+}
+
+impl Deref for PooledController<'_> {
+	type Target = TorController;
+
+	fn deref(&self) -> &TorController {
+		self
+			.controller
+			.as_ref()
+			.expect("the controller is only taken on drop")
+	}
+}
+
+impl DerefMut for PooledController<'_> {
+	fn deref_mut(&mut self) -> &mut TorController {
+		self
+			.controller
+			.as_mut()
+			.expect("the controller is only taken on drop")
+	}
+}
+
+impl Drop for PooledController<'_> {
+	fn drop(&mut self) {
+		if let Some(controller) = self.controller.take() {
+			self.pool.release(controller);
+		}
+	}
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use super::error::Error;
+use super::events::EventKind;
+use super::identity::Base32Address;
+use super::version::Capability;
+use super::{ServiceID, TorController};
+use crate::parsers;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HsDescAction {
+	Requested,
+	Upload,
+	Received,
+	Uploaded,
+	Ignore,
+	Failed,
+	Created,
+	Unknown(String),
+}
+
+impl FromStr for HsDescAction {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"REQUESTED" => HsDescAction::Requested,
+			"UPLOAD" => HsDescAction::Upload,
+			"RECEIVED" => HsDescAction::Received,
+			"UPLOADED" => HsDescAction::Uploaded,
+			"IGNORE" => HsDescAction::Ignore,
+			"FAILED" => HsDescAction::Failed,
+			"CREATED" => HsDescAction::Created,
+			other => HsDescAction::Unknown(other.to_string()),
+		})
+	}
+}
+
+impl fmt::Display for HsDescAction {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			HsDescAction::Requested => "REQUESTED",
+			HsDescAction::Upload => "UPLOAD",
+			HsDescAction::Received => "RECEIVED",
+			HsDescAction::Uploaded => "UPLOADED",
+			HsDescAction::Ignore => "IGNORE",
+			HsDescAction::Failed => "FAILED",
+			HsDescAction::Created => "CREATED",
+			HsDescAction::Unknown(action) => action,
+		})
+	}
+}
+
+/// An HS_DESC event, reporting progress fetching or publishing an onion
+/// service descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HsDescEvent {
+	pub action:        HsDescAction,
+	/// The onion address without ".onion", or "UNKNOWN".
+	pub address:       String,
+	pub auth_type:     String,
+	/// The HSDir involved, as `$fingerprint~nickname`, if Tor knows it.
+	pub hs_dir:        Option<String>,
+	pub descriptor_id: Option<String>,
+	pub reason:        Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnionProbeStatus {
+	/// An HSDir returned the service's descriptor.
+	Received,
+	/// Every HSDir Tor asked failed to return the descriptor.
+	Failed,
+	/// Tor didn't finish fetching the descriptor in time.
+	TimedOut,
+}
+
+/// The outcome of `TorController::probe_onion`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnionProbe {
+	pub status:  OnionProbeStatus,
+	/// The HSDir that returned the descriptor or, on failure, the last one
+	/// that failed.
+	pub hs_dir:  Option<String>,
+	/// Time from sending HSFETCH until the outcome was known.
+	pub latency: Duration,
+	/// Tor's reason for the last failure, e.g. "NOT_FOUND".
+	pub reason:  Option<String>,
+}
+
+impl TorController {
+	/// Checks whether the descriptor of an onion service can be fetched from
+	/// the HSDirs, which is the case once the service has been published.
+	///
+	/// Events for other addresses, such as those of concurrent probes, are left
+	/// queued for `poll_event`.
+	pub fn probe_onion(
+		&mut self,
+		service_id: &ServiceID,
+		timeout: Duration,
+	) -> Result<OnionProbe, Error> {
+		self.capabilities.require(Capability::HsFetch)?;
+		let address = service_id.0.trim_end_matches(".onion").to_string();
+		if let Ok(address) = Base32Address::from_str(&address) {
+			self.remember_hs_lookup(&address);
+		}
+
+		self.with_events(&[EventKind::HsDesc], |controller| {
+			let started = Instant::now();
+			let deadline = started + timeout;
+			controller.send(format!("HSFETCH {}", address), parsers::is_ok)?;
+
+			let mut probe = OnionProbe {
+				status:  OnionProbeStatus::TimedOut,
+				hs_dir:  None,
+				latency: timeout,
+				reason:  None,
+			};
+			let mut pending = 0;
+			loop {
+				let event = controller.wait_for_event(deadline, |event| {
+					match parsers::hs_desc_event(&event.raw) {
+						Ok((_, hs_desc)) => hs_desc.address == address,
+						Err(_) => false,
+					}
+				})?;
+				let hs_desc = match event {
+					Some(event) => parsers::hs_desc_event(&event.raw).unwrap().1,
+					None => return Ok(probe),
+				};
+
+				match hs_desc.action {
+					HsDescAction::Requested => pending += 1,
+					HsDescAction::Received => {
+						probe.status = OnionProbeStatus::Received;
+						probe.hs_dir = hs_desc.hs_dir;
+						probe.reason = None;
+						probe.latency = started.elapsed();
+						return Ok(probe);
+					}
+					HsDescAction::Failed => {
+						probe.hs_dir = hs_desc.hs_dir;
+						probe.reason = hs_desc.reason;
+						// Tor may fail right away without asking any HSDir.
+						pending -= 1;
+						if pending <= 0 {
+							probe.status = OnionProbeStatus::Failed;
+							probe.latency = started.elapsed();
+							return Ok(probe);
+						}
+					}
+					_ => {}
+				}
+			}
+		})
+	}
+}
@@ -0,0 +1,87 @@
+use std::sync::{Mutex, Weak};
+use std::time::Instant;
+
+use thiserror::Error;
+
+use super::connection::Connection;
+use super::error::Error;
+use super::events::RawEvent;
+use super::TorController;
+
+/// Returned by `EventReceiver` once no more events can arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("The controller was dropped or its connection was lost")]
+pub struct Disconnected;
+
+/// Takes asynchronous events off a controller's connection without blocking,
+/// for event loops that poll.
+///
+/// Created by `TorController::event_receiver`. It shares the event queue with
+/// `TorController::poll_event`, so every event goes to whichever asks first.
+/// Once the controller, and everything else sharing its connection, is dropped
+/// or the connection is lost, the receiver reports `Disconnected`.
+pub struct EventReceiver {
+	conn: Weak<Mutex<Connection>>,
+}
+
+impl EventReceiver {
+	/// The next event that has been received, or `None` if there is none yet.
+	pub fn poll_event(&mut self) -> Result<Option<RawEvent>, Disconnected> {
+		self.with_connection(|conn| conn.try_read_event())
+	}
+
+	/// Waits until `deadline` for the next event. Commands sent through the
+	/// controller in the meantime wait as well.
+	pub fn poll_deadline(&mut self, deadline: Instant) -> Result<Option<RawEvent>, Disconnected> {
+		let timeout = deadline.saturating_duration_since(Instant::now());
+		if timeout.is_zero() {
+			return self.poll_event();
+		}
+		self.with_connection(|conn| conn.read_event_timeout(timeout))
+	}
+
+	/// How many events have been received and not taken yet. Zero once
+	/// disconnected.
+	pub fn len(&self) -> usize {
+		self
+			.with_connection(|conn| conn.queue_pending_events())
+			.unwrap_or(0)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// How many events were dropped because the event queue was full, see
+	/// `TorController::set_events_bounded`.
+	pub fn dropped_count(&self) -> Result<u64, Disconnected> {
+		self.with_connection(|conn| Ok(conn.dropped_events()))
+	}
+
+	fn with_connection<F, T>(&self, f: F) -> Result<T, Disconnected>
+	where
+		F: FnOnce(&mut Connection) -> Result<T, Error>,
+		T: Default,
+	{
+		let conn = self.conn.upgrade().ok_or(Disconnected)?;
+		let mut conn = conn.lock().map_err(|_| Disconnected)?;
+		match f(&mut conn) {
+			Ok(value) => Ok(value),
+			Err(_) if conn.is_broken() => Err(Disconnected),
+			Err(err) => {
+				warn!("Dropping malformed event: {}", err);
+				Ok(T::default())
+			}
+		}
+	}
+}
+
+impl TorController {
+	/// Creates a receiver for polling events without blocking, see
+	/// `EventReceiver`. Subscribe to events with `set_events` as usual.
+	pub fn event_receiver(&self) -> EventReceiver {
+		EventReceiver {
+			conn: std::sync::Arc::downgrade(&self.conn),
+		}
+	}
+}
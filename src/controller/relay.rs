@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::identity::RelayFingerprint;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// Whether a relay has stopped serving traffic because it reached its
+/// accounting limit, as reported by GETINFO accounting/hibernating.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HibernationState {
+	/// Not hibernating ("awake").
+	None,
+	/// Close to the limit; no longer accepting new circuits ("soft").
+	SoftLimit,
+	/// The limit is reached and all connections are closed ("hard").
+	HardLimit,
+	Unknown(String),
+}
+
+impl FromStr for HibernationState {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"awake" => HibernationState::None,
+			"soft" => HibernationState::SoftLimit,
+			"hard" => HibernationState::HardLimit,
+			other => HibernationState::Unknown(other.to_string()),
+		})
+	}
+}
+
+impl fmt::Display for HibernationState {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			HibernationState::None => "awake",
+			HibernationState::SoftLimit => "soft",
+			HibernationState::HardLimit => "hard",
+			HibernationState::Unknown(state) => state,
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayUptime {
+	pub uptime_seconds: u64,
+	pub is_hibernating: bool,
+	/// When the relay last went down, as reported by GETINFO relay/down-since.
+	pub last_restarted: Option<String>,
+}
+
+/// Whether Tor's reachability self-test could reach the relay's ports from
+/// the outside. Ports that aren't configured are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReachabilityStatus {
+	pub or_port:     bool,
+	pub dir_port:    Option<bool>,
+	pub ext_or_port: Option<bool>,
+}
+
+impl ReachabilityStatus {
+	/// Whether every configured port is reachable.
+	pub fn all_reachable(&self) -> bool {
+		self.or_port && self.dir_port != Some(false) && self.ext_or_port != Some(false)
+	}
+}
+
+/// How uploading our descriptor to one directory authority went.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PublishStatus {
+	/// Whether the authority accepted the descriptor last uploaded.
+	pub accepted:      bool,
+	/// When the last upload was attempted, e.g. "2024-05-01 11:20:00".
+	pub last_tried:    Option<String>,
+	/// When the authority last accepted an upload.
+	pub last_uploaded: Option<String>,
+}
+
+/// Our descriptor's upload status at every directory authority, from GETINFO
+/// dir/server-status.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerPublishStatusDetailed {
+	/// Keyed by the authorities' fingerprints, as 40 upper case hex
+	/// characters.
+	pub descriptors_published_to: HashMap<String, PublishStatus>,
+}
+
+/// Bandwidth accounting for the current interval, see GETINFO accounting/*.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountingStats {
+	/// When the interval ends, e.g. "2024-01-31 00:00:00".
+	pub interval_end:       String,
+	pub bytes_read:         u64,
+	pub bytes_written:      u64,
+	pub bytes_left_read:    u64,
+	pub bytes_left_written: u64,
+	pub hibernating:        HibernationState,
+}
+
+/// An overview of our own relay, see `TorController::relay_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayInfo {
+	pub fingerprint:           RelayFingerprint,
+	/// The flags the current consensus assigns us, empty if it doesn't list us
+	/// yet.
+	pub flags:                 Vec<String>,
+	/// The smallest of the average, burst and observed bandwidth in our
+	/// descriptor, in bytes per second.
+	pub advertised_bandwidth:  Option<u64>,
+	/// What the consensus thinks of our version, e.g. "recommended" or
+	/// "obsolete".
+	pub version_status:        String,
+	/// The addresses our ORPort listens on.
+	pub or_listeners:          Vec<String>,
+	/// Whether the consensus lists the descriptor we currently publish.
+	pub descriptor_up_to_date: bool,
+}
+
+impl TorController {
+	/// Gathers what a relay operator usually wants to see at a glance. Fails
+	/// with `Error::NotARelay` if Tor is running as a client only.
+	pub fn relay_info(&mut self) -> Result<RelayInfo, Error> {
+		let keys = ["fingerprint", "status/version/current", "net/listeners/or"];
+		let mut values = match self.send_get_info(&keys)? {
+			Ok(values) => values,
+			// "551 Not running in server mode", for the fingerprint.
+			Err((551, _)) => return Err(Error::NotARelay),
+			Err(_) => return Err(Error::InternalError),
+		};
+		let mut take = |key: &str| values.remove(key).ok_or(Error::InternalError);
+		let fingerprint = RelayFingerprint::from_hex(&take("fingerprint")?)?;
+		let version_status = take("status/version/current")?;
+		let or_listeners = parse_reply(&take("net/listeners/or")?, parsers::listeners)?;
+
+		let status = self.get_router_status_by_fingerprint(&fingerprint)?;
+		let descriptor = match self.get_router_descriptor(fingerprint.clone()) {
+			Ok(descriptor) => Some(descriptor),
+			Err(Error::UnknownRelay(_)) => None,
+			Err(err) => return Err(err),
+		};
+
+		let descriptor_up_to_date = match (&status, &descriptor) {
+			(Some(status), Some(descriptor)) => descriptor.published == Some(status.published),
+			_ => false,
+		};
+		Ok(RelayInfo {
+			fingerprint,
+			flags: status.map(|status| status.flags).unwrap_or_default(),
+			advertised_bandwidth: descriptor
+				.and_then(|descriptor| descriptor.bandwidth)
+				.map(|(average, burst, observed)| average.min(burst).min(observed)),
+			version_status,
+			or_listeners,
+			descriptor_up_to_date,
+		})
+	}
+
+	/// To which directory authorities our descriptor was uploaded. Fails with
+	/// `Error::NotARelay` if Tor is running as a client only.
+	pub fn get_server_publish_status_detailed(
+		&mut self,
+	) -> Result<ServerPublishStatusDetailed, Error> {
+		const KEY: &str = "dir/server-status";
+		let status = match self.send_get_info(&[KEY])? {
+			Ok(mut values) => values.remove(KEY).ok_or(Error::InternalError)?,
+			Err((551, _)) => return Err(Error::NotARelay),
+			Err(_) => return Err(Error::InternalError),
+		};
+
+		let mut detailed = ServerPublishStatusDetailed::default();
+		for line in status.lines().filter(|line| !line.is_empty()) {
+			let (authority, publish_status) = parse_reply(line, parsers::publish_status)?;
+			let authority = RelayFingerprint::from_hex(&authority)?;
+			detailed
+				.descriptors_published_to
+				.insert(authority.as_ref().to_string(), publish_status);
+		}
+		Ok(detailed)
+	}
+
+	pub fn is_accounting_enabled(&mut self) -> Result<bool, Error> {
+		match self.get_info_value("accounting/enabled")?.as_str() {
+			"1" => Ok(true),
+			"0" => Ok(false),
+			_ => Err(Error::InternalError),
+		}
+	}
+
+	/// The accounting statistics, or `None` if accounting is disabled, in which
+	/// case Tor rejects the other accounting/* keys.
+	pub fn get_accounting_stats(&mut self) -> Result<Option<AccountingStats>, Error> {
+		if !self.is_accounting_enabled()? {
+			return Ok(None);
+		}
+
+		let mut response = self.get_info(vec![
+			"accounting/interval-end",
+			"accounting/bytes",
+			"accounting/bytes-left",
+			"accounting/hibernating",
+		])?;
+		let mut take = |key: &str| response.remove(key).ok_or(Error::InternalError);
+		let interval_end = take("accounting/interval-end")?;
+		let (bytes_read, bytes_written) =
+			parse_reply(&take("accounting/bytes")?, parsers::byte_counts)?;
+		let (bytes_left_read, bytes_left_written) =
+			parse_reply(&take("accounting/bytes-left")?, parsers::byte_counts)?;
+		let hibernating = HibernationState::from_str(&take("accounting/hibernating")?)?;
+
+		Ok(Some(AccountingStats {
+			interval_end,
+			bytes_read,
+			bytes_written,
+			bytes_left_read,
+			bytes_left_written,
+			hibernating,
+		}))
+	}
+
+	/// The result of the relay's reachability self-test, or
+	/// `Error::NotTested` if it hasn't run yet.
+	pub fn get_reachability_status(&mut self) -> Result<ReachabilityStatus, Error> {
+		const KEY: &str = "status/reachability-succeeded";
+		match self.send_get_info(&[KEY])? {
+			Ok(mut values) => {
+				let status = values.remove(KEY).ok_or(Error::InternalError)?;
+				parse_reply(&status, parsers::reachability_status)
+			}
+			Err((551, _)) => Err(Error::NotTested),
+			Err(_) => Err(Error::InternalError),
+		}
+	}
+
+	pub fn is_reachable(&mut self) -> Result<bool, Error> {
+		Ok(self.get_reachability_status()?.all_reachable())
+	}
+
+	pub fn get_hibernation_state(&mut self) -> Result<HibernationState, Error> {
+		let state = self.get_info_value("accounting/hibernating")?;
+		HibernationState::from_str(&state)
+	}
+
+	/// Reports how long Tor has been running and whether it is hibernating.
+	///
+	/// `last_restarted` is `None` if Tor doesn't know relay/down-since, which
+	/// is the case for clients and relays that never went down.
+	pub fn get_relay_uptime(&mut self) -> Result<RelayUptime, Error> {
+		let mut response = self.get_info(vec!["uptime", "accounting/hibernating"])?;
+		let uptime = response.remove("uptime").ok_or(Error::InternalError)?;
+		let hibernating = response
+			.remove("accounting/hibernating")
+			.ok_or(Error::InternalError)?;
+
+		let last_restarted = match self.get_info_value("relay/down-since") {
+			Ok(down_since) if down_since.is_empty() => None,
+			Ok(down_since) => Some(down_since),
+			Err(Error::Io(err)) => return Err(Error::Io(err)),
+			Err(Error::ConnectionLost) => return Err(Error::ConnectionLost),
+			Err(_) => None,
+		};
+
+		Ok(RelayUptime {
+			uptime_seconds: u64::from_str(&uptime).map_err(|_| Error::InternalError)?,
+			is_hibernating: HibernationState::from_str(&hibernating)? != HibernationState::None,
+			last_restarted,
+		})
+	}
+}
@@ -0,0 +1,83 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use super::Signal;
+
+/// Signals waiting to be sent, handed out by `Signal::priority` with the most
+/// urgent first and in the order they were pushed otherwise.
+#[derive(Debug, Default)]
+pub struct SignalQueue {
+	heap:   BinaryHeap<Queued>,
+	pushed: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Queued {
+	signal: Signal,
+	// Breaks ties between signals of the same priority.
+	order:  u64,
+}
+
+impl Ord for Queued {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self
+			.signal
+			.priority()
+			.cmp(&other.signal.priority())
+			.then_with(|| other.order.cmp(&self.order))
+	}
+}
+
+impl PartialOrd for Queued {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl SignalQueue {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn push(&mut self, signal: Signal) {
+		self.heap.push(Queued {
+			signal,
+			order: self.pushed,
+		});
+		self.pushed += 1;
+	}
+
+	pub fn pop(&mut self) -> Option<Signal> {
+		self.heap.pop().map(|queued| queued.signal)
+	}
+
+	pub fn len(&self) -> usize {
+		self.heap.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.heap.is_empty()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn most_urgent_first() {
+		let mut queue = SignalQueue::new();
+		queue.push(Signal::Reload);
+		queue.push(Signal::Newnym);
+		queue.push(Signal::Halt);
+		queue.push(Signal::ClearDNSCache);
+		assert_eq!(queue.len(), 4);
+
+		assert_eq!(queue.pop(), Some(Signal::Halt));
+		assert_eq!(queue.pop(), Some(Signal::Reload));
+		assert_eq!(queue.pop(), Some(Signal::Newnym));
+		assert_eq!(queue.pop(), Some(Signal::ClearDNSCache));
+		assert_eq!(queue.pop(), None);
+		assert!(queue.is_empty());
+	}
+}
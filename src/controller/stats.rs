@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+
+use super::TorController;
+
+/// Health counters of a controller, see `TorController::stats`.
+///
+/// Only commands sent one at a time are counted, not those sent through a
+/// `Pipeline`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TorControllerStats {
+	/// Every attempt counts, including retries.
+	pub commands_sent:      u64,
+	/// Commands that didn't get a reply or that Tor answered with an error.
+	pub commands_failed:    u64,
+	pub bytes_sent:         u64,
+	/// The bytes of the replies, leaving out events received along the way.
+	pub bytes_received:     u64,
+	pub reconnections:      u64,
+	pub last_command_at:    Option<Instant>,
+	/// How long commands took from sending until their reply was read.
+	pub total_command_time: Duration,
+}
+
+impl TorControllerStats {
+	/// The mean time a command took, zero before the first one.
+	pub fn average_command_latency(&self) -> Duration {
+		if self.commands_sent == 0 {
+			return Duration::ZERO;
+		}
+		let nanos = self.total_command_time.as_nanos() / u128::from(self.commands_sent);
+		Duration::from_nanos(nanos as u64)
+	}
+
+	pub(super) fn record(&mut self, started: Instant, sent: usize, received: usize, failed: bool) {
+		self.commands_sent += 1;
+		if failed {
+			self.commands_failed += 1;
+		}
+		self.bytes_sent += sent as u64;
+		self.bytes_received += received as u64;
+		self.last_command_at = Some(started);
+		self.total_command_time += started.elapsed();
+	}
+}
+
+impl TorController {
+	/// The counters accumulated since the controller connected. They carry
+	/// over `reconnect`.
+	pub fn stats(&self) -> &TorControllerStats {
+		&self.stats
+	}
+}
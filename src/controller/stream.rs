@@ -0,0 +1,96 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// The socket a control connection runs over: a TCP control port or, on unix,
+/// a ControlSocket.
+pub(super) enum Stream {
+	Tcp(TcpStream),
+	#[cfg(unix)]
+	Unix(UnixStream),
+}
+
+impl Stream {
+	pub(super) fn read_timeout(&self) -> io::Result<Option<Duration>> {
+		match self {
+			Stream::Tcp(stream) => stream.read_timeout(),
+			#[cfg(unix)]
+			Stream::Unix(stream) => stream.read_timeout(),
+		}
+	}
+
+	pub(super) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		match self {
+			Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+			#[cfg(unix)]
+			Stream::Unix(stream) => stream.set_read_timeout(timeout),
+		}
+	}
+
+	pub(super) fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		match self {
+			Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+			#[cfg(unix)]
+			Stream::Unix(stream) => stream.set_write_timeout(timeout),
+		}
+	}
+
+	pub(super) fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		match self {
+			Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+			#[cfg(unix)]
+			Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+		}
+	}
+
+	pub(super) fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+		match self {
+			Stream::Tcp(stream) => stream.peek(buf),
+			#[cfg(unix)]
+			Stream::Unix(stream) => stream.peek(buf),
+		}
+	}
+}
+
+impl From<TcpStream> for Stream {
+	fn from(stream: TcpStream) -> Self {
+		Stream::Tcp(stream)
+	}
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+	fn from(stream: UnixStream) -> Self {
+		Stream::Unix(stream)
+	}
+}
+
+impl Read for Stream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match self {
+			Stream::Tcp(stream) => stream.read(buf),
+			#[cfg(unix)]
+			Stream::Unix(stream) => stream.read(buf),
+		}
+	}
+}
+
+impl Write for Stream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self {
+			Stream::Tcp(stream) => stream.write(buf),
+			#[cfg(unix)]
+			Stream::Unix(stream) => stream.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match self {
+			Stream::Tcp(stream) => stream.flush(),
+			#[cfg(unix)]
+			Stream::Unix(stream) => stream.flush(),
+		}
+	}
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::circuits::{Circuit, CircuitId};
+use super::error::Error;
+use super::{parse_reply, TorController};
+use crate::parsers;
+
+/// The identifier Tor assigns to a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StreamId(pub u32);
+
+impl fmt::Display for StreamId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamStatus {
+	New,
+	NewResolve,
+	Remap,
+	SentConnect,
+	SentResolve,
+	Succeeded,
+	Failed,
+	Closed,
+	Detached,
+	ControllerWait,
+	Unknown(String),
+}
+
+impl FromStr for StreamStatus {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"NEW" => StreamStatus::New,
+			"NEWRESOLVE" => StreamStatus::NewResolve,
+			"REMAP" => StreamStatus::Remap,
+			"SENTCONNECT" => StreamStatus::SentConnect,
+			"SENTRESOLVE" => StreamStatus::SentResolve,
+			"SUCCEEDED" => StreamStatus::Succeeded,
+			"FAILED" => StreamStatus::Failed,
+			"CLOSED" => StreamStatus::Closed,
+			"DETACHED" => StreamStatus::Detached,
+			"CONTROLLER_WAIT" => StreamStatus::ControllerWait,
+			other => StreamStatus::Unknown(other.to_string()),
+		})
+	}
+}
+
+impl fmt::Display for StreamStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			StreamStatus::New => "NEW",
+			StreamStatus::NewResolve => "NEWRESOLVE",
+			StreamStatus::Remap => "REMAP",
+			StreamStatus::SentConnect => "SENTCONNECT",
+			StreamStatus::SentResolve => "SENTRESOLVE",
+			StreamStatus::Succeeded => "SUCCEEDED",
+			StreamStatus::Failed => "FAILED",
+			StreamStatus::Closed => "CLOSED",
+			StreamStatus::Detached => "DETACHED",
+			StreamStatus::ControllerWait => "CONTROLLER_WAIT",
+			StreamStatus::Unknown(status) => status,
+		})
+	}
+}
+
+/// A stream as listed by GETINFO stream-status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamEntry {
+	pub id:         StreamId,
+	pub status:     StreamStatus,
+	/// `None` while the stream isn't attached to a circuit.
+	pub circuit_id: Option<CircuitId>,
+	/// The destination as "host:port".
+	pub target:     String,
+}
+
+impl TorController {
+	/// Lists all streams Tor currently knows about.
+	pub fn streams(&mut self) -> Result<Vec<StreamEntry>, Error> {
+		let streams = self.get_info_value("stream-status")?;
+		parse_reply(&streams, parsers::stream_status)
+	}
+
+	/// Lists all streams along with the circuit each one is attached to.
+	///
+	/// Streams and circuits are fetched with a single GETINFO, so they are
+	/// consistent with each other.
+	pub fn streams_with_circuits(&mut self) -> Result<Vec<(StreamEntry, Option<Circuit>)>, Error> {
+		let (streams, circuits) = self.streams_and_circuits()?;
+		let circuits: HashMap<CircuitId, Circuit> = circuits
+			.into_iter()
+			.map(|circuit| (circuit.id, circuit))
+			.collect();
+
+		Ok(
+			streams
+				.into_iter()
+				.map(|stream| {
+					let circuit = stream.circuit_id.and_then(|id| circuits.get(&id).cloned());
+					(stream, circuit)
+				})
+				.collect(),
+		)
+	}
+
+	/// Lists every circuit along with the streams attached to it, fetched in a
+	/// single GETINFO like `streams_with_circuits`.
+	pub fn get_circuits_with_streams(&mut self) -> Result<Vec<(Circuit, Vec<StreamEntry>)>, Error> {
+		let (streams, circuits) = self.streams_and_circuits()?;
+		let mut attached: HashMap<CircuitId, Vec<StreamEntry>> = HashMap::new();
+		for stream in streams {
+			if let Some(id) = stream.circuit_id {
+				attached.entry(id).or_default().push(stream);
+			}
+		}
+		Ok(
+			circuits
+				.into_iter()
+				.map(|circuit| {
+					let streams = attached.remove(&circuit.id).unwrap_or_default();
+					(circuit, streams)
+				})
+				.collect(),
+		)
+	}
+
+	/// How many streams are attached to each circuit, including circuits
+	/// without any.
+	pub fn get_circuit_stream_counts(&mut self) -> Result<HashMap<CircuitId, u32>, Error> {
+		Ok(
+			self
+				.get_circuits_with_streams()?
+				.into_iter()
+				.map(|(circuit, streams)| (circuit.id, streams.len() as u32))
+				.collect(),
+		)
+	}
+
+	fn streams_and_circuits(&mut self) -> Result<(Vec<StreamEntry>, Vec<Circuit>), Error> {
+		let mut response = self.get_info(vec!["stream-status", "circuit-status"])?;
+		let streams = response
+			.remove("stream-status")
+			.ok_or(Error::InternalError)?;
+		let circuits = response
+			.remove("circuit-status")
+			.ok_or(Error::InternalError)?;
+		Ok((
+			parse_reply(&streams, parsers::stream_status)?,
+			parse_reply(&circuits, parsers::circuit_status)?,
+		))
+	}
+}
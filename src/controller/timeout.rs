@@ -0,0 +1,49 @@
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use super::TorController;
+
+/// Bounds how long each command sent through it may take, see
+/// `TorController::with_timeout`. The previous limit is restored when the
+/// guard is dropped.
+pub struct TimeoutGuard<'a> {
+	controller: &'a mut TorController,
+	previous:   Option<Duration>,
+}
+
+impl<'a> Deref for TimeoutGuard<'a> {
+	type Target = TorController;
+
+	fn deref(&self) -> &TorController {
+		self.controller
+	}
+}
+
+impl<'a> DerefMut for TimeoutGuard<'a> {
+	fn deref_mut(&mut self) -> &mut TorController {
+		self.controller
+	}
+}
+
+impl<'a> Drop for TimeoutGuard<'a> {
+	fn drop(&mut self) {
+		self.controller.command_timeout = self.previous;
+	}
+}
+
+impl TorController {
+	/// Fails commands sent through the returned guard whose reply takes longer
+	/// than `timeout` to arrive with `Error::Timeout`, independently of the
+	/// socket's read timeout.
+	///
+	/// A reply that timed out can't be told apart from the next one, so the
+	/// connection is broken afterwards and every command fails with
+	/// `Error::ConnectionLost` until `reconnect`.
+	pub fn with_timeout(&mut self, timeout: Duration) -> TimeoutGuard<'_> {
+		let previous = self.command_timeout.replace(timeout);
+		TimeoutGuard {
+			controller: self,
+			previous,
+		}
+	}
+}
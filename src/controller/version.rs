@@ -0,0 +1,371 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::TorController;
+
+/// A Tor version such as "0.4.8.9" or "0.4.9.1-alpha".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorVersion {
+	pub major:  u32,
+	pub minor:  u32,
+	pub micro:  u32,
+	pub patch:  u32,
+	/// The status tag after the numbers, e.g. "alpha" or "rc".
+	pub status: Option<String>,
+	/// The build tag in parentheses after the version, e.g. "git-7bcaf9b3b1e65f43".
+	pub tag:    Option<String>,
+}
+
+impl FromStr for TorVersion {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (version, tag) = match s.split_once(' ') {
+			Some((version, tag)) => {
+				let tag = tag.trim().trim_start_matches('(').trim_end_matches(')');
+				(version, Some(tag.to_string()))
+			}
+			None => (s, None),
+		};
+		let (numbers, status) = match version.split_once('-') {
+			Some((numbers, status)) => (numbers, Some(status.to_string())),
+			None => (version, None),
+		};
+
+		let numbers = numbers
+			.split('.')
+			.map(u32::from_str)
+			.collect::<Result<Vec<u32>, _>>()
+			.map_err(|_| Error::InternalError)?;
+		match numbers[..] {
+			[major, minor, micro, patch] => Ok(TorVersion {
+				major,
+				minor,
+				micro,
+				patch,
+				status,
+				tag,
+			}),
+			[major, minor, micro] => Ok(TorVersion {
+				major,
+				minor,
+				micro,
+				patch: 0,
+				status,
+				tag,
+			}),
+			_ => Err(Error::InternalError),
+		}
+	}
+}
+
+/// Leaves out the build tag.
+impl fmt::Display for TorVersion {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}.{}.{}.{}",
+			self.major, self.minor, self.micro, self.patch
+		)?;
+		if let Some(status) = &self.status {
+			write!(f, "-{}", status)?;
+		}
+		Ok(())
+	}
+}
+
+impl Ord for TorVersion {
+	// A release sorts after its alphas and release candidates. The build tag
+	// only breaks ties.
+	fn cmp(&self, other: &Self) -> Ordering {
+		let numbers = |v: &TorVersion| (v.major, v.minor, v.micro, v.patch);
+		numbers(self)
+			.cmp(&numbers(other))
+			.then_with(|| match (&self.status, &other.status) {
+				(None, None) => Ordering::Equal,
+				(None, Some(_)) => Ordering::Greater,
+				(Some(_), None) => Ordering::Less,
+				(Some(status), Some(other)) => status.cmp(other),
+			})
+			.then_with(|| self.tag.cmp(&other.tag))
+	}
+}
+
+impl PartialOrd for TorVersion {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+/// How the running Tor version compares to the versions the consensus
+/// recommends, from GETINFO status/version/current.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionStatus {
+	Recommended,
+	/// Tor reports "obsolete": a newer version of the same series is
+	/// recommended, namely the one given.
+	NewVersionAvailable(TorVersion),
+	/// Tor reports "obsolete", but none of the recommended versions is newer
+	/// in the same series.
+	ObsoleteVersion,
+	/// The running series isn't recommended anymore.
+	UnrecommendedVersion,
+	/// Any other status, e.g. "new" for a version newer than every recommended
+	/// one or "unknown" before Tor has a consensus.
+	Unknown(String),
+}
+
+impl VersionStatus {
+	fn new(status: &str, running: &TorVersion, recommended: &[TorVersion]) -> Self {
+		match status {
+			"recommended" => VersionStatus::Recommended,
+			"obsolete" => recommended
+				.iter()
+				.filter(|version| {
+					(version.major, version.minor, version.micro)
+						== (running.major, running.minor, running.micro)
+				})
+				.filter(|version| *version > running)
+				.max()
+				.cloned()
+				.map_or(
+					VersionStatus::ObsoleteVersion,
+					VersionStatus::NewVersionAvailable,
+				),
+			"unrecommended" => VersionStatus::UnrecommendedVersion,
+			other => VersionStatus::Unknown(other.to_string()),
+		}
+	}
+}
+
+/// Commands and arguments that only newer versions of Tor understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+	TakeOwnership,
+	AddOnion,
+	HsFetch,
+	DropOwnership,
+	OnionClientAuth,
+	/// The V3Auth flag of ADD_ONION.
+	V3Auth,
+}
+
+// The first version supporting each capability, from the control spec.
+const REQUIREMENTS: &[(Capability, &str)] = &[
+	(Capability::TakeOwnership, "0.2.2.28-beta"),
+	(Capability::AddOnion, "0.2.7.1-alpha"),
+	(Capability::HsFetch, "0.2.7.1-alpha"),
+	(Capability::DropOwnership, "0.4.0.0-alpha"),
+	(Capability::OnionClientAuth, "0.4.3.1-alpha"),
+	(Capability::V3Auth, "0.4.6.1-alpha"),
+];
+
+impl Capability {
+	pub fn required_version(&self) -> TorVersion {
+		let (_, version) = REQUIREMENTS
+			.iter()
+			.find(|(capability, _)| capability == self)
+			.expect("every capability has a required version");
+		TorVersion::from_str(version).expect("required versions are valid")
+	}
+}
+
+/// What the connected Tor supports, based on the version it reported when
+/// connecting.
+///
+/// If the version is unknown every capability is assumed to be supported and
+/// Tor gets to reject what it doesn't understand.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+	version: Option<TorVersion>,
+}
+
+impl Capabilities {
+	pub(super) fn for_version(version: &str) -> Self {
+		Capabilities {
+			version: TorVersion::from_str(version).ok(),
+		}
+	}
+
+	pub fn version(&self) -> Option<&TorVersion> {
+		self.version.as_ref()
+	}
+
+	pub fn supports(&self, capability: Capability) -> bool {
+		self.require(capability).is_ok()
+	}
+
+	/// Fails with `Error::UnsupportedByTorVersion` if Tor is too old for
+	/// `capability`.
+	pub fn require(&self, capability: Capability) -> Result<(), Error> {
+		let running = match &self.version {
+			Some(running) => running,
+			None => return Ok(()),
+		};
+		let needed = capability.required_version();
+		if *running < needed {
+			return Err(Error::UnsupportedByTorVersion {
+				needed:  Box::new(needed),
+				running: Box::new(running.clone()),
+			});
+		}
+		Ok(())
+	}
+}
+
+impl TorController {
+	pub fn capabilities(&self) -> &Capabilities {
+		&self.capabilities
+	}
+
+	/// The version of the running Tor, the same as `get_tor_version`.
+	pub fn get_version(&mut self) -> Result<TorVersion, Error> {
+		self.get_tor_version()
+	}
+
+	/// The version of the running Tor.
+	///
+	/// Newer versions of Tor answer GETINFO tor/version, while older ones only
+	/// know GETINFO version, which is asked for instead if Tor doesn't
+	/// recognize the first key.
+	pub fn get_tor_version(&mut self) -> Result<TorVersion, Error> {
+		const KEY: &str = "tor/version";
+		let mut values = match self.send_get_info(&[KEY])? {
+			Ok(values) => values,
+			Err((552, _)) => return TorVersion::from_str(&self.get_info_value("version")?),
+			Err(_) => return Err(Error::InternalError),
+		};
+		TorVersion::from_str(&values.remove(KEY).ok_or(Error::InternalError)?)
+	}
+
+	/// The git commit Tor was built from, from GETINFO tor/git-commit. None for
+	/// builds that don't record it, which includes some release builds.
+	pub fn get_tor_version_hash(&mut self) -> Result<Option<String>, Error> {
+		const KEY: &str = "tor/git-commit";
+		let mut values = match self.send_get_info(&[KEY])? {
+			Ok(values) => values,
+			Err((551, _)) | Err((552, _)) => return Ok(None),
+			Err(_) => return Err(Error::InternalError),
+		};
+		Ok(values.remove(KEY).filter(|hash| !hash.is_empty()))
+	}
+
+	/// The versions the consensus recommends, from GETINFO
+	/// status/version/recommended.
+	pub fn get_recommended_versions(&mut self) -> Result<Vec<TorVersion>, Error> {
+		let versions = self.get_info_value("status/version/recommended")?;
+		versions
+			.split(',')
+			.filter(|version| !version.is_empty())
+			.map(TorVersion::from_str)
+			.collect()
+	}
+
+	/// Whether the consensus recommends the running version of Tor.
+	pub fn get_version_status(&mut self) -> Result<VersionStatus, Error> {
+		Ok(self.get_version_full_status()?.1)
+	}
+
+	/// The running version together with its status, asked for in one GETINFO.
+	pub fn get_version_full_status(&mut self) -> Result<(TorVersion, VersionStatus), Error> {
+		let info = self.get_info(vec![
+			"version",
+			"status/version/current",
+			"status/version/recommended",
+		])?;
+		let value = |key: &str| info.get(key).ok_or(Error::InternalError);
+
+		let running = TorVersion::from_str(value("version")?)?;
+		// Versions this crate can't parse can't be newer in the series either.
+		let recommended: Vec<TorVersion> = value("status/version/recommended")?
+			.split(',')
+			.filter_map(|version| TorVersion::from_str(version).ok())
+			.collect();
+		let status = VersionStatus::new(value("status/version/current")?, &running, &recommended);
+		Ok((running, status))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn capabilities(version: &str) -> Capabilities {
+		Capabilities::for_version(version)
+	}
+
+	#[test]
+	fn parse_versions() {
+		let version = TorVersion::from_str("0.4.8.9").unwrap();
+		assert_eq!(
+			(version.major, version.minor, version.micro, version.patch),
+			(0, 4, 8, 9)
+		);
+		assert_eq!(version.status, None);
+
+		assert_eq!(version.tag, None);
+
+		let version = TorVersion::from_str("0.4.9.1-alpha-dev (git-7bcaf9b3b1e65f43)").unwrap();
+		assert_eq!(version.status.as_deref(), Some("alpha-dev"));
+		assert_eq!(version.tag.as_deref(), Some("git-7bcaf9b3b1e65f43"));
+		assert_eq!(version.to_string(), "0.4.9.1-alpha-dev");
+
+		let version = TorVersion::from_str("0.4.7.16 (git-abc123def)").unwrap();
+		assert_eq!(version.to_string(), "0.4.7.16");
+		assert_eq!(version.tag.as_deref(), Some("git-abc123def"));
+
+		let version = TorVersion::from_str("0.4.8.0-alpha").unwrap();
+		assert_eq!(version.micro, 8);
+		assert_eq!(
+			(version.status.as_deref(), version.tag),
+			(Some("alpha"), None)
+		);
+
+		assert!(TorVersion::from_str("0.4").is_err());
+		assert!(TorVersion::from_str("tor").is_err());
+	}
+
+	#[test]
+	fn order_versions() {
+		let version = |s| TorVersion::from_str(s).unwrap();
+		assert!(version("0.4.8.10") > version("0.4.8.9"));
+		assert!(version("0.4.3.1") > version("0.4.3.1-alpha"));
+		assert!(version("0.4.3.1-rc") > version("0.4.3.1-alpha"));
+		assert!(version("0.4.2.7") < version("0.4.3.1-alpha"));
+	}
+
+	#[test]
+	fn requirements_table() {
+		for (capability, _) in REQUIREMENTS {
+			capability.required_version();
+		}
+
+		let old = capabilities("0.3.5.17");
+		assert!(old.supports(Capability::AddOnion));
+		assert!(!old.supports(Capability::DropOwnership));
+		assert!(!old.supports(Capability::OnionClientAuth));
+
+		let client_auth = capabilities("0.4.3.1-alpha");
+		assert!(client_auth.supports(Capability::OnionClientAuth));
+		assert!(!client_auth.supports(Capability::V3Auth));
+
+		let current = capabilities("0.4.8.9");
+		for (capability, _) in REQUIREMENTS {
+			assert!(current.supports(*capability));
+		}
+
+		let unknown = capabilities("unknown");
+		assert_eq!(unknown.version(), None);
+		assert!(unknown.supports(Capability::V3Auth));
+
+		match capabilities("0.2.9.17").require(Capability::OnionClientAuth) {
+			Err(Error::UnsupportedByTorVersion { needed, running }) => {
+				assert_eq!(needed.to_string(), "0.4.3.1-alpha");
+				assert_eq!(running.to_string(), "0.2.9.17");
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+	}
+}
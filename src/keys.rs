@@ -0,0 +1,142 @@
+//! Generating ED25519-V3 onion service keys without asking Tor, so the onion
+//! address is known before the service is ever added.
+//!
+//! Only available with the `keys` feature.
+
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use data_encoding::BASE32_NOPAD;
+use ed25519_dalek::SigningKey;
+use rand::RngCore;
+use sha2::{Digest, Sha512};
+use sha3::Sha3_256;
+
+use crate::controller::{KeyType, ServiceID};
+
+/// An ED25519-V3 onion service key pair.
+///
+/// The secret key is kept in the expanded form ADD_ONION expects, i.e. the
+/// SHA-512 hash of the seed with the scalar clamped.
+#[derive(Clone, PartialEq, Eq)]
+pub struct OnionKeyPair {
+	secret_key: [u8; 64],
+	public_key: [u8; 32],
+}
+
+impl OnionKeyPair {
+	pub fn generate() -> Self {
+		let mut seed = [0u8; 32];
+		rand::thread_rng().fill_bytes(&mut seed);
+		OnionKeyPair::from_seed(&seed)
+	}
+
+	/// Derives the key pair from a 32 byte ed25519 seed, as in RFC 8032.
+	pub fn from_seed(seed: &[u8; 32]) -> Self {
+		let mut secret_key: [u8; 64] = Sha512::digest(seed).into();
+		secret_key[0] &= 248;
+		secret_key[31] &= 127;
+		secret_key[31] |= 64;
+
+		let public_key = SigningKey::from_bytes(seed).verifying_key().to_bytes();
+		OnionKeyPair {
+			secret_key,
+			public_key,
+		}
+	}
+
+	pub fn public_key(&self) -> &[u8; 32] {
+		&self.public_key
+	}
+
+	/// The key as passed to ADD_ONION, "ED25519-V3:<base64>".
+	pub fn key_blob(&self) -> String {
+		format!(
+			"{}:{}",
+			KeyType::ED25519V3.to_string(),
+			BASE64.encode(self.secret_key)
+		)
+	}
+
+	/// The v3 onion address, without ".onion", as described in rend-spec-v3:
+	/// base32(PUBKEY | CHECKSUM | VERSION).
+	pub fn service_id(&self) -> ServiceID {
+		const VERSION: u8 = 3;
+
+		let checksum = Sha3_256::new()
+			.chain_update(b".onion checksum")
+			.chain_update(self.public_key)
+			.chain_update([VERSION])
+			.finalize();
+
+		let mut address = self.public_key.to_vec();
+		address.extend_from_slice(&checksum[..2]);
+		address.push(VERSION);
+		ServiceID::from(BASE32_NOPAD.encode(&address).to_ascii_lowercase())
+	}
+}
+
+// Leaves out the secret key so it doesn't end up in logs.
+impl fmt::Debug for OnionKeyPair {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("OnionKeyPair")
+			.field("service_id", &self.service_id())
+			.finish_non_exhaustive()
+	}
+}
+
+/// Lets the key pair be passed to `TorController::add_onion_with_key`.
+impl From<&OnionKeyPair> for (KeyType, String) {
+	fn from(key: &OnionKeyPair) -> Self {
+		(KeyType::ED25519V3, BASE64.encode(key.secret_key))
+	}
+}
+
+impl From<OnionKeyPair> for (KeyType, String) {
+	fn from(key: OnionKeyPair) -> Self {
+		(&key).into()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hex(bytes: &[u8]) -> String {
+		bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+	}
+
+	#[test]
+	fn key_pair_from_seed() {
+		// RFC 8032, section 7.1, test 1.
+		let seed = [
+			0x9d, 0x61, 0xb1, 0x9d, 0xef, 0xfd, 0x5a, 0x60, 0xba, 0x84, 0x4a, 0xf4, 0x92, 0xec, 0x2c,
+			0xc4, 0x44, 0x49, 0xc5, 0x69, 0x7b, 0x32, 0x69, 0x19, 0x70, 0x3b, 0xac, 0x03, 0x1c, 0xae,
+			0x7f, 0x60,
+		];
+		let key = OnionKeyPair::from_seed(&seed);
+
+		assert_eq!(
+			hex(key.public_key()),
+			"d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+		);
+		assert_eq!(
+			key.service_id(),
+			ServiceID::from("25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid")
+		);
+		assert_eq!(
+			key.key_blob(),
+			"ED25519-V3:MHyDhk8oM8tCei7xwAoBPP3/J2jZgMCjpSDwBpBN6U+bTwr+KAt0aneGhOdUQlAgV7dHOgPwj5b1o46Sh+Afjw=="
+		);
+		assert!(!format!("{:?}", key).contains("MHyDhk8o"));
+	}
+
+	#[test]
+	fn generated_keys_differ() {
+		let first = OnionKeyPair::generate();
+		let second = OnionKeyPair::generate();
+		assert_ne!(first.service_id(), second.service_id());
+		assert_eq!(first.key_blob().len(), "ED25519-V3:".len() + 88);
+	}
+}
@@ -0,0 +1,355 @@
+//! Running a private tor process owned by the application, for apps that
+//! bundle tor instead of relying on a system daemon.
+//!
+//! Only available with the `launch` feature.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::controller::{Error, Signal, TorController};
+
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(120);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// How many of tor's last log lines are kept for the errors of a failed start.
+const STARTUP_LOG_LINES: usize = 50;
+
+// Tor's log during startup, None once tor has started and its log is only
+// drained anymore.
+type StartupLog = Mutex<Option<VecDeque<String>>>;
+
+#[derive(Debug, Error)]
+pub enum LaunchError {
+	#[error("Tor binary `{0}` not found")]
+	NotFound(PathBuf),
+	#[error("Tor failed to bind a port that is already in use: {0}")]
+	PortInUse(String),
+	#[error("Tor exited during startup with {status}: {log}")]
+	Exited { status: ExitStatus, log: String },
+	#[error("Tor didn't finish bootstrapping in time, got to {progress}%")]
+	BootstrapTimeout { progress: u8 },
+	#[error(transparent)]
+	Controller(#[from] Error),
+	#[error("I/O Error: `{0}`")]
+	Io(#[from] io::Error),
+}
+
+/// Starts a tor process with its own data directory and hands back a
+/// controller for it.
+///
+/// The torrc is generated from the given options, with a control port on a
+/// free local port and cookie authentication. Tor is made to exit when this
+/// process exits or the controller is dropped, so it never outlives the
+/// application.
+pub struct TorLauncher {
+	tor_binary:      PathBuf,
+	data_dir:        PathBuf,
+	options:         Vec<(String, String)>,
+	startup_timeout: Duration,
+}
+
+/// A tor process started by `TorLauncher`. Dropping it shuts tor down and waits
+/// for it to exit.
+pub struct ManagedTor {
+	pub controller: TorController,
+	child:          Child,
+}
+
+impl TorLauncher {
+	pub fn new(data_dir: impl AsRef<Path>) -> Self {
+		TorLauncher {
+			tor_binary:      PathBuf::from("tor"),
+			data_dir:        data_dir.as_ref().to_path_buf(),
+			options:         Vec::new(),
+			startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+		}
+	}
+
+	/// The tor binary to run, "tor" from the PATH by default.
+	pub fn tor_binary(mut self, path: impl AsRef<Path>) -> Self {
+		self.tor_binary = path.as_ref().to_path_buf();
+		self
+	}
+
+	/// Adds a torrc line. Options that may be given several times, like
+	/// SocksPort, can be added more than once.
+	pub fn option(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+		self.options.push((key.into(), value.into()));
+		self
+	}
+
+	pub fn options<K, V>(mut self, options: impl IntoIterator<Item = (K, V)>) -> Self
+	where
+		K: Into<String>,
+		V: Into<String>,
+	{
+		for (key, value) in options {
+			self.options.push((key.into(), value.into()));
+		}
+		self
+	}
+
+	/// How long tor may take to start and bootstrap, two minutes by default.
+	pub fn startup_timeout(mut self, timeout: Duration) -> Self {
+		self.startup_timeout = timeout;
+		self
+	}
+
+	fn control_port_file(&self) -> PathBuf {
+		self.data_dir.join("control-port")
+	}
+
+	fn cookie_file(&self) -> PathBuf {
+		self.data_dir.join("control_auth_cookie")
+	}
+
+	fn write_torrc(&self) -> Result<PathBuf, LaunchError> {
+		std::fs::create_dir_all(&self.data_dir)?;
+		let torrc_path = self.data_dir.join("torrc");
+		let mut torrc = std::fs::File::create(&torrc_path)?;
+		writeln!(torrc, "DataDirectory {}", self.data_dir.display())?;
+		writeln!(torrc, "ControlPort auto")?;
+		writeln!(
+			torrc,
+			"ControlPortWriteToFile {}",
+			self.control_port_file().display()
+		)?;
+		writeln!(torrc, "CookieAuthentication 1")?;
+		writeln!(torrc, "CookieAuthFile {}", self.cookie_file().display())?;
+		for (key, value) in self.options.iter() {
+			writeln!(torrc, "{} {}", key, value)?;
+		}
+		Ok(torrc_path)
+	}
+
+	/// Starts tor and waits until it has bootstrapped.
+	pub fn launch(self) -> Result<ManagedTor, LaunchError> {
+		let torrc_path = self.write_torrc()?;
+		// A stale file from an earlier run would point at the wrong port.
+		let _ = std::fs::remove_file(self.control_port_file());
+
+		let spawned = Command::new(&self.tor_binary)
+			.arg("-f")
+			.arg(&torrc_path)
+			.arg("__OwningControllerProcess")
+			.arg(std::process::id().to_string())
+			.stdin(Stdio::null())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::null())
+			.spawn();
+		let mut child = match spawned {
+			Ok(child) => child,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => {
+				return Err(LaunchError::NotFound(self.tor_binary));
+			}
+			Err(err) => return Err(err.into()),
+		};
+
+		// Tor blocks once the pipe is full, so its log is read until it exits.
+		let log: Arc<StartupLog> = Arc::new(Mutex::new(Some(VecDeque::new())));
+		if let Some(stdout) = child.stdout.take() {
+			let log = log.clone();
+			thread::spawn(move || {
+				for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+					debug!("tor: {}", line);
+					let mut log = log.lock().unwrap_or_else(PoisonError::into_inner);
+					if let Some(log) = log.as_mut() {
+						if log.len() == STARTUP_LOG_LINES {
+							log.pop_front();
+						}
+						log.push_back(line);
+					}
+				}
+			});
+		}
+
+		let deadline = Instant::now() + self.startup_timeout;
+		match self.connect(&mut child, &log, deadline) {
+			Ok(controller) => {
+				*log.lock().unwrap_or_else(PoisonError::into_inner) = None;
+				Ok(ManagedTor { controller, child })
+			}
+			Err(err) => {
+				let _ = child.kill();
+				let _ = child.wait();
+				Err(err)
+			}
+		}
+	}
+
+	fn connect(
+		&self,
+		child: &mut Child,
+		log: &StartupLog,
+		deadline: Instant,
+	) -> Result<TorController, LaunchError> {
+		let control_port = loop {
+			if let Some(status) = child.try_wait()? {
+				// Give the log reader a moment to catch up with the last lines.
+				thread::sleep(POLL_INTERVAL);
+				let log = log.lock().unwrap_or_else(PoisonError::into_inner);
+				let lines: Vec<&str> = log.iter().flatten().map(String::as_str).collect();
+				if let Some(line) = lines
+					.iter()
+					.find(|line| line.contains("Address already in use"))
+				{
+					return Err(LaunchError::PortInUse(line.to_string()));
+				}
+				return Err(LaunchError::Exited {
+					status,
+					log: lines.join("\n"),
+				});
+			}
+			if let Ok(contents) = std::fs::read_to_string(self.control_port_file()) {
+				if let Some(addr) = contents.lines().find_map(|line| line.strip_prefix("PORT=")) {
+					break addr.to_string();
+				}
+			}
+			if Instant::now() >= deadline {
+				return Err(LaunchError::BootstrapTimeout { progress: 0 });
+			}
+			thread::sleep(POLL_INTERVAL);
+		};
+
+		let mut controller = TorController::builder()
+			.addr(control_port.as_str())
+			.auth_cookie_path(self.cookie_file())
+			.build()?;
+		controller.take_ownership()?;
+
+		let remaining = deadline.saturating_duration_since(Instant::now());
+		let phase = controller.wait_for_bootstrap(remaining, |_| {})?;
+		if !phase.is_done() {
+			return Err(LaunchError::BootstrapTimeout {
+				progress: phase.progress,
+			});
+		}
+		Ok(controller)
+	}
+}
+
+impl ManagedTor {
+	pub fn pid(&self) -> u32 {
+		self.child.id()
+	}
+}
+
+impl Drop for ManagedTor {
+	fn drop(&mut self) {
+		// Tor is about to exit, so there is no ownership to hand back when the
+		// controller is dropped.
+		self.controller.forget_ownership();
+		if let Err(err) = self.controller.signal_and_confirm(Signal::Shutdown) {
+			warn!("Tor refused to shut down: {}", err);
+		}
+
+		let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+		loop {
+			match self.child.try_wait() {
+				Ok(Some(_)) => return,
+				Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+				_ => break,
+			}
+		}
+		warn!("Tor didn't exit after SHUTDOWN, killing it");
+		let _ = self.child.kill();
+		let _ = self.child.wait();
+	}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use super::*;
+
+	fn data_dir(name: &str) -> PathBuf {
+		std::env::temp_dir().join(format!("torcc-launch-{}-{}", name, std::process::id()))
+	}
+
+	/// Writes a shell script standing in for the tor binary.
+	fn fake_tor(data_dir: &Path, script: &str) -> PathBuf {
+		use std::os::unix::fs::PermissionsExt;
+
+		std::fs::create_dir_all(data_dir).unwrap();
+		let path = data_dir.join("fake-tor");
+		std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+		std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+		path
+	}
+
+	#[test]
+	fn missing_binary_is_reported() {
+		let data_dir = data_dir("missing");
+		let result = TorLauncher::new(&data_dir)
+			.tor_binary(data_dir.join("no-such-tor"))
+			.launch();
+		assert!(matches!(result, Err(LaunchError::NotFound(_))));
+		std::fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn port_in_use_is_reported() {
+		let data_dir = data_dir("port-in-use");
+		let tor = fake_tor(
+			&data_dir,
+			"echo '[warn] Could not bind to 127.0.0.1:9050: Address already in use. Is Tor already running?'\nexit 1",
+		);
+
+		let result = TorLauncher::new(&data_dir)
+			.tor_binary(tor)
+			.option("SocksPort", "9050")
+			.launch();
+		match result {
+			Err(LaunchError::PortInUse(line)) => assert!(line.contains("127.0.0.1:9050")),
+			other => panic!("unexpected result: {:?}", other.map(|tor| tor.pid())),
+		}
+
+		let torrc = std::fs::read_to_string(data_dir.join("torrc")).unwrap();
+		assert!(torrc.contains("ControlPort auto\n"));
+		assert!(torrc.contains("SocksPort 9050\n"));
+		std::fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn startup_log_keeps_the_last_lines() {
+		let data_dir = data_dir("log-tail");
+		let tor = fake_tor(
+			&data_dir,
+			"i=0\nwhile [ $i -lt 200 ]; do echo \"line $i\"; i=$((i + 1)); done\nexit 1",
+		);
+
+		match TorLauncher::new(&data_dir).tor_binary(tor).launch() {
+			Err(LaunchError::Exited { log, .. }) => {
+				let lines: Vec<&str> = log.lines().collect();
+				assert_eq!(lines.len(), STARTUP_LOG_LINES);
+				assert_eq!(lines.last(), Some(&"line 199"));
+			}
+			other => panic!("unexpected result: {:?}", other.map(|tor| tor.pid())),
+		}
+		std::fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn startup_timeout_kills_tor() {
+		let data_dir = data_dir("timeout");
+		let tor = fake_tor(&data_dir, "exec sleep 30");
+
+		let started = Instant::now();
+		let result = TorLauncher::new(&data_dir)
+			.tor_binary(tor)
+			.startup_timeout(Duration::from_millis(300))
+			.launch();
+		assert!(matches!(
+			result,
+			Err(LaunchError::BootstrapTimeout { progress: 0 })
+		));
+		assert!(started.elapsed() < Duration::from_secs(10));
+		std::fs::remove_dir_all(&data_dir).unwrap();
+	}
+}
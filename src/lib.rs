@@ -1,8 +1,13 @@
 #![feature(buf_read_has_data_left)]
+#![cfg_attr(unix, feature(unix_socket_peek))]
 
 extern crate nom;
 #[macro_use]
 extern crate log;
 
 pub mod controller;
+#[cfg(feature = "keys")]
+pub mod keys;
+#[cfg(feature = "launch")]
+pub mod launch;
 pub mod parsers;
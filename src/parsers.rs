@@ -1,38 +1,44 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
-use crate::controller::{AuthMethod, KeyType, ProtocolInfo, ServiceID};
+use crate::controller::{
+	AuthMethod, BootstrapPhase, CellStatsEvent, Circuit, CircuitBwEvent, CircuitHop, CircuitId,
+	CircuitPurpose, CircuitStatus, ConfigSchema, ConfigType, EntryGuard, EntryGuardInfo,
+	ExitPolicySummary, HsClientAuthInfo, HsDescAction, HsDescEvent, KeyType, Microdescriptor,
+	OnionClientAuthEntry, PortRange, ProtocolInfo, PublishStatus, ReachabilityStatus, RouterStatus,
+	ServiceID, StreamEntry, StreamId, StreamStatus, SUPPORTED_PROTOCOL_VERSIONS,
+};
 use nom::{
-	bytes::complete::{is_not, tag},
-	character::complete::line_ending,
-	combinator::opt,
-	multi::many0,
-	multi::separated_list0,
-	sequence::{delimited, terminated},
+	branch::alt,
+	bytes::complete::{is_not, tag, take, take_till},
+	character::complete::{digit1, line_ending, not_line_ending, one_of},
+	combinator::{map, map_res, not, opt, verify},
+	error::{Error, ErrorKind},
+	multi::{count, many0, many_till, separated_list0, separated_list1},
+	sequence::{delimited, preceded, separated_pair, terminated},
 	IResult,
 };
 
+// A reply ends with a line whose status code is followed by a space, e.g.
+// "250 OK\r\n", as opposed to "250-" or "250+" for the lines before it.
 pub fn is_final_line(line: &str) -> bool {
-	if line.len() < 5 {
-		return false;
-	}
-	for (i, char) in line.chars().enumerate().take(4) {
-		match i {
-			0 | 1 | 2 => {
-				if !char.is_numeric() {
-					return false;
-				}
-			}
-			_ => return char.is_whitespace(),
-		}
-	}
-	return false;
+	let line = line.trim_end_matches(['\r', '\n']);
+	let bytes = line.as_bytes();
+	bytes.len() >= 4 && bytes[..3].iter().all(u8::is_ascii_digit) && bytes[3] == b' '
 }
 
-fn comma_separated_values(input: &str) -> IResult<&str, Vec<&str>> {
-	let (remainder, interesting_part) = is_not(" \r\n")(input)?;
-	let (_, values) = separated_list0(tag(","), is_not(","))(interesting_part)?;
-	return Ok((remainder, values));
+// COOKIE,SAFECOOKIE or "quoted, with commas","and \"escapes\""
+//
+// A trailing comma is consumed without producing an empty value.
+fn comma_separated_values(input: &str) -> IResult<&str, Vec<String>> {
+	let (i, values) = separated_list0(
+		tag(","),
+		alt((quoted_string, map(is_not("\", \r\n"), String::from))),
+	)(input)?;
+	let (i, _) = opt(tag(","))(i)?;
+	Ok((i, values))
 }
 
 pub fn is_ok(input: &str) -> IResult<&str, ()> {
@@ -40,30 +46,47 @@ pub fn is_ok(input: &str) -> IResult<&str, ()> {
 	return Ok((i, ()));
 }
 
+// 552 Unrecognized key "bogus"
+pub fn error_reply(input: &str) -> IResult<&str, (u16, String)> {
+	let (i, code) = map_res(preceded(not(tag("2")), take(3usize)), u16::from_str)(input)?;
+	let (i, message) = preceded(tag(" "), not_line_ending)(i)?;
+	Ok((i, (code, message.to_string())))
+}
+
+// 250-PROTOCOLINFO 1
+pub fn protocol_info_version(input: &str) -> IResult<&str, u32> {
+	preceded(tag("250-PROTOCOLINFO "), map_res(digit1, u32::from_str))(input)
+}
+
 pub fn protocol_info(input: &str) -> IResult<&str, ProtocolInfo> {
-	let (i, _) = tag("250-PROTOCOLINFO 1")(input)?;
+	let (i, _) = verify(protocol_info_version, |version| {
+		SUPPORTED_PROTOCOL_VERSIONS.contains(version)
+	})(input)?;
 	let (i, _) = line_ending(i)?;
 
-	let (i, methods) = delimited(tag("250-AUTH METHODS="), comma_separated_values, tag(" "))(i)?;
-	// TODO: do something with cookiefile
-	let (i, cookiefile) = delimited(tag("COOKIEFILE=\""), is_not("\""), tag("\""))(i)?;
+	let (i, methods) = preceded(tag("250-AUTH METHODS="), comma_separated_values)(i)?;
+	let (i, cookiefile) = opt(preceded(tag(" COOKIEFILE="), quoted_string))(i)?;
 	let (i, _) = line_ending(i)?;
 
-	let (i, version) = delimited(tag("250-VERSION Tor=\""), is_not("\""), tag("\""))(i)?;
+	let (i, version) = preceded(tag("250-VERSION Tor="), quoted_string)(i)?;
 	// TODO: use opt version arguments
 	let (i, _opt_version_arguments) = opt(is_not("\r\n"))(i)?;
 	let (i, _) = line_ending(i)?;
 
-	// TODO: handle optional other line with optional arguments
+	// Lines added by newer versions of the protocol are ignored.
+	let (i, _) = many0(terminated(
+		preceded(tag("250-"), not_line_ending),
+		line_ending,
+	))(i)?;
 	let (i, _) = tag("250 OK")(i)?;
 
 	let protocol_info = ProtocolInfo {
-		cookiefile: cookiefile.to_string(),
+		cookiefile,
 		auth_methods: methods
 			.iter()
 			.map(|method| AuthMethod::from_str(method).unwrap())
 			.collect(),
-		version:      version.to_string(),
+		version: version.to_string(),
 	};
 	return Ok((i, protocol_info));
 }
@@ -73,25 +96,617 @@ pub fn authenticate(input: &str) -> IResult<&str, ()> {
 	return Ok((i, ()));
 }
 
+/// Returns true if the line opens a data block ("250+keyword=") whose
+/// contents run until a line containing a single ".".
+pub fn is_data_line(line: &str) -> bool {
+	line.len() > 3 && line.as_bytes()[3] == b'+' && line[..3].chars().all(char::is_numeric)
+}
+
+/// Returns true if the line terminates a data block.
+pub fn is_data_end(line: &str) -> bool {
+	line.trim_end_matches(&['\r', '\n'][..]) == "."
+}
+
+/// Returns true if the message is an asynchronous event rather than a reply.
+pub fn is_event(message: &str) -> bool {
+	message.starts_with("650")
+}
+
+// 650 CIRC 1000 EXTENDED moria1,moria2
+pub fn event_keyword(input: &str) -> IResult<&str, &str> {
+	let (i, _) = tag("650")(input)?;
+	let (i, _) = one_of(" -+")(i)?;
+	is_not(" \r\n")(i)
+}
+
+fn data_block(input: &str) -> IResult<&str, String> {
+	let (i, (lines, _)) = many_till(
+		terminated(not_line_ending, line_ending),
+		terminated(tag("."), line_ending),
+	)(input)?;
+
+	// Lines starting with a period are escaped by doubling it.
+	let lines: Vec<&str> = lines
+		.into_iter()
+		.map(|line| line.strip_prefix('.').unwrap_or(line))
+		.collect();
+	Ok((i, lines.join("\n")))
+}
+
+fn get_info_line(input: &str) -> IResult<&str, (&str, String)> {
+	let (i, key) = delimited(tag("250-"), is_not("="), tag("="))(input)?;
+	let (i, value) = terminated(not_line_ending, line_ending)(i)?;
+	Ok((i, (key, value.to_string())))
+}
+
+fn get_info_data(input: &str) -> IResult<&str, (&str, String)> {
+	let (i, key) = delimited(tag("250+"), is_not("="), tag("="))(input)?;
+	let (i, _) = line_ending(i)?;
+	let (i, value) = data_block(i)?;
+	Ok((i, (key, value)))
+}
+
 // GETINFO version dormant
 // 250-version
 // 250-dormant
 // 250 OK
+//
+// Values spanning several lines are sent as a data block:
+// 250+entry-guards/in-sample=
+// ...
+// .
 pub fn get_info(input: &str) -> IResult<&str, HashMap<String, String>> {
-	let (i, pairs) = many0(|i| {
-		let (i, key) = delimited(tag("250-"), is_not("="), tag("="))(i)?;
-		let (i, value) = terminated(is_not("\r\n"), line_ending)(i)?;
-		Ok((i, (key, value)))
-	})(input)?;
+	let (i, pairs) = many0(alt((get_info_line, get_info_data)))(input)?;
+	let (i, _) = tag("250 OK")(i)?;
 
 	let mut response = HashMap::new();
 	for (key, value) in pairs.into_iter() {
-		response.insert(key.to_string(), value.to_string());
+		response.insert(key.to_string(), value);
 	}
 
 	Ok((i, response))
 }
 
+fn key_value(input: &str) -> IResult<&str, (&str, &str)> {
+	separated_pair(
+		is_not(" =\r\n"),
+		tag("="),
+		take_till(|c| c == ' ' || c == '\r' || c == '\n'),
+	)(input)
+}
+
+// in=default rsa_id=<fingerprint> nickname=<nickname> sampled_on=<time> listed=1 ...
+fn guard_sample_entry(input: &str) -> IResult<&str, EntryGuardInfo> {
+	let (i, pairs) = separated_list1(tag(" "), key_value)(input)?;
+	let fields: HashMap<&str, &str> = pairs.into_iter().collect();
+
+	let fingerprint = match fields.get("rsa_id") {
+		Some(fingerprint) => fingerprint.to_string(),
+		None => return Err(nom::Err::Error(Error::new(input, ErrorKind::Tag))),
+	};
+	let sampled_on = match fields.get("sampled_on") {
+		Some(sampled_on) => sampled_on.to_string(),
+		None => return Err(nom::Err::Error(Error::new(input, ErrorKind::Tag))),
+	};
+	let flag = |key| fields.get(key) == Some(&"1");
+	let float = |key| {
+		fields
+			.get(key)
+			.and_then(|value| f64::from_str(value).ok())
+			.unwrap_or(0.0)
+	};
+
+	let entry = EntryGuardInfo {
+		guard: EntryGuard {
+			fingerprint,
+			nickname: fields.get("nickname").map(|nickname| nickname.to_string()),
+		},
+		is_listed: flag("listed"),
+		is_filtered: flag("filtered"),
+		is_usable: flag("usable"),
+		is_running: flag("running"),
+		sampled_on,
+		last_tried_to_connect_on: fields.get("last_tried_connect").map(|v| v.to_string()),
+		last_confirmed_on: fields.get("confirmed_on").map(|v| v.to_string()),
+		path_bias_successes: float("pb_circ_successes"),
+		path_bias_use_successes: float("pb_use_successes"),
+	};
+	Ok((i, entry))
+}
+
+/// Parses the value of GETINFO entry-guards/in-sample, one guard per line.
+pub fn guard_sample(input: &str) -> IResult<&str, Vec<EntryGuardInfo>> {
+	let (i, entries) = separated_list0(line_ending, guard_sample_entry)(input)?;
+	let (i, _) = opt(line_ending)(i)?;
+	Ok((i, entries))
+}
+
+/// Parses the value of GETINFO status/guards: the name of each section on a
+/// line of its own, followed by its guards like in entry-guards/in-sample.
+pub fn guard_status_sections(input: &str) -> IResult<&str, Vec<(String, Vec<EntryGuardInfo>)>> {
+	many0(map(
+		separated_pair(is_not("= \r\n"), line_ending, guard_sample),
+		|(section, guards)| (section.to_string(), guards),
+	))(input)
+}
+
+/// Parses a newline separated list of onion services, with or without the
+/// ".onion" suffix.
+pub fn service_list(input: &str) -> IResult<&str, Vec<ServiceID>> {
+	let (i, services) = separated_list0(line_ending, is_not("\r\n"))(input)?;
+	let (i, _) = opt(line_ending)(i)?;
+
+	let services = services
+		.into_iter()
+		.map(|service| ServiceID::from(service.trim_end_matches(".onion")))
+		.collect();
+	Ok((i, services))
+}
+
+// ControlPort LineList
+// SocksPort LineList Ports to accept SOCKS connections on
+fn config_name(input: &str) -> IResult<&str, ConfigSchema> {
+	let (i, name) = is_not(" \r\n")(input)?;
+	let (i, _) = tag(" ")(i)?;
+	let (i, value_type) = is_not(" \r\n")(i)?;
+	let (i, description) = opt(preceded(tag(" "), not_line_ending))(i)?;
+
+	let schema = ConfigSchema {
+		name:        name.to_string(),
+		value_type:  ConfigType::from_str(value_type).unwrap(),
+		description: description
+			.filter(|description| !description.is_empty())
+			.map(|description| description.to_string()),
+	};
+	Ok((i, schema))
+}
+
+/// Parses the value of GETINFO config/names, one option per line.
+pub fn config_names(input: &str) -> IResult<&str, Vec<ConfigSchema>> {
+	let (i, names) = separated_list0(line_ending, config_name)(input)?;
+	let (i, _) = opt(line_ending)(i)?;
+	Ok((i, names))
+}
+
+/// Parses a quoted string, undoing the backslash escapes inside it.
+pub fn quoted_string(input: &str) -> IResult<&str, String> {
+	let (i, _) = tag("\"")(input)?;
+	let mut chars = i.char_indices();
+	while let Some((n, c)) = chars.next() {
+		match c {
+			'"' => return Ok((&i[n + 1..], unescape_tor_string(&i[..n]))),
+			'\\' if chars.next().is_none() => break,
+			_ => {}
+		}
+	}
+	Err(nom::Err::Error(Error::new(input, ErrorKind::Escaped)))
+}
+
+/// Undoes the backslash escapes of a quoted string's contents: `\\n`, `\\r`
+/// and `\\t` stand for control characters, and a backslash before anything
+/// else, like `\\\\` or `\\"`, for the character itself.
+pub(crate) fn unescape_tor_string(s: &str) -> String {
+	let mut value = String::with_capacity(s.len());
+	let mut chars = s.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => match chars.next() {
+				Some('n') => value.push('\n'),
+				Some('r') => value.push('\r'),
+				Some('t') => value.push('\t'),
+				Some(escaped) => value.push(escaped),
+				None => value.push('\\'),
+			},
+			_ => value.push(c),
+		}
+	}
+	value
+}
+
+// KEY=value or KEY="quoted value"
+fn keyword_arg(input: &str) -> IResult<&str, (&str, String)> {
+	let (i, key) = is_not(" =\r\n")(input)?;
+	let (i, _) = tag("=")(i)?;
+	let (i, value) = alt((
+		quoted_string,
+		map(
+			take_till(|c| c == ' ' || c == '\r' || c == '\n'),
+			String::from,
+		),
+	))(i)?;
+	Ok((i, (key, value)))
+}
+
+fn keyword_args(input: &str) -> IResult<&str, HashMap<&str, String>> {
+	let (i, args) = many0(preceded(tag(" "), keyword_arg))(input)?;
+	Ok((i, args.into_iter().collect()))
+}
+
+// $F533C81CEF0BC0267857C99B2F471ADF249FA232 ACCEPTED=1 LAST_TRIED="2024-05-01 11:20:00" LAST_UPLOADED="2024-05-01 11:20:01"
+pub fn publish_status(input: &str) -> IResult<&str, (String, PublishStatus)> {
+	let (i, authority) = is_not(" \r\n")(input)?;
+	let (i, mut args) = keyword_args(i)?;
+	let accepted = match args.get("ACCEPTED").map(String::as_str) {
+		Some("1") => true,
+		Some("0") | None => false,
+		Some(_) => return Err(nom::Err::Error(Error::new(input, ErrorKind::Digit))),
+	};
+	let status = PublishStatus {
+		accepted,
+		last_tried: args.remove("LAST_TRIED"),
+		last_uploaded: args.remove("LAST_UPLOADED"),
+	};
+	Ok((i, (authority.to_string(), status)))
+}
+
+// ORPORT=1 DIRPORT=0
+//
+// Tor itself uses OR= and DIR=, which are accepted as well.
+pub fn reachability_status(input: &str) -> IResult<&str, ReachabilityStatus> {
+	let (i, args) = separated_list1(tag(" "), keyword_arg)(input)?;
+	let mut status = ReachabilityStatus::default();
+	let mut has_or_port = false;
+	for (key, value) in args {
+		let reachable = match value.as_str() {
+			"1" => true,
+			"0" => false,
+			_ => return Err(nom::Err::Error(Error::new(input, ErrorKind::Digit))),
+		};
+		match key {
+			"ORPORT" | "OR" => {
+				status.or_port = reachable;
+				has_or_port = true;
+			}
+			"DIRPORT" | "DIR" => status.dir_port = Some(reachable),
+			"EXTORPORT" | "EXTOR" => status.ext_or_port = Some(reachable),
+			_ => {}
+		}
+	}
+	if !has_or_port {
+		return Err(nom::Err::Error(Error::new(input, ErrorKind::Tag)));
+	}
+	Ok((i, status))
+}
+
+// TYPE=x25519 FLAGS=Permanent HAS_KEY=1
+pub fn hs_client_auth_info(input: &str) -> IResult<&str, HsClientAuthInfo> {
+	let (i, args) = separated_list1(tag(" "), keyword_arg)(input)?;
+	let args: HashMap<&str, String> = args.into_iter().collect();
+	let key_type = match args.get("TYPE") {
+		Some(key_type) => key_type.clone(),
+		None => return Err(nom::Err::Error(Error::new(input, ErrorKind::Tag))),
+	};
+	let info = HsClientAuthInfo {
+		key_type,
+		is_permanent: args
+			.get("FLAGS")
+			.is_some_and(|flags| flags.split(',').any(|flag| flag == "Permanent")),
+		has_key: args.get("HAS_KEY").is_some_and(|has_key| has_key == "1"),
+	};
+	Ok((i, info))
+}
+
+// $7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82~Quintex41
+fn long_name(input: &str) -> IResult<&str, CircuitHop> {
+	let (i, _) = tag("$")(input)?;
+	let (i, fingerprint) = is_not("~=, \r\n")(i)?;
+	let (i, nickname) = opt(preceded(one_of("~="), is_not(", \r\n")))(i)?;
+	let hop = CircuitHop {
+		fingerprint: fingerprint.to_string(),
+		nickname:    nickname.map(String::from),
+	};
+	Ok((i, hop))
+}
+
+// 5 BUILT $7A9B...~Quintex41,$05C3...~nick BUILD_FLAGS=NEED_CAPACITY PURPOSE=GENERAL
+pub fn circuit(input: &str) -> IResult<&str, Circuit> {
+	let (i, id) = map_res(digit1, u32::from_str)(input)?;
+	let (i, status) = preceded(tag(" "), is_not(" \r\n"))(i)?;
+	let (i, path) = opt(preceded(tag(" "), separated_list1(tag(","), long_name)))(i)?;
+	let (i, mut args) = keyword_args(i)?;
+
+	let circuit = Circuit {
+		id:           CircuitId(id),
+		status:       CircuitStatus::from_str(status).unwrap(),
+		path:         path.unwrap_or_default(),
+		build_flags:  args
+			.get("BUILD_FLAGS")
+			.map(|flags| flags.split(',').map(String::from).collect())
+			.unwrap_or_default(),
+		purpose:      args
+			.get("PURPOSE")
+			.map(|purpose| CircuitPurpose::from_str(purpose).unwrap()),
+		hs_state:     args.remove("HS_STATE"),
+		rend_query:   args.remove("REND_QUERY"),
+		time_created: args.remove("TIME_CREATED"),
+		reason:       args.remove("REASON"),
+	};
+	Ok((i, circuit))
+}
+
+/// Parses the value of GETINFO circuit-status, one circuit per line.
+pub fn circuit_status(input: &str) -> IResult<&str, Vec<Circuit>> {
+	let (i, circuits) = separated_list0(line_ending, circuit)(input)?;
+	let (i, _) = opt(line_ending)(i)?;
+	Ok((i, circuits))
+}
+
+// 12 SUCCEEDED 5 www.torproject.org:443
+fn stream(input: &str) -> IResult<&str, StreamEntry> {
+	let (i, id) = map_res(digit1, u32::from_str)(input)?;
+	let (i, status) = preceded(tag(" "), is_not(" \r\n"))(i)?;
+	let (i, circuit_id) = preceded(tag(" "), map_res(digit1, u32::from_str))(i)?;
+	let (i, target) = preceded(tag(" "), is_not(" \r\n"))(i)?;
+
+	let stream = StreamEntry {
+		id:         StreamId(id),
+		status:     StreamStatus::from_str(status).unwrap(),
+		circuit_id: Some(CircuitId(circuit_id)).filter(|id| id.0 != 0),
+		target:     target.to_string(),
+	};
+	Ok((i, stream))
+}
+
+/// Parses the value of GETINFO stream-status, one stream per line.
+pub fn stream_status(input: &str) -> IResult<&str, Vec<StreamEntry>> {
+	let (i, streams) = separated_list0(line_ending, stream)(input)?;
+	let (i, _) = opt(line_ending)(i)?;
+	Ok((i, streams))
+}
+
+// 650 STREAM 42 SUCCEEDED 5 example.com:443 SOURCE_ADDR=127.0.0.1:51234
+pub fn stream_event(input: &str) -> IResult<&str, StreamEntry> {
+	preceded(tag("650 STREAM "), stream)(input)
+}
+
+// 650 CIRC 5 BUILT $7A9B...~Quintex41 PURPOSE=GENERAL
+pub fn circuit_event(input: &str) -> IResult<&str, Circuit> {
+	preceded(tag("650 CIRC "), circuit)(input)
+}
+
+// "127.0.0.1:9050" "unix:/run/tor/socks"
+pub fn listeners(input: &str) -> IResult<&str, Vec<String>> {
+	separated_list0(
+		tag(" "),
+		alt((quoted_string, map(is_not(" \r\n"), String::from))),
+	)(input)
+}
+
+// NOTICE BOOTSTRAP PROGRESS=50 TAG=loading_descriptors SUMMARY="Loading relay descriptors"
+pub fn bootstrap_phase(input: &str) -> IResult<&str, BootstrapPhase> {
+	let (i, severity) = terminated(is_not(" \r\n"), tag(" BOOTSTRAP"))(input)?;
+	let (i, mut args) = keyword_args(i)?;
+	let progress = match args.get("PROGRESS").map(|progress| u8::from_str(progress)) {
+		Some(Ok(progress)) => progress,
+		_ => return Err(nom::Err::Error(Error::new(input, ErrorKind::Digit))),
+	};
+
+	let phase = BootstrapPhase {
+		severity: severity.to_string(),
+		progress,
+		tag: args.remove("TAG").unwrap_or_default(),
+		summary: args.remove("SUMMARY").unwrap_or_default(),
+		warning: args.remove("WARNING"),
+		reason: args.remove("REASON"),
+		recommendation: args.remove("RECOMMENDATION"),
+	};
+	Ok((i, phase))
+}
+
+// 650 STATUS_CLIENT WARN BOOTSTRAP PROGRESS=10 TAG=conn_done SUMMARY="Connected to a relay" WARNING="Connection refused" REASON=CONNECTREFUSED
+pub fn status_client_bootstrap(input: &str) -> IResult<&str, BootstrapPhase> {
+	preceded(tag("650 STATUS_CLIENT "), bootstrap_phase)(input)
+}
+
+// 250 AUTHCHALLENGE SERVERHASH=<64 hex digits> SERVERNONCE=<64 hex digits>
+pub fn auth_challenge(input: &str) -> IResult<&str, (String, String)> {
+	let (i, mut args) = preceded(tag("250 AUTHCHALLENGE"), keyword_args)(input)?;
+	match (args.remove("SERVERHASH"), args.remove("SERVERNONCE")) {
+		(Some(hash), Some(nonce)) => Ok((i, (hash, nonce))),
+		_ => Err(nom::Err::Error(Error::new(input, ErrorKind::Tag))),
+	}
+}
+
+// 650 HS_DESC RECEIVED rdwu5tfgmibbgvff NO_AUTH $F6740DEABFD5F62612FA025A5079EA72846B1F67~nick b4ftvqowwk3e3ydmgof5jcqe4j2nwuxv
+// 650 HS_DESC FAILED rdwu5tfgmibbgvff NO_AUTH UNKNOWN REASON=QUERY_NO_HSDIR
+pub fn hs_desc_event(input: &str) -> IResult<&str, HsDescEvent> {
+	let (i, action) = preceded(tag("650 HS_DESC "), is_not(" \r\n"))(input)?;
+	let (i, address) = preceded(tag(" "), is_not(" \r\n"))(i)?;
+	let (i, auth_type) = preceded(tag(" "), is_not(" \r\n"))(i)?;
+	let (i, hs_dir) = preceded(tag(" "), is_not(" \r\n"))(i)?;
+	let (i, descriptor_id) = opt(preceded(
+		tag(" "),
+		terminated(is_not(" =\r\n"), not(tag("="))),
+	))(i)?;
+	let (i, mut args) = keyword_args(i)?;
+
+	let event = HsDescEvent {
+		action:        HsDescAction::from_str(action).unwrap(),
+		address:       address.to_string(),
+		auth_type:     auth_type.to_string(),
+		hs_dir:        Some(hs_dir)
+			.filter(|hs_dir| *hs_dir != "UNKNOWN")
+			.map(String::from),
+		descriptor_id: descriptor_id.map(String::from),
+		reason:        args.remove("REASON"),
+	};
+	Ok((i, event))
+}
+
+// 1024 2048
+pub fn byte_counts(input: &str) -> IResult<&str, (u64, u64)> {
+	separated_pair(
+		map_res(digit1, u64::from_str),
+		tag(" "),
+		map_res(digit1, u64::from_str),
+	)(input)
+}
+
+// 650 BW 1024 2048
+pub fn bw_event(input: &str) -> IResult<&str, (u64, u64)> {
+	preceded(tag("650 BW "), byte_counts)(input)
+}
+
+// 650 CIRC_BW ID=5 READ=1024 WRITTEN=2048 TIME=2024-05-01T12:00:00.123456 DELIVERED_READ=996 ...
+pub fn circuit_bw_event(input: &str) -> IResult<&str, CircuitBwEvent> {
+	let (i, mut args) = preceded(tag("650 CIRC_BW"), keyword_args)(input)?;
+	let invalid = || nom::Err::Error(Error::new(input, ErrorKind::Digit));
+	let number = |key: &str| match args.get(key).map(|value| u64::from_str(value)) {
+		Some(Ok(number)) => Ok(Some(number)),
+		Some(Err(_)) => Err(invalid()),
+		None => Ok(None),
+	};
+
+	let event = CircuitBwEvent {
+		circuit_id:        match number("ID")?.map(u32::try_from) {
+			Some(Ok(id)) => id,
+			_ => return Err(invalid()),
+		},
+		read:              number("READ")?.ok_or_else(invalid)?,
+		written:           number("WRITTEN")?.ok_or_else(invalid)?,
+		delivered_read:    number("DELIVERED_READ")?,
+		delivered_written: number("DELIVERED_WRITTEN")?,
+		overhead_read:     number("OVERHEAD_READ")?,
+		overhead_written:  number("OVERHEAD_WRITTEN")?,
+		time:              args.remove("TIME"),
+	};
+	Ok((i, event))
+}
+
+// 650 CELL_STATS InboundQueue=19403 InboundConn=32 InboundAdded=relay:1,created:1 ...
+pub fn cell_stats_event(input: &str) -> IResult<&str, CellStatsEvent> {
+	let (i, args) = preceded(tag("650 CELL_STATS"), keyword_args)(input)?;
+	let invalid = || nom::Err::Error(Error::new(input, ErrorKind::Digit));
+	let number = |key: &str| match args.get(key).map(|value| u32::from_str(value)) {
+		Some(Ok(number)) => Ok(Some(number)),
+		Some(Err(_)) => Err(invalid()),
+		None => Ok(None),
+	};
+	let conn = |key: &str| match args.get(key).map(|value| u64::from_str(value)) {
+		Some(Ok(conn)) => Ok(Some(conn)),
+		Some(Err(_)) => Err(invalid()),
+		None => Ok(None),
+	};
+	// relay:12,created:1, summed over the cell types.
+	let by_type = |key: &str| {
+		let counts = match args.get(key) {
+			Some(counts) => counts,
+			None => return Ok(0),
+		};
+		counts
+			.split(',')
+			.filter(|count| !count.is_empty())
+			.try_fold(0u64, |total, count| {
+				let (_, count) = count.split_once(':').ok_or_else(invalid)?;
+				Ok(total + u64::from_str(count).map_err(|_| invalid())?)
+			})
+	};
+	let cells =
+		|key: &str| by_type(key).and_then(|cells| u32::try_from(cells).map_err(|_| invalid()));
+
+	let inbound_queue = number("InboundQueue")?;
+	let event = CellStatsEvent {
+		circuit_id:       number("ID")?.or(inbound_queue).ok_or_else(invalid)?,
+		inbound_queue:    inbound_queue.unwrap_or(0),
+		inbound_conn:     conn("InboundConn")?,
+		inbound_added:    cells("InboundAdded")?,
+		inbound_removed:  cells("InboundRemoved")?,
+		inbound_time:     by_type("InboundTime")? as f64,
+		outbound_queue:   number("OutboundQueue")?.unwrap_or(0),
+		outbound_conn:    conn("OutboundConn")?,
+		outbound_added:   cells("OutboundAdded")?,
+		outbound_removed: cells("OutboundRemoved")?,
+		outbound_time:    by_type("OutboundTime")? as f64,
+	};
+	Ok((i, event))
+}
+
+// r moria1 lpXfw1/+uGEym58asExGOXAgzjE IpcU7dolas8+Q+oAzwgvZIWx7PA 2018-05-23 02:52:29 128.31.0.34 9101 9131
+// s Authority Fast Running Stable V2Dir Valid
+pub fn router_status_address(input: &str) -> IResult<&str, std::net::IpAddr> {
+	let (i, _) = tag("r ")(input)?;
+	let (i, _) = count(terminated(is_not(" "), tag(" ")), 5)(i)?;
+	map_res(is_not(" \r\n"), std::net::IpAddr::from_str)(i)
+}
+
+// 80 or 8080-8090
+fn port_range(input: &str) -> IResult<&str, PortRange> {
+	let (i, start) = map_res(digit1, u16::from_str)(input)?;
+	let (i, end) = opt(preceded(tag("-"), map_res(digit1, u16::from_str)))(i)?;
+	let range = PortRange {
+		start,
+		end: end.unwrap_or(start),
+	};
+	Ok((i, range))
+}
+
+fn port_ranges(input: &str) -> IResult<&str, Vec<PortRange>> {
+	alt((
+		map(tag("*"), |_| vec![PortRange::ALL]),
+		separated_list1(tag(","), port_range),
+	))(input)
+}
+
+// accept 80,443,8080-8090 reject *
+pub fn exit_policy_summary(input: &str) -> IResult<&str, ExitPolicySummary> {
+	let (i, entries) = separated_list1(
+		tag(" "),
+		separated_pair(alt((tag("accept"), tag("reject"))), tag(" "), port_ranges),
+	)(input)?;
+
+	let mut summary = ExitPolicySummary::default();
+	for (action, ports) in entries {
+		match action {
+			"accept" => summary.accept_ports.extend(ports),
+			_ => summary.reject_ports.extend(ports),
+		}
+	}
+	Ok((i, summary))
+}
+
+// onion-key
+// -----BEGIN RSA PUBLIC KEY-----
+// ...
+// -----END RSA PUBLIC KEY-----
+// ntor-onion-key 3dZm3xJcPtkGQ5iRX+xQEaTgm8s3+tjxAOyw1VsUWBg=
+// p accept 80,443
+pub fn microdescriptor(input: &str) -> IResult<&str, Microdescriptor> {
+	let (i, lines) = many0(terminated(not_line_ending, line_ending))(input)?;
+
+	let mut microdescriptor = Microdescriptor::default();
+	let mut lines = lines.into_iter();
+	while let Some(line) = lines.next() {
+		let (keyword, arguments) = line.split_once(' ').unwrap_or((line, ""));
+		match keyword {
+			"onion-key" => {
+				let mut key = String::new();
+				for key_line in lines.by_ref() {
+					key.push_str(key_line);
+					key.push('\n');
+					if key_line.starts_with("-----END") {
+						break;
+					}
+				}
+				microdescriptor.onion_key = Some(key);
+			}
+			"ntor-onion-key" => microdescriptor.ntor_onion_key = arguments.to_string(),
+			"family" => microdescriptor.family = arguments.split(' ').map(String::from).collect(),
+			"p" => microdescriptor.policy = Some(arguments.to_string()),
+			"p6" => microdescriptor.policy_ipv6 = Some(arguments.to_string()),
+			"a" => microdescriptor.addresses.push(arguments.to_string()),
+			"id" => {
+				if let Some(id) = arguments.strip_prefix("ed25519 ") {
+					microdescriptor.ed25519_id = Some(id.to_string());
+				}
+			}
+			// Unknown lines are ignored, as the spec requires.
+			_ => {}
+		}
+	}
+
+	Ok((i, microdescriptor))
+}
+
 // named!(pub get_info_version <&str, Vec<(&str, &str)> >,
 //   do_parse!(
 //     tag_s!("250-") >>
@@ -117,7 +732,7 @@ pub fn add_onion(input: &str) -> IResult<&str, (ServiceID, Option<(KeyType, Stri
 	})(i)?;
 
 	// TODO: do something with optional client parameters
-	let (i, _client) = opt(|i| {
+	let (i, _clients) = many0(|i| {
 		let (i, client_name) = delimited(tag("250-ClientAuth="), is_not(":"), tag(":"))(i)?;
 		let (i, client_blob) = is_not("\r\n")(i)?;
 		let (i, _) = line_ending(i)?;
@@ -130,37 +745,679 @@ pub fn add_onion(input: &str) -> IResult<&str, (ServiceID, Option<(KeyType, Stri
 	return Ok((i, (service_id, key)));
 }
 
+// 250-CLIENT 2gzyxa5ihm2nl6ag3eikq6lobtktkvzxfy2emprx3c6st4evgmpzmyad x25519:ZXhhbXBsZQ== Flags=Permanent ClientName=alice
+fn onion_client_auth_entry(input: &str) -> IResult<&str, OnionClientAuthEntry> {
+	let (i, service_id) = preceded(tag("250-CLIENT "), is_not(" \r\n"))(input)?;
+	let (i, (key_type, key_blob)) = preceded(
+		tag(" "),
+		separated_pair(is_not(":"), tag(":"), is_not(" \r\n")),
+	)(i)?;
+	let (i, mut args) = terminated(keyword_args, line_ending)(i)?;
+
+	let entry = OnionClientAuthEntry {
+		service_id:   ServiceID::from(service_id),
+		key_type:     key_type.to_string(),
+		key_blob:     key_blob.to_string(),
+		is_permanent: args
+			.get("Flags")
+			.is_some_and(|flags| flags.split(',').any(|flag| flag == "Permanent")),
+		client_name:  args.remove("ClientName"),
+	};
+	Ok((i, entry))
+}
+
+// ONION_CLIENT_AUTH_VIEW
+// 250-ONION_CLIENT_AUTH_VIEW
+// 250-CLIENT <service_id> x25519:<key> [Flags=Permanent] [ClientName=<name>]
+// 250 OK
+pub fn onion_client_auth_view(input: &str) -> IResult<&str, Vec<OnionClientAuthEntry>> {
+	let (i, _) = opt(terminated(
+		preceded(tag("250-ONION_CLIENT_AUTH_VIEW"), not_line_ending),
+		line_ending,
+	))(input)?;
+	let (i, entries) = many0(onion_client_auth_entry)(i)?;
+	let (i, _) = tag("250 OK")(i)?;
+	Ok((i, entries))
+}
+
+// Days from 1970-01-01 to the given date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year.div_euclid(400);
+	let year_of_era = year - era * 400;
+	let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146097 + day_of_era - 719468
+}
+
+fn number<T: FromStr>(digits: usize) -> impl Fn(&str) -> IResult<&str, T> {
+	move |input| map_res(take(digits), T::from_str)(input)
+}
+
+// 2024-05-01 12:00:00 (UTC)
+pub fn utc_time(input: &str) -> IResult<&str, SystemTime> {
+	let (i, year) = terminated(number::<i64>(4), tag("-"))(input)?;
+	let (i, month) = terminated(number::<i64>(2), tag("-"))(i)?;
+	let (i, day) = terminated(number::<i64>(2), tag(" "))(i)?;
+	let (i, hour) = terminated(number::<u64>(2), tag(":"))(i)?;
+	let (i, minute) = terminated(number::<u64>(2), tag(":"))(i)?;
+	let (i, second) = number::<u64>(2)(i)?;
+
+	let days = days_from_civil(year, month, day);
+	if !(1..=12).contains(&month) || !(1..=31).contains(&day) || days < 0 {
+		return Err(nom::Err::Error(Error::new(input, ErrorKind::Verify)));
+	}
+	let seconds = days as u64 * 86400 + hour * 3600 + minute * 60 + second;
+	Ok((i, SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)))
+}
+
+// r seele AAoQ1DAR6kkoo19hBAX5K0QztNw BfLdeTv0m5Bzh5XmjMHgIPEngIk 2024-05-01 05:47:17 104.53.221.159 9001 0
+//
+// Starts a router status entry; the other fields come from the lines after it.
+pub fn router_status_line(input: &str) -> IResult<&str, RouterStatus> {
+	let word = || terminated(is_not(" "), tag(" "));
+	let (i, _) = tag("r ")(input)?;
+	let (i, nickname) = word()(i)?;
+	let (i, identity) = word()(i)?;
+	let (i, digest) = word()(i)?;
+	let (i, published) = terminated(utc_time, tag(" "))(i)?;
+	let (i, address) = map_res(word(), std::net::IpAddr::from_str)(i)?;
+	let (i, or_port) = terminated(map_res(digit1, u16::from_str), tag(" "))(i)?;
+	let (i, dir_port) = map_res(digit1, u16::from_str)(i)?;
+
+	let status = RouterStatus {
+		nickname: nickname.to_string(),
+		identity: identity.to_string(),
+		digest: digest.to_string(),
+		published,
+		address,
+		or_port,
+		dir_port,
+		or_addresses: Vec::new(),
+		flags: Vec::new(),
+		version: None,
+		protocols: None,
+		bandwidth: None,
+		exit_policy: None,
+	};
+	Ok((i, status))
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::controller::{AuthMethod, KeyType, ProtocolInfo, ServiceID};
 
+	// Tor terminates lines with CRLF, but the parsers also accept a bare LF.
+	const LINE_ENDINGS: [&str; 2] = ["\r\n", "\n"];
+
 	#[test]
 	fn test_protocol_info() {
 		use crate::parsers::protocol_info;
 
+		for eol in LINE_ENDINGS {
+			let reply = format!(
+				"250-PROTOCOLINFO 1{eol}\
+				250-AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE=\"/var/run/tor/control.authcookie\"{eol}\
+				250-VERSION Tor=\"0.1.2.3\"{eol}\
+				250 OK{eol}",
+				eol = eol
+			);
+			assert_eq!(
+				protocol_info(&reply),
+				Ok((
+					eol,
+					ProtocolInfo {
+						auth_methods: vec![AuthMethod::Cookie, AuthMethod::SafeCookie],
+						version:      "0.1.2.3".to_string(),
+						cookiefile:   Some("/var/run/tor/control.authcookie".to_string()),
+					}
+				))
+			)
+		}
+	}
+
+	#[test]
+	fn test_protocol_info_cookiefile_escapes() {
+		use crate::parsers::protocol_info;
+
+		let cookiefile = |quoted: &str| {
+			let reply = format!(
+				"250-PROTOCOLINFO 1\r\n\
+				250-AUTH METHODS=COOKIE COOKIEFILE={}\r\n\
+				250-VERSION Tor=\"0.4.8.9\"\r\n\
+				250 OK\r\n",
+				quoted
+			);
+			protocol_info(&reply).unwrap().1.cookiefile.unwrap()
+		};
+		assert_eq!(
+			cookiefile("\"/home/tor user/Tor Data/control_auth_cookie\""),
+			"/home/tor user/Tor Data/control_auth_cookie"
+		);
+		assert_eq!(
+			cookiefile("\"C:\\\\Users\\\\tor\\\\AppData\\\\Roaming\\\\tor\\\\control_auth_cookie\""),
+			"C:\\Users\\tor\\AppData\\Roaming\\tor\\control_auth_cookie"
+		);
+		assert_eq!(
+			cookiefile("\"C:\\\\odd \\\"name\\\"\\\\cookie\""),
+			"C:\\odd \"name\"\\cookie"
+		);
+	}
+
+	#[test]
+	fn test_comma_separated_values() {
+		use crate::parsers::comma_separated_values;
+
+		let values = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+		assert_eq!(comma_separated_values(""), Ok(("", vec![])));
+		assert_eq!(
+			comma_separated_values(" COOKIEFILE"),
+			Ok((" COOKIEFILE", vec![]))
+		);
+		assert_eq!(
+			comma_separated_values("COOKIE\r\n"),
+			Ok(("\r\n", values(&["COOKIE"])))
+		);
+		assert_eq!(
+			comma_separated_values("COOKIE,SAFECOOKIE,HASHEDPASSWORD COOKIEFILE=x"),
+			Ok((
+				" COOKIEFILE=x",
+				values(&["COOKIE", "SAFECOOKIE", "HASHEDPASSWORD"])
+			))
+		);
+		assert_eq!(
+			comma_separated_values("COOKIE,SAFECOOKIE,"),
+			Ok(("", values(&["COOKIE", "SAFECOOKIE"])))
+		);
+		assert_eq!(
+			comma_separated_values("\"with space\",plain,\"with, comma\""),
+			Ok(("", values(&["with space", "plain", "with, comma"])))
+		);
+		assert_eq!(
+			comma_separated_values("\"say \\\"hi\\\"\",\"\""),
+			Ok(("", values(&["say \"hi\"", ""])))
+		);
+	}
+
+	#[test]
+	fn test_error_reply() {
+		use crate::parsers::error_reply;
+
 		assert_eq!(
-      protocol_info("250-PROTOCOLINFO 1\n250-AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE=\"/var/run/tor/control.authcookie\"\n250-VERSION Tor=\"0.1.2.3\"\n250 OK"),
-      Ok(("", ProtocolInfo {
-        auth_methods: vec![AuthMethod::Cookie, AuthMethod::SafeCookie],
-        version: "0.1.2.3".to_string(),
-				cookiefile: "/var/run/tor/control.authcookie".to_string(),
-      }))
-    )
+			error_reply("552 Unrecognized key \"bogus\"\r\n"),
+			Ok(("\r\n", (552, "Unrecognized key \"bogus\"".to_string())))
+		);
+		assert_eq!(
+			error_reply("551 Address unknown\r\n"),
+			Ok(("\r\n", (551, "Address unknown".to_string())))
+		);
+		assert!(error_reply("250 OK\r\n").is_err());
+	}
+
+	#[test]
+	fn test_is_final_line() {
+		use crate::parsers::is_final_line;
+
+		for eol in LINE_ENDINGS {
+			assert!(is_final_line(&format!("250 OK{}", eol)));
+			assert!(is_final_line(&format!("650 BW 0 0{}", eol)));
+			assert!(!is_final_line(&format!("250-version=0.4.7.16{}", eol)));
+			assert!(!is_final_line(&format!("250+config-text={}", eol)));
+			assert!(!is_final_line(&format!("250{}", eol)));
+		}
+		assert!(is_final_line("250 OK"));
+		assert!(!is_final_line("25 OK\r\n"));
 	}
 
 	#[test]
 	fn test_authenticate() {
 		use crate::parsers::authenticate;
 
-		assert_eq!(authenticate("250 OK"), Ok(("", ())),)
+		assert_eq!(authenticate("250 OK"), Ok(("", ())),);
+		assert_eq!(authenticate("250 OK\r\n"), Ok(("\r\n", ())),)
 	}
 
 	#[test]
 	fn test_get_info_version() {
 		use crate::parsers::get_info;
 
-		let (_, info) = get_info("250-version=0.1.2.3\n250 OK").expect("Error parsing response");
-		assert_eq!(info.get("version"), Some(&String::from("0.1.2.3")),)
+		for eol in LINE_ENDINGS {
+			let reply = format!("250-version=0.1.2.3{eol}250 OK{eol}", eol = eol);
+			let (_, info) = get_info(&reply).expect("Error parsing response");
+			assert_eq!(info.get("version"), Some(&String::from("0.1.2.3")),)
+		}
+	}
+
+	#[test]
+	fn test_get_info_data_block() {
+		use crate::parsers::get_info;
+
+		let (_, info) = get_info(
+			"250+config-text=\r\nSocksPort 9050\r\n..hidden\r\n.\r\n250-version=0.4.7.16\r\n250 OK\r\n",
+		)
+		.expect("Error parsing response");
+		assert_eq!(
+			info.get("config-text"),
+			Some(&String::from("SocksPort 9050\n.hidden"))
+		);
+		assert_eq!(info.get("version"), Some(&String::from("0.4.7.16")));
+	}
+
+	#[test]
+	fn test_guard_sample() {
+		use crate::parsers::guard_sample;
+
+		let sample = "in=default rsa_id=7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82 nickname=Quintex41 sampled_on=2021-11-20T08:34:12 sampled_by=0.4.6.8 listed=1 filtered=1 usable=1 running=1 last_tried_connect=2021-11-30T10:00:41 confirmed_on=2021-11-22T15:21:08 confirmed_idx=0 pb_circ_successes=124.000000 pb_use_successes=93.500000\n\
+			in=default rsa_id=05C3A6B5EAD301FA8DD6D82F3E3E8F3D4ED0E5D4 sampled_on=2021-11-25T21:02:57 sampled_by=0.4.6.8 listed=1 filtered=0 usable=0 running=1\n\
+			in=default rsa_id=F6740DEABFD5F62612FA025A5079EA72846B1F67 nickname=torpidsDEinterwerk sampled_on=2021-11-27T03:44:19 sampled_by=0.4.6.8 listed=0";
+
+		let (rest, guards) = guard_sample(sample).expect("Error parsing guard sample");
+		assert_eq!(rest, "");
+		assert_eq!(guards.len(), 3);
+
+		assert_eq!(guards[0].guard.nickname, Some(String::from("Quintex41")));
+		assert!(guards[0].is_listed && guards[0].is_usable && guards[0].is_running);
+		assert_eq!(
+			guards[0].last_tried_to_connect_on,
+			Some(String::from("2021-11-30T10:00:41"))
+		);
+		assert_eq!(
+			guards[0].last_confirmed_on,
+			Some(String::from("2021-11-22T15:21:08"))
+		);
+		assert_eq!(guards[0].path_bias_successes, 124.0);
+		assert_eq!(guards[0].path_bias_use_successes, 93.5);
+
+		assert_eq!(
+			guards[1].guard.fingerprint,
+			"05C3A6B5EAD301FA8DD6D82F3E3E8F3D4ED0E5D4"
+		);
+		assert_eq!(guards[1].guard.nickname, None);
+		assert!(!guards[1].is_filtered && !guards[1].is_usable);
+		assert_eq!(guards[1].last_tried_to_connect_on, None);
+		assert_eq!(guards[1].last_confirmed_on, None);
+		assert_eq!(guards[1].path_bias_successes, 0.0);
+
+		assert!(!guards[2].is_listed && !guards[2].is_running);
+		assert_eq!(guards[2].sampled_on, "2021-11-27T03:44:19");
+	}
+
+	#[test]
+	fn test_service_list() {
+		use crate::parsers::service_list;
+
+		assert_eq!(service_list(""), Ok(("", vec![])));
+		assert_eq!(
+			service_list("rdwu5tfgmibbgvff.onion"),
+			Ok(("", vec![ServiceID::from("rdwu5tfgmibbgvff")]))
+		);
+		assert_eq!(
+			service_list("rdwu5tfgmibbgvff.onion\nk2edzso5c4rxyay3.onion\n"),
+			Ok((
+				"",
+				vec![
+					ServiceID::from("rdwu5tfgmibbgvff"),
+					ServiceID::from("k2edzso5c4rxyay3")
+				]
+			))
+		);
+	}
+
+	#[test]
+	fn test_event_keyword() {
+		use crate::parsers::event_keyword;
+
+		assert_eq!(
+			event_keyword("650 CIRC 1000 EXTENDED moria1,moria2\r\n"),
+			Ok((" 1000 EXTENDED moria1,moria2\r\n", "CIRC"))
+		);
+		assert_eq!(event_keyword("650-NS\r\n"), Ok(("\r\n", "NS")));
+		assert!(event_keyword("250 OK\r\n").is_err());
+	}
+
+	#[test]
+	fn test_config_names() {
+		use crate::controller::ConfigType;
+		use crate::parsers::config_names;
+
+		let names = "Address String\n\
+			AvoidDiskWrites Bool\n\
+			BandwidthRate DataSize\n\
+			CircuitBuildTimeout TimeInterval\n\
+			CircuitPriorityHalflife Float\n\
+			ClientUseIPv6 BoolAuto\n\
+			ConnLimit Integer\n\
+			DataDirectory Filename\n\
+			SocksPort Linelist Ports to accept SOCKS connections on\n\
+			__ReloadTorrcOnSIGHUP Hidden\n";
+
+		let (rest, schema) = config_names(names).expect("Error parsing config names");
+		assert_eq!(rest, "");
+		assert_eq!(schema.len(), 10);
+
+		let types: Vec<ConfigType> = schema.iter().map(|s| s.value_type.clone()).collect();
+		assert_eq!(
+			types,
+			vec![
+				ConfigType::String,
+				ConfigType::Bool,
+				ConfigType::DataSize,
+				ConfigType::TimeInterval,
+				ConfigType::Float,
+				ConfigType::BoolAuto,
+				ConfigType::Integer,
+				ConfigType::Filename,
+				ConfigType::Linelist,
+				ConfigType::Hidden,
+			]
+		);
+		assert_eq!(schema[0].name, "Address");
+		assert_eq!(schema[0].description, None);
+		assert_eq!(schema[8].name, "SocksPort");
+		assert_eq!(
+			schema[8].description,
+			Some(String::from("Ports to accept SOCKS connections on"))
+		);
+	}
+
+	#[test]
+	fn test_config_type_round_trip() {
+		use crate::controller::ConfigType;
+		use std::str::FromStr;
+
+		for name in ["Linelist_S", "TimeMsecInterval", "AddrPortList", "Virtual"] {
+			assert_eq!(ConfigType::from_str(name).unwrap().to_string(), name);
+		}
+		assert_eq!(
+			ConfigType::from_str("Virtual").unwrap(),
+			ConfigType::Unknown(String::from("Virtual"))
+		);
+	}
+
+	#[test]
+	fn test_quoted_string() {
+		use crate::parsers::quoted_string;
+
+		assert_eq!(quoted_string("\"\" rest"), Ok((" rest", String::new())));
+		assert_eq!(
+			quoted_string("\"say \\\"hi\\\"\\\\\""),
+			Ok(("", String::from("say \"hi\"\\")))
+		);
+		assert!(quoted_string("\"unterminated").is_err());
+		assert!(quoted_string("\"escaped end\\\"").is_err());
+	}
+
+	#[test]
+	fn test_unescape_tor_string() {
+		use crate::parsers::unescape_tor_string;
+
+		assert_eq!(unescape_tor_string("plain"), "plain");
+		assert_eq!(
+			unescape_tor_string("C:\\\\Tor\\\\cookie"),
+			"C:\\Tor\\cookie"
+		);
+		assert_eq!(unescape_tor_string("\\\"quoted\\\""), "\"quoted\"");
+		assert_eq!(unescape_tor_string("tab\\tline\\n"), "tab\tline\n");
+		assert_eq!(unescape_tor_string("trailing\\"), "trailing\\");
+	}
+
+	#[test]
+	fn test_circuit_status() {
+		use crate::controller::{CircuitId, CircuitPurpose, CircuitStatus};
+		use crate::parsers::circuit_status;
+
+		let status = "1 BUILT $7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82~Quintex41,$05C3A6B5EAD301FA8DD6D82F3E3E8F3D4ED0E5D4~nick2,$F6740DEABFD5F62612FA025A5079EA72846B1F67=exit BUILD_FLAGS=NEED_CAPACITY PURPOSE=GENERAL TIME_CREATED=2021-11-30T10:00:41.735217\n\
+			2 LAUNCHED BUILD_FLAGS=IS_INTERNAL,NEED_CAPACITY,NEED_UPTIME PURPOSE=HS_SERVICE_INTRO HS_STATE=HSSI_CONNECTING\n\
+			3 BUILT $7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82 PURPOSE=HS_CLIENT_REND REND_QUERY=rdwu5tfgmibbgvff SOCKS_USERNAME=\"user name\"";
+
+		let (rest, circuits) = circuit_status(status).expect("Error parsing circuit status");
+		assert_eq!(rest, "");
+		assert_eq!(circuits.len(), 3);
+
+		assert_eq!(circuits[0].id, CircuitId(1));
+		assert_eq!(circuits[0].status, CircuitStatus::Built);
+		assert_eq!(circuits[0].path.len(), 3);
+		assert_eq!(circuits[0].path[2].nickname, Some(String::from("exit")));
+		assert_eq!(circuits[0].build_flags, vec!["NEED_CAPACITY"]);
+		assert_eq!(circuits[0].purpose, Some(CircuitPurpose::General));
+
+		assert_eq!(circuits[1].status, CircuitStatus::Launched);
+		assert!(circuits[1].path.is_empty());
+		assert_eq!(circuits[1].build_flags.len(), 3);
+		assert_eq!(circuits[1].hs_state, Some(String::from("HSSI_CONNECTING")));
+
+		assert_eq!(
+			circuits[2].path[0].fingerprint,
+			"7A9BDFDBE8E5465A1164A6C5E0A65FC9E14B3F82"
+		);
+		assert_eq!(circuits[2].path[0].nickname, None);
+		assert_eq!(
+			circuits[2].rend_query,
+			Some(String::from("rdwu5tfgmibbgvff"))
+		);
+	}
+
+	#[test]
+	fn test_listeners() {
+		use crate::parsers::listeners;
+
+		assert_eq!(listeners(""), Ok(("", vec![])));
+		assert_eq!(
+			listeners("\"127.0.0.1:9050\""),
+			Ok(("", vec![String::from("127.0.0.1:9050")]))
+		);
+		assert_eq!(
+			listeners("\"127.0.0.1:9050\" \"unix:/run/tor/socks\" [::1]:9150"),
+			Ok((
+				"",
+				vec![
+					String::from("127.0.0.1:9050"),
+					String::from("unix:/run/tor/socks"),
+					String::from("[::1]:9150")
+				]
+			))
+		);
+	}
+
+	#[test]
+	fn hs_desc_event() {
+		use crate::controller::{HsDescAction, HsDescEvent};
+		use crate::parsers::hs_desc_event;
+
+		assert_eq!(
+			hs_desc_event(
+				"650 HS_DESC FAILED rdwu5tfgmibbgvff NO_AUTH UNKNOWN REASON=QUERY_NO_HSDIR\r\n"
+			),
+			Ok((
+				"\r\n",
+				HsDescEvent {
+					action:        HsDescAction::Failed,
+					address:       "rdwu5tfgmibbgvff".to_string(),
+					auth_type:     "NO_AUTH".to_string(),
+					hs_dir:        None,
+					descriptor_id: None,
+					reason:        Some("QUERY_NO_HSDIR".to_string()),
+				}
+			))
+		);
+		let (_, event) = hs_desc_event(
+			"650 HS_DESC RECEIVED rdwu5tfgmibbgvff NO_AUTH $F6740DEABFD5F62612FA025A5079EA72846B1F67~nick b4ftvqowwk3e3ydm\r\n",
+		)
+		.unwrap();
+		assert_eq!(event.action, HsDescAction::Received);
+		assert_eq!(
+			event.hs_dir.as_deref(),
+			Some("$F6740DEABFD5F62612FA025A5079EA72846B1F67~nick")
+		);
+		assert_eq!(event.descriptor_id.as_deref(), Some("b4ftvqowwk3e3ydm"));
+	}
+
+	#[test]
+	fn circuit_bw_event() {
+		use crate::controller::CircuitBwEvent;
+		use crate::parsers::circuit_bw_event;
+
+		assert_eq!(
+			circuit_bw_event(
+				"650 CIRC_BW ID=17 READ=36864 WRITTEN=6144 TIME=2024-05-01T12:00:00.500813 \
+				DELIVERED_READ=35923 DELIVERED_WRITTEN=5492 OVERHEAD_READ=941 OVERHEAD_WRITTEN=652\r\n"
+			),
+			Ok((
+				"\r\n",
+				CircuitBwEvent {
+					circuit_id:        17,
+					read:              36864,
+					written:           6144,
+					time:              Some("2024-05-01T12:00:00.500813".to_string()),
+					delivered_read:    Some(35923),
+					delivered_written: Some(5492),
+					overhead_read:     Some(941),
+					overhead_written:  Some(652),
+				}
+			))
+		);
+		let (_, event) = circuit_bw_event("650 CIRC_BW ID=3 READ=0 WRITTEN=512").unwrap();
+		assert_eq!((event.time, event.delivered_read), (None, None));
+		assert!(circuit_bw_event("650 CIRC_BW ID=3 READ=0").is_err());
+		assert!(circuit_bw_event("650 CIRC_BW ID=x READ=0 WRITTEN=0").is_err());
+	}
+
+	#[test]
+	fn cell_stats_event() {
+		use crate::parsers::cell_stats_event;
+
+		let (_, event) = cell_stats_event(
+			"650 CELL_STATS InboundQueue=19403 InboundConn=32 InboundAdded=relay:1,created:1 \
+			InboundRemoved=relay:1,created:1 InboundTime=relay:0,created:0 OutboundQueue=6710 \
+			OutboundConn=18 OutboundAdded=create:1,relay_early:1 \
+			OutboundRemoved=create:1,relay_early:1 OutboundTime=create:0,relay_early:5\r\n",
+		)
+		.unwrap();
+		assert_eq!((event.circuit_id, event.inbound_queue), (19403, 19403));
+		assert_eq!(
+			(event.inbound_conn, event.outbound_conn),
+			(Some(32), Some(18))
+		);
+		assert_eq!((event.inbound_added, event.inbound_removed), (2, 2));
+		assert_eq!(event.outbound_queue, 6710);
+		assert_eq!((event.outbound_added, event.outbound_time), (2, 5.0));
+
+		// Circuits Tor originated have an ID and no inbound side.
+		let (_, event) = cell_stats_event(
+			"650 CELL_STATS ID=14 OutboundQueue=19403 OutboundConn=15 \
+			OutboundAdded=create_fast:1,relay_early:2 OutboundRemoved=create_fast:1,relay_early:2 \
+			OutboundTime=create_fast:0,relay_early:10",
+		)
+		.unwrap();
+		assert_eq!((event.circuit_id, event.inbound_queue), (14, 0));
+		assert_eq!((event.inbound_added, event.inbound_time), (0, 0.0));
+		assert_eq!((event.outbound_removed, event.outbound_time), (3, 10.0));
+
+		assert!(cell_stats_event("650 CELL_STATS OutboundQueue=1").is_err());
+		assert!(cell_stats_event("650 CELL_STATS ID=1 OutboundAdded=relay").is_err());
+	}
+
+	#[test]
+	fn exit_policy_summary() {
+		use crate::controller::{ExitPolicySummary, PortRange};
+		use crate::parsers::exit_policy_summary;
+
+		let port = |port| PortRange {
+			start: port,
+			end:   port,
+		};
+		assert_eq!(
+			exit_policy_summary("accept 80,443,8080-8090 reject *"),
+			Ok((
+				"",
+				ExitPolicySummary {
+					accept_ports: vec![
+						port(80),
+						port(443),
+						PortRange {
+							start: 8080,
+							end:   8090,
+						},
+					],
+					reject_ports: vec![PortRange::ALL],
+				}
+			))
+		);
+		assert_eq!(
+			exit_policy_summary("reject 25,119,135-139"),
+			Ok((
+				"",
+				ExitPolicySummary {
+					accept_ports: vec![],
+					reject_ports: vec![
+						port(25),
+						port(119),
+						PortRange {
+							start: 135,
+							end:   139,
+						},
+					],
+				}
+			))
+		);
+		assert!(exit_policy_summary("allow 80").is_err());
+	}
+
+	#[test]
+	fn router_status_address() {
+		use crate::parsers::router_status_address;
+
+		let (_, address) = router_status_address(
+			"r moria1 lpXfw1/+uGEym58asExGOXAgzjE IpcU7dolas8+Q+oAzwgvZIWx7PA 2018-05-23 02:52:29 128.31.0.34 9101 9131\ns Authority Fast Running",
+		)
+		.unwrap();
+		assert_eq!(address, "128.31.0.34".parse::<std::net::IpAddr>().unwrap());
+		assert!(router_status_address("s Authority Fast Running").is_err());
+	}
+
+	#[test]
+	fn onion_client_auth_view() {
+		use crate::controller::OnionClientAuthEntry;
+		use crate::parsers::onion_client_auth_view;
+
+		const SERVICE: &str = "2gzyxa5ihm2nl6ag3eikq6lobtktkvzxfy2emprx3c6st4evgmpzmyad";
+		let plain = OnionClientAuthEntry {
+			service_id:   ServiceID::from(SERVICE),
+			key_type:     "x25519".to_string(),
+			key_blob:     "ZXhhbXBsZQ==".to_string(),
+			is_permanent: false,
+			client_name:  None,
+		};
+		let named = OnionClientAuthEntry {
+			is_permanent: true,
+			client_name: Some("alice".to_string()),
+			..plain.clone()
+		};
+
+		assert_eq!(onion_client_auth_view("250 OK\r\n"), Ok(("\r\n", vec![])));
+		assert_eq!(
+			onion_client_auth_view(&format!(
+				"250-ONION_CLIENT_AUTH_VIEW\r\n250-CLIENT {} x25519:ZXhhbXBsZQ==\r\n250 OK\r\n",
+				SERVICE
+			)),
+			Ok(("\r\n", vec![plain.clone()]))
+		);
+		assert_eq!(
+			onion_client_auth_view(&format!(
+				"250-CLIENT {} x25519:ZXhhbXBsZQ== Flags=Permanent ClientName=alice\r\n250 OK\r\n",
+				SERVICE
+			)),
+			Ok(("\r\n", vec![named.clone()]))
+		);
+		assert_eq!(
+			onion_client_auth_view(&format!(
+				"250-CLIENT {0} x25519:ZXhhbXBsZQ==\r\n\
+				250-CLIENT {0} x25519:ZXhhbXBsZQ== Flags=Permanent ClientName=alice\r\n\
+				250 OK\r\n",
+				SERVICE
+			)),
+			Ok(("\r\n", vec![plain, named]))
+		);
 	}
 
 	// //   ADD_ONION NEW:RSA1024 port=12345
@@ -171,16 +1428,187 @@ mod tests {
 	fn add_onion() {
 		use crate::parsers::add_onion;
 
+		for eol in LINE_ENDINGS {
+			let reply = format!(
+				"250-ServiceID=rdwu5tfgmibbgvff{eol}250-PrivateKey=RSA1024:MIIC{eol}250 OK{eol}",
+				eol = eol
+			);
+			assert_eq!(
+				add_onion(&reply),
+				Ok((
+					eol,
+					(
+						ServiceID::from("rdwu5tfgmibbgvff".to_string()),
+						Some((KeyType::RSA1024, "MIIC".to_string())),
+					),
+				))
+			)
+		}
+	}
+
+	#[test]
+	fn protocol_info_with_extra_lines() {
+		use crate::parsers::protocol_info;
+
+		let reply = "250-PROTOCOLINFO 1\r\n\
+			250-AUTH METHODS=NULL\r\n\
+			250-VERSION Tor=\"0.4.8.9\"\r\n\
+			250-FUTURE-LINE some=\"arguments\"\r\n\
+			250 OK\r\n";
 		assert_eq!(
-			add_onion("250-ServiceID=rdwu5tfgmibbgvff\n250-PrivateKey=RSA1024:MIIC\n250 OK"),
+			protocol_info(reply),
 			Ok((
-				"",
+				"\r\n",
+				ProtocolInfo {
+					auth_methods: vec![AuthMethod::Null],
+					version:      "0.4.8.9".to_string(),
+					cookiefile:   None,
+				}
+			))
+		);
+	}
+
+	#[test]
+	fn add_onion_without_private_key() {
+		use crate::parsers::add_onion;
+
+		// ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port=80
+		let reply =
+			"250-ServiceID=2gzyxa5ihm2nl6ag3eikq6lobtktkvzxfy2emprx3c6st4evgmpzmyad\r\n250 OK\r\n";
+		assert_eq!(
+			add_onion(reply),
+			Ok((
+				"\r\n",
+				(
+					ServiceID::from("2gzyxa5ihm2nl6ag3eikq6lobtktkvzxfy2emprx3c6st4evgmpzmyad"),
+					None
+				)
+			))
+		);
+	}
+
+	#[test]
+	fn add_onion_with_client_auth() {
+		use crate::parsers::add_onion;
+
+		// ADD_ONION NEW:RSA1024 Flags=BasicAuth Port=80 ClientAuth=alice ClientAuth=bob
+		let reply = "250-ServiceID=rdwu5tfgmibbgvff\r\n\
+			250-PrivateKey=RSA1024:MIICXAIBAAKBgQ\r\n\
+			250-ClientAuth=alice:UBVCL52FL6IRYIOLEAYUVQ\r\n\
+			250-ClientAuth=bob:kaLT8dqEWgv6MvBxD1xhyQ\r\n\
+			250 OK\r\n";
+		assert_eq!(
+			add_onion(reply),
+			Ok((
+				"\r\n",
 				(
-					ServiceID::from("rdwu5tfgmibbgvff".to_string()),
-					Some((KeyType::RSA1024, "MIIC".to_string())),
-				),
+					ServiceID::from("rdwu5tfgmibbgvff"),
+					Some((KeyType::RSA1024, "MIICXAIBAAKBgQ".to_string()))
+				)
 			))
+		);
+	}
+
+	#[test]
+	fn get_info_edge_cases() {
+		use crate::parsers::get_info;
+
+		let (_, info) = get_info("250 OK\r\n").unwrap();
+		assert!(info.is_empty());
+
+		// GETINFO config-text version
+		let (_, info) = get_info(
+			"250+config-text=\r\n\
+			ControlPort 9051\r\n\
+			CookieAuthentication 1\r\n\
+			SocksPort 0\r\n\
+			.\r\n\
+			250-version=0.4.8.9\r\n\
+			250 OK\r\n",
+		)
+		.unwrap();
+		assert_eq!(
+			info["config-text"],
+			"ControlPort 9051\nCookieAuthentication 1\nSocksPort 0"
+		);
+		assert_eq!(info["version"], "0.4.8.9");
+
+		assert!(get_info("552 Unrecognized key \"bogus\"\r\n").is_err());
+	}
+
+	#[test]
+	fn is_ok_rejects_other_replies() {
+		use crate::parsers::is_ok;
+
+		assert_eq!(is_ok("250 OK\r\n"), Ok(("\r\n", ())));
+		assert!(is_ok("250-OK\r\n").is_err());
+		assert!(is_ok("251 Resource exhausted\r\n").is_err());
+		assert!(is_ok("451 Resource exhausted\r\n").is_err());
+		assert!(is_ok("510 Unrecognized command \"FOO\"\r\n").is_err());
+		assert!(is_ok("515 Authentication failed: Password did not match HashedControlPassword value from configuration\r\n").is_err());
+	}
+
+	#[test]
+	fn is_final_line_status_codes() {
+		use crate::parsers::is_final_line;
+
+		for line in [
+			"250 OK\r\n",
+			"251 Resource exhausted\r\n",
+			"451 Resource exhausted\r\n",
+			"510 Unrecognized command \"FOO\"\r\n",
+			"552 Unrecognized key \"bogus\"\r\n",
+			"650 CIRC 1000 EXTENDED moria1,moria2\r\n",
+		] {
+			assert!(is_final_line(line), "{:?}", line);
+		}
+		for line in [
+			"250-version=0.4.8.9\r\n",
+			"552-Unrecognized\r\n",
+			"650+NS\r\n",
+		] {
+			assert!(!is_final_line(line), "{:?}", line);
+		}
+	}
+
+	#[test]
+	fn utc_time() {
+		use crate::parsers::utc_time;
+		use std::time::{Duration, SystemTime};
+
+		let (_, time) = utc_time("2024-05-01 12:00:00").unwrap();
+		assert_eq!(
+			time,
+			SystemTime::UNIX_EPOCH + Duration::from_secs(1714564800)
+		);
+		let (_, time) = utc_time("1970-01-01 00:00:01").unwrap();
+		assert_eq!(time, SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+		let (_, time) = utc_time("2000-02-29 23:59:59").unwrap();
+		assert_eq!(
+			time,
+			SystemTime::UNIX_EPOCH + Duration::from_secs(951868799)
+		);
+
+		assert!(utc_time("2024-13-01 00:00:00").is_err());
+		assert!(utc_time("2024-05-01T12:00:00").is_err());
+	}
+
+	#[test]
+	fn router_status_line() {
+		use crate::parsers::router_status_line;
+
+		let (_, status) = router_status_line(
+			"r seele AAoQ1DAR6kkoo19hBAX5K0QztNw BfLdeTv0m5Bzh5XmjMHgIPEngIk 2024-05-01 05:47:17 104.53.221.159 9001 0",
 		)
+		.unwrap();
+		assert_eq!(status.nickname, "seele");
+		assert_eq!(status.identity, "AAoQ1DAR6kkoo19hBAX5K0QztNw");
+		assert_eq!(status.address.to_string(), "104.53.221.159");
+		assert_eq!((status.or_port, status.dir_port), (9001, 0));
+
+		assert!(
+			router_status_line("r seele AAoQ1DAR6kkoo19hBAX5K0QztNw 104.53.221.159 9001 0").is_err()
+		);
 	}
 
 	//   DEL_ONION k2edzso5c4rxyay3